@@ -0,0 +1,5 @@
+ALTER TABLE sessions
+    ADD COLUMN user_agent TEXT,
+    ADD COLUMN ip_address TEXT,
+    -- Bumped whenever the session's refresh token is exchanged
+    ADD COLUMN last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,8 @@
+ALTER TABLE users
+    -- The TOTP secret, encrypted with the server's secret encryption key
+    ADD COLUMN totp_secret_encrypted BYTEA,
+    ADD COLUMN totp_secret_nonce BYTEA,
+    -- Only true once the user has confirmed enrollment with a valid code
+    ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    -- The last accepted time step, so a code can't be replayed
+    ADD COLUMN totp_last_used_step BIGINT;
@@ -0,0 +1,15 @@
+-- Issued after a correct password when the user has 2FA enabled; exchanged for a session
+-- once the second factor is verified.
+CREATE TABLE two_factor_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash BYTEA NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX two_factor_challenges_user_id_idx ON two_factor_challenges(user_id);
+
+ALTER TABLE users
+    ADD COLUMN two_factor_failed_attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN two_factor_locked_until TIMESTAMPTZ;
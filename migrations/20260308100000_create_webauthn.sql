@@ -0,0 +1,25 @@
+CREATE TABLE webauthn_credentials (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The authenticator-assigned credential id
+    credential_id BYTEA NOT NULL UNIQUE,
+    -- The serialized passkey, including its COSE public key
+    public_key JSONB NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    -- A user-chosen name such as "YubiKey 5C"
+    label TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX webauthn_credentials_user_id_idx ON webauthn_credentials(user_id);
+
+-- Server-side state of an in-progress registration or authentication ceremony
+CREATE TABLE webauthn_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    ceremony TEXT NOT NULL CHECK (ceremony IN ('registration', 'authentication')),
+    state JSONB NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,6 @@
+-- Argon2 parameters the client used to derive the master key. Existing accounts were
+-- created with the previously hard-coded client defaults.
+ALTER TABLE users
+    ADD COLUMN kdf_memory_kib INTEGER NOT NULL DEFAULT 65536,
+    ADD COLUMN kdf_iterations INTEGER NOT NULL DEFAULT 3,
+    ADD COLUMN kdf_parallelism INTEGER NOT NULL DEFAULT 4;
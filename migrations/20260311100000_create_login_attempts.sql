@@ -0,0 +1,12 @@
+-- Failed password attempts, used to lock out accounts and IPs under brute force.
+-- Keyed by the submitted email rather than a user id so unknown addresses are locked
+-- out the same way and the lockout can't be used to enumerate accounts.
+CREATE TABLE login_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    email TEXT NOT NULL,
+    ip_address TEXT,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX login_attempts_email_idx ON login_attempts(email);
+CREATE INDEX login_attempts_ip_address_idx ON login_attempts(ip_address, attempted_at);
@@ -0,0 +1,8 @@
+-- Admins can mint invite codes. The first admin is promoted through the
+-- bootstrap_admin_email setting.
+ALTER TABLE users
+    ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Who minted each invite; kept even if that account is later deleted.
+ALTER TABLE invite_codes
+    ADD COLUMN created_by UUID REFERENCES users(id) ON DELETE SET NULL;
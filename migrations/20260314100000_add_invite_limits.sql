@@ -0,0 +1,8 @@
+-- Invites may expire and may be used more than once. is_used is kept in sync and
+-- becomes true once use_count reaches max_uses.
+ALTER TABLE invite_codes
+    ADD COLUMN expires_at TIMESTAMPTZ,
+    ADD COLUMN max_uses INTEGER NOT NULL DEFAULT 1 CHECK (max_uses > 0),
+    ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE invite_codes SET use_count = 1 WHERE is_used;
@@ -0,0 +1,3 @@
+-- Revoked codes are kept for the record but can no longer be used to sign up.
+ALTER TABLE invite_codes
+    ADD COLUMN revoked BOOLEAN NOT NULL DEFAULT FALSE;
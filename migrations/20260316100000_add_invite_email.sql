@@ -0,0 +1,3 @@
+-- When set, only an account registering with this email (case-insensitively) may use the code.
+ALTER TABLE invite_codes
+    ADD COLUMN email TEXT;
@@ -0,0 +1,6 @@
+-- Secrets the server generates for itself on first boot, shared by every instance.
+CREATE TABLE server_secrets (
+    name TEXT PRIMARY KEY,
+    value BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
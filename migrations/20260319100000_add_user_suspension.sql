@@ -0,0 +1,3 @@
+-- Set while an admin has suspended the account; nothing else about it changes.
+ALTER TABLE users
+    ADD COLUMN suspended_at TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- Set by an admin after a suspected compromise; cleared once the user changes their password.
+ALTER TABLE users
+    ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,14 @@
+-- Updated on every completed login, for spotting inactive accounts.
+ALTER TABLE users
+    ADD COLUMN last_login_at TIMESTAMPTZ,
+    ADD COLUMN last_login_ip TEXT;
+
+-- Seed from the most recent session of each user.
+UPDATE users u
+SET last_login_at = s.created_at, last_login_ip = s.ip_address
+FROM (
+    SELECT DISTINCT ON (user_id) user_id, created_at, ip_address
+    FROM sessions
+    ORDER BY user_id, created_at DESC
+) s
+WHERE s.user_id = u.id;
@@ -0,0 +1,18 @@
+-- An audit trail of login attempts. Unlike login_attempts, which only feeds the lockout
+-- and is cleared on success, rows stay until the configured retention has passed.
+CREATE TABLE login_events (
+    id BIGSERIAL PRIMARY KEY,
+    -- NULL for attempts against emails without an account
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    outcome TEXT NOT NULL CHECK (outcome IN (
+        'success', 'wrong_password', 'unknown_email', 'two_factor_failed',
+        'locked_out', 'account_suspended', 'email_unverified'
+    )),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX login_events_user_id_idx ON login_events(user_id, created_at DESC);
+CREATE INDEX login_events_created_at_idx ON login_events(created_at);
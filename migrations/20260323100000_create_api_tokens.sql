@@ -0,0 +1,15 @@
+-- Personal access tokens for scripts and CI, used in place of a login session.
+CREATE TABLE api_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- The random id embedded in the token after `hd_pat_`, used to find the row
+    lookup_id TEXT NOT NULL UNIQUE,
+    -- SHA256 of the whole token
+    token_hash BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens(user_id);
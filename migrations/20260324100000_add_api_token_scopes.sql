@@ -0,0 +1,7 @@
+CREATE TYPE api_token_access AS ENUM ('read', 'read_write');
+
+-- Scopes limiting what a personal access token may do; the defaults grant full access.
+ALTER TABLE api_tokens
+    ADD COLUMN access api_token_access NOT NULL DEFAULT 'read_write',
+    -- The teams the token may see and act on; NULL means every team the user belongs to
+    ADD COLUMN team_ids UUID[];
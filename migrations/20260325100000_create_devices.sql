@@ -0,0 +1,23 @@
+-- The devices (browsers, apps) a user logs in from.
+CREATE TABLE devices (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The identifier the client generated for itself; never returned by the API
+    device_identifier TEXT NOT NULL,
+    name TEXT NOT NULL,
+    platform TEXT,
+    -- Whether the device may do more than the basics under `require_device_approval`
+    approved BOOLEAN NOT NULL DEFAULT FALSE,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, device_identifier)
+);
+
+ALTER TABLE sessions
+    ADD COLUMN device_id UUID REFERENCES devices(id) ON DELETE SET NULL;
+
+CREATE INDEX sessions_device_id_idx ON sessions(device_id);
+
+-- The device a pending second factor login was started from
+ALTER TABLE two_factor_challenges
+    ADD COLUMN device JSONB;
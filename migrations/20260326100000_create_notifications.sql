@@ -0,0 +1,16 @@
+-- Security notifications sent to users, e.g. about a login from a new device.
+CREATE TABLE notifications (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- What happened, e.g. 'new_device_login'
+    kind TEXT NOT NULL,
+    ip_address TEXT,
+    -- A short description of the client, e.g. "Firefox on Linux"
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications(user_id);
+
+ALTER TABLE users
+    ADD COLUMN notify_new_device_login BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,14 @@
+-- Cookie-based sessions for the web client, each backing a login session in place of
+-- an access/refresh token pair.
+CREATE TABLE web_sessions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    -- SHA256 of the session cookie's value
+    token_hash BYTEA NOT NULL UNIQUE,
+    -- SHA256 of the CSRF token that must accompany state-changing requests
+    csrf_token_hash BYTEA NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX web_sessions_session_id_idx ON web_sessions(session_id);
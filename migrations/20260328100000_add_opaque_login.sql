@@ -0,0 +1,26 @@
+-- Accounts log in either with the client-side password hash or through OPAQUE, where
+-- the server only keeps a registration record that can't be used to log in.
+CREATE TYPE auth_scheme AS ENUM ('password_hash', 'opaque');
+
+ALTER TABLE users
+    ADD COLUMN auth_scheme auth_scheme NOT NULL DEFAULT 'password_hash',
+    -- The serialized OPAQUE registration record of an 'opaque' account
+    ADD COLUMN opaque_registration BYTEA,
+    ALTER COLUMN password_hash DROP NOT NULL,
+    ADD CONSTRAINT users_auth_scheme_check CHECK (
+        (auth_scheme = 'password_hash' AND password_hash IS NOT NULL AND opaque_registration IS NULL)
+        OR (auth_scheme = 'opaque' AND opaque_registration IS NOT NULL AND password_hash IS NULL)
+    );
+
+-- Server side of an OPAQUE login between its start and finish. Logins for unknown
+-- emails get one too, so they can't be told apart until they fail.
+CREATE TABLE opaque_login_states (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    state BYTEA NOT NULL,
+    -- The device sent with the login, carried over to the session it completes
+    device JSONB,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,27 @@
+-- A high-entropy key that recovers an account without email. The client keeps a copy
+-- of the private key encrypted under a key derived from it; the key only works once
+-- that copy has been stored.
+CREATE TABLE recovery_keys (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- SHA256 of the recovery key
+    key_hash BYTEA NOT NULL UNIQUE,
+    encrypted_private_key BYTEA,
+    private_key_nonce BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Issued when a recovery key is used, to set a new password with.
+CREATE TABLE account_recovery_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash BYTEA NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX account_recovery_tokens_user_id_idx ON account_recovery_tokens(user_id);
+
+-- Set once a recovery key has been used; cleared when a new one is stored.
+ALTER TABLE users
+    ADD COLUMN recovery_key_required BOOLEAN NOT NULL DEFAULT FALSE;
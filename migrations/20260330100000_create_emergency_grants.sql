@@ -0,0 +1,36 @@
+CREATE TYPE emergency_grant_status AS ENUM ('pending', 'requested', 'rejected', 'unlocked');
+
+-- Emergency access granted by one user (the grantor) to a trusted contact (the grantee),
+-- who can get the grantor's private key once a waiting period passes without rejection.
+CREATE TABLE emergency_grants (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    grantor_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    grantee_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The grantor's private key, wrapped by the client for the grantee's public key
+    wrapped_private_key BYTEA NOT NULL,
+    wait_days INTEGER NOT NULL CHECK (wait_days BETWEEN 1 AND 90),
+    status emergency_grant_status NOT NULL DEFAULT 'pending',
+    -- When the grantee last requested access; the waiting period runs from here
+    requested_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (grantor_id, grantee_id),
+    CHECK (grantor_id <> grantee_id),
+    CHECK ((status = 'requested') = (requested_at IS NOT NULL) OR status = 'unlocked')
+);
+
+CREATE INDEX emergency_grants_grantee_id_idx ON emergency_grants(grantee_id);
+
+-- Every change to an emergency grant. Rows outlive the grant when it is revoked, so
+-- grant_id deliberately has no foreign key.
+CREATE TABLE emergency_grant_events (
+    id BIGSERIAL PRIMARY KEY,
+    grant_id UUID NOT NULL,
+    grantor_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    grantee_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Who made the change: the grantor or the grantee
+    actor_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    action TEXT NOT NULL CHECK (action IN ('created', 'requested', 'rejected', 'unlocked', 'revoked')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX emergency_grant_events_grant_id_idx ON emergency_grant_events(grant_id, created_at);
@@ -0,0 +1,13 @@
+-- An audit trail of public key lookups by email, to spot accounts enumerating users.
+CREATE TABLE public_key_lookups (
+    id BIGSERIAL PRIMARY KEY,
+    -- The user who made the lookup
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    -- The account found, if any
+    found_user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    ip_address TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX public_key_lookups_user_id_idx ON public_key_lookups(user_id, created_at DESC);
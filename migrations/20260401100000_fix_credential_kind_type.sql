@@ -0,0 +1,6 @@
+-- The credential kind type was created as secret_type, but the code knows it as secret_kind.
+ALTER TYPE secret_type RENAME TO secret_kind;
+
+-- The public half of an ssh_key credential, kept in plaintext so it can be shown and copied
+ALTER TABLE credentials
+    ADD COLUMN public_key TEXT;
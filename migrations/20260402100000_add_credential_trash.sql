@@ -0,0 +1,7 @@
+-- Deleted credentials go to the trash first, from which they can be restored or purged.
+ALTER TABLE credentials
+    ADD COLUMN deleted_at TIMESTAMPTZ,
+    ADD COLUMN deleted_by UUID REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE INDEX credentials_team_id_idx ON credentials(team_id) WHERE deleted_at IS NULL;
+CREATE INDEX credentials_trash_idx ON credentials(team_id, deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,3 @@
+-- When a member last used a credential, e.g. to fill in a login. Lets listings sort by
+-- recent use; credentials never used stay NULL.
+ALTER TABLE credentials ADD COLUMN last_used_at TIMESTAMPTZ;
@@ -0,0 +1,7 @@
+-- Trigram indexes let the case-insensitive substring search on credentials use an index
+-- instead of scanning every row of the team.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX credentials_title_trgm_idx ON credentials USING gin (title gin_trgm_ops);
+CREATE INDEX credentials_hostname_trgm_idx ON credentials USING gin (hostname gin_trgm_ops);
+CREATE INDEX credentials_username_trgm_idx ON credentials USING gin (username gin_trgm_ops);
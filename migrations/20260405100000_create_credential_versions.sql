@@ -0,0 +1,19 @@
+-- Earlier versions of a credential, snapshotted whenever it is updated. Version numbers
+-- count up per credential and are never reused, even after old versions are pruned.
+CREATE TABLE credential_versions (
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL CHECK (version > 0),
+    title TEXT NOT NULL,
+    hostname TEXT NOT NULL,
+    username TEXT NOT NULL,
+    kind secret_kind NOT NULL,
+    public_key TEXT,
+    encrypted_secret BYTEA NOT NULL,
+    nonce BYTEA NOT NULL,
+    -- When this version was written, by its creation or the update that produced it
+    written_at TIMESTAMPTZ NOT NULL,
+    -- Who replaced this version with the next one, and when
+    changed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (credential_id, version)
+);
@@ -0,0 +1,2 @@
+-- The version that the change replacing this one restored, when the change was a restore
+ALTER TABLE credential_versions ADD COLUMN restored_from INTEGER;
@@ -0,0 +1,4 @@
+-- API tokens, free-form secure notes, and TOTP seeds
+ALTER TYPE secret_kind ADD VALUE 'api_token';
+ALTER TYPE secret_kind ADD VALUE 'secure_note';
+ALTER TYPE secret_kind ADD VALUE 'totp_secret';
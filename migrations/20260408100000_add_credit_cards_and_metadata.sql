@@ -0,0 +1,6 @@
+ALTER TYPE secret_kind ADD VALUE 'credit_card';
+
+-- Plaintext hints shown in listings without decrypting, e.g. a card's brand and masked
+-- last digits. Clients must never put anything sensitive here.
+ALTER TABLE credentials ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE credential_versions ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
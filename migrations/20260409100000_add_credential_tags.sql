@@ -0,0 +1,3 @@
+-- Free-form labels for filtering. Compared case-insensitively, stored as entered.
+ALTER TABLE credentials ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE credential_versions ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
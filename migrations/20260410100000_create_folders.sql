@@ -0,0 +1,22 @@
+-- Folders organizing the credentials of a team. The composite foreign keys keep a
+-- folder's parent and its credentials in the same team as the folder.
+CREATE TABLE folders (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    parent_id UUID,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (id, team_id),
+    FOREIGN KEY (parent_id, team_id) REFERENCES folders(id, team_id),
+    CHECK (parent_id <> id)
+);
+
+-- Sibling folders have distinct names, ignoring case
+CREATE UNIQUE INDEX folders_sibling_name_idx
+    ON folders(team_id, COALESCE(parent_id, '00000000-0000-0000-0000-000000000000'), lower(name));
+
+ALTER TABLE credentials
+    ADD COLUMN folder_id UUID,
+    ADD FOREIGN KEY (folder_id, team_id) REFERENCES folders(id, team_id);
+
+CREATE INDEX credentials_folder_id_idx ON credentials(folder_id) WHERE folder_id IS NOT NULL;
@@ -0,0 +1,12 @@
+-- Every move of a credential from one team to another. Team ids deliberately have no
+-- foreign keys, so the record outlives either team.
+CREATE TABLE credential_moves (
+    id BIGSERIAL PRIMARY KEY,
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    from_team_id UUID NOT NULL,
+    to_team_id UUID NOT NULL,
+    moved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    moved_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX credential_moves_credential_id_idx ON credential_moves(credential_id, moved_at);
@@ -0,0 +1,17 @@
+-- Credentials a user has starred for themselves. The team id is kept so the foreign key
+-- to team_members drops a user's favorites when they leave the team; the one to
+-- credentials keeps it in step when a credential moves.
+ALTER TABLE credentials ADD UNIQUE (id, team_id);
+
+CREATE TABLE credential_favorites (
+    user_id UUID NOT NULL,
+    credential_id UUID NOT NULL,
+    team_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, credential_id),
+    FOREIGN KEY (credential_id, team_id) REFERENCES credentials(id, team_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (team_id, user_id) REFERENCES team_members(team_id, user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX credential_favorites_credential_id_idx ON credential_favorites(credential_id);
+CREATE INDEX credential_favorites_team_id_idx ON credential_favorites(team_id, user_id);
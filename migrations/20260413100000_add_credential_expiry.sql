@@ -0,0 +1,14 @@
+-- When a credential should be rotated. expiry_notice_days is the smallest number of
+-- days before expires_at members were last notified at, so each notice goes out once;
+-- it is cleared whenever expires_at changes.
+ALTER TABLE credentials
+    ADD COLUMN expires_at TIMESTAMPTZ,
+    ADD COLUMN expiry_notice_days INTEGER;
+
+CREATE INDEX credentials_expires_at_idx ON credentials(expires_at) WHERE expires_at IS NOT NULL AND deleted_at IS NULL;
+
+-- The credential a notification is about, e.g. one that is about to expire
+ALTER TABLE notifications
+    ADD COLUMN credential_id UUID REFERENCES credentials(id) ON DELETE CASCADE;
+
+CREATE INDEX notifications_credential_id_idx ON notifications(credential_id) WHERE credential_id IS NOT NULL;
@@ -0,0 +1,11 @@
+-- When the encrypted secret itself last changed, unlike updated_at, which any edit
+-- bumps. Existing credentials start from their last update, the closest thing known.
+ALTER TABLE credentials ADD COLUMN secret_changed_at TIMESTAMPTZ;
+UPDATE credentials SET secret_changed_at = COALESCE(updated_at, created_at);
+ALTER TABLE credentials
+    ALTER COLUMN secret_changed_at SET NOT NULL,
+    ALTER COLUMN secret_changed_at SET DEFAULT NOW();
+
+-- How many days a team's secrets may go unchanged before they count as stale; NULL
+-- for no limit
+ALTER TABLE teams ADD COLUMN max_secret_age_days INTEGER CHECK (max_secret_age_days > 0);
@@ -0,0 +1,27 @@
+-- Files attached to a credential. Each is encrypted by the client with its own key,
+-- which is stored wrapped with the team key, so moving the credential to another team
+-- only re-wraps the key.
+CREATE TABLE attachments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    filename TEXT NOT NULL,
+    -- The size of the encrypted content, in bytes
+    size BIGINT NOT NULL DEFAULT 0,
+    -- The size of every chunk but the last, so a byte range maps to the chunks holding it
+    chunk_size INTEGER NOT NULL CHECK (chunk_size > 0),
+    encrypted_key BYTEA NOT NULL,
+    key_nonce BYTEA NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX attachments_credential_id_idx ON attachments(credential_id);
+
+-- The encrypted content of an attachment, split into chunks so it never has to be held
+-- in memory at once
+CREATE TABLE attachment_chunks (
+    attachment_id UUID NOT NULL REFERENCES attachments(id) ON DELETE CASCADE,
+    seq INTEGER NOT NULL CHECK (seq >= 0),
+    data BYTEA NOT NULL,
+    PRIMARY KEY (attachment_id, seq)
+);
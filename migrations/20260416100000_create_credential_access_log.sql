@@ -0,0 +1,14 @@
+-- An audit trail of reads of encrypted secrets. Rows stay until the configured
+-- retention has passed, or the credential is purged.
+CREATE TABLE credential_access_log (
+    id BIGSERIAL PRIMARY KEY,
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    -- NULL once the reader's account has been deleted
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    action TEXT NOT NULL CHECK (action IN ('view', 'view_version', 'export')),
+    ip_address TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX credential_access_log_credential_id_idx ON credential_access_log(credential_id, created_at DESC);
+CREATE INDEX credential_access_log_created_at_idx ON credential_access_log(created_at);
@@ -0,0 +1,14 @@
+CREATE TYPE credential_field_type AS ENUM ('text', 'hidden', 'boolean');
+
+-- Extra fields of a credential besides its main secret, e.g. a PIN or a security
+-- question. Labels are plaintext for display; values are encrypted with the team key.
+CREATE TABLE credential_fields (
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    -- The field's place among the credential's fields, counting from 0
+    position INTEGER NOT NULL CHECK (position >= 0),
+    label TEXT NOT NULL,
+    field_type credential_field_type NOT NULL,
+    encrypted_value BYTEA NOT NULL,
+    nonce BYTEA NOT NULL,
+    PRIMARY KEY (credential_id, position)
+);
@@ -0,0 +1,10 @@
+-- When each user last used each credential, for their personal "recent" list.
+-- credentials.last_used_at is the latest use by anyone on the team.
+CREATE TABLE credential_usage (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    used_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, credential_id)
+);
+
+CREATE INDEX credential_usage_user_id_idx ON credential_usage(user_id, used_at DESC);
@@ -0,0 +1,3 @@
+-- Where a credential was pinned among the others in its folder (or the top level of its
+-- team), from 0. NULL for credentials never ordered, which sort after the pinned ones.
+ALTER TABLE credentials ADD COLUMN position INTEGER;
@@ -0,0 +1,12 @@
+CREATE TYPE uri_match_type AS ENUM ('exact', 'host', 'domain', 'starts_with');
+
+-- The addresses a credential applies to when filling in logins, in order. The host of
+-- the first one is also kept in credentials.hostname for clients that only know that.
+CREATE TABLE credential_uris (
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    -- The URI's place among the credential's URIs, counting from 0
+    position INTEGER NOT NULL CHECK (position >= 0),
+    uri TEXT NOT NULL,
+    match_type uri_match_type NOT NULL,
+    PRIMARY KEY (credential_id, position)
+);
@@ -0,0 +1,3 @@
+-- An image for clients to show next to a credential in listings, e.g. its site's
+-- favicon. The server only stores the URL; clients load it themselves.
+ALTER TABLE credentials ADD COLUMN icon_url TEXT;
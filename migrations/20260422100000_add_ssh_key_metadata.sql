@@ -0,0 +1,9 @@
+CREATE TYPE ssh_key_type AS ENUM ('ed25519', 'rsa', 'ecdsa');
+
+-- Plaintext details of an ssh_key credential's public half, so clients can show its
+-- fingerprint without fetching the secret. NULL for every other kind.
+ALTER TABLE credentials
+    ADD COLUMN ssh_key_type ssh_key_type,
+    -- In the SHA256:<base64> form printed by ssh-keygen -l
+    ADD COLUMN ssh_fingerprint TEXT,
+    ADD COLUMN ssh_comment TEXT;
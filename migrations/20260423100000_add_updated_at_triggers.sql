@@ -0,0 +1,63 @@
+-- Sets updated_at on every change to a row, for clients to sync from and to detect
+-- concurrent edits. An UPDATE that changes nothing, or only the bookkeeping columns
+-- named as trigger arguments (such as when a row was last used), leaves it alone.
+CREATE FUNCTION set_updated_at()
+    RETURNS TRIGGER AS $$
+DECLARE
+    -- TG_ARGV is NULL, not empty, without arguments.
+    ignored TEXT[] := COALESCE(TG_ARGV, '{}') || '{updated_at}';
+BEGIN
+    IF to_jsonb(NEW) - ignored IS DISTINCT FROM to_jsonb(OLD) - ignored THEN
+        NEW.updated_at = now();
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Users had their own trigger, which also fired on every login.
+DROP TRIGGER update_user_modtime ON users;
+DROP FUNCTION update_modified_column();
+
+-- Credentials only had it set by hand, and not on creation.
+UPDATE credentials SET updated_at = created_at WHERE updated_at IS NULL;
+ALTER TABLE credentials
+    ALTER COLUMN updated_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT NOW();
+
+ALTER TABLE teams ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE teams SET updated_at = created_at;
+
+-- Memberships and team keys don't record when they were made, so existing ones start
+-- from when their team was.
+ALTER TABLE team_members ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE team_members m SET updated_at = t.created_at FROM teams t WHERE t.id = m.team_id;
+
+ALTER TABLE team_key_access ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE team_key_access k SET updated_at = t.created_at FROM teams t WHERE t.id = k.team_id;
+
+CREATE TRIGGER users_set_updated_at
+    BEFORE UPDATE ON users
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at(
+    'last_login_at', 'last_login_ip', 'totp_last_used_step', 'two_factor_failed_attempts', 'two_factor_locked_until'
+);
+
+CREATE TRIGGER teams_set_updated_at
+    BEFORE UPDATE ON teams
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
+
+CREATE TRIGGER team_members_set_updated_at
+    BEFORE UPDATE ON team_members
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
+
+CREATE TRIGGER team_key_access_set_updated_at
+    BEFORE UPDATE ON team_key_access
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
+
+CREATE TRIGGER credentials_set_updated_at
+    BEFORE UPDATE ON credentials
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at('last_used_at');
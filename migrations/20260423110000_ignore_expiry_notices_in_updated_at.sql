@@ -0,0 +1,7 @@
+-- Expiry notices are recorded by a background job, not an edit, so they shouldn't
+-- count as a change either.
+DROP TRIGGER credentials_set_updated_at ON credentials;
+CREATE TRIGGER credentials_set_updated_at
+    BEFORE UPDATE ON credentials
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at('last_used_at', 'expiry_notice_days');
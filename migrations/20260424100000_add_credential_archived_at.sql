@@ -0,0 +1,3 @@
+-- When a credential was retired from listings without going to the trash; NULL while
+-- it is in use.
+ALTER TABLE credentials ADD COLUMN archived_at TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+-- Copying a credential returns the copy's secret, which is the original's.
+ALTER TABLE credential_access_log DROP CONSTRAINT credential_access_log_action_check;
+ALTER TABLE credential_access_log
+    ADD CONSTRAINT credential_access_log_action_check CHECK (action IN ('view', 'view_version', 'export', 'copy'));
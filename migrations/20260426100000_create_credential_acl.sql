@@ -0,0 +1,35 @@
+-- Restricts a credential to some members of its team. A credential without entries is
+-- open to the whole team; one with any is visible only to its team's admins and the
+-- members listed to read it, and editable only by admins and those listed to write.
+-- Entries outlive a membership, as only current members get access anyway, so leaving
+-- the team doesn't lift a restriction.
+CREATE TABLE credential_acl (
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    can_read BOOLEAN NOT NULL DEFAULT TRUE,
+    can_write BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (credential_id, user_id),
+    -- Writing a secret means being able to read it.
+    CHECK (can_read OR NOT can_write)
+);
+
+CREATE INDEX credential_acl_user_id_idx ON credential_acl(user_id);
+
+-- Whether a member of a credential's team may read it, or with `write`, change it, as
+-- far as its entries go. Membership and roles are checked by the caller.
+CREATE FUNCTION credential_acl_allows(credential UUID, member UUID, write BOOLEAN)
+    RETURNS BOOLEAN
+    STABLE LANGUAGE sql AS $$
+    SELECT NOT EXISTS (SELECT 1 FROM credential_acl WHERE credential_id = credential)
+        OR EXISTS (
+            SELECT 1 FROM credential_acl
+            WHERE credential_id = credential AND user_id = member
+              AND CASE WHEN write THEN can_write ELSE can_read END
+        )
+        OR EXISTS (
+            SELECT 1 FROM credentials c
+            JOIN team_members m ON m.team_id = c.team_id
+            WHERE c.id = credential AND m.user_id = member AND m.role = 'admin'
+        )
+$$;
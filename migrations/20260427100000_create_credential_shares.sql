@@ -0,0 +1,20 @@
+-- Credentials shared read-only into another team without moving them. The secret is
+-- re-encrypted by the sharing client with the target team's key, so it goes stale when
+-- the credential's secret changes until it is shared again.
+CREATE TABLE credential_shares (
+    credential_id UUID NOT NULL REFERENCES credentials(id) ON DELETE CASCADE,
+    target_team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    encrypted_secret BYTEA NOT NULL,
+    nonce BYTEA NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (credential_id, target_team_id)
+);
+
+CREATE INDEX credential_shares_target_team_id_idx ON credential_shares(target_team_id);
+
+CREATE TRIGGER credential_shares_set_updated_at
+    BEFORE UPDATE ON credential_shares
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
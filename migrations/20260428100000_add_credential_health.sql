@@ -0,0 +1,12 @@
+-- How strong a credential's secret is and which others it is reused with, as reported
+-- by a client that decrypted it. reused_group is a keyed hash of the plaintext made by
+-- the client, so equal secrets compare equal without the server learning them. All
+-- NULL until reported, and cleared again whenever the secret changes.
+ALTER TABLE credentials
+    ADD COLUMN health_strength_score SMALLINT CHECK (health_strength_score BETWEEN 0 AND 4),
+    ADD COLUMN health_reused_group TEXT,
+    ADD COLUMN health_reported_at TIMESTAMPTZ,
+    ADD CHECK (health_reported_at IS NOT NULL OR (health_strength_score IS NULL AND health_reused_group IS NULL));
+
+CREATE INDEX credentials_health_reused_group_idx ON credentials(team_id, health_reused_group)
+    WHERE health_reused_group IS NOT NULL;
@@ -0,0 +1,11 @@
+-- Responses of the Have I Been Pwned range API, kept so repeated breach checks of a
+-- prefix needn't go upstream. Rows past hibp.cache_ttl_secs are fetched again and
+-- deleted hourly.
+CREATE TABLE hibp_range_cache (
+    -- Five uppercase hex digits
+    prefix TEXT PRIMARY KEY,
+    body TEXT NOT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX hibp_range_cache_fetched_at_idx ON hibp_range_cache(fetched_at);
@@ -0,0 +1,19 @@
+CREATE TYPE totp_algorithm AS ENUM ('sha1', 'sha256', 'sha512');
+
+-- How a totp_secret credential's codes are generated, in plaintext so clients can show
+-- a live code right after fetching the seed. Set for every totp_secret credential and
+-- NULL for every other kind.
+ALTER TABLE credentials
+    ADD COLUMN totp_digits SMALLINT CHECK (totp_digits IN (6, 8)),
+    ADD COLUMN totp_period INTEGER CHECK (totp_period > 0),
+    ADD COLUMN totp_algorithm totp_algorithm;
+
+-- Existing seeds were saved without parameters, so they get the usual ones.
+UPDATE credentials SET totp_digits = 6, totp_period = 30, totp_algorithm = 'sha1' WHERE kind = 'totp_secret';
+
+ALTER TABLE credentials ADD CHECK (
+    CASE WHEN kind = 'totp_secret'
+        THEN totp_digits IS NOT NULL AND totp_period IS NOT NULL AND totp_algorithm IS NOT NULL
+        ELSE totp_digits IS NULL AND totp_period IS NULL AND totp_algorithm IS NULL
+    END
+);
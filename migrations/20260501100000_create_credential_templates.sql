@@ -0,0 +1,31 @@
+-- Shapes of credentials a team creates again and again, such as a database login with
+-- the same custom fields and tags, to start new credentials from. Templates hold no
+-- secrets, only the plaintext parts of a credential and the labels of its custom fields,
+-- and credentials created from one don't refer back to it.
+CREATE TABLE credential_templates (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- May name {hostname} and {username}, filled in from the new credential
+    title_pattern TEXT,
+    kind secret_kind NOT NULL,
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    folder_id UUID,
+    -- [{"uri": ..., "match_type": ...}], in order
+    uris JSONB NOT NULL DEFAULT '[]',
+    -- [{"label": ..., "field_type": ...}], in order
+    fields JSONB NOT NULL DEFAULT '[]',
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Deleting the folder only drops it from the template.
+    FOREIGN KEY (folder_id, team_id) REFERENCES folders(id, team_id) ON DELETE SET NULL (folder_id)
+);
+
+-- Templates of a team have distinct names, ignoring case
+CREATE UNIQUE INDEX credential_templates_team_name_idx ON credential_templates(team_id, lower(name));
+
+CREATE TRIGGER credential_templates_set_updated_at
+    BEFORE UPDATE ON credential_templates
+    FOR EACH ROW
+EXECUTE FUNCTION set_updated_at();
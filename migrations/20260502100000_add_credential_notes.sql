@@ -0,0 +1,4 @@
+-- Plaintext notes on a credential for anyone in the team to see in listings, such as
+-- who owns it or how often it is rotated. Unlike custom fields, the server can read
+-- them, so they are never meant for anything secret.
+ALTER TABLE credentials ADD COLUMN notes TEXT NOT NULL DEFAULT '';
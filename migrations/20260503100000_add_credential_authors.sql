@@ -0,0 +1,5 @@
+-- Who created a credential and who last edited it, for accountability within the team.
+-- Credentials from before these were recorded have neither.
+ALTER TABLE credentials
+    ADD COLUMN created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN updated_by UUID REFERENCES users(id) ON DELETE SET NULL;
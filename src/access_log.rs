@@ -0,0 +1,37 @@
+use sqlx::PgPool;
+
+/// How an encrypted secret was read, as stored in `credential_access_log.action`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessAction {
+    /// The current secret, fetched on its own.
+    View,
+    /// The secret of an earlier version.
+    ViewVersion,
+    /// The secret, as part of an account export.
+    Export,
+    /// The secret, returned with a copy of the credential.
+    Copy,
+}
+
+impl AccessAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccessAction::View => "view",
+            AccessAction::ViewVersion => "view_version",
+            AccessAction::Export => "export",
+            AccessAction::Copy => "copy",
+        }
+    }
+}
+
+/// Deletes access log entries older than `retention_days`, returning how many were removed.
+pub async fn prune_access_log(pool: &PgPool, retention_days: u32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM credential_access_log WHERE created_at < NOW() - make_interval(days => $1)",
+        retention_days.min(i32::MAX as u32) as i32
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
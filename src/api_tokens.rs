@@ -0,0 +1,58 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::crypto::constant_time_eq;
+use crate::guards::Permissions;
+use crate::models::TokenAccess;
+use crate::tokens::{api_token_lookup, hash_token};
+
+/// What the auth guard needs to know about a valid personal access token.
+pub struct ActiveApiToken {
+    pub user_id: Uuid,
+    /// The scopes the token was created with.
+    pub permissions: Permissions,
+    /// Whether the user must change their password before doing anything else.
+    pub must_change_password: bool,
+    /// Whether the user must store a new recovery key after using theirs.
+    pub recovery_key_required: bool,
+}
+
+/// Looks up a personal access token that hasn't expired and belongs to an account that
+/// isn't suspended, returning `None` if `token` isn't such a token.
+///
+/// The row is found through the token's indexed lookup id, and the stored hash is then
+/// compared in constant time. A match bumps the token's `last_used_at`.
+pub async fn active_api_token(
+    conn: &mut PgConnection,
+    token: &str,
+) -> Result<Option<ActiveApiToken>, sqlx::Error> {
+    let Some(lookup_id) = api_token_lookup(token) else {
+        return Ok(None);
+    };
+
+    let row = sqlx::query!(
+        "SELECT t.id, t.user_id, t.token_hash, t.access AS \"access: TokenAccess\", t.team_ids,
+                u.must_change_password, u.recovery_key_required
+         FROM api_tokens t JOIN users u ON u.id = t.user_id
+         WHERE t.lookup_id = $1 AND (t.expires_at IS NULL OR t.expires_at > NOW())
+           AND u.suspended_at IS NULL",
+        lookup_id
+    )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    let Some(row) = row.filter(|row| constant_time_eq(&row.token_hash, &hash_token(token))) else {
+        return Ok(None);
+    };
+
+    sqlx::query!("UPDATE api_tokens SET last_used_at = NOW() WHERE id = $1", row.id)
+        .execute(conn)
+        .await?;
+
+    Ok(Some(ActiveApiToken {
+        user_id: row.user_id,
+        permissions: Permissions { team_ids: row.team_ids, access: row.access },
+        must_change_password: row.must_change_password,
+        recovery_key_required: row.recovery_key_required,
+    }))
+}
@@ -0,0 +1,203 @@
+use std::io;
+use std::ops::{Deref, DerefMut};
+
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::{self, serde_json};
+use rocket::serde::Deserialize;
+use rocket::serde::de::DeserializeOwned;
+
+use crate::error::ApiError;
+
+/// A JSON request body, like [`rocket::serde::json::Json`], with errors that say where
+/// parsing failed.
+///
+/// Rocket's catchers only see the status of a failed request, so this guard stores the
+/// reason in the request-local cache where the JSON catchers (see
+/// [`crate::error::bad_request`]) can report it. The reason names the field it was
+/// raised in, e.g. "invalid base64 in field `public_key`: ...".
+pub struct JsonBody<T>(pub T);
+
+/// The reason a [`JsonBody`] was rejected, if one was.
+pub struct BodyError {
+    pub message: Option<String>,
+    /// The path of the offending field (e.g. `team_keys[0].nonce`), if known.
+    pub field: Option<String>,
+}
+
+/// The raw body, cached so the parsed value and errors can borrow from it.
+struct RawBody(String);
+
+/// Strips serde_json's " at line X column Y" suffix from an error message.
+fn reason(error: &serde_json::Error) -> String {
+    let message = error.to_string();
+    let location = format!(" at line {} column {}", error.line(), error.column());
+    message.strip_suffix(&location).unwrap_or(&message).to_string()
+}
+
+/// Deserializes `s`, recording the path of the field an error occurred in.
+fn parse<'r, T: Deserialize<'r>>(s: &'r str) -> Result<T, (serde_json::Error, Option<String>)> {
+    let mut deserializer = serde_json::Deserializer::from_str(s);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        let field = (path != ".").then_some(path);
+        (e.into_inner(), field)
+    })?;
+    deserializer.end().map_err(|e| (e, None))?;
+    Ok(value)
+}
+
+/// Describes why parsing failed, naming the field if it is known.
+fn body_error(error: &serde_json::Error, field: Option<String>) -> BodyError {
+    let reason = reason(error);
+    let message = match (&field, reason.split_once(": ")) {
+        // Unknown and missing field errors already name the field.
+        (Some(field), _) if reason.contains(&format!("`{}`", field)) => reason,
+        // "invalid base64: ..." becomes "invalid base64 in field `x`: ...".
+        (Some(field), Some((head, tail))) => format!("{} in field `{}`: {}", head, field, tail),
+        (Some(field), None) => format!("{} in field `{}`", reason, field),
+        (None, _) => reason,
+    };
+    BodyError { message: Some(message), field }
+}
+
+/// The status a body failing to parse with `error` is answered with.
+fn error_status(error: &serde_json::Error) -> Status {
+    match error.classify() {
+        serde_json::error::Category::Data => Status::UnprocessableEntity,
+        _ => Status::BadRequest,
+    }
+}
+
+/// Deserializes a body a route has reworked as JSON, e.g. filled in with defaults,
+/// failing with the same error a [`JsonBody`] of `T` would have.
+pub fn from_value<T: DeserializeOwned>(value: serde_json::Value) -> Result<T, ApiError> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        let field = (path != ".").then_some(path);
+        let error = e.into_inner();
+        ApiError::invalid_body(error_status(&error), &body_error(&error, field))
+    })
+}
+
+/// Deserializes a body a route has read itself, e.g. to accept an empty one, failing
+/// with the same error a [`JsonBody`] of `T` would have.
+pub fn from_str<'r, T: Deserialize<'r>>(s: &'r str) -> Result<T, ApiError> {
+    parse(s).map_err(|(error, field)| ApiError::invalid_body(error_status(&error), &body_error(&error, field)))
+}
+
+#[rocket::async_trait]
+impl<'r, T: Deserialize<'r>> FromData<'r> for JsonBody<T> {
+    type Error = json::Error<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        // A route can be given a limit of its own as `json/<route name>`, e.g. for bulk
+        // imports, falling back to the `json` limit.
+        let limit = match request.route().and_then(|route| route.name.as_deref()) {
+            Some(name) => request.limits().find(["json", name]),
+            None => request.limits().get("json"),
+        }
+            .unwrap_or(Limits::JSON);
+        let string = match data.open(limit).into_string().await {
+            Ok(s) if s.is_complete() => s.into_inner(),
+            Ok(_) => {
+                let error = io::Error::new(io::ErrorKind::UnexpectedEof, "data limit exceeded");
+                request.local_cache(|| BodyError { message: Some(error.to_string()), field: None });
+                return data::Outcome::Error((Status::PayloadTooLarge, json::Error::Io(error)));
+            }
+            Err(error) => {
+                request.local_cache(|| BodyError { message: Some(error.to_string()), field: None });
+                return data::Outcome::Error((Status::BadRequest, json::Error::Io(error)));
+            }
+        };
+
+        let raw = &request.local_cache(|| RawBody(string)).0;
+        match parse(raw) {
+            Ok(value) => data::Outcome::Success(JsonBody(value)),
+            Err((error, field)) => {
+                request.local_cache(|| body_error(&error, field));
+                data::Outcome::Error((error_status(&error), json::Error::Parse(raw, error)))
+            }
+        }
+    }
+}
+
+impl<T> Deref for JsonBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for JsonBody<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::serde::json::{json, Value};
+    use sqlx::PgPool;
+    use crate::testing;
+
+    /// Posts a signup for a fresh invite with `change` applied to a valid body, returning
+    /// the status and the JSON error.
+    async fn post_signup(client: &Client, change: impl FnOnce(&mut Value)) -> (Status, Value) {
+        sqlx::query("INSERT INTO invite_codes (code) VALUES ('invite')").execute(testing::pool(client)).await.unwrap();
+        let mut body: Value = rocket::serde::json::from_str(&testing::signup_body("invite", "a@example.com")).unwrap();
+        change(&mut body);
+        let response = client.post("/auth/signup").header(ContentType::JSON).body(body.to_string()).dispatch().await;
+        let status = response.status();
+        (status, response.into_json().await.unwrap_or(Value::Null))
+    }
+
+    #[sqlx::test]
+    async fn signup_names_a_misspelt_field(pool: PgPool) {
+        let client = testing::client(pool).await;
+        let (status, error) = post_signup(&client, |body| {
+            let hash = body.as_object_mut().unwrap().remove("password_hash").unwrap();
+            body["pasword_hash"] = hash;
+        }).await;
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(error["error"], "invalid_body");
+        assert_eq!(error["field"], "pasword_hash");
+    }
+
+    #[sqlx::test]
+    async fn signup_names_an_extra_field(pool: PgPool) {
+        let client = testing::client(pool).await;
+        let (status, error) = post_signup(&client, |body| body["remember_me"] = json!(true)).await;
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(error["error"], "invalid_body");
+        assert_eq!(error["field"], "remember_me");
+    }
+
+    #[sqlx::test]
+    async fn reworked_bodies_name_an_extra_field(pool: PgPool) {
+        // Credentials are created from a body that may be filled in from a template first.
+        let client = testing::client(pool).await;
+        let signup = testing::signup(&client, "a@example.com").await;
+        let token = testing::access_token(&client, "a@example.com").await;
+        let response = client.post(format!("/teams/{}/credentials", signup["personal_team_id"].as_str().unwrap()))
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(json!({
+                "title": "Router",
+                "username": "admin",
+                "kind": "password",
+                "encrypted_secret": "c2VjcmV0",
+                "nonce": "bm9uY2Vub25jZW5vbmNlbm9uY2Vub25j",
+                "pasword": "hunter2",
+            }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["field"], "pasword");
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use rocket::serde::{Deserialize, Deserializer};
+
+/// Application settings extracted from Rocket's figment.
+///
+/// Each section maps to a table in `Rocket.toml` (e.g. `[default.auth]`) and can be
+/// overridden through environment variables like `ROCKET_AUTH={jwt_secret="..."}`.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AppConfig {
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub webauthn: WebauthnConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub email: EmailConfig,
+    #[serde(default)]
+    pub trash: TrashConfig,
+    #[serde(default)]
+    pub credentials: CredentialsConfig,
+    #[serde(default)]
+    pub attachments: AttachmentsConfig,
+    #[serde(default)]
+    pub hibp: HibpConfig,
+    /// An email address whose account is made an admin at its first login, once the
+    /// address is verified. Used to bootstrap the first admin, e.g. via
+    /// `ROCKET_BOOTSTRAP_ADMIN_EMAIL`.
+    #[serde(default)]
+    pub bootstrap_admin_email: Option<String>,
+    /// Whether accounts must verify their email address before they can log in.
+    #[serde(default)]
+    pub require_verified_email: bool,
+    /// Whether logins from a new device stay restricted until the device is approved
+    /// from one the user already trusts.
+    #[serde(default)]
+    pub require_device_approval: bool,
+}
+
+/// Settings for token issuance and validation.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AuthConfig {
+    /// The HS256 secret used to sign and verify access tokens.
+    pub jwt_secret: String,
+    /// The AES-256 key used to encrypt secrets the server itself must be able to read,
+    /// such as TOTP seeds. Given as 32 Base64-encoded bytes.
+    #[serde(deserialize_with = "deserialize_key")]
+    pub secret_encryption_key: [u8; 32],
+    /// The HMAC key deriving fake salts for unknown emails. Given as 32 Base64-encoded
+    /// bytes; if unset, one is generated on first boot and kept in the database.
+    #[serde(default, deserialize_with = "deserialize_optional_key")]
+    pub salt_secret: Option<[u8; 32]>,
+    /// The least time a salt lookup takes, in milliseconds, plus up to half as much random
+    /// jitter, so response times don't reveal whether the email exists. 0 disables the floor.
+    #[serde(default)]
+    pub salt_response_floor_ms: u64,
+    /// How long an access token stays valid, in seconds.
+    #[serde(default = "default_access_token_ttl")]
+    pub access_token_ttl_secs: i64,
+    /// How long a refresh token stays valid, in seconds.
+    #[serde(default = "default_refresh_token_ttl")]
+    pub refresh_token_ttl_secs: i64,
+    /// How long a password reset token stays valid, in seconds.
+    #[serde(default = "default_password_reset_ttl")]
+    pub password_reset_ttl_secs: i64,
+    /// How long the token linking the password step to the second-factor step stays valid, in seconds.
+    #[serde(default = "default_two_factor_token_ttl")]
+    pub two_factor_token_ttl_secs: i64,
+    /// How long an OPAQUE login may take between its start and finish, in seconds.
+    #[cfg(feature = "opaque")]
+    #[serde(default = "default_opaque_login_ttl")]
+    pub opaque_login_ttl_secs: i64,
+    /// How long the token returned by a recovery key login stays valid, in seconds.
+    #[serde(default = "default_account_recovery_ttl")]
+    pub account_recovery_ttl_secs: i64,
+    /// How long an email change confirmation token stays valid, in seconds.
+    #[serde(default = "default_email_change_ttl")]
+    pub email_change_ttl_secs: i64,
+    /// How long an email verification token stays valid, in seconds.
+    #[serde(default = "default_email_verification_ttl")]
+    pub email_verification_ttl_secs: i64,
+    /// How long login history is kept, in days.
+    #[serde(default = "default_login_event_retention_days")]
+    pub login_event_retention_days: u32,
+}
+
+/// Settings identifying this server as a WebAuthn relying party.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct WebauthnConfig {
+    /// The relying party id, usually the registrable domain of the web client.
+    pub rp_id: String,
+    /// The exact origin the web client is served from.
+    pub rp_origin: String,
+    /// The name shown by authenticators during registration.
+    pub rp_name: String,
+}
+
+impl Default for WebauthnConfig {
+    fn default() -> Self {
+        WebauthnConfig {
+            rp_id: "localhost".to_string(),
+            rp_origin: "http://localhost:8000".to_string(),
+            rp_name: "HomeDesk".to_string(),
+        }
+    }
+}
+
+/// Settings for the per-IP rate limiter on the auth routes.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RateLimitConfig {
+    /// Whether requests are rate limited at all.
+    pub enabled: bool,
+    /// Whether to take the client IP from `X-Forwarded-For`. Only enable this behind a
+    /// reverse proxy that sets the header, or clients can pick their own IP.
+    pub trust_proxy: bool,
+    /// Where token buckets are kept; use `postgres` when running several instances.
+    pub store: RateLimitStoreKind,
+    /// Requests allowed per minute per IP, keyed by the path under the auth mount
+    /// (e.g. `signup`). Paths without an entry aren't limited. Routes limited per
+    /// caller instead use their own keys, e.g. `users/public-key`.
+    pub limits: HashMap<String, u32>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            trust_proxy: false,
+            store: RateLimitStoreKind::Memory,
+            limits: HashMap::from([
+                ("signup".to_string(), 10),
+                ("login".to_string(), 20),
+                ("login/opaque/start".to_string(), 20),
+                ("signup/opaque/start".to_string(), 10),
+                ("salt".to_string(), 30),
+                ("recover".to_string(), 5),
+                ("recover/finish".to_string(), 5),
+                ("verify-email/resend".to_string(), 5),
+                ("users/public-key".to_string(), 30),
+            ]),
+        }
+    }
+}
+
+/// The backing store for rate limit buckets.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    /// Buckets live in process memory; each instance limits independently.
+    Memory,
+    /// Buckets live in the database and are shared by all instances.
+    Postgres,
+}
+
+/// Settings for purging deleted credentials from the trash.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TrashConfig {
+    /// Whether credentials are purged in the background once their retention is over.
+    /// Disable it to keep the trash forever.
+    pub auto_purge: bool,
+    /// How long a credential stays in the trash before it is purged, in days.
+    pub retention_days: u32,
+    /// How often the background purge runs, in seconds.
+    pub purge_interval_secs: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig {
+            auto_purge: true,
+            retention_days: 30,
+            purge_interval_secs: 60 * 60,
+        }
+    }
+}
+
+/// Settings for stored credentials.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CredentialsConfig {
+    /// How many earlier versions are kept per credential; older ones are pruned on update.
+    pub max_versions: u32,
+    /// How many days before a credential expires its team's members are notified, once
+    /// for each entry. Leave it empty to send no notices.
+    pub expiry_notice_days: Vec<u32>,
+    /// How often the background check for expiring credentials runs, in seconds.
+    pub expiry_check_interval_secs: u64,
+    /// The most credentials accepted by one bulk, Bitwarden, KeePass, or CSV import.
+    pub max_import_batch: usize,
+    /// How many days reads of secrets are kept in the access log.
+    pub access_log_retention_days: u32,
+}
+
+impl Default for CredentialsConfig {
+    fn default() -> Self {
+        CredentialsConfig {
+            max_versions: 20,
+            expiry_notice_days: vec![14, 3],
+            expiry_check_interval_secs: 60 * 60,
+            max_import_batch: 1000,
+            access_log_retention_days: 365,
+        }
+    }
+}
+
+/// Settings for files attached to credentials.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AttachmentsConfig {
+    /// The largest attachment accepted, in MiB of encrypted content.
+    pub max_size_mib: u32,
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        AttachmentsConfig { max_size_mib: 100 }
+    }
+}
+
+/// Settings for the proxy to the Have I Been Pwned password range API.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HibpConfig {
+    /// Whether `/hibp/range/<prefix>` is served. Disable it for installs without
+    /// internet access.
+    pub enabled: bool,
+    /// The range API, to which the prefix is appended.
+    pub api_url: String,
+    /// The `User-Agent` sent upstream, which HIBP requires.
+    pub user_agent: String,
+    /// Sent as `hibp-api-key`, if the upstream wants one.
+    pub api_key: Option<String>,
+    /// How long a range is served from the cache before it is fetched again, in seconds.
+    /// 0 turns the cache off.
+    pub cache_ttl_secs: u64,
+    /// How long to wait for the upstream, in seconds.
+    pub timeout_secs: u64,
+}
+
+impl Default for HibpConfig {
+    fn default() -> Self {
+        HibpConfig {
+            enabled: true,
+            api_url: "https://api.pwnedpasswords.com/range/".to_string(),
+            user_agent: "HomeDesk-API".to_string(),
+            api_key: None,
+            cache_ttl_secs: 24 * 60 * 60,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// Settings for outgoing email.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct EmailConfig {
+    /// How emails are delivered.
+    pub transport: EmailTransport,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// How the SMTP connection is secured.
+    pub smtp_tls: SmtpTls,
+    /// The sender address, e.g. `HomeDesk <noreply@example.com>`.
+    pub from: String,
+    /// The public URL of this server, included in invitations.
+    pub server_url: String,
+    /// The subject of invitation emails.
+    pub invite_subject: String,
+    /// The body of invitation emails; `{code}` and `{server_url}` are filled in.
+    pub invite_template: String,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        EmailConfig {
+            transport: EmailTransport::Log,
+            smtp_host: "localhost".to_string(),
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_tls: SmtpTls::Starttls,
+            from: "HomeDesk <noreply@localhost>".to_string(),
+            server_url: "http://localhost:8000".to_string(),
+            invite_subject: "You've been invited to HomeDesk".to_string(),
+            invite_template: "You've been invited to create a HomeDesk account.\n\n\
+                              Server: {server_url}\n\
+                              Invite code: {code}\n"
+                .to_string(),
+        }
+    }
+}
+
+/// The delivery mechanism for outgoing email.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum EmailTransport {
+    /// Emails are only written to the log. Useful for development.
+    Log,
+    /// Emails are sent through the configured SMTP server.
+    Smtp,
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Implicit TLS, usually on port 465.
+    Tls,
+    /// A plain connection upgraded with STARTTLS, usually on port 587.
+    Starttls,
+    /// No encryption. Only for local relays.
+    None,
+}
+
+fn default_access_token_ttl() -> i64 {
+    15 * 60
+}
+
+fn default_refresh_token_ttl() -> i64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_password_reset_ttl() -> i64 {
+    60 * 60
+}
+
+fn default_two_factor_token_ttl() -> i64 {
+    2 * 60
+}
+
+#[cfg(feature = "opaque")]
+fn default_opaque_login_ttl() -> i64 {
+    60
+}
+
+fn default_account_recovery_ttl() -> i64 {
+    15 * 60
+}
+
+fn default_email_change_ttl() -> i64 {
+    24 * 60 * 60
+}
+
+fn default_email_verification_ttl() -> i64 {
+    48 * 60 * 60
+}
+
+fn default_login_event_retention_days() -> u32 {
+    90
+}
+
+/// Decodes a Base64-encoded 32-byte key, rejecting any other length.
+fn deserialize_key<'de, D>(deserializer: D) -> Result<[u8; 32], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(s)
+        .map_err(rocket::serde::de::Error::custom)?;
+    bytes
+        .try_into()
+        .map_err(|_| rocket::serde::de::Error::custom("expected a Base64-encoded 32-byte key"))
+}
+
+fn deserialize_optional_key<'de, D>(deserializer: D) -> Result<Option<[u8; 32]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_key(deserializer).map(Some)
+}
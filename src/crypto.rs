@@ -0,0 +1,88 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::Aes256Gcm;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// The key deriving fake salts for unknown emails, managed by Rocket.
+pub struct SaltSecret(pub [u8; 32]);
+
+/// Compares two byte slices without short-circuiting on the first differing byte.
+///
+/// Slices of different lengths compare unequal immediately; only the contents are
+/// protected against timing leaks, which is all we need for fixed-size hashes.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Encrypts a server-side secret (e.g. a TOTP seed) with AES-256-GCM.
+///
+/// Returns the ciphertext and the freshly generated nonce, both of which must be
+/// stored to decrypt it again.
+pub fn encrypt_secret(key: &[u8; 32], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), aes_gcm::Error> {
+    let cipher = Aes256Gcm::new(key.into());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext)?;
+    Ok((ciphertext, nonce.to_vec()))
+}
+
+/// Decrypts a secret produced by [`encrypt_secret`].
+pub fn decrypt_secret(key: &[u8; 32], ciphertext: &[u8], nonce: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+    if nonce.len() != 12 {
+        return Err(aes_gcm::Error);
+    }
+    let cipher = Aes256Gcm::new(key.into());
+    cipher.decrypt(nonce.into(), ciphertext)
+}
+
+/// Derives the fake salt returned for an email with no account.
+///
+/// HMAC-SHA256 over the lowercased email, truncated to 16 bytes: stable for a given
+/// email and key, and not computable by anyone without the key.
+pub fn fake_salt(secret: &SaltSecret, email: &str) -> [u8; 16] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&secret.0).expect("HMAC accepts keys of any length");
+    mac.update(email.to_lowercase().as_bytes());
+    let digest = mac.finalize().into_bytes();
+    let mut salt = [0u8; 16];
+    salt.copy_from_slice(&digest[..16]);
+    salt
+}
+
+/// The fingerprint of a public key, for users to compare out of band: the SHA256 of
+/// the key as lowercase hex, in colon-separated groups of four.
+pub fn public_key_fingerprint(public_key: &[u8]) -> String {
+    let hex: String = Sha256::digest(public_key).iter().map(|b| format!("{:02x}", b)).collect();
+    hex.as_bytes()
+        .chunks(4)
+        .map(|group| std::str::from_utf8(group).expect("hex is ASCII"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: SaltSecret = SaltSecret([7; 32]);
+
+    #[test]
+    fn fake_salt_is_stable_per_email() {
+        assert_eq!(fake_salt(&SECRET, "alice@example.com"), fake_salt(&SECRET, "alice@example.com"));
+    }
+
+    #[test]
+    fn fake_salt_ignores_the_case_of_the_email() {
+        assert_eq!(fake_salt(&SECRET, "Alice@Example.COM"), fake_salt(&SECRET, "alice@example.com"));
+    }
+
+    #[test]
+    fn fake_salt_differs_between_emails() {
+        assert_ne!(fake_salt(&SECRET, "alice@example.com"), fake_salt(&SECRET, "bob@example.com"));
+    }
+
+    #[test]
+    fn fake_salt_depends_on_the_secret() {
+        let other = SaltSecret([8; 32]);
+        assert_ne!(fake_salt(&SECRET, "alice@example.com"), fake_salt(&other, "alice@example.com"));
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::sync::Arc;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::{EmailConfig, SmtpTls};
+
+/// An outgoing email.
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// The error returned when an email could not be sent.
+#[derive(Debug)]
+pub struct EmailError(pub String);
+
+impl fmt::Display for EmailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to send email: {}", self.0)
+    }
+}
+
+/// Sends emails on behalf of the API (password resets, notifications, ...).
+///
+/// The implementation is held in Rocket's managed state as a [`SharedEmailer`], so
+/// deployments and tests can swap in whatever transport they need.
+#[rocket::async_trait]
+pub trait Emailer: Send + Sync {
+    async fn send(&self, message: EmailMessage) -> Result<(), EmailError>;
+}
+
+/// The emailer type stored in Rocket's managed state.
+pub type SharedEmailer = Arc<dyn Emailer>;
+
+/// An emailer that only writes messages to the log. Useful for development.
+pub struct LogEmailer;
+
+#[rocket::async_trait]
+impl Emailer for LogEmailer {
+    async fn send(&self, message: EmailMessage) -> Result<(), EmailError> {
+        info!("📧 Email to {}: {}\n{}", message.to, message.subject, message.body);
+        Ok(())
+    }
+}
+
+/// An emailer that delivers through an SMTP server.
+pub struct SmtpEmailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpEmailer {
+    /// Builds the SMTP transport from the email settings.
+    ///
+    /// No connection is made yet, so an unreachable server only shows up on send.
+    pub fn new(config: &EmailConfig) -> Result<Self, EmailError> {
+        let builder = match config.smtp_tls {
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host),
+            SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host)),
+        }
+        .map_err(|e| EmailError(e.to_string()))?;
+
+        let mut builder = builder.port(config.smtp_port);
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        let from = config.from.parse().map_err(|e: lettre::address::AddressError| EmailError(e.to_string()))?;
+
+        Ok(SmtpEmailer { transport: builder.build(), from })
+    }
+}
+
+#[rocket::async_trait]
+impl Emailer for SmtpEmailer {
+    async fn send(&self, message: EmailMessage) -> Result<(), EmailError> {
+        let to: Mailbox = message.to.parse().map_err(|e: lettre::address::AddressError| EmailError(e.to_string()))?;
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(message.subject)
+            .body(message.body)
+            .map_err(|e| EmailError(e.to_string()))?;
+
+        self.transport.send(email).await.map_err(|e| EmailError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Sends an email in the background, logging failures instead of surfacing them.
+///
+/// Used where the caller must not learn whether an email was sent at all, such as
+/// the forgot-password flow, and where the response shouldn't wait on the mail server.
+pub fn send_in_background(emailer: &SharedEmailer, message: EmailMessage) {
+    let emailer = Arc::clone(emailer);
+    rocket::tokio::spawn(async move {
+        if let Err(e) = emailer.send(message).await {
+            error!("{}", e);
+        }
+    });
+}
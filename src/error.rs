@@ -0,0 +1,133 @@
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{serde_json, Json, Value};
+use rocket::serde::Serialize;
+
+use crate::body::BodyError;
+use crate::guards::ForbiddenReason;
+
+/// An error response carrying a machine-readable JSON body.
+///
+/// The body always has an `error` code (e.g. `"email_taken"`) and may carry extra
+/// fields describing the problem. Converting from a bare [`Status`] derives the code
+/// from the status reason, so routes can mix `?` on `Status` errors with richer ones.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub body: serde_json::Map<String, Value>,
+    pub headers: Vec<Header<'static>>,
+}
+
+impl ApiError {
+    /// Creates an error with the given status and error code.
+    pub fn new(status: Status, error: &str) -> Self {
+        let mut body = serde_json::Map::new();
+        body.insert("error".to_string(), Value::from(error));
+        ApiError { status, body, headers: Vec::new() }
+    }
+
+    /// Creates an `invalid_body` error for a body that couldn't be parsed, naming the
+    /// offending field where possible.
+    pub fn invalid_body(status: Status, reason: &BodyError) -> Self {
+        let mut error = ApiError::new(status, "invalid_body");
+        if let Some(message) = &reason.message {
+            let field = reason.field.as_deref().or_else(|| field_from_message(message));
+            if let Some(field) = field {
+                error = error.with("field", field);
+            }
+            error = error.with("message", message);
+        }
+        error
+    }
+
+    /// Adds an extra field to the error body.
+    pub fn with(mut self, key: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self.body.insert(key.to_string(), value);
+        self
+    }
+
+    /// Adds a header to the response, e.g. `Retry-After`.
+    pub fn with_header(mut self, header: Header<'static>) -> Self {
+        self.headers.push(header);
+        self
+    }
+}
+
+impl ApiError {
+    /// Maps a database error to a response, telling constraint violations caused by
+    /// the request apart from genuine server errors.
+    ///
+    /// Unique violations become `409 Conflict` and other integrity violations `422
+    /// Unprocessable Entity`, both naming the constraint; anything else is a `500`.
+    pub fn from_db(error: sqlx::Error) -> Self {
+        use sqlx::error::ErrorKind;
+
+        let sqlx::Error::Database(db_error) = &error else {
+            return Status::InternalServerError.into();
+        };
+        let (status, code) = match db_error.kind() {
+            ErrorKind::UniqueViolation => (Status::Conflict, "conflict"),
+            ErrorKind::ForeignKeyViolation => (Status::UnprocessableEntity, "invalid_reference"),
+            ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+                (Status::UnprocessableEntity, "constraint_violation")
+            }
+            _ => return Status::InternalServerError.into(),
+        };
+        ApiError::new(status, code).with("constraint", db_error.constraint())
+    }
+}
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        let code = status.reason_lossy().to_lowercase().replace([' ', '-'], "_");
+        ApiError::new(status, &code)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = (self.status, Json(Value::Object(self.body))).respond_to(request)?;
+        for header in self.headers {
+            response.set_header(header);
+        }
+        Ok(response)
+    }
+}
+
+/// Extracts the field named in a serde error such as "unknown field `x`, expected ...".
+fn field_from_message(message: &str) -> Option<&str> {
+    let rest = message
+        .strip_prefix("unknown field `")
+        .or_else(|| message.strip_prefix("missing field `"))?;
+    rest.split('`').next()
+}
+
+/// Builds the JSON error for a request whose body couldn't be parsed.
+fn invalid_body(status: Status, request: &Request<'_>) -> ApiError {
+    ApiError::invalid_body(status, request.local_cache(|| BodyError { message: None, field: None }))
+}
+
+/// Answers `400 Bad Request` (e.g. malformed JSON) with a JSON error body.
+#[catch(400)]
+pub fn bad_request(request: &Request<'_>) -> ApiError {
+    invalid_body(Status::BadRequest, request)
+}
+
+/// Answers `422 Unprocessable Entity` (e.g. unknown or missing fields) with a JSON
+/// error body naming the offending field where possible.
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request<'_>) -> ApiError {
+    invalid_body(Status::UnprocessableEntity, request)
+}
+
+/// Answers `403 Forbidden` with a JSON error body, naming the reason when a guard
+/// recorded one.
+#[catch(403)]
+pub fn forbidden(request: &Request<'_>) -> ApiError {
+    match request.local_cache(|| ForbiddenReason(None)) {
+        ForbiddenReason(Some(code)) => ApiError::new(Status::Forbidden, code),
+        ForbiddenReason(None) => Status::Forbidden.into(),
+    }
+}
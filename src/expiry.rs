@@ -0,0 +1,81 @@
+use sqlx::PgPool;
+
+use crate::email::{EmailMessage, SharedEmailer};
+
+/// Notifies the members of a team about its credentials expiring within each of
+/// `notice_days` days, e.g. 14 and 3, recording a notification for each member allowed
+/// to read the credential and emailing them. Returns how many credentials members were notified about.
+///
+/// Each notice goes out once per expiry date: a credential found within several of the
+/// windows at once, e.g. after downtime, only gets the one for the smallest. Credentials
+/// archived, in the trash, or already expired are skipped. Email failures are only logged.
+pub async fn notify_expiring_credentials(
+    pool: &PgPool,
+    emailer: &SharedEmailer,
+    notice_days: &[u32],
+) -> Result<u64, sqlx::Error> {
+    let notice_days: Vec<i32> = notice_days.iter().map(|days| (*days).min(i32::MAX as u32) as i32).collect();
+
+    let mut tx = pool.begin().await?;
+
+    let due = sqlx::query!(
+        "WITH due AS (
+             SELECT c.id, MIN(d) AS days
+             FROM credentials c, unnest($1::int[]) d
+             WHERE c.deleted_at IS NULL AND c.archived_at IS NULL AND c.expires_at > NOW()
+               AND c.expires_at <= NOW() + make_interval(days => d)
+             GROUP BY c.id
+         )
+         UPDATE credentials c SET expiry_notice_days = due.days
+         FROM due
+         WHERE c.id = due.id AND (c.expiry_notice_days IS NULL OR c.expiry_notice_days > due.days)
+         RETURNING c.id, c.team_id, c.title, c.expires_at AS \"expires_at!\"",
+        &notice_days
+    )
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let mut messages = Vec::new();
+    for credential in &due {
+        let members = sqlx::query!(
+            "WITH notified AS (
+                 INSERT INTO notifications (user_id, kind, credential_id)
+                 SELECT user_id, 'credential_expiring', $2 FROM team_members
+                 WHERE team_id = $1 AND credential_acl_allows($2, user_id, FALSE)
+                 RETURNING user_id
+             )
+             SELECT u.email, t.name AS team_name
+             FROM notified n
+             JOIN users u ON u.id = n.user_id
+             JOIN teams t ON t.id = $1",
+            credential.team_id,
+            credential.id
+        )
+            .fetch_all(&mut *tx)
+            .await?;
+
+        for member in members {
+            messages.push(EmailMessage {
+                to: member.email,
+                subject: format!("\"{}\" expires on {}", credential.title, credential.expires_at.format("%Y-%m-%d")),
+                body: format!(
+                    "The credential \"{}\" of the team {} expires on {}.\n\n\
+                     Rotate it and set a new expiry date before then.",
+                    credential.title,
+                    member.team_name,
+                    credential.expires_at.format("%Y-%m-%d %H:%M UTC"),
+                ),
+            });
+        }
+    }
+
+    tx.commit().await?;
+
+    for message in messages {
+        if let Err(e) = emailer.send(message).await {
+            error!("{}", e);
+        }
+    }
+
+    Ok(due.len() as u64)
+}
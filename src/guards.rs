@@ -0,0 +1,385 @@
+use std::net::IpAddr;
+
+use rocket::http::{Method, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use uuid::Uuid;
+
+use rocket_db_pools::Database;
+use sqlx::PgConnection;
+
+use crate::config::AppConfig;
+use crate::api_tokens::active_api_token;
+use crate::crypto::constant_time_eq;
+use crate::error::ApiError;
+use crate::models::TokenAccess;
+use crate::sessions::active_session;
+use crate::tokens::{decode_access_token, API_TOKEN_PREFIX};
+use crate::web_sessions::{find_web_session, CSRF_COOKIE, CSRF_HEADER, SESSION_COOKIE};
+use crate::DatabasePool;
+
+/// A request guard for routes that require a logged-in user.
+///
+/// Extracts the access token from the `Authorization: Bearer <token>` header, validates
+/// it, and checks that the session it belongs to has not been revoked (e.g. by a logout).
+/// Personal access tokens (`hd_pat_...`) are accepted in place of an access token, as
+/// long as they haven't expired or been deleted. Without the header, a web session
+/// cookie is accepted instead. Missing credentials, a malformed header, an
+/// invalid/expired token or cookie, or a revoked session all fail the request with
+/// `401 Unauthorized`.
+///
+/// Requests authenticated by cookie other than `GET` and `HEAD` must echo the CSRF
+/// token issued at login in the `X-CSRF-Token` header, matching the CSRF cookie, or
+/// fail with `403 Forbidden` and `{"error": "csrf_token_invalid"}`. Bearer requests
+/// can't be forged cross-site, so they skip this check.
+///
+/// A read-only personal access token only gets through on `GET` and `HEAD` requests;
+/// anything else fails with `403 Forbidden` and `{"error": "insufficient_scope"}`. Team
+/// scopes are left to the routes, through [`AuthenticatedUser::permissions`].
+///
+/// While an admin requires the user to change their password, every route except those
+/// in [`PASSWORD_CHANGE_ALLOWED`] fails with `403 Forbidden` and
+/// `{"error": "password_change_required"}`. Likewise, once a recovery key has been used,
+/// only the routes in [`RECOVERY_KEY_ALLOWED`] work until a new one is stored, failing
+/// with `{"error": "recovery_key_required"}` otherwise.
+pub struct AuthenticatedUser {
+    /// The id of the user the token was issued to.
+    pub id: Uuid,
+    /// The id of the session the token belongs to, or `None` for a personal access token.
+    pub session_id: Option<Uuid>,
+    /// What the request may do; full access unless made with a scoped token.
+    pub permissions: Permissions,
+}
+
+/// The scopes a request is limited to by the personal access token it was made with.
+///
+/// These only ever narrow what the user could do anyway: routes must still check team
+/// membership and roles as usual.
+#[derive(Debug, Clone)]
+pub struct Permissions {
+    /// The teams the request may see and act on, or `None` for all of the user's teams.
+    pub team_ids: Option<Vec<Uuid>>,
+    pub access: TokenAccess,
+}
+
+impl Permissions {
+    /// The permissions of a login session, which are never restricted.
+    pub fn full() -> Self {
+        Permissions { team_ids: None, access: TokenAccess::ReadWrite }
+    }
+
+    /// Whether the request may see the team's data.
+    pub fn can_read_team(&self, team_id: Uuid) -> bool {
+        self.team_ids.as_ref().is_none_or(|ids| ids.contains(&team_id))
+    }
+
+    /// Whether the request may make changes to the team's data.
+    pub fn can_write_team(&self, team_id: Uuid) -> bool {
+        self.access == TokenAccess::ReadWrite && self.can_read_team(team_id)
+    }
+
+    /// Whether the request may act on every team the user belongs to, as needed by
+    /// operations that touch all of them at once.
+    pub fn covers_all_teams(&self) -> bool {
+        self.team_ids.is_none()
+    }
+}
+
+/// The error returned when a token's scopes don't allow what was asked.
+pub fn insufficient_scope() -> ApiError {
+    ApiError::new(Status::Forbidden, "insufficient_scope")
+}
+
+/// The routes (method and full path) a user can still reach while they must change
+/// their password.
+const PASSWORD_CHANGE_ALLOWED: &[(Method, &str)] = &[
+    (Method::Post, "/auth/change-password"),
+    (Method::Get, "/auth/me"),
+    (Method::Post, "/auth/logout"),
+];
+
+/// The routes a user can still reach after recovering their account, until they store
+/// a new recovery key.
+const RECOVERY_KEY_ALLOWED: &[(Method, &str)] = &[
+    (Method::Post, "/auth/recovery-key"),
+    (Method::Put, "/auth/recovery-key"),
+    (Method::Get, "/auth/me"),
+    (Method::Post, "/auth/logout"),
+];
+
+/// The routes a session on a device awaiting approval can reach.
+const DEVICE_APPROVAL_ALLOWED: &[(Method, &str)] = &[
+    (Method::Get, "/auth/me"),
+    (Method::Get, "/auth/devices"),
+    (Method::Post, "/auth/logout"),
+];
+
+/// The error code a guard refused a request with, for the `403` catcher to report.
+pub struct ForbiddenReason(pub Option<&'static str>);
+
+/// A request's user as established by its credentials, before any restrictions apply.
+struct Authenticated {
+    user: AuthenticatedUser,
+    must_change_password: bool,
+    recovery_key_required: bool,
+    device_approved: bool,
+}
+
+/// Authenticates a request made within a login session, returning `None` if the session
+/// was revoked or the account suspended.
+async fn authenticate_session(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Authenticated>, sqlx::Error> {
+    Ok(active_session(conn, session_id, user_id).await?.map(|session| Authenticated {
+        user: AuthenticatedUser {
+            id: user_id,
+            session_id: Some(session_id),
+            permissions: Permissions::full(),
+        },
+        must_change_password: session.must_change_password,
+        recovery_key_required: session.recovery_key_required,
+        device_approved: session.device_approved,
+    }))
+}
+
+/// Whether the request's method and path are one of `routes`.
+fn is_route_in(request: &Request<'_>, routes: &[(Method, &str)]) -> bool {
+    let path = request.uri().path();
+    routes.iter().any(|(method, route)| *method == request.method() && path == *route)
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(config) = request.rocket().state::<AppConfig>() else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+        let session_cookie = request.cookies().get(SESSION_COOKIE).map(|cookie| cookie.value());
+
+        let Some(db) = DatabasePool::fetch(request.rocket()) else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        let Ok(mut conn) = db.acquire().await else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        let safe_method = matches!(request.method(), Method::Get | Method::Head);
+
+        let authenticated = match (bearer, session_cookie) {
+            (None, None) => return Outcome::Error((Status::Unauthorized, ())),
+            (Some(token), _) if token.starts_with(API_TOKEN_PREFIX) => {
+                active_api_token(&mut conn, token).await.map(|api_token| {
+                    api_token.map(|api_token| Authenticated {
+                        user: AuthenticatedUser {
+                            id: api_token.user_id,
+                            session_id: None,
+                            permissions: api_token.permissions,
+                        },
+                        must_change_password: api_token.must_change_password,
+                        recovery_key_required: api_token.recovery_key_required,
+                        device_approved: true,
+                    })
+                })
+            }
+            (Some(token), _) => {
+                let Ok(claims) = decode_access_token(&config.auth, token) else {
+                    return Outcome::Error((Status::Unauthorized, ()));
+                };
+                authenticate_session(&mut conn, claims.sid, claims.sub).await
+            }
+            (None, Some(cookie)) => {
+                let web_session = match find_web_session(&mut conn, cookie).await {
+                    Ok(Some(web_session)) => web_session,
+                    Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+                    Err(_) => return Outcome::Error((Status::InternalServerError, ())),
+                };
+
+                // Double submit: a cross-site page can make the browser send the cookies,
+                // but can't read the CSRF cookie to copy it into the header.
+                if !safe_method {
+                    let header = request.headers().get_one(CSRF_HEADER);
+                    let csrf_cookie = request.cookies().get(CSRF_COOKIE).map(|cookie| cookie.value());
+                    let valid = match (header, csrf_cookie) {
+                        (Some(header), Some(cookie)) => {
+                            constant_time_eq(header.as_bytes(), cookie.as_bytes())
+                                && web_session.csrf_token_matches(header)
+                        }
+                        _ => false,
+                    };
+                    if !valid {
+                        request.local_cache(|| ForbiddenReason(Some("csrf_token_invalid")));
+                        return Outcome::Error((Status::Forbidden, ()));
+                    }
+                }
+
+                authenticate_session(&mut conn, web_session.session_id, web_session.user_id).await
+            }
+        };
+
+        let Authenticated { user, must_change_password, recovery_key_required, device_approved } = match authenticated {
+            Ok(Some(authenticated)) => authenticated,
+            Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+            Err(_) => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        let restrictions = [
+            (must_change_password, PASSWORD_CHANGE_ALLOWED, "password_change_required"),
+            (recovery_key_required, RECOVERY_KEY_ALLOWED, "recovery_key_required"),
+            (config.require_device_approval && !device_approved, DEVICE_APPROVAL_ALLOWED, "device_approval_required"),
+        ];
+        for (restricted, allowed, reason) in restrictions {
+            if restricted && !is_route_in(request, allowed) {
+                request.local_cache(|| ForbiddenReason(Some(reason)));
+                return Outcome::Error((Status::Forbidden, ()));
+            }
+        }
+
+        if user.permissions.access == TokenAccess::Read && !safe_method {
+            request.local_cache(|| ForbiddenReason(Some("insufficient_scope")));
+            return Outcome::Error((Status::Forbidden, ()));
+        }
+
+        Outcome::Success(user)
+    }
+}
+
+/// A request guard for routes restricted to admins.
+///
+/// Authenticates like [`AuthenticatedUser`], failing with `401 Unauthorized` the same
+/// way, then fails with `403 Forbidden` unless the user has `is_admin` set, or with
+/// `{"error": "insufficient_scope"}` for a token limited to some teams, since admin
+/// routes reach across all of them.
+pub struct AdminUser {
+    /// The id of the admin.
+    pub id: Uuid,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = match request.guard::<AuthenticatedUser>().await {
+            Outcome::Success(user) => user,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        if !user.permissions.covers_all_teams() {
+            request.local_cache(|| ForbiddenReason(Some("insufficient_scope")));
+            return Outcome::Error((Status::Forbidden, ()));
+        }
+
+        let Some(db) = DatabasePool::fetch(request.rocket()) else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        let is_admin = sqlx::query_scalar!("SELECT is_admin FROM users WHERE id = $1", user.id)
+            .fetch_optional(&db.0)
+            .await;
+
+        match is_admin {
+            Ok(Some(true)) => Outcome::Success(AdminUser { id: user.id }),
+            Ok(_) => Outcome::Error((Status::Forbidden, ())),
+            Err(_) => Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+/// Information about the client making a request, recorded on sessions.
+///
+/// This guard never fails; either field is `None` if it can't be determined.
+pub struct ClientInfo {
+    /// The client's IP address as seen by Rocket.
+    pub ip: Option<IpAddr>,
+    /// The `User-Agent` header, if any.
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    /// The IP address in the textual form it is stored in.
+    pub fn ip_address(&self) -> Option<String> {
+        self.ip.map(|ip| ip.to_string())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            ip: request.client_ip(),
+            user_agent: request.headers().get_one("User-Agent").map(str::to_owned),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use rocket::http::{ContentType, Status};
+    use rocket::serde::json::{json, Value};
+    use sqlx::PgPool;
+    use crate::testing;
+
+    #[sqlx::test]
+    async fn password_change_required_blocks_routes_until_the_password_is_changed(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        testing::signup(&client, "user@example.com").await;
+        let token = testing::access_token(&client, "user@example.com").await;
+        sqlx::query("UPDATE users SET must_change_password = true").execute(&pool).await.unwrap();
+
+        let response = client.get("/auth/sessions").header(testing::bearer(&token)).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"], "password_change_required");
+        let response = client.get("/auth/me").header(testing::bearer(&token)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.post("/auth/change-password")
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(json!({
+                "old_password_hash": STANDARD.encode(testing::PASSWORD_HASH),
+                "new_password_hash": STANDARD.encode([8; 32]),
+                "new_password_salt": STANDARD.encode([9; 16]),
+                "encrypted_private_key": STANDARD.encode([10; 48]),
+                "private_key_nonce": STANDARD.encode([11; 24]),
+            }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        let response = client.get("/auth/sessions").header(testing::bearer(&token)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[sqlx::test]
+    async fn admin_routes_refuse_tokens_limited_to_some_teams(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let signup = testing::signup(&client, "admin@example.com").await;
+        sqlx::query("UPDATE users SET is_admin = true").execute(&pool).await.unwrap();
+        let session = testing::access_token(&client, "admin@example.com").await;
+        let scoped = testing::api_token(&client, &session, json!({
+            "name": "personal team only",
+            "team_ids": [signup["personal_team_id"]],
+        })).await;
+        let unscoped = testing::api_token(&client, &session, json!({ "name": "every team" })).await;
+
+        let response = client.get("/admin/users").header(testing::bearer(&scoped)).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"], "insufficient_scope");
+        for token in [&session, &unscoped] {
+            let response = client.get("/admin/users").header(testing::bearer(token)).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use sqlx::{PgConnection, PgPool};
+use std::time::Duration;
+
+use crate::config::HibpConfig;
+
+/// Why a range couldn't be fetched from upstream.
+#[derive(Debug)]
+pub enum UpstreamError {
+    /// No response, e.g. after a timeout or DNS failure.
+    Unreachable(reqwest::Error),
+    /// A response other than `200 OK`, with its status code.
+    Status(u16),
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::Unreachable(e) => write!(f, "{}", e),
+            UpstreamError::Status(status) => write!(f, "upstream answered {}", status),
+        }
+    }
+}
+
+/// Fetches password hash ranges from Have I Been Pwned for `/hibp/range/<prefix>`.
+pub struct HibpClient {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+}
+
+impl HibpClient {
+    pub fn new(config: &HibpConfig) -> Result<Self, reqwest::Error> {
+        let http = reqwest::Client::builder()
+            .user_agent(config.user_agent.clone())
+            .timeout(Duration::from_secs(config.timeout_secs.max(1)))
+            .build()?;
+        Ok(HibpClient { http, api_url: config.api_url.clone(), api_key: config.api_key.clone() })
+    }
+
+    /// The suffixes and counts of every breached password hash starting with `prefix`,
+    /// as HIBP returns them. HIBP pads the list with fake entries, so its length gives
+    /// away nothing about the prefix.
+    pub async fn fetch_range(&self, prefix: &str) -> Result<String, UpstreamError> {
+        let mut request = self.http.get(format!("{}{}", self.api_url, prefix)).header("Add-Padding", "true");
+        if let Some(api_key) = &self.api_key {
+            request = request.header("hibp-api-key", api_key);
+        }
+
+        let response = request.send().await.map_err(UpstreamError::Unreachable)?;
+        if !response.status().is_success() {
+            return Err(UpstreamError::Status(response.status().as_u16()));
+        }
+        response.text().await.map_err(UpstreamError::Unreachable)
+    }
+}
+
+/// The cached range for `prefix`, unless it was fetched more than `ttl_secs` ago.
+pub async fn cached_range(conn: &mut PgConnection, prefix: &str, ttl_secs: u64) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT body FROM hibp_range_cache
+         WHERE prefix = $1 AND fetched_at > NOW() - make_interval(secs => $2)",
+        prefix,
+        ttl_secs as f64
+    )
+        .fetch_optional(conn)
+        .await
+}
+
+/// Stores a freshly fetched range, replacing any older one.
+pub async fn cache_range(conn: &mut PgConnection, prefix: &str, body: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO hibp_range_cache (prefix, body) VALUES ($1, $2)
+         ON CONFLICT (prefix) DO UPDATE SET body = EXCLUDED.body, fetched_at = NOW()",
+        prefix,
+        body
+    )
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes cached ranges older than `ttl_secs`, returning how many were removed.
+pub async fn prune_range_cache(pool: &PgPool, ttl_secs: u64) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM hibp_range_cache WHERE fetched_at < NOW() - make_interval(secs => $1)",
+        ttl_secs as f64
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
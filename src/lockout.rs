@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use rocket::http::{Header, Status};
+use sqlx::PgConnection;
+
+use crate::error::ApiError;
+
+/// How long an account is locked for after a number of consecutive failures, checked
+/// from the highest tier down. A lockout runs from the most recent failure.
+const ACCOUNT_LOCKOUT_TIERS: &[(i64, i64)] = &[(20, 60 * 60), (10, 15 * 60), (5, 60)];
+
+/// How long an IP is locked for after a number of failures within [`IP_WINDOW_SECS`],
+/// across any accounts. Higher than the account tiers, since many users can share an IP.
+const IP_LOCKOUT_TIERS: &[(i64, i64)] = &[(100, 60 * 60), (50, 15 * 60), (20, 60)];
+
+/// The window over which failures from one IP are counted.
+const IP_WINDOW_SECS: i64 = 60 * 60;
+
+/// Failed attempts older than this are deleted. Must exceed the longest lockout.
+const RETENTION_SECS: i64 = 24 * 60 * 60;
+
+/// Returns the seconds left in a lockout starting at `last_failure`, if any.
+fn remaining_secs(tiers: &[(i64, i64)], failures: i64, last_failure: Option<DateTime<Utc>>) -> Option<i64> {
+    let last_failure = last_failure?;
+    let (_, lock_secs) = tiers.iter().find(|(threshold, _)| failures >= *threshold)?;
+    let remaining = (last_failure + chrono::Duration::seconds(*lock_secs) - Utc::now()).num_seconds();
+    (remaining > 0).then_some(remaining)
+}
+
+/// Returns how many seconds are left before logins for `email`, or from `ip`, are
+/// accepted again, or `None` if neither is locked out.
+pub async fn lockout_remaining(
+    conn: &mut PgConnection,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let account = sqlx::query!(
+        "SELECT COUNT(*) AS \"failures!\", MAX(attempted_at) AS last_failure
+         FROM login_attempts WHERE email = $1",
+        email
+    )
+        .fetch_one(&mut *conn)
+        .await?;
+    let mut remaining = remaining_secs(ACCOUNT_LOCKOUT_TIERS, account.failures, account.last_failure);
+
+    if let Some(ip) = ip {
+        let from_ip = sqlx::query!(
+            "SELECT COUNT(*) AS \"failures!\", MAX(attempted_at) AS last_failure
+             FROM login_attempts
+             WHERE ip_address = $1 AND attempted_at > NOW() - make_interval(secs => $2)",
+            ip,
+            IP_WINDOW_SECS as f64
+        )
+            .fetch_one(&mut *conn)
+            .await?;
+        let ip_remaining = remaining_secs(IP_LOCKOUT_TIERS, from_ip.failures, from_ip.last_failure);
+        remaining = remaining.max(ip_remaining);
+    }
+
+    Ok(remaining)
+}
+
+/// Records a failed login for `email` from `ip`, pruning attempts past retention.
+pub async fn record_login_failure(
+    conn: &mut PgConnection,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM login_attempts WHERE attempted_at < NOW() - make_interval(secs => $1)",
+        RETENTION_SECS as f64
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        "INSERT INTO login_attempts (email, ip_address) VALUES ($1, $2)",
+        email,
+        ip
+    )
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Resets the consecutive failure count for `email` after a completed login.
+pub async fn clear_login_failures(conn: &mut PgConnection, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM login_attempts WHERE email = $1", email)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// The error returned while locked out: `429 Too Many Requests` with `Retry-After`.
+pub fn locked_out(retry_after: i64) -> ApiError {
+    ApiError::new(Status::TooManyRequests, "login_locked")
+        .with("retry_after", retry_after)
+        .with_header(Header::new("Retry-After", retry_after.to_string()))
+}
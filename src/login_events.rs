@@ -0,0 +1,83 @@
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::guards::ClientInfo;
+
+/// How a login attempt ended, as stored in `login_events.outcome`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginEventOutcome {
+    Success,
+    WrongPassword,
+    /// The email has no account; such events are never shown to users.
+    UnknownEmail,
+    TwoFactorFailed,
+    LockedOut,
+    AccountSuspended,
+    EmailUnverified,
+}
+
+impl LoginEventOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LoginEventOutcome::Success => "success",
+            LoginEventOutcome::WrongPassword => "wrong_password",
+            LoginEventOutcome::UnknownEmail => "unknown_email",
+            LoginEventOutcome::TwoFactorFailed => "two_factor_failed",
+            LoginEventOutcome::LockedOut => "locked_out",
+            LoginEventOutcome::AccountSuspended => "account_suspended",
+            LoginEventOutcome::EmailUnverified => "email_unverified",
+        }
+    }
+}
+
+/// Whom a login attempt was for: a known user, or just the email that was entered.
+pub enum LoginSubject<'a> {
+    User(Uuid),
+    Email(&'a str),
+}
+
+/// Appends a login attempt to the audit log.
+///
+/// An attempt by email is attributed to the account with that email, if any. Failing
+/// to write the event is logged but never fails the login itself.
+pub async fn record_login_event(
+    conn: &mut PgConnection,
+    subject: LoginSubject<'_>,
+    client: &ClientInfo,
+    outcome: LoginEventOutcome,
+) {
+    let (user_id, email) = match subject {
+        LoginSubject::User(id) => (Some(id), None),
+        LoginSubject::Email(email) => (None, Some(email)),
+    };
+
+    let result = sqlx::query!(
+        "INSERT INTO login_events (user_id, email, ip_address, user_agent, outcome)
+         SELECT u.id, COALESCE(u.email, $2), $3, $4, $5
+         FROM (SELECT 1) AS one
+         LEFT JOIN users u ON u.id = $1 OR ($1 IS NULL AND u.email = $2)",
+        user_id,
+        email,
+        client.ip_address(),
+        client.user_agent,
+        outcome.as_str()
+    )
+        .execute(conn)
+        .await;
+
+    if let Err(e) = result {
+        warn!("Failed to record login event: {}", e);
+    }
+}
+
+/// Deletes login events older than `retention_days`, returning how many were removed.
+pub async fn prune_login_events(pool: &PgPool, retention_days: u32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM login_events WHERE created_at < NOW() - make_interval(days => $1)",
+        retention_days.min(i32::MAX as u32) as i32
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
@@ -1,4 +1,33 @@
+// Models mirror the schema; not every table is read by a route yet.
+#[allow(dead_code)]
 mod models;
+mod access_log;
+mod api_tokens;
+mod body;
+mod config;
+mod crypto;
+mod email;
+mod error;
+mod expiry;
+mod guards;
+mod hibp;
+mod lockout;
+mod login_events;
+mod notifications;
+#[cfg(feature = "opaque")]
+mod opaque;
+mod rate_limit;
+mod sessions;
+mod ssh;
+#[cfg(test)]
+mod testing;
+mod tokens;
+mod trash;
+mod totp;
+mod uri_match;
+mod usage;
+mod validation;
+mod web_sessions;
 pub mod routes;
 
 #[macro_use] extern crate rocket;
@@ -7,6 +36,9 @@ use rocket::{fairing, Build, Rocket};
 use rocket::fairing::AdHoc;
 use rocket_db_pools::{sqlx, Database, Connection};
 use rocket_db_pools::sqlx::Row;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Database)]
 #[database("postgres_db")]
@@ -45,12 +77,295 @@ async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
 
 
 
+/// Email transport setup
+async fn init_emailer(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(config) = rocket.state::<config::AppConfig>() else {
+        error!("❌ Email setup requires the application config.");
+        return Err(rocket);
+    };
+
+    let emailer: email::SharedEmailer = match config.email.transport {
+        config::EmailTransport::Log => Arc::new(email::LogEmailer),
+        config::EmailTransport::Smtp => match email::SmtpEmailer::new(&config.email) {
+            Ok(emailer) => Arc::new(emailer),
+            Err(e) => {
+                error!("❌ Invalid SMTP configuration: {}", e);
+                return Err(rocket);
+            }
+        },
+    };
+
+    Ok(rocket.manage(emailer))
+}
+
+
+
+/// Fake salt secret setup
+async fn init_salt_secret(rocket: Rocket<Build>) -> fairing::Result {
+    if let Some(secret) = rocket.state::<config::AppConfig>().and_then(|c| c.auth.salt_secret) {
+        return Ok(rocket.manage(crypto::SaltSecret(secret)));
+    }
+    let Some(db) = DatabasePool::fetch(&rocket) else {
+        error!("❌ Failed to fetch database pool for the salt secret.");
+        return Err(rocket);
+    };
+
+    // Every instance ends up with whichever secret was inserted first.
+    let generated: [u8; 32] = rand::random();
+    let stored = sqlx::query_scalar!(
+        "WITH inserted AS (
+             INSERT INTO server_secrets (name, value) VALUES ('salt_secret', $1)
+             ON CONFLICT (name) DO NOTHING
+             RETURNING value
+         )
+         SELECT value AS \"value!\" FROM inserted
+         UNION ALL
+         SELECT value FROM server_secrets WHERE name = 'salt_secret'",
+        &generated[..]
+    )
+        .fetch_one(&db.0)
+        .await;
+
+    match stored.map(<[u8; 32]>::try_from) {
+        Ok(Ok(secret)) => Ok(rocket.manage(crypto::SaltSecret(secret))),
+        Ok(Err(_)) => {
+            error!("❌ The stored salt secret is not 32 bytes.");
+            Err(rocket)
+        }
+        Err(e) => {
+            error!("❌ Loading the salt secret failed: {}", e);
+            Err(rocket)
+        }
+    }
+}
+
+
+
+/// OPAQUE server setup
+#[cfg(feature = "opaque")]
+async fn init_opaque_server(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(key) = rocket.state::<config::AppConfig>().map(|c| c.auth.secret_encryption_key) else {
+        error!("❌ OPAQUE setup requires the application config.");
+        return Err(rocket);
+    };
+    let Some(db) = DatabasePool::fetch(&rocket) else {
+        error!("❌ Failed to fetch database pool for the OPAQUE setup.");
+        return Err(rocket);
+    };
+
+    let generated = match opaque::OpaqueServer::generate().seal(&key) {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            error!("❌ Encrypting the OPAQUE setup failed: {}", e);
+            return Err(rocket);
+        }
+    };
+    // Every instance ends up with whichever setup was inserted first.
+    let stored = sqlx::query_scalar!(
+        "WITH inserted AS (
+             INSERT INTO server_secrets (name, value) VALUES ('opaque_server_setup', $1)
+             ON CONFLICT (name) DO NOTHING
+             RETURNING value
+         )
+         SELECT value AS \"value!\" FROM inserted
+         UNION ALL
+         SELECT value FROM server_secrets WHERE name = 'opaque_server_setup'",
+        generated
+    )
+        .fetch_one(&db.0)
+        .await;
+
+    match stored.map(|sealed| opaque::OpaqueServer::open(&key, &sealed)) {
+        Ok(Some(server)) => Ok(rocket.manage(server)),
+        Ok(None) => {
+            // Replacing it would lock out every OPAQUE account, so refuse to start instead.
+            error!("❌ The stored OPAQUE setup can't be decrypted with auth.secret_encryption_key.");
+            Err(rocket)
+        }
+        Err(e) => {
+            error!("❌ Loading the OPAQUE setup failed: {}", e);
+            Err(rocket)
+        }
+    }
+}
+
+
+
+/// How often expired rows are cleaned up.
+const CLEANUP_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Work run periodically in the background
+///
+/// `setup` reads its settings from the app and returns how often to run and the work
+/// to run, or `None` to leave it off. The work reports how many rows it handled, which
+/// are logged as `done` describes them; failures, e.g. while the database is briefly
+/// down, are retried next tick.
+fn spawn_periodic<F, P, Fut>(rocket: &Rocket<rocket::Orbit>, name: &'static str, done: &'static str, setup: F)
+where
+    F: FnOnce(&Rocket<rocket::Orbit>, &config::AppConfig) -> Option<(Duration, P)>,
+    P: Fn(sqlx::PgPool) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<u64, sqlx::Error>> + Send,
+{
+    let (Some(config), Some(db)) = (rocket.state::<config::AppConfig>(), DatabasePool::fetch(rocket)) else {
+        error!("❌ {} requires the config and database pool.", name);
+        return;
+    };
+    let Some((period, work)) = setup(rocket, config) else {
+        return;
+    };
+    let pool = db.0.clone();
+
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(period.max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            match work(pool.clone()).await {
+                Ok(0) => {}
+                Ok(handled) => info!("{}: {} {}", name, handled, done),
+                Err(e) => warn!("{} failed: {}", name, e),
+            }
+        }
+    });
+}
+
+
+
+/// Credential usage, written in batches in the background
+fn spawn_usage_flush(rocket: &Rocket<rocket::Orbit>) {
+    let (Some(recorder), Some(db)) = (rocket.state::<usage::UsageRecorder>(), DatabasePool::fetch(rocket)) else {
+        error!("❌ Recording credential usage requires the recorder and database pool.");
+        return;
+    };
+    let Some(receiver) = recorder.take_receiver() else {
+        return;
+    };
+
+    rocket::tokio::spawn(usage::flush_usage(db.0.clone(), receiver));
+}
+
+
+
+/// Have I Been Pwned client setup
+async fn init_hibp(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(config) = rocket.state::<config::AppConfig>() else {
+        error!("❌ HIBP setup requires the application config.");
+        return Err(rocket);
+    };
+
+    match hibp::HibpClient::new(&config.hibp) {
+        Ok(client) => Ok(rocket.manage(client)),
+        Err(e) => {
+            error!("❌ Invalid HIBP configuration: {}", e);
+            Err(rocket)
+        }
+    }
+}
+
+
+
+/// WebAuthn relying party setup
+async fn init_webauthn(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(config) = rocket.state::<config::AppConfig>() else {
+        error!("❌ WebAuthn setup requires the application config.");
+        return Err(rocket);
+    };
+
+    let webauthn = webauthn_rs::prelude::Url::parse(&config.webauthn.rp_origin)
+        .map_err(|e| e.to_string())
+        .and_then(|origin| {
+            webauthn_rs::WebauthnBuilder::new(&config.webauthn.rp_id, &origin)
+                .map(|builder| builder.rp_name(&config.webauthn.rp_name))
+                .and_then(|builder| builder.build())
+                .map_err(|e| e.to_string())
+        });
+
+    match webauthn {
+        Ok(webauthn) => Ok(rocket.manage(webauthn)),
+        Err(e) => {
+            error!("❌ Invalid WebAuthn configuration: {}", e);
+            Err(rocket)
+        }
+    }
+}
+
+
+
 /// Application entry point
 #[launch]
 fn rocket() -> _ {
-    rocket::build()
-        .attach(DatabasePool::init())
+    app(rocket::build().attach(DatabasePool::init()))
+}
+
+/// Everything but the database pool, which tests supply themselves
+fn app(rocket: Rocket<Build>) -> Rocket<Build> {
+    let rocket = rocket
+        .attach(AdHoc::config::<config::AppConfig>())
+        .attach(AdHoc::try_on_ignite("Emailer", init_emailer))
         .attach(AdHoc::try_on_ignite("Run Migrations", run_migrations))
+        .attach(AdHoc::try_on_ignite("Salt Secret", init_salt_secret));
+    #[cfg(feature = "opaque")]
+    let rocket = rocket.attach(AdHoc::try_on_ignite("OPAQUE Server Setup", init_opaque_server));
+    rocket
+        .attach(AdHoc::try_on_ignite("WebAuthn", init_webauthn))
+        .attach(AdHoc::try_on_ignite("HIBP Client", init_hibp))
+        .attach(rate_limit::RateLimiter::new("/auth"))
+        .manage(usage::UsageRecorder::new())
+        .attach(AdHoc::on_liftoff("Login History Cleanup", |rocket| Box::pin(async move {
+            spawn_periodic(rocket, "Login history cleanup", "expired rows deleted", |_, config| {
+                let retention_days = config.auth.login_event_retention_days;
+                Some((CLEANUP_PERIOD, move |pool| async move { login_events::prune_login_events(&pool, retention_days).await }))
+            });
+        })))
+        .attach(AdHoc::on_liftoff("Access Log Cleanup", |rocket| Box::pin(async move {
+            spawn_periodic(rocket, "Access log cleanup", "expired rows deleted", |_, config| {
+                let retention_days = config.credentials.access_log_retention_days;
+                Some((CLEANUP_PERIOD, move |pool| async move { access_log::prune_access_log(&pool, retention_days).await }))
+            });
+        })))
+        .attach(AdHoc::on_liftoff("Credential Usage", |rocket| Box::pin(async move {
+            spawn_usage_flush(rocket);
+        })))
+        .attach(AdHoc::on_liftoff("Trash Purge", |rocket| Box::pin(async move {
+            spawn_periodic(rocket, "Trash purge", "credentials purged from the trash", |_, config| {
+                let retention_days = config.trash.retention_days;
+                let period = Duration::from_secs(config.trash.purge_interval_secs);
+                config.trash.auto_purge.then_some((period, move |pool: sqlx::PgPool| async move {
+                    trash::purge_trash(&pool, retention_days).await
+                }))
+            });
+        })))
+        .attach(AdHoc::on_liftoff("Expiry Notices", |rocket| Box::pin(async move {
+            spawn_periodic(rocket, "Expiry notices", "credentials with expiry notices sent", |rocket, config| {
+                if config.credentials.expiry_notice_days.is_empty() {
+                    return None;
+                }
+                let Some(emailer) = rocket.state::<email::SharedEmailer>().cloned() else {
+                    error!("❌ Expiry notices require the emailer.");
+                    return None;
+                };
+                let notice_days = config.credentials.expiry_notice_days.clone();
+                let period = Duration::from_secs(config.credentials.expiry_check_interval_secs);
+                Some((period, move |pool: sqlx::PgPool| {
+                    let (emailer, notice_days) = (emailer.clone(), notice_days.clone());
+                    async move { expiry::notify_expiring_credentials(&pool, &emailer, &notice_days).await }
+                }))
+            });
+        })))
+        .attach(AdHoc::on_liftoff("HIBP Cache Cleanup", |rocket| Box::pin(async move {
+            spawn_periodic(rocket, "HIBP cache cleanup", "expired rows deleted", |_, config| {
+                let ttl_secs = config.hibp.cache_ttl_secs;
+                Some((CLEANUP_PERIOD, move |pool| async move { hibp::prune_range_cache(&pool, ttl_secs).await }))
+            });
+        })))
+        .register("/", catchers![error::bad_request, error::forbidden, error::unprocessable_entity])
         .mount("/", routes![index])
         .mount("/auth", routes::auth_routes())
+        .mount("/admin", routes::admin_routes())
+        .mount("/emergency", routes::emergency_routes())
+        .mount("/teams", routes::team_routes())
+        .mount("/credentials", routes::credential_routes())
+        .mount("/ssh-keys", routes::ssh_key_routes())
+        .mount("/attachments", routes::attachment_routes())
+        .mount("/users", routes::user_routes())
+        .mount("/hibp", routes::hibp_routes())
 }
\ No newline at end of file
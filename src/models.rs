@@ -6,33 +6,217 @@ use chrono::{DateTime, Utc};
 // --- Enums ---
 
 #[derive(Debug, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "lowercase")]
 #[sqlx(type_name = "team_role", rename_all = "lowercase")]
 pub enum TeamRole {
     Member,
     Admin,
 }
 
-#[derive(Debug, Serialize, Deserialize, Type)]
+/// Whether a personal access token may make changes or only read.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "api_token_access", rename_all = "snake_case")]
+pub enum TokenAccess {
+    Read,
+    ReadWrite,
+}
+
+/// How an account proves its password at login.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "auth_scheme", rename_all = "snake_case")]
+pub enum AuthScheme {
+    /// The client sends a hash derived from the password, compared with `password_hash`.
+    PasswordHash,
+    /// The client runs OPAQUE against `opaque_registration`; nothing derived from the
+    /// password is sent.
+    Opaque,
+}
+
+/// Where an emergency grant stands. Rejection sends a grant back to waiting for a new
+/// request; an unlocked grant stays unlocked until the grantor revokes it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "emergency_grant_status", rename_all = "snake_case")]
+pub enum EmergencyGrantStatus {
+    /// Granted, but the grantee hasn't asked for access.
+    Pending,
+    /// The grantee asked for access and the waiting period is running.
+    Requested,
+    /// The grantor rejected the last request.
+    Rejected,
+    /// The waiting period passed and the grantee can fetch the key.
+    Unlocked,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
 #[sqlx(type_name = "secret_kind", rename_all = "snake_case")]
 pub enum SecretKind {
     Password,
     SshKey,
+    ApiToken,
+    /// Free-form text, which needs no hostname or username.
+    SecureNote,
+    /// The seed of a TOTP authenticator.
+    TotpSecret,
+    /// A payment card, whose number, expiry, CVC, and cardholder the client serializes
+    /// into the encrypted secret. It has no hostname.
+    CreditCard,
+}
+
+impl SecretKind {
+    /// Every kind, in the order they were added.
+    pub const ALL: &'static [SecretKind] = &[
+        SecretKind::Password,
+        SecretKind::SshKey,
+        SecretKind::ApiToken,
+        SecretKind::SecureNote,
+        SecretKind::TotpSecret,
+        SecretKind::CreditCard,
+    ];
+
+    /// The name of the kind in JSON and in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecretKind::Password => "password",
+            SecretKind::SshKey => "ssh_key",
+            SecretKind::ApiToken => "api_token",
+            SecretKind::SecureNote => "secure_note",
+            SecretKind::TotpSecret => "totp_secret",
+            SecretKind::CreditCard => "credit_card",
+        }
+    }
+
+    /// The kind named `name`, if there is one.
+    pub fn from_name(name: &str) -> Option<SecretKind> {
+        SecretKind::ALL.iter().find(|kind| kind.as_str() == name).copied()
+    }
+}
+
+/// The algorithm of an SSH key.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "ssh_key_type", rename_all = "snake_case")]
+pub enum SshKeyType {
+    Ed25519,
+    Rsa,
+    Ecdsa,
+}
+
+impl SshKeyType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SshKeyType::Ed25519 => "ed25519",
+            SshKeyType::Rsa => "rsa",
+            SshKeyType::Ecdsa => "ecdsa",
+        }
+    }
+}
+
+/// The HMAC hash a `totp_secret` credential's codes are generated with.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "totp_algorithm", rename_all = "snake_case")]
+pub enum TotpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl TotpAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TotpAlgorithm::Sha1 => "sha1",
+            TotpAlgorithm::Sha256 => "sha256",
+            TotpAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+/// How a custom field of a credential is shown; its value is encrypted either way.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "credential_field_type", rename_all = "snake_case")]
+pub enum FieldType {
+    Text,
+    /// Masked until revealed, like a password.
+    Hidden,
+    Boolean,
+}
+
+impl FieldType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Hidden => "hidden",
+            FieldType::Boolean => "boolean",
+        }
+    }
+}
+
+/// How a URI of a credential is compared with the address being filled in.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Type, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "uri_match_type", rename_all = "snake_case")]
+pub enum UriMatchType {
+    /// The whole address must be the same.
+    Exact,
+    /// The host and port must be the same.
+    Host,
+    /// The address must be on the same registrable domain, e.g. any `*.example.com`.
+    #[default]
+    Domain,
+    /// The address must start with the URI.
+    StartsWith,
+}
+
+impl UriMatchType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UriMatchType::Exact => "exact",
+            UriMatchType::Host => "host",
+            UriMatchType::Domain => "domain",
+            UriMatchType::StartsWith => "starts_with",
+        }
+    }
 }
 
 // --- User Models ---
 
-#[derive(Debug, Serialize, FromRow)]
+/// A row of the `users` table.
+///
+/// Deliberately not `Serialize`: it holds the password hash and key material, so
+/// responses must go through a dedicated DTO that picks what to expose.
+#[derive(Debug, FromRow)]
 pub struct User {
     pub id: Uuid,
     pub email: String,
-    #[serde(skip)]
-    pub password_hash: String,
-    pub public_key: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub encrypted_private_key: Option<Vec<u8>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub private_key_nonce: Option<Vec<u8>>,
+    pub name: String,
+    pub auth_scheme: AuthScheme,
+    /// Set for `password_hash` accounts.
+    pub password_hash: Option<Vec<u8>>,
+    /// Set for `opaque` accounts.
+    pub opaque_registration: Option<Vec<u8>>,
+    pub password_salt: Vec<u8>,
+    pub kdf_memory_kib: i32,
+    pub kdf_iterations: i32,
+    pub kdf_parallelism: i32,
+    pub public_key: Vec<u8>,
+    pub encrypted_private_key: Vec<u8>,
+    pub private_key_nonce: Vec<u8>,
+    pub totp_enabled: bool,
+    pub is_admin: bool,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub suspended_at: Option<DateTime<Utc>>,
+    pub must_change_password: bool,
+    pub recovery_key_required: bool,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub last_login_ip: Option<String>,
+    pub notify_new_device_login: bool,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 // --- Team Models ---
@@ -43,6 +227,7 @@ pub struct Team {
     pub name: String,
     pub is_personal: bool,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize, FromRow)]
@@ -50,6 +235,7 @@ pub struct TeamMember {
     pub team_id: Uuid,
     pub user_id: Uuid,
     pub role: TeamRole,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize, FromRow)]
@@ -58,11 +244,14 @@ pub struct TeamKeyAccess {
     pub user_id: Uuid,
     pub encrypted_team_key: Vec<u8>,
     pub nonce: Vec<u8>,
+    pub updated_at: DateTime<Utc>,
 }
 
 // --- Credential Models ---
 
-#[derive(Debug, Serialize, FromRow)]
+/// A credential row, secret included. Never serialized: responses use DTOs that pick
+/// the fields they expose.
+#[derive(Debug, FromRow)]
 pub struct Credential {
     pub id: Uuid,
     pub team_id: Uuid,
@@ -71,9 +260,8 @@ pub struct Credential {
     pub username: String,
     pub kind: SecretKind,
     pub public_key: Option<String>,
-    #[serde(skip)]
     pub encrypted_secret: Vec<u8>,
-    #[serde(skip)]
     pub nonce: Vec<u8>,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
\ No newline at end of file
@@ -0,0 +1,175 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_db_pools::Database;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::email::{EmailMessage, SharedEmailer};
+use crate::guards::ClientInfo;
+use crate::DatabasePool;
+
+/// The longest user agent fallback kept when the browser and OS aren't recognised.
+const MAX_USER_AGENT_SUMMARY_LEN: usize = 50;
+
+/// Browsers recognised in a `User-Agent`, in the order they must be checked: most
+/// browsers also claim to be the ones after them.
+const BROWSERS: &[(&str, &str)] = &[
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Firefox/", "Firefox"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+];
+
+/// Operating systems recognised in a `User-Agent`, checked in order.
+const OPERATING_SYSTEMS: &[(&str, &str)] = &[
+    ("Android", "Android"),
+    ("iPhone", "iOS"),
+    ("iPad", "iPadOS"),
+    ("Windows", "Windows"),
+    ("Mac OS X", "macOS"),
+    ("Linux", "Linux"),
+];
+
+/// Describes a `User-Agent` in a few words, e.g. "Firefox on Linux", for people to
+/// recognise their devices by. Unrecognised agents are reduced to their first product
+/// token (e.g. "curl/8.5.0").
+pub fn describe_user_agent(user_agent: &str) -> String {
+    let find = |table: &[(&str, &'static str)]| {
+        table.iter().find(|(marker, _)| user_agent.contains(marker)).map(|(_, name)| *name)
+    };
+
+    match (find(BROWSERS), find(OPERATING_SYSTEMS)) {
+        (Some(browser), Some(os)) => format!("{} on {}", browser, os),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        (None, None) => user_agent
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .take(MAX_USER_AGENT_SUMMARY_LEN)
+            .collect(),
+    }
+}
+
+/// Tells users about logins from devices they haven't logged in from before.
+///
+/// A request guard, so login routes can hand it to `complete_login`. It fails with
+/// `500 Internal Server Error` only if the database pool or emailer isn't managed.
+pub struct LoginNotifier {
+    pool: PgPool,
+    emailer: SharedEmailer,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LoginNotifier {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let db = DatabasePool::fetch(request.rocket());
+        let emailer = request.rocket().state::<SharedEmailer>();
+        match (db, emailer) {
+            (Some(db), Some(emailer)) => Outcome::Success(LoginNotifier { pool: db.0.clone(), emailer: emailer.clone() }),
+            _ => Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+impl LoginNotifier {
+    /// Checks in the background whether a completed login came from a new device and,
+    /// if so, records a notification and emails the user about it.
+    ///
+    /// `new_device` says whether the device the client identified itself as was seen
+    /// for the first time; without one, a login is new unless an earlier session came
+    /// from the same IP address and user agent. Nothing is sent for the user's very
+    /// first login or if they turned these notifications off. The login response never
+    /// waits for this, and failures are only logged.
+    pub fn new_device_login(&self, user_id: Uuid, session_id: Uuid, new_device: Option<bool>, client: &ClientInfo) {
+        let pool = self.pool.clone();
+        let emailer = self.emailer.clone();
+        let ip_address = client.ip_address();
+        let user_agent = client.user_agent.clone();
+
+        rocket::tokio::spawn(async move {
+            let result = notify_new_device_login(
+                &pool,
+                &emailer,
+                user_id,
+                session_id,
+                new_device,
+                ip_address,
+                user_agent,
+            )
+                .await;
+            if let Err(e) = result {
+                warn!("Failed to check login of user {} for a new device: {}", user_id, e);
+            }
+        });
+    }
+}
+
+/// Does the work of [`LoginNotifier::new_device_login`].
+async fn notify_new_device_login(
+    pool: &PgPool,
+    emailer: &SharedEmailer,
+    user_id: Uuid,
+    session_id: Uuid,
+    new_device: Option<bool>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+) -> Result<(), sqlx::Error> {
+    let user = sqlx::query!(
+        "SELECT u.email, u.notify_new_device_login,
+                EXISTS(SELECT 1 FROM sessions s WHERE s.user_id = u.id AND s.id <> $2) AS \"logged_in_before!\",
+                EXISTS(
+                    SELECT 1 FROM sessions s
+                    WHERE s.user_id = u.id AND s.id <> $2
+                      AND s.ip_address IS NOT DISTINCT FROM $3 AND s.user_agent IS NOT DISTINCT FROM $4
+                ) AS \"seen_client!\"
+         FROM users u WHERE u.id = $1",
+        user_id,
+        session_id,
+        ip_address,
+        user_agent
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    let Some(user) = user else {
+        return Ok(());
+    };
+    let is_new = new_device.unwrap_or(!user.seen_client);
+    if !is_new || !user.logged_in_before || !user.notify_new_device_login {
+        return Ok(());
+    }
+
+    let client = user_agent.as_deref().map(describe_user_agent);
+    let created_at = sqlx::query_scalar!(
+        "INSERT INTO notifications (user_id, kind, ip_address, user_agent)
+         VALUES ($1, 'new_device_login', $2, $3) RETURNING created_at",
+        user_id,
+        ip_address,
+        client
+    )
+        .fetch_one(pool)
+        .await?;
+
+    let message = EmailMessage {
+        to: user.email,
+        subject: "New login to your HomeDesk account".to_string(),
+        body: format!(
+            "Your HomeDesk account was just logged into from a device we haven't seen before.\n\n\
+             Time: {}\nIP address: {}\nDevice: {}\n\n\
+             If this was you, there's nothing to do. Otherwise, change your password and \
+             review your sessions and devices.",
+            created_at.format("%Y-%m-%d %H:%M UTC"),
+            ip_address.as_deref().unwrap_or("unknown"),
+            client.as_deref().unwrap_or("unknown"),
+        ),
+    };
+    if let Err(e) = emailer.send(message).await {
+        error!("{}", e);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use opaque_ke::argon2::Argon2;
+use opaque_ke::ciphersuite::CipherSuite;
+use opaque_ke::errors::ProtocolError;
+use opaque_ke::{
+    CredentialFinalization, CredentialRequest, RegistrationRequest, RegistrationUpload, Ristretto255,
+    ServerLogin, ServerLoginParameters, ServerRegistration, ServerSetup, TripleDh,
+};
+use rand::rngs::OsRng;
+
+use crate::crypto::{decrypt_secret, encrypt_secret};
+
+/// The length of the nonce stored in front of the encrypted server setup.
+const SETUP_NONCE_LEN: usize = 12;
+
+/// The OPAQUE configuration clients must use: ristretto255 for the OPRF, 3DH with
+/// SHA-512 for the key exchange, and Argon2id with the library's default parameters
+/// to stretch the password.
+pub struct OpaqueSuite;
+
+impl CipherSuite for OpaqueSuite {
+    type OprfCs = Ristretto255;
+    type KeyExchange = TripleDh<Ristretto255, sha2::Sha512>;
+    type Ksf = Argon2<'static>;
+}
+
+/// The server's OPAQUE key pair and OPRF seed, managed by Rocket.
+///
+/// Generated on first boot and kept in the database, encrypted with
+/// `auth.secret_encryption_key`. Every registration record depends on it.
+pub struct OpaqueServer(pub ServerSetup<OpaqueSuite>);
+
+impl OpaqueServer {
+    /// Generates a new server setup.
+    pub fn generate() -> Self {
+        OpaqueServer(ServerSetup::new(&mut OsRng))
+    }
+
+    /// Encrypts the setup for storage, prefixed with its nonce.
+    pub fn seal(&self, key: &[u8; 32]) -> Result<Vec<u8>, aes_gcm::Error> {
+        let (ciphertext, nonce) = encrypt_secret(key, &self.0.serialize())?;
+        Ok([nonce, ciphertext].concat())
+    }
+
+    /// Decrypts a setup produced by [`OpaqueServer::seal`], returning `None` if the key
+    /// is wrong or the bytes aren't a setup.
+    pub fn open(key: &[u8; 32], sealed: &[u8]) -> Option<Self> {
+        let (nonce, ciphertext) = sealed.split_at_checked(SETUP_NONCE_LEN)?;
+        let setup = decrypt_secret(key, ciphertext, nonce).ok()?;
+        ServerSetup::deserialize(&setup).ok().map(OpaqueServer)
+    }
+
+    /// Answers the first message of a registration for `email`, returning the
+    /// message to send back. Nothing needs to be kept: the client's upload can be
+    /// stored as it is once it arrives.
+    pub fn start_registration(&self, request: RegistrationRequest<OpaqueSuite>, email: &str) -> Result<Vec<u8>, ProtocolError> {
+        let result = ServerRegistration::start(&self.0, request, email.as_bytes())?;
+        Ok(result.message.serialize().to_vec())
+    }
+
+    /// Answers the first message of a login for `email`, returning the message to
+    /// send back and the state [`finish_login`] needs.
+    ///
+    /// Without a `registration` (the email is unknown or doesn't use OPAQUE) the
+    /// response is a fake one that looks the same to the client, and the login can't
+    /// be finished.
+    pub fn start_login(
+        &self,
+        registration: Option<&[u8]>,
+        request: CredentialRequest<OpaqueSuite>,
+        email: &str,
+    ) -> Result<(Vec<u8>, Vec<u8>), ProtocolError> {
+        let registration = registration.map(ServerRegistration::deserialize).transpose()?;
+        let result = ServerLogin::start(
+            &mut OsRng,
+            &self.0,
+            registration,
+            request,
+            email.as_bytes(),
+            ServerLoginParameters::default(),
+        )?;
+        Ok((result.message.serialize().to_vec(), result.state.serialize().to_vec()))
+    }
+}
+
+/// Turns a client's registration upload into the record stored for the account.
+pub fn registration_record(upload: RegistrationUpload<OpaqueSuite>) -> Vec<u8> {
+    ServerRegistration::finish(upload).serialize().to_vec()
+}
+
+/// Checks the client's last login message against the state kept from
+/// [`OpaqueServer::start_login`]. Succeeds only if the client knew the password.
+pub fn finish_login(state: &[u8], finalization: CredentialFinalization<OpaqueSuite>) -> Result<(), ProtocolError> {
+    ServerLogin::<OpaqueSuite>::deserialize(state)?.finish(finalization, ServerLoginParameters::default())?;
+    Ok(())
+}
+
+/// An OPAQUE message a client sends, decoded from its wire format.
+pub trait ClientMessage: Sized {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError>;
+}
+
+impl ClientMessage for RegistrationRequest<OpaqueSuite> {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        RegistrationRequest::deserialize(bytes)
+    }
+}
+
+impl ClientMessage for RegistrationUpload<OpaqueSuite> {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        RegistrationUpload::deserialize(bytes)
+    }
+}
+
+impl ClientMessage for CredentialRequest<OpaqueSuite> {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        CredentialRequest::deserialize(bytes)
+    }
+}
+
+impl ClientMessage for CredentialFinalization<OpaqueSuite> {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        CredentialFinalization::deserialize(bytes)
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Build, Data, Rocket};
+use rocket_db_pools::Database;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::DatabasePool;
+use crate::config::{AppConfig, RateLimitStoreKind};
+use crate::error::ApiError;
+
+/// Where rejected requests are rerouted to, since a fairing can't answer a request itself.
+const REJECTED_PATH: &str = "/__rate_limited";
+
+/// Buckets kept in memory before stale ones are pruned.
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// Buckets untouched for this long have refilled under any per-minute limit and are
+/// deleted from either store.
+const STALE_BUCKET_SECS: f64 = 60.0 * 60.0;
+
+/// A token bucket's shape: it holds up to `capacity` tokens and refills continuously.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+impl Limit {
+    /// A limit allowing `n` requests per minute, all of which may come in a burst.
+    pub fn per_minute(n: u32) -> Self {
+        Limit { capacity: f64::from(n), refill_per_sec: f64::from(n) / 60.0 }
+    }
+
+    /// Refills a bucket holding `tokens` after `elapsed_secs` and tries to take one.
+    ///
+    /// Returns the new token count and, if no token was available, the whole seconds
+    /// until one will be.
+    fn take(&self, tokens: f64, elapsed_secs: f64) -> (f64, Option<u64>) {
+        let available = (tokens + elapsed_secs * self.refill_per_sec).min(self.capacity);
+        if available >= 1.0 {
+            (available - 1.0, None)
+        } else {
+            let retry_after = ((1.0 - available) / self.refill_per_sec).ceil().max(1.0);
+            (available, Some(retry_after as u64))
+        }
+    }
+}
+
+/// Storage for token buckets.
+///
+/// The in-memory store is enough for a single instance; deployments running several
+/// instances behind a load balancer should use the Postgres store so they share limits.
+#[rocket::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket for `key`, creating it full if needed.
+    ///
+    /// Returns `None` if the request is allowed, or the seconds until it would be.
+    async fn take(&self, key: &str, limit: Limit) -> Result<Option<u64>, String>;
+}
+
+/// Keeps buckets in process memory.
+#[derive(Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+#[rocket::async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn take(&self, key: &str, limit: Limit) -> Result<Option<u64>, String> {
+        let mut buckets = self.buckets.lock().map_err(|e| e.to_string())?;
+        let now = Instant::now();
+
+        if buckets.len() >= MAX_MEMORY_BUCKETS {
+            // A bucket that has refilled completely is the same as a missing one.
+            buckets.retain(|_, (_, updated)| now.duration_since(*updated).as_secs_f64() < STALE_BUCKET_SECS);
+        }
+
+        let (tokens, updated) = buckets.entry(key.to_string()).or_insert((limit.capacity, now));
+        let (remaining, retry_after) = limit.take(*tokens, now.duration_since(*updated).as_secs_f64());
+        *tokens = remaining;
+        *updated = now;
+
+        Ok(retry_after)
+    }
+}
+
+/// Keeps buckets in the `rate_limit_buckets` table, shared by every instance.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresStore { pool }
+    }
+}
+
+#[rocket::async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn take(&self, key: &str, limit: Limit) -> Result<Option<u64>, String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+        sqlx::query!(
+            "INSERT INTO rate_limit_buckets (key, tokens) VALUES ($1, $2) ON CONFLICT (key) DO NOTHING",
+            key,
+            limit.capacity
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // Lock the bucket so concurrent requests from other instances queue up behind us.
+        let bucket = sqlx::query!(
+            "SELECT tokens, EXTRACT(EPOCH FROM NOW() - updated_at)::DOUBLE PRECISION AS \"elapsed!\"
+             FROM rate_limit_buckets WHERE key = $1 FOR UPDATE",
+            key
+        )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let (remaining, retry_after) = limit.take(bucket.tokens, bucket.elapsed.max(0.0));
+
+        sqlx::query!(
+            "UPDATE rate_limit_buckets SET tokens = $2, updated_at = NOW() WHERE key = $1",
+            key,
+            remaining
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        tx.commit().await.map_err(|e| e.to_string())?;
+
+        if retry_after.is_some() {
+            // Rejections are rare enough to piggyback cleanup of idle buckets on.
+            let _ = sqlx::query!(
+                "DELETE FROM rate_limit_buckets WHERE updated_at < NOW() - make_interval(secs => $1)",
+                STALE_BUCKET_SECS
+            )
+                .execute(&self.pool)
+                .await;
+        }
+
+        Ok(retry_after)
+    }
+}
+
+/// Marks a request rejected by the rate limiter, for the rejection route to answer.
+struct Rejected(Option<u64>);
+
+/// Limits requests under a mount point, per client IP, with a token bucket per path.
+///
+/// Limits, the store, and whether `X-Forwarded-For` is trusted come from the
+/// `rate_limit` section of the config. Requests over the limit get `429 Too Many
+/// Requests` with a JSON body and a `Retry-After` header. If the store fails, the
+/// request is let through rather than locking everyone out.
+pub struct RateLimiter {
+    mount: &'static str,
+}
+
+impl RateLimiter {
+    /// Creates a limiter for the routes mounted at `mount`.
+    pub fn new(mount: &'static str) -> Self {
+        RateLimiter { mount }
+    }
+}
+
+/// The limiter's configuration and store, built on ignite.
+struct RateLimitState {
+    store: Box<dyn RateLimitStore>,
+    limits: HashMap<String, Limit>,
+    trust_proxy: bool,
+}
+
+impl RateLimitState {
+    /// The client's IP: the proxy-appended `X-Forwarded-For` entry if proxies are
+    /// trusted, otherwise the connection's remote address.
+    fn client_ip(&self, request: &Request<'_>) -> Option<IpAddr> {
+        if self.trust_proxy {
+            // The last entry is the one added by our own proxy; earlier entries are
+            // supplied by the client and can't be trusted.
+            let forwarded = request
+                .headers()
+                .get("X-Forwarded-For")
+                .flat_map(|value| value.split(','))
+                .filter_map(|entry| entry.trim().parse().ok())
+                .last();
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        request.remote().map(|addr| addr.ip())
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info { name: "Rate Limiter", kind: Kind::Ignite | Kind::Request }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let Some(config) = rocket.state::<AppConfig>() else {
+            error!("❌ Rate limiting requires the application config.");
+            return Err(rocket);
+        };
+        let config = &config.rate_limit;
+        if !config.enabled {
+            return Ok(rocket);
+        }
+
+        let store: Box<dyn RateLimitStore> = match config.store {
+            RateLimitStoreKind::Memory => Box::new(MemoryStore::default()),
+            RateLimitStoreKind::Postgres => match DatabasePool::fetch(&rocket) {
+                Some(db) => Box::new(PostgresStore::new(db.0.clone())),
+                None => {
+                    error!("❌ The Postgres rate limit store requires the database pool.");
+                    return Err(rocket);
+                }
+            },
+        };
+
+        let state = RateLimitState {
+            store,
+            limits: config.limits.iter().map(|(path, &n)| (path.clone(), Limit::per_minute(n))).collect(),
+            trust_proxy: config.trust_proxy,
+        };
+
+        Ok(rocket.manage(state).mount("/", routes![rejected]))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        // Absent when rate limiting is disabled.
+        let Some(state) = request.rocket().state::<RateLimitState>() else { return };
+
+        let path = request.uri().path().as_str();
+        let Some(rest) = path.strip_prefix(self.mount).and_then(|p| p.strip_prefix('/')) else { return };
+        let Some(&limit) = state.limits.get(rest) else { return };
+        let Some(ip) = state.client_ip(request) else { return };
+
+        let key = format!("{}:{}", rest, ip);
+        let retry_after = match state.store.take(&key, limit).await {
+            Ok(retry_after) => retry_after,
+            Err(e) => {
+                warn!("Rate limit store failed, letting request through: {}", e);
+                None
+            }
+        };
+
+        if let Some(retry_after) = retry_after {
+            request.local_cache(|| Rejected(Some(retry_after)));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(REJECTED_PATH).expect("valid rejection path"));
+        }
+    }
+}
+
+/// Present only on requests the rate limiter rejected.
+struct RateLimited(u64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.local_cache(|| Rejected(None)) {
+            Rejected(Some(retry_after)) => request::Outcome::Success(RateLimited(*retry_after)),
+            Rejected(None) => request::Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+/// Answers requests rerouted by the rate limiter.
+#[get("/__rate_limited")]
+fn rejected(limited: RateLimited) -> ApiError {
+    rate_limited(limited.0)
+}
+
+/// The error returned for a request over its limit.
+fn rate_limited(retry_after: u64) -> ApiError {
+    ApiError::new(Status::TooManyRequests, "rate_limited")
+        .with("retry_after", retry_after)
+        .with_header(Header::new("Retry-After", retry_after.to_string()))
+}
+
+/// Limits requests per authenticated caller rather than per IP, for routes that check
+/// it themselves once they know who is calling.
+///
+/// Shares the store and `rate_limit.limits` of [`RateLimiter`], looked up by a name the
+/// route picks. Never fails as a guard; everything is let through while rate limiting
+/// is disabled.
+pub struct CallerRateLimit<'r> {
+    state: Option<&'r RateLimitState>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CallerRateLimit<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(CallerRateLimit { state: request.rocket().state::<RateLimitState>() })
+    }
+}
+
+impl CallerRateLimit<'_> {
+    /// Takes a token from `caller`'s bucket for the limit named `name`.
+    ///
+    /// Fails with `429 Too Many Requests` and a `Retry-After` header, like the
+    /// [`RateLimiter`], if the caller is over the limit. Names without a configured
+    /// limit aren't limited, and a failing store lets the request through.
+    pub async fn check(&self, name: &str, caller: Uuid) -> Result<(), ApiError> {
+        let Some(state) = self.state else { return Ok(()) };
+        let Some(&limit) = state.limits.get(name) else { return Ok(()) };
+
+        let key = format!("{}:user:{}", name, caller);
+        match state.store.take(&key, limit).await {
+            Ok(Some(retry_after)) => Err(rate_limited(retry_after)),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                warn!("Rate limit store failed, letting request through: {}", e);
+                Ok(())
+            }
+        }
+    }
+}
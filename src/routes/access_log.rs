@@ -0,0 +1,172 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::models::TeamRole;
+
+/// Access log entries returned per page when no `limit` is given.
+const DEFAULT_PAGE_SIZE: i64 = 50;
+/// The largest `limit` accepted for access logs.
+const MAX_PAGE_SIZE: i64 = 200;
+
+// --- Response DTOs ---
+
+/// A read of a credential's encrypted secret.
+#[derive(Serialize)]
+pub struct AccessLogEntry {
+    pub id: i64,
+    pub credential_id: Uuid,
+    pub credential_title: String,
+    /// Who read it, unless their account has been deleted since.
+    pub user_id: Option<Uuid>,
+    /// `view`, `view_version`, `export`, or `copy`.
+    pub action: String,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One page of an access log, newest first.
+#[derive(Serialize)]
+pub struct AccessLogPage {
+    pub entries: Vec<AccessLogEntry>,
+    /// The total number of entries across all pages.
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// --- Helpers ---
+
+/// Fails unless the user is an admin of the team: `404 Not Found` for non-members and
+/// `403 Forbidden` for other members.
+pub(super) async fn require_team_admin(conn: &mut sqlx::PgConnection, team_id: Uuid, user_id: Uuid) -> Result<(), ApiError> {
+    let role = sqlx::query_scalar!(
+        "SELECT role AS \"role: TeamRole\" FROM team_members WHERE team_id = $1 AND user_id = $2",
+        team_id,
+        user_id
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if role != TeamRole::Admin {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+    Ok(())
+}
+
+// --- Routes ---
+
+/// Lists the reads of a credential's secret, newest first, for an admin of its team.
+/// Credentials in the trash keep their log until they are purged.
+///
+/// Paginated with `limit` (default 50, at most 200) and `offset`. Entries are kept for
+/// `credentials.access_log_retention_days`. Returns `404 Not Found` if the credential
+/// doesn't exist or the caller isn't a member of its team, or `403 Forbidden` for a
+/// token not scoped to the team or a member who isn't a team admin.
+#[get("/<id>/access-log?<limit>&<offset>")]
+pub async fn credential_access_log(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Json<AccessLogPage>, ApiError> {
+    let team_id = sqlx::query_scalar!("SELECT team_id FROM credentials WHERE id = $1", id)
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+    require_team_admin(db.as_mut(), team_id, user.id).await?;
+
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = offset.unwrap_or(0).max(0);
+
+    let entries = sqlx::query_as!(
+        AccessLogEntry,
+        "SELECT l.id, l.credential_id, c.title AS credential_title, l.user_id, l.action, l.ip_address, l.created_at
+         FROM credential_access_log l
+         JOIN credentials c ON c.id = l.credential_id
+         WHERE l.credential_id = $1
+         ORDER BY l.created_at DESC, l.id DESC
+         LIMIT $2 OFFSET $3",
+        id,
+        limit,
+        offset
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let total = sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"count!\" FROM credential_access_log WHERE credential_id = $1",
+        id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(AccessLogPage { entries, total, limit, offset }))
+}
+
+/// Lists the reads of secrets of all of a team's credentials, newest first, for an
+/// admin of the team. Reads from before a credential moved into the team are included.
+///
+/// Paginated with `limit` (default 50, at most 200) and `offset`. Returns `404 Not
+/// Found` if the team doesn't exist or the caller isn't a member, or `403 Forbidden` for
+/// a token not scoped to the team or a member who isn't a team admin.
+#[get("/<team_id>/access-log?<limit>&<offset>")]
+pub async fn team_access_log(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Json<AccessLogPage>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+    require_team_admin(db.as_mut(), team_id, user.id).await?;
+
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = offset.unwrap_or(0).max(0);
+
+    let entries = sqlx::query_as!(
+        AccessLogEntry,
+        "SELECT l.id, l.credential_id, c.title AS credential_title, l.user_id, l.action, l.ip_address, l.created_at
+         FROM credential_access_log l
+         JOIN credentials c ON c.id = l.credential_id
+         WHERE c.team_id = $1
+         ORDER BY l.created_at DESC, l.id DESC
+         LIMIT $2 OFFSET $3",
+        team_id,
+        limit,
+        offset
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let total = sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"count!\"
+         FROM credential_access_log l
+         JOIN credentials c ON c.id = l.credential_id
+         WHERE c.team_id = $1",
+        team_id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(AccessLogPage { entries, total, limit, offset }))
+}
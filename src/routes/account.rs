@@ -0,0 +1,623 @@
+use std::collections::HashSet;
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::{http::Status, State};
+use rocket::serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::crypto::constant_time_eq;
+use crate::email::{send_in_background, EmailMessage, SharedEmailer};
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::models::{AuthScheme, TeamRole, User};
+use crate::tokens::{generate_opaque_token, hash_token};
+use crate::validation::{is_valid_name, Validator, MAX_NAME_LEN};
+use super::auth::{check_key_material, deserialize_base64, opaque_account, serialize_base64, PUBLIC_KEY_LEN};
+use super::two_factor::verify_totp_code;
+
+// --- Request DTOs ---
+
+/// Represents a change to the caller's profile.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateProfileRequest {
+    /// The new display name.
+    pub name: String,
+    /// Whether to rename the personal team to "<name>'s Personal Team" as well.
+    #[serde(default)]
+    pub rename_personal_team: bool,
+}
+
+/// Changes to the caller's preferences. Omitted fields are left as they are.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdatePreferencesRequest {
+    /// Whether to be emailed about logins from devices not seen before.
+    pub notify_new_device_login: Option<bool>,
+}
+
+/// Represents the confirmation required to delete the caller's account.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteAccountRequest {
+    /// The hash of the current password.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_hash: Vec<u8>,
+    /// A current TOTP code; required if the account has TOTP enabled.
+    pub totp_code: Option<String>,
+}
+
+/// Represents a request to change the caller's email address.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailChangeRequest {
+    /// The address to switch to.
+    pub new_email: String,
+    /// The hash of the current password.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_hash: Vec<u8>,
+}
+
+/// Request DTO for confirming an email change with the token sent to the new address.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfirmRequest {
+    pub token: String,
+}
+
+/// Represents a replacement of the caller's key pair.
+///
+/// Every team key the user holds was wrapped against the old public key, so the request
+/// must carry a re-wrapped copy for exactly the teams the user currently belongs to.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RotateKeysRequest {
+    /// The new public key.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub public_key: Vec<u8>,
+    /// The new private key, encrypted with the user's master key.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_private_key`.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+    /// One re-wrapped key per team the user belongs to.
+    pub team_keys: Vec<WrappedTeamKey>,
+}
+
+/// A team's symmetric key, wrapped for the user's new public key.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WrappedTeamKey {
+    pub team_id: Uuid,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_team_key: Vec<u8>,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+// --- Response DTOs ---
+
+/// The authenticated user's profile and key material, used by clients to bootstrap
+/// after login. Binary fields are encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct MeResponse {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// When the profile, keys, or settings last changed. Logins don't count.
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub public_key: Vec<u8>,
+    /// The private key, encrypted with the user's master key.
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+    /// Whether the account logs in with a password hash or through OPAQUE.
+    pub auth_scheme: AuthScheme,
+    /// Whether a second factor (TOTP or WebAuthn) is required at login.
+    pub two_factor_enabled: bool,
+    /// Whether the user may perform admin actions such as generating invites.
+    pub is_admin: bool,
+    /// When the user proved they own `email`, if they have yet.
+    pub email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Whether an admin requires a password change before anything else can be done.
+    pub must_change_password: bool,
+    /// Whether the account was recovered with its recovery key and must store a new one
+    /// before anything but `/auth/recovery-key`, `GET /auth/me`, and `/auth/logout` will work.
+    pub recovery_key_required: bool,
+    /// When and from which IP the user last logged in, if ever.
+    pub last_login_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_login_ip: Option<String>,
+    /// Whether the user is emailed about logins from devices not seen before.
+    pub notify_new_device_login: bool,
+    /// Every team the user belongs to, with their role in it.
+    pub teams: Vec<TeamMembershipResponse>,
+}
+
+/// A team the user belongs to.
+#[derive(Serialize, Deserialize)]
+pub struct TeamMembershipResponse {
+    pub team_id: Uuid,
+    pub role: TeamRole,
+    /// When the membership, such as the role, last changed.
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+// --- Helpers ---
+
+/// A user row together with everything `/auth/me` needs, fetched in one query.
+#[derive(FromRow)]
+struct MeRow {
+    #[sqlx(flatten)]
+    user: User,
+    has_webauthn: bool,
+    teams: sqlx::types::Json<Vec<TeamMembershipResponse>>,
+}
+
+/// Checks whether an email address already belongs to an account.
+async fn email_in_use(conn: &mut sqlx::PgConnection, email: &str) -> Result<bool, Status> {
+    let taken = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)", email)
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(taken.unwrap_or(false))
+}
+
+/// The error returned when an email address already belongs to another account.
+fn email_taken() -> ApiError {
+    ApiError::new(Status::Conflict, "email_taken").with("field", "new_email")
+}
+
+/// Loads everything `/auth/me` returns for the caller in a single query, leaving out
+/// teams the caller's token isn't scoped to.
+pub(super) async fn fetch_me(conn: &mut sqlx::PgConnection, user: &AuthenticatedUser) -> Result<MeResponse, Status> {
+    let row: MeRow = sqlx::query_as(
+        "SELECT u.id, u.email, u.name, u.auth_scheme, u.password_hash, u.opaque_registration, u.password_salt,
+                u.kdf_memory_kib, u.kdf_iterations, u.kdf_parallelism, u.public_key,
+                u.encrypted_private_key, u.private_key_nonce, u.totp_enabled, u.is_admin,
+                u.email_verified_at, u.suspended_at, u.must_change_password,
+                u.recovery_key_required, u.last_login_at, u.last_login_ip, u.notify_new_device_login, u.created_at,
+                u.updated_at, EXISTS(SELECT 1 FROM webauthn_credentials w WHERE w.user_id = u.id) AS has_webauthn,
+                COALESCE(
+                    (SELECT json_agg(json_build_object('team_id', m.team_id, 'role', m.role, 'updated_at', m.updated_at) ORDER BY m.team_id)
+                     FROM team_members m WHERE m.user_id = u.id),
+                    '[]'
+                ) AS teams
+         FROM users u WHERE u.id = $1",
+    )
+        .bind(user.id)
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(MeResponse {
+        id: row.user.id,
+        email: row.user.email,
+        name: row.user.name,
+        created_at: row.user.created_at,
+        updated_at: row.user.updated_at,
+        public_key: row.user.public_key,
+        encrypted_private_key: row.user.encrypted_private_key,
+        private_key_nonce: row.user.private_key_nonce,
+        auth_scheme: row.user.auth_scheme,
+        two_factor_enabled: row.user.totp_enabled || row.has_webauthn,
+        is_admin: row.user.is_admin,
+        email_verified_at: row.user.email_verified_at,
+        must_change_password: row.user.must_change_password,
+        recovery_key_required: row.user.recovery_key_required,
+        last_login_at: row.user.last_login_at,
+        last_login_ip: row.user.last_login_ip,
+        notify_new_device_login: row.user.notify_new_device_login,
+        teams: row.teams.0.into_iter().filter(|team| user.permissions.can_read_team(team.team_id)).collect(),
+    })
+}
+
+/// Trims a display name and checks it is 1–100 characters long.
+fn validate_name(name: &str) -> Result<&str, ApiError> {
+    if !is_valid_name(name) {
+        return Err(ApiError::new(Status::UnprocessableEntity, "validation_failed")
+            .with("field", "name")
+            .with("message", format!("must be between 1 and {} characters", MAX_NAME_LEN)));
+    }
+    Ok(name.trim())
+}
+
+// --- Routes ---
+
+/// Returns the caller's profile, key material, 2FA status, and team memberships.
+///
+/// Everything is fetched in a single query, with memberships aggregated in the database.
+/// A token limited to some teams only lists those.
+#[get("/me")]
+pub async fn me(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<MeResponse>, Status> {
+    Ok(Json(fetch_me(&mut db, &user).await?))
+}
+
+/// Updates the caller's display name and returns the updated profile.
+///
+/// The name is trimmed and must be 1–100 characters. With `rename_personal_team`, the
+/// personal team is renamed to match, which a token limited to some teams can't do.
+/// Returns `422 Unprocessable Entity` naming the offending field if validation fails.
+#[patch("/me", data = "<update_data>")]
+pub async fn update_me(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    update_data: JsonBody<UpdateProfileRequest>,
+) -> Result<Json<MeResponse>, ApiError> {
+    let name = validate_name(&update_data.name)?;
+    if update_data.rename_personal_team && !user.permissions.covers_all_teams() {
+        return Err(insufficient_scope());
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!("UPDATE users SET name = $1 WHERE id = $2", name, user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if update_data.rename_personal_team {
+        sqlx::query!(
+            "UPDATE teams SET name = $1
+             WHERE is_personal = true
+               AND id IN (SELECT team_id FROM team_members WHERE user_id = $2)",
+            format!("{}'s Personal Team", name),
+            user.id
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(fetch_me(&mut db, &user).await?))
+}
+
+/// Updates the caller's preferences and returns the updated profile.
+#[patch("/me/preferences", data = "<preferences_data>")]
+pub async fn update_preferences(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    preferences_data: JsonBody<UpdatePreferencesRequest>,
+) -> Result<Json<MeResponse>, Status> {
+    sqlx::query!(
+        "UPDATE users SET notify_new_device_login = COALESCE($1, notify_new_device_login) WHERE id = $2",
+        preferences_data.notify_new_device_login,
+        user.id
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(fetch_me(&mut db, &user).await?))
+}
+
+/// Replaces the caller's key pair and every team key wrapped for it.
+///
+/// The submitted `team_keys` must cover exactly the teams the user belongs to; the
+/// user's keys and all `team_key_access` rows are then replaced in a single transaction.
+/// The recovery key, which only unlocks the old private key, is deleted; generate a new
+/// one afterwards.
+///
+/// Returns `204 No Content` on success, `403 Forbidden` for a token limited to some
+/// teams, or `422 Unprocessable Entity` listing invalid fields, or listing
+/// `missing_team_ids` (and any `unexpected_team_ids`) if the set doesn't match.
+#[post("/rotate-keys", data = "<rotate_data>")]
+pub async fn rotate_keys(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    rotate_data: JsonBody<RotateKeysRequest>,
+) -> Result<Status, ApiError> {
+    if !user.permissions.covers_all_teams() {
+        return Err(insufficient_scope());
+    }
+
+    let mut v = Validator::new();
+    v.check_len("public_key", &rotate_data.public_key, &[PUBLIC_KEY_LEN]);
+    check_key_material(&mut v, None, &rotate_data.encrypted_private_key, &rotate_data.private_key_nonce);
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Lock the memberships so the team set can't change between the check and the update.
+    let current: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT team_id FROM team_members WHERE user_id = $1 FOR UPDATE",
+        user.id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .collect();
+
+    let mut submitted = HashSet::new();
+    let mut unexpected = Vec::new();
+    for entry in &rotate_data.team_keys {
+        if !current.contains(&entry.team_id) || !submitted.insert(entry.team_id) {
+            unexpected.push(entry.team_id);
+        }
+    }
+    let mut missing: Vec<Uuid> = current.difference(&submitted).copied().collect();
+    missing.sort();
+
+    if !missing.is_empty() || !unexpected.is_empty() {
+        return Err(ApiError::new(Status::UnprocessableEntity, "team_keys_mismatch")
+            .with("field", "team_keys")
+            .with("missing_team_ids", missing)
+            .with("unexpected_team_ids", unexpected));
+    }
+
+    sqlx::query!(
+        "UPDATE users SET public_key = $1, encrypted_private_key = $2, private_key_nonce = $3
+         WHERE id = $4",
+        rotate_data.public_key,
+        rotate_data.encrypted_private_key,
+        rotate_data.private_key_nonce,
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let team_ids: Vec<Uuid> = rotate_data.team_keys.iter().map(|k| k.team_id).collect();
+    let keys: Vec<Vec<u8>> = rotate_data.team_keys.iter().map(|k| k.encrypted_team_key.clone()).collect();
+    let nonces: Vec<Vec<u8>> = rotate_data.team_keys.iter().map(|k| k.nonce.clone()).collect();
+    sqlx::query!(
+        "INSERT INTO team_key_access (team_id, user_id, encrypted_team_key, nonce)
+         SELECT t.team_id, $1, t.encrypted_team_key, t.nonce
+         FROM UNNEST($2::uuid[], $3::bytea[], $4::bytea[]) AS t(team_id, encrypted_team_key, nonce)
+         ON CONFLICT (team_id, user_id)
+         DO UPDATE SET encrypted_team_key = EXCLUDED.encrypted_team_key, nonce = EXCLUDED.nonce",
+        user.id,
+        &team_ids,
+        &keys,
+        &nonces
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!("DELETE FROM recovery_keys WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Permanently deletes the caller's account.
+///
+/// Requires the current password hash and, if TOTP is enabled, a current code.
+/// Everything attributable solely to the user is removed in a single transaction:
+/// their personal team (and any other team they are the only member of) together with
+/// its credentials, their `team_key_access` rows, team memberships, sessions, and
+/// refresh tokens. Most of this happens through `ON DELETE CASCADE` on the `users` row.
+///
+/// If the user is the only admin of a non-personal team that still has other members,
+/// the request fails with `409 Conflict` and lists the blocking `team_ids`, so ownership
+/// can be transferred first.
+///
+/// Returns `204 No Content` on success or `403 Forbidden` if the password hash or TOTP
+/// code is wrong. Accounts using OPAQUE have no hash to check and get `409 Conflict` with
+/// `{"error": "opaque_account"}`.
+#[delete("/account", data = "<delete_data>")]
+pub async fn delete_account(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    user: AuthenticatedUser,
+    delete_data: JsonBody<DeleteAccountRequest>,
+) -> Result<Status, ApiError> {
+    let account = sqlx::query!(
+        "SELECT password_hash, totp_enabled FROM users WHERE id = $1",
+        user.id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let Some(password_hash) = account.password_hash else {
+        return Err(opaque_account());
+    };
+    if !constant_time_eq(&password_hash, &delete_data.password_hash) {
+        return Err(ApiError::new(Status::Forbidden, "invalid_password"));
+    }
+
+    if account.totp_enabled {
+        let code = delete_data.totp_code.as_deref().unwrap_or_default();
+        if !verify_totp_code(db.as_mut(), config, user.id, code).await? {
+            return Err(ApiError::new(Status::Forbidden, "invalid_totp_code"));
+        }
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Shared teams that would be left without any admin.
+    let blocking: Vec<Uuid> = sqlx::query_scalar!(
+        "SELECT t.id FROM teams t
+         JOIN team_members m ON m.team_id = t.id AND m.user_id = $1 AND m.role = 'admin'
+         WHERE t.is_personal IS NOT TRUE
+           AND EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = t.id AND o.user_id <> $1)
+           AND NOT EXISTS (
+               SELECT 1 FROM team_members o WHERE o.team_id = t.id AND o.user_id <> $1 AND o.role = 'admin'
+           )
+         ORDER BY t.id",
+        user.id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !blocking.is_empty() {
+        return Err(ApiError::new(Status::Conflict, "sole_admin_of_shared_team").with("team_ids", blocking));
+    }
+
+    // Teams nobody else can access anymore go too; their credentials cascade.
+    sqlx::query!(
+        "DELETE FROM teams t
+         WHERE t.id IN (SELECT team_id FROM team_members WHERE user_id = $1)
+           AND (t.is_personal = true
+                OR NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = t.id AND o.user_id <> $1))",
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Memberships, key access, sessions, and refresh tokens cascade from the user row.
+    sqlx::query!("DELETE FROM users WHERE id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Requests a change of the caller's email address.
+///
+/// Verifies the current password hash and that the new address is free, then stores
+/// the pending change and emails a confirmation token to the new address. Only one
+/// change can be in flight: a newer request replaces the previous one. Until the change
+/// is confirmed, the old address keeps working for login.
+///
+/// Returns `202 Accepted` on success, `403 Forbidden` if the password hash is wrong,
+/// or `409 Conflict` if the new address is already in use or, with
+/// `{"error": "opaque_account"}`, if the account uses OPAQUE. Its registration is
+/// bound to the email address, so the address can't change.
+#[post("/email/change-request", data = "<change_data>")]
+pub async fn request_email_change(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    emailer: &State<SharedEmailer>,
+    user: AuthenticatedUser,
+    change_data: JsonBody<EmailChangeRequest>,
+) -> Result<Status, ApiError> {
+    let current_hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", user.id)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or_else(opaque_account)?;
+
+    if !constant_time_eq(&current_hash, &change_data.password_hash) {
+        return Err(ApiError::new(Status::Forbidden, "invalid_password"));
+    }
+
+    if email_in_use(db.as_mut(), &change_data.new_email).await? {
+        return Err(email_taken());
+    }
+
+    let token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.email_change_ttl_secs);
+
+    sqlx::query!(
+        "INSERT INTO email_change_requests (user_id, new_email, token_hash, expires_at) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (user_id) DO UPDATE
+         SET new_email = EXCLUDED.new_email, token_hash = EXCLUDED.token_hash,
+             expires_at = EXCLUDED.expires_at, created_at = NOW()",
+        user.id,
+        change_data.new_email,
+        hash_token(&token),
+        expires_at
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    send_in_background(emailer, EmailMessage {
+        to: change_data.new_email.clone(),
+        subject: "Confirm your new HomeDesk email address".to_string(),
+        body: format!(
+            "Someone asked to change the email address of a HomeDesk account to this one.\n\n\
+             Your confirmation token is: {}\n\n\
+             If you didn't request this, you can ignore this email.",
+            token
+        ),
+    });
+
+    Ok(Status::Accepted)
+}
+
+/// Applies a pending email change using the token sent to the new address.
+///
+/// The new address is checked for uniqueness again, since it may have been taken
+/// since the request was made. Any outstanding password reset tokens, which were sent
+/// to the old address, are invalidated.
+///
+/// Returns `204 No Content` on success, `403 Forbidden` if the token is invalid or
+/// expired, or `409 Conflict` if the new address is now in use.
+#[post("/email/confirm", data = "<confirm_data>")]
+pub async fn confirm_email_change(
+    mut db: Connection<DatabasePool>,
+    confirm_data: JsonBody<EmailConfirmRequest>,
+) -> Result<Status, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let pending = sqlx::query!(
+        "DELETE FROM email_change_requests WHERE token_hash = $1 AND expires_at > NOW()
+         RETURNING user_id, new_email",
+        hash_token(&confirm_data.token)
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Forbidden)?;
+
+    if email_in_use(&mut tx, &pending.new_email).await? {
+        return Err(email_taken());
+    }
+
+    // The unique constraint still guards against a signup racing this update.
+    // Confirming the change proves the user owns the new address.
+    sqlx::query!(
+        "UPDATE users SET email = $1, email_verified_at = NOW() WHERE id = $2",
+        pending.new_email,
+        pending.user_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e.as_database_error() {
+            Some(db_err) if db_err.is_unique_violation() => email_taken(),
+            _ => Status::InternalServerError.into(),
+        })?;
+
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL",
+        pending.user_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,388 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use rocket::State;
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::guards::AdminUser;
+use crate::sessions::revoke_user_sessions;
+use crate::trash::purge_trash;
+use super::account::TeamMembershipResponse;
+
+// --- Response DTOs ---
+
+/// A user account as seen by a server admin.
+///
+/// Deliberately limited to profile and status columns: password hashes and key
+/// material never leave the server through the admin API.
+#[derive(Serialize, FromRow)]
+pub struct AdminUserResponse {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    /// Whether the user is a server admin.
+    pub is_admin: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When and from which IP the user last logged in, if ever.
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub last_login_ip: Option<String>,
+    /// Whether a second factor (TOTP or WebAuthn) is required at login.
+    pub two_factor_enabled: bool,
+    /// When an admin suspended the account, if it is suspended.
+    pub suspended_at: Option<DateTime<Utc>>,
+    /// Whether the user has to change their password before using the API again.
+    pub must_change_password: bool,
+}
+
+/// A user account with its team memberships.
+#[derive(Serialize)]
+pub struct AdminUserDetail {
+    #[serde(flatten)]
+    pub user: AdminUserResponse,
+    pub teams: Vec<TeamMembershipResponse>,
+}
+
+/// One page of users.
+#[derive(Serialize)]
+pub struct UserPage {
+    pub users: Vec<AdminUserResponse>,
+    /// The total number of users matching the search across all pages.
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// The outcome of a trash purge.
+#[derive(Serialize)]
+pub struct PurgeTrashResponse {
+    /// How many credentials were permanently deleted.
+    pub purged: u64,
+}
+
+/// Users returned per page when no `limit` is given.
+const DEFAULT_PAGE_SIZE: i64 = 50;
+/// The largest `limit` accepted.
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// Selects the columns of [`AdminUserResponse`] from `users u`.
+const USER_SELECT: &str =
+    "SELECT u.id, u.email, u.name, u.is_admin, u.created_at, u.updated_at, u.suspended_at, u.must_change_password,
+            u.last_login_at, u.last_login_ip,
+            u.totp_enabled OR EXISTS(SELECT 1 FROM webauthn_credentials w WHERE w.user_id = u.id)
+                AS two_factor_enabled
+     FROM users u";
+
+// --- Helpers ---
+
+/// Fetches a user for the admin API, or `None` if there is no user with that id.
+async fn fetch_user(conn: &mut sqlx::PgConnection, id: Uuid) -> Result<Option<AdminUserResponse>, sqlx::Error> {
+    sqlx::query_as(&format!("{} WHERE u.id = $1", USER_SELECT))
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Maps a `sort` parameter to its `ORDER BY` clause. A leading `-` sorts descending.
+fn order_by(sort: &str) -> Option<&'static str> {
+    match sort {
+        "created_at" => Some("u.created_at, u.id"),
+        "-created_at" => Some("u.created_at DESC, u.id"),
+        "email" => Some("u.email, u.id"),
+        "-email" => Some("u.email DESC, u.id"),
+        _ => None,
+    }
+}
+
+/// Locks the rows of every admin who isn't suspended and returns their ids.
+///
+/// Anything that takes an admin out of action checks this first, so two admins can't
+/// concurrently demote or suspend each other and leave the server without one.
+async fn lock_active_admins(conn: &mut sqlx::PgConnection) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT id FROM users WHERE is_admin AND suspended_at IS NULL FOR UPDATE")
+        .fetch_all(conn)
+        .await
+}
+
+/// Escapes `LIKE` wildcards so a search term matches literally.
+pub(super) fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// --- Routes ---
+
+/// Lists user accounts, newest first by default.
+///
+/// `q` filters to users whose email or name contains it, case-insensitively, and
+/// `inactive_days` to users who haven't logged in for that many days (counting from
+/// signup for those who never have). `sort` is
+/// one of `created_at`, `email`, or either prefixed with `-` for descending order
+/// (default `-created_at`). Paginated with `limit` (default 50, at most 200) and `offset`.
+/// Returns `422 Unprocessable Entity` for any other `sort`.
+#[get("/users?<q>&<inactive_days>&<sort>&<limit>&<offset>")]
+pub async fn list_users(
+    mut db: Connection<DatabasePool>,
+    _admin: AdminUser,
+    q: Option<&str>,
+    inactive_days: Option<u32>,
+    sort: Option<&str>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Json<UserPage>, ApiError> {
+    let order = order_by(sort.unwrap_or("-created_at")).ok_or_else(|| {
+        ApiError::new(Status::UnprocessableEntity, "validation_failed")
+            .with("field", "sort")
+            .with("message", "must be one of created_at, -created_at, email, -email")
+    })?;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = offset.unwrap_or(0).max(0);
+    let pattern = format!("%{}%", escape_like(q.unwrap_or("").trim()));
+
+    let inactive_days = inactive_days.map(|days| days.min(i32::MAX as u32) as i32);
+
+    let filter = "WHERE (u.email ILIKE $1 OR u.name ILIKE $1)
+                    AND ($2::int IS NULL
+                         OR COALESCE(u.last_login_at, u.created_at) < NOW() - make_interval(days => $2))";
+    let users: Vec<AdminUserResponse> = sqlx::query_as(&format!(
+        "{} {} ORDER BY {} LIMIT $3 OFFSET $4",
+        USER_SELECT, filter, order
+    ))
+        .bind(&pattern)
+        .bind(inactive_days)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM users u {}", filter))
+        .bind(&pattern)
+        .bind(inactive_days)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(UserPage { users, total, limit, offset }))
+}
+
+/// Returns a user account together with its team memberships, or `404 Not Found` if
+/// there is no user with that id.
+#[get("/users/<id>")]
+pub async fn get_user(
+    mut db: Connection<DatabasePool>,
+    _admin: AdminUser,
+    id: Uuid,
+) -> Result<Json<AdminUserDetail>, ApiError> {
+    let user = fetch_user(&mut db, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let teams = sqlx::query_as!(
+        TeamMembershipResponse,
+        "SELECT team_id, role AS \"role: _\", updated_at FROM team_members WHERE user_id = $1 ORDER BY team_id",
+        id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(AdminUserDetail { user, teams }))
+}
+
+/// Makes a user a server admin.
+///
+/// Promoting an existing admin is a no-op. Returns the updated user, or `404 Not Found`
+/// if there is no user with that id.
+#[post("/users/<id>/promote")]
+pub async fn promote_user(
+    mut db: Connection<DatabasePool>,
+    _admin: AdminUser,
+    id: Uuid,
+) -> Result<Json<AdminUserResponse>, ApiError> {
+    sqlx::query_scalar!("UPDATE users SET is_admin = true WHERE id = $1 RETURNING id", id)
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let user = fetch_user(&mut db, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(user))
+}
+
+/// Takes server admin rights away from a user, who may be the caller.
+///
+/// The last admin who isn't suspended can't be demoted, so the server is never left
+/// without one; that fails with `409 Conflict` and `{"error": "last_admin"}`. Returns the
+/// updated user, or `404 Not Found` if there is no user with that id.
+#[post("/users/<id>/demote")]
+pub async fn demote_user(
+    mut db: Connection<DatabasePool>,
+    _admin: AdminUser,
+    id: Uuid,
+) -> Result<Json<AdminUserResponse>, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let admins = lock_active_admins(&mut tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if admins == [id] {
+        return Err(ApiError::new(Status::Conflict, "last_admin"));
+    }
+
+    sqlx::query_scalar!("UPDATE users SET is_admin = false WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let user = fetch_user(&mut tx, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(user))
+}
+
+/// Suspends a user account, locking the user out without deleting anything.
+///
+/// Every session and refresh token of the user is revoked, so access tokens stop working
+/// at once, and login fails with `403 Forbidden` and `{"error": "account_suspended"}`
+/// until the account is unsuspended. Suspending a suspended account changes nothing.
+///
+/// Fails with `409 Conflict` and `{"error": "cannot_suspend_self"}` for the caller's own
+/// account, or `{"error": "last_admin"}` for the last admin who isn't suspended. Returns
+/// the updated user, or `404 Not Found` if there is no user with that id.
+#[post("/users/<id>/suspend")]
+pub async fn suspend_user(
+    mut db: Connection<DatabasePool>,
+    admin: AdminUser,
+    id: Uuid,
+) -> Result<Json<AdminUserResponse>, ApiError> {
+    if id == admin.id {
+        return Err(ApiError::new(Status::Conflict, "cannot_suspend_self"));
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let admins = lock_active_admins(&mut tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if admins == [id] {
+        return Err(ApiError::new(Status::Conflict, "last_admin"));
+    }
+
+    sqlx::query_scalar!(
+        "UPDATE users SET suspended_at = COALESCE(suspended_at, NOW()) WHERE id = $1 RETURNING id",
+        id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    revoke_user_sessions(&mut tx, id, None)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let user = fetch_user(&mut tx, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    info!("Admin {} suspended user {}", admin.id, id);
+
+    Ok(Json(user))
+}
+
+/// Lifts a suspension, letting the user log in again with everything as it was.
+///
+/// Sessions revoked at suspension stay revoked, so the user has to log in afresh.
+/// Unsuspending an account that isn't suspended changes nothing. Returns the updated
+/// user, or `404 Not Found` if there is no user with that id.
+#[post("/users/<id>/unsuspend")]
+pub async fn unsuspend_user(
+    mut db: Connection<DatabasePool>,
+    admin: AdminUser,
+    id: Uuid,
+) -> Result<Json<AdminUserResponse>, ApiError> {
+    sqlx::query_scalar!("UPDATE users SET suspended_at = NULL WHERE id = $1 RETURNING id", id)
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let user = fetch_user(&mut db, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    info!("Admin {} unsuspended user {}", admin.id, id);
+
+    Ok(Json(user))
+}
+
+/// Requires a user to change their password, e.g. after a suspected compromise.
+///
+/// The user can still log in, but until they complete `/auth/change-password` their
+/// tokens only work for that, `GET /auth/me`, and `/auth/logout`. Changing the password
+/// clears the flag and revokes the user's other sessions. Returns the updated user, or
+/// `404 Not Found` if there is no user with that id.
+#[post("/users/<id>/require-password-change")]
+pub async fn require_password_change(
+    mut db: Connection<DatabasePool>,
+    admin: AdminUser,
+    id: Uuid,
+) -> Result<Json<AdminUserResponse>, ApiError> {
+    sqlx::query_scalar!("UPDATE users SET must_change_password = true WHERE id = $1 RETURNING id", id)
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let user = fetch_user(&mut db, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    info!("Admin {} required user {} to change their password", admin.id, id);
+
+    Ok(Json(user))
+}
+
+/// Runs the trash purge now instead of waiting for the background task: permanently
+/// deletes every credential that has been in the trash for longer than
+/// `trash.retention_days`. Runs even when `trash.auto_purge` is disabled.
+#[post("/maintenance/purge-trash")]
+pub async fn purge_trash_now(
+    mut db: Connection<DatabasePool>,
+    admin: AdminUser,
+    config: &State<AppConfig>,
+) -> Result<Json<PurgeTrashResponse>, ApiError> {
+    let purged = purge_trash(db.as_mut(), config.trash.retention_days)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    info!("Admin {} purged {} credentials from the trash", admin.id, purged);
+
+    Ok(Json(PurgeTrashResponse { purged }))
+}
@@ -0,0 +1,165 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::error::ApiError;
+use crate::guards::AuthenticatedUser;
+use crate::models::TokenAccess;
+use crate::tokens::{generate_api_token, hash_token};
+use crate::validation::{is_valid_name, Validator, MAX_NAME_LEN};
+
+// --- Request DTOs ---
+
+/// The settings for a new personal access token.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateApiTokenRequest {
+    /// A label to recognise the token by, e.g. "nightly backup".
+    pub name: String,
+    /// When the token stops working, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the token may make changes (`read_write`, the default) or only read (`read`).
+    #[serde(default = "default_access")]
+    pub access: TokenAccess,
+    /// The teams the token may see and act on. Omit for every team the user belongs to.
+    pub team_ids: Option<Vec<Uuid>>,
+}
+
+fn default_access() -> TokenAccess {
+    TokenAccess::ReadWrite
+}
+
+// --- Response DTOs ---
+
+/// A personal access token's metadata. The token itself is only shown once, at creation.
+#[derive(Serialize)]
+pub struct ApiTokenResponse {
+    pub id: Uuid,
+    pub name: String,
+    /// The part of the token after `hd_pat_` that identifies it, for telling tokens apart.
+    pub lookup_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub access: TokenAccess,
+    /// The teams the token is limited to, or `null` if it isn't.
+    pub team_ids: Option<Vec<Uuid>>,
+}
+
+/// A newly created personal access token.
+#[derive(Serialize)]
+pub struct CreatedApiTokenResponse {
+    #[serde(flatten)]
+    pub metadata: ApiTokenResponse,
+    /// The token to send as `Authorization: Bearer <token>`. It can't be retrieved again.
+    pub token: String,
+}
+
+// --- Routes ---
+
+/// Creates a personal access token for scripts and CI, which authenticates as the caller
+/// without a password.
+///
+/// Only a logged-in session can create tokens, so a leaked token can't be used to mint
+/// more; requests made with a token fail with `403 Forbidden` and
+/// `{"error": "session_required"}`. Returns `201 Created` with the plaintext token,
+/// which is never shown again, or `422 Unprocessable Entity` for an invalid name, an
+/// `expires_at` in the past, or `team_ids` that is empty or names a team the caller
+/// doesn't belong to.
+#[post("/tokens", data = "<token_data>")]
+pub async fn create_api_token(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    token_data: JsonBody<CreateApiTokenRequest>,
+) -> Result<(Status, Json<CreatedApiTokenResponse>), ApiError> {
+    if user.session_id.is_none() {
+        return Err(ApiError::new(Status::Forbidden, "session_required"));
+    }
+
+    let mut team_ids = token_data.team_ids.clone();
+    if let Some(ids) = &mut team_ids {
+        ids.sort();
+        ids.dedup();
+    }
+
+    let member_of = sqlx::query_scalar!("SELECT team_id FROM team_members WHERE user_id = $1", user.id)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut v = Validator::new();
+    v.check("name", is_valid_name(&token_data.name), format!("must be between 1 and {} characters", MAX_NAME_LEN))
+        .check("expires_at", token_data.expires_at.is_none_or(|at| at > Utc::now()), "must be in the future")
+        .check("team_ids", team_ids.as_ref().is_none_or(|ids| !ids.is_empty()), "must not be empty")
+        .check(
+            "team_ids",
+            team_ids.iter().flatten().all(|id| member_of.contains(id)),
+            "must only name teams you belong to",
+        );
+    v.finish()?;
+
+    let (lookup_id, token) = generate_api_token();
+
+    let metadata = sqlx::query_as!(
+        ApiTokenResponse,
+        "INSERT INTO api_tokens (user_id, name, lookup_id, token_hash, expires_at, access, team_ids)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         RETURNING id, name, lookup_id, created_at, last_used_at, expires_at,
+                   access AS \"access: TokenAccess\", team_ids",
+        user.id,
+        token_data.name.trim(),
+        lookup_id,
+        hash_token(&token),
+        token_data.expires_at,
+        token_data.access as TokenAccess,
+        team_ids.as_deref()
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(ApiError::from_db)?;
+
+    Ok((Status::Created, Json(CreatedApiTokenResponse { metadata, token })))
+}
+
+/// Lists the caller's personal access tokens, newest first, without the tokens themselves.
+#[get("/tokens")]
+pub async fn list_api_tokens(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiTokenResponse>>, Status> {
+    let tokens = sqlx::query_as!(
+        ApiTokenResponse,
+        "SELECT id, name, lookup_id, created_at, last_used_at, expires_at,
+                access AS \"access: TokenAccess\", team_ids
+         FROM api_tokens WHERE user_id = $1
+         ORDER BY created_at DESC",
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(tokens))
+}
+
+/// Deletes one of the caller's personal access tokens, which stops working immediately.
+///
+/// Returns `204 No Content`, or `404 Not Found` if the caller has no token with that id.
+#[delete("/tokens/<id>")]
+pub async fn delete_api_token(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, Status> {
+    let deleted = sqlx::query!("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2", id, user.id)
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected();
+
+    if deleted > 0 { Ok(Status::NoContent) } else { Err(Status::NotFound) }
+}
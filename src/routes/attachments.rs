@@ -0,0 +1,542 @@
+use base64::Engine;
+use rocket_db_pools::{sqlx, Connection};
+use rocket::data::{Data, ToByteUnit};
+use rocket::futures::StreamExt;
+use rocket::futures::stream::Stream;
+use rocket::http::{ContentType, Header, RawStr, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::response::stream::ByteStream;
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::State;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::models::TeamRole;
+use crate::validation::Validator;
+use super::auth::serialize_base64;
+use super::credentials::{can_edit_credentials, member_credential_team, not_found_unless_shared, restricted_credential, NONCE_LEN};
+
+/// The header carrying an attachment's key, wrapped with the team key, as standard Base64.
+const ENCRYPTED_KEY_HEADER: &str = "X-Encrypted-Key";
+/// The header carrying the nonce the attachment key was wrapped with, as standard Base64.
+const KEY_NONCE_HEADER: &str = "X-Key-Nonce";
+/// The size of the chunks attachments are stored in.
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// The longest file name accepted, in characters.
+const MAX_FILENAME_LEN: usize = 255;
+/// The largest wrapped attachment key accepted, in bytes.
+const MAX_KEY_LEN: usize = 1024;
+
+// --- Response DTOs ---
+
+/// A file attached to a credential, without its content. Binary fields are encoded as
+/// Base64 in JSON.
+#[derive(Serialize)]
+pub struct AttachmentResponse {
+    pub id: Uuid,
+    pub filename: String,
+    /// The size of the encrypted content, in bytes.
+    pub size: i64,
+    /// The key the content is encrypted with, wrapped with the team key.
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_key: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub key_nonce: Vec<u8>,
+    /// Who attached the file, unless their account has been deleted since.
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The encrypted content of an attachment, or the part of it asked for with a `Range`
+/// header, streamed as it is read from the database.
+pub struct AttachmentContent<S> {
+    stream: ByteStream<S>,
+    filename: String,
+    /// The number of bytes in the body.
+    length: u64,
+    /// The first and last byte sent and the full size, for a partial response.
+    range: Option<(u64, u64, u64)>,
+}
+
+impl<'r, S> Responder<'r, 'r> for AttachmentContent<S>
+where
+    S: Stream<Item = Vec<u8>> + Send + 'r,
+{
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        let mut response = self.stream.respond_to(request)?;
+        response.set_header(ContentType::Binary);
+        response.set_header(Header::new("Accept-Ranges", "bytes"));
+        // The stream is unsized to Rocket, but its length is known up front.
+        response.set_header(Header::new("Content-Length", self.length.to_string()));
+        response.set_header(Header::new(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+                ascii_filename(&self.filename),
+                RawStr::new(&self.filename).percent_encode(),
+            ),
+        ));
+        if let Some((first, last, size)) = self.range {
+            response.set_status(Status::PartialContent);
+            response.set_header(Header::new("Content-Range", format!("bytes {}-{}/{}", first, last, size)));
+        }
+        Ok(response)
+    }
+}
+
+// --- Helpers ---
+
+/// The wrapped attachment key and its nonce sent in [`ENCRYPTED_KEY_HEADER`] and
+/// [`KEY_NONCE_HEADER`], each `None` if the header is missing or isn't Base64.
+pub struct AttachmentKeyHeaders {
+    encrypted_key: Option<Vec<u8>>,
+    key_nonce: Option<Vec<u8>>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AttachmentKeyHeaders {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let decode = |name| {
+            request
+                .headers()
+                .get_one(name)
+                .and_then(|value| base64::engine::general_purpose::STANDARD.decode(value).ok())
+        };
+        request::Outcome::Success(AttachmentKeyHeaders {
+            encrypted_key: decode(ENCRYPTED_KEY_HEADER),
+            key_nonce: decode(KEY_NONCE_HEADER),
+        })
+    }
+}
+
+/// The byte range asked for with a `Range` header, if any.
+pub struct RangeHeader(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(RangeHeader(request.headers().get_one("Range").map(str::to_string)))
+    }
+}
+
+/// Parses a `Range` header for content of `size` bytes into the first and last byte
+/// asked for. Anything but a single byte range is ignored, as servers may; `Err` means
+/// the range lies wholly past the end.
+fn parse_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    let (first, last) = (first.trim(), last.trim());
+
+    let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+        // "bytes=-500" asks for the last 500 bytes.
+        (Err(_), Ok(suffix)) if first.is_empty() => {
+            if suffix == 0 {
+                return Err(());
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        (Ok(first), Err(_)) if last.is_empty() => (first, size.saturating_sub(1)),
+        (Ok(first), Ok(last)) if first <= last => (first, last.min(size.saturating_sub(1))),
+        _ => return Ok(None),
+    };
+
+    if range.0 >= size { Err(()) } else { Ok(Some(range)) }
+}
+
+/// The file name reduced to printable ASCII for the plain `filename` parameter of
+/// `Content-Disposition`; clients that understand `filename*` get the real one.
+fn ascii_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect()
+}
+
+pub(super) fn check_filename(v: &mut Validator, filename: &str) {
+    let len = filename.trim().chars().count();
+    v.check(
+        "filename",
+        (1..=MAX_FILENAME_LEN).contains(&len),
+        format!("must be between 1 and {} characters", MAX_FILENAME_LEN),
+    );
+    v.check(
+        "filename",
+        !filename.chars().any(|c| c.is_control() || c == '/' || c == '\\'),
+        "must not contain control characters or slashes",
+    );
+}
+
+/// Checks an attachment key, wrapped with the team key, and its nonce.
+pub(super) fn check_attachment_key(v: &mut Validator, encrypted_key: &[u8], key_nonce: &[u8]) {
+    v.check_max_len("encrypted_key", encrypted_key, MAX_KEY_LEN)
+        .check_len("key_nonce", key_nonce, &[NONCE_LEN]);
+}
+
+/// Stores a file already held in memory as an attachment of a credential, in chunks as
+/// [`upload_attachment`] does. The caller checks the file and the credential.
+pub(super) async fn insert_attachment(
+    conn: &mut sqlx::PgConnection,
+    credential_id: Uuid,
+    filename: &str,
+    content: &[u8],
+    encrypted_key: &[u8],
+    key_nonce: &[u8],
+    created_by: Uuid,
+) -> Result<Uuid, Status> {
+    let id = sqlx::query_scalar!(
+        "INSERT INTO attachments (credential_id, filename, size, chunk_size, encrypted_key, key_nonce, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         RETURNING id",
+        credential_id,
+        filename.trim(),
+        content.len() as i64,
+        CHUNK_SIZE as i32,
+        encrypted_key,
+        key_nonce,
+        created_by
+    )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    for (seq, chunk) in content.chunks(CHUNK_SIZE).enumerate() {
+        sqlx::query!(
+            "INSERT INTO attachment_chunks (attachment_id, seq, data) VALUES ($1, $2, $3)",
+            id,
+            seq as i32,
+            chunk
+        )
+            .execute(&mut *conn)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    Ok(id)
+}
+
+/// The team of an attachment's credential, the caller's role there, and whether its
+/// access restrictions let them change it, or `None` if there is no such attachment, its
+/// credential is in the trash, or the user isn't a member of its team allowed to read it.
+async fn member_attachment(
+    conn: &mut sqlx::PgConnection,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<(Uuid, TeamRole, bool)>, Status> {
+    let row = sqlx::query!(
+        "SELECT c.team_id, m.role AS \"role: TeamRole\", credential_acl_allows(c.id, $2, TRUE) AS \"writable!\"
+         FROM attachments a
+         JOIN credentials c ON c.id = a.credential_id
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $2
+         WHERE a.id = $1 AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)",
+        id,
+        user_id
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(row.map(|row| (row.team_id, row.role, row.writable)))
+}
+
+// --- Routes ---
+
+/// Attaches a file to a credential of a team the caller belongs to. The body is the
+/// content, encrypted by the client with a key of its own, sent as is; it is stored in
+/// chunks as it arrives. The key, wrapped with the team key, goes in the
+/// `X-Encrypted-Key` header and its nonce in `X-Key-Nonce`, both as Base64.
+///
+/// Returns `201 Created` with the attachment, `404 Not Found` if the credential doesn't
+/// exist or is in the trash, or the caller isn't a member of its team or may not read
+/// it, `403 Forbidden` for a token not scoped to the team, a member whose role may not
+/// edit, or with `{"error": "restricted_credential"}` for a member it is restricted to
+/// reading or `{"error": "shared_credential"}` for a credential shared into one of the
+/// caller's teams, `413 Payload Too Large` with `{"error": "attachment_too_large",
+/// "max_size": ...}` beyond `attachments.max_size_mib`, or `422 Unprocessable Entity`
+/// listing invalid fields.
+#[post("/<id>/attachments?<filename>", data = "<data>")]
+pub async fn upload_attachment(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    id: Uuid,
+    filename: Option<&str>,
+    key: AttachmentKeyHeaders,
+    data: Data<'_>,
+) -> Result<(Status, Json<AttachmentResponse>), ApiError> {
+    let filename = filename.unwrap_or("");
+    let mut v = Validator::new();
+    check_filename(&mut v, filename);
+    match &key.encrypted_key {
+        Some(encrypted_key) => v.check_max_len("encrypted_key", encrypted_key, MAX_KEY_LEN),
+        None => v.check("encrypted_key", false, "must be sent as Base64 in the X-Encrypted-Key header"),
+    };
+    match &key.key_nonce {
+        Some(key_nonce) => v.check_len("key_nonce", key_nonce, &[NONCE_LEN]),
+        None => v.check("key_nonce", false, "must be sent as Base64 in the X-Key-Nonce header"),
+    };
+    v.finish()?;
+    let (Some(encrypted_key), Some(key_nonce)) = (key.encrypted_key, key.key_nonce) else {
+        unreachable!("validated above");
+    };
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // A shared lock keeps the credential from moving teams while its file comes in.
+    let current = sqlx::query!(
+        "SELECT c.team_id, m.role AS \"role: TeamRole\", credential_acl_allows(c.id, $2, TRUE) AS \"writable!\"
+         FROM credentials c
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $2
+         WHERE c.id = $1 AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)
+         FOR SHARE OF c",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let Some(current) = current else {
+        return Err(not_found_unless_shared(&mut tx, id, user.id).await?);
+    };
+
+    if !user.permissions.can_write_team(current.team_id) {
+        return Err(insufficient_scope());
+    }
+    if !can_edit_credentials(&current.role) {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+    if !current.writable {
+        return Err(restricted_credential());
+    }
+
+    let created = sqlx::query!(
+        "INSERT INTO attachments (credential_id, filename, chunk_size, encrypted_key, key_nonce, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id, created_at",
+        id,
+        filename.trim(),
+        CHUNK_SIZE as i32,
+        encrypted_key,
+        key_nonce,
+        user.id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let max_size = u64::from(config.attachments.max_size_mib) * 1024 * 1024;
+    // One byte past the limit tells a body of exactly the limit from a larger one.
+    let mut body = data.open((max_size + 1).bytes());
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    let mut size: u64 = 0;
+    for seq in 0.. {
+        chunk.clear();
+        (&mut body)
+            .take(CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)
+            .await
+            .map_err(|_| Status::BadRequest)?;
+        if chunk.is_empty() {
+            break;
+        }
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(ApiError::new(Status::PayloadTooLarge, "attachment_too_large").with("max_size", max_size));
+        }
+
+        sqlx::query!(
+            "INSERT INTO attachment_chunks (attachment_id, seq, data) VALUES ($1, $2, $3)",
+            created.id,
+            seq,
+            &chunk
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    sqlx::query!("UPDATE attachments SET size = $2 WHERE id = $1", created.id, size as i64)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok((Status::Created, Json(AttachmentResponse {
+        id: created.id,
+        filename: filename.trim().to_string(),
+        size: size as i64,
+        encrypted_key,
+        key_nonce,
+        created_by: Some(user.id),
+        created_at: created.created_at,
+    })))
+}
+
+/// Lists the files attached to a credential of a team the caller belongs to, oldest
+/// first, without their content.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team, or `403 Forbidden` for a token not scoped to the
+/// team.
+#[get("/<id>/attachments")]
+pub async fn list_attachments(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<Vec<AttachmentResponse>>, ApiError> {
+    let team_id = member_credential_team(db.as_mut(), id, user.id)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let attachments = sqlx::query_as!(
+        AttachmentResponse,
+        "SELECT id, filename, size, encrypted_key, key_nonce, created_by, created_at
+         FROM attachments WHERE credential_id = $1
+         ORDER BY created_at, id",
+        id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(attachments))
+}
+
+/// Downloads the encrypted content of a file attached to a credential of a team the
+/// caller belongs to, streamed chunk by chunk as `application/octet-stream`.
+///
+/// A single byte range in a `Range` header, e.g. `bytes=1048576-`, gets `206 Partial
+/// Content` with just those bytes, so interrupted downloads can resume. Returns `404 Not
+/// Found` if there is no such attachment, its credential is in the trash, or the caller
+/// isn't a member of its team, `403 Forbidden` for a token not scoped to the team, or
+/// `416 Range Not Satisfiable` for a range past the end. A database error after
+/// streaming has started cuts the download short of its `Content-Length`.
+#[get("/<id>/content")]
+pub async fn download_attachment(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    range: RangeHeader,
+    id: Uuid,
+) -> Result<AttachmentContent<impl Stream<Item = Vec<u8>>>, ApiError> {
+    let (team_id, _, _) = member_attachment(db.as_mut(), id, user.id)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let attachment = sqlx::query!("SELECT filename, size, chunk_size FROM attachments WHERE id = $1", id)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let size = attachment.size as u64;
+    let range = match range.0.as_deref().map(|range| parse_range(range, size)) {
+        Some(Ok(range)) => range,
+        None => None,
+        Some(Err(())) => {
+            return Err(ApiError::from(Status::RangeNotSatisfiable)
+                .with_header(Header::new("Content-Range", format!("bytes */{}", size))));
+        }
+    };
+    // An empty attachment has no bytes to range over, leaving `last` before `first`.
+    let (first, last) = range.unwrap_or((0, size.saturating_sub(1)));
+    let length = if size == 0 { 0 } else { last - first + 1 };
+
+    let chunk_size = attachment.chunk_size as u64;
+    let first_seq = (first / chunk_size) as i32;
+    let last_seq = (last / chunk_size) as i32;
+
+    let stream = ByteStream! {
+        if length == 0 {
+            return;
+        }
+        let mut chunks = sqlx::query!(
+            "SELECT seq, data FROM attachment_chunks
+             WHERE attachment_id = $1 AND seq BETWEEN $2 AND $3
+             ORDER BY seq",
+            id,
+            first_seq,
+            last_seq
+        )
+            .fetch(db.as_mut());
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    warn!("Failed to stream attachment {}: {}", id, e);
+                    return;
+                }
+            };
+            // Trim the chunks at either end of a range to the bytes asked for.
+            let offset = chunk.seq as u64 * chunk_size;
+            let start = first.saturating_sub(offset) as usize;
+            let end = ((last + 1 - offset) as usize).min(chunk.data.len());
+            yield chunk.data[start..end].to_vec();
+        }
+    };
+
+    Ok(AttachmentContent {
+        stream,
+        filename: attachment.filename,
+        length,
+        range: range.map(|(first, last)| (first, last, size)),
+    })
+}
+
+/// Deletes a file attached to a credential of a team the caller belongs to.
+///
+/// Returns `204 No Content`, `404 Not Found` if there is no such attachment, its
+/// credential is in the trash, or the caller isn't a member of its team or may not read
+/// the credential, or `403 Forbidden` for a token not scoped to the team, a member whose
+/// role may not edit, or with `{"error": "restricted_credential"}` for a member the
+/// credential is restricted to reading.
+#[delete("/<id>")]
+pub async fn delete_attachment(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, ApiError> {
+    let (team_id, role, writable) = member_attachment(db.as_mut(), id, user.id)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+    if !can_edit_credentials(&role) {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+    if !writable {
+        return Err(restricted_credential());
+    }
+
+    sqlx::query!("DELETE FROM attachments WHERE id = $1", id)
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
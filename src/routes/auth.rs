@@ -1,12 +1,52 @@
-use rand::Rng;
-use argon2::password_hash::rand_core::SeedableRng;
 use rocket_db_pools::{sqlx, Connection};
 use rocket::serde::json::Json;
-use rocket::{post, http::Status};
-use rocket::serde::{Deserialize, Deserializer};
+use rocket::{post, http::Status, State};
+use rocket::http::{CookieJar, Header};
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::{Deserialize, Deserializer, Serialize, Serializer};
 use base64::{Engine};
 use uuid::Uuid;
 use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::crypto::{constant_time_eq, fake_salt, SaltSecret};
+use crate::email::{send_in_background, EmailMessage, SharedEmailer};
+use crate::error::ApiError;
+use crate::guards::{AuthenticatedUser, ClientInfo};
+use crate::models::AuthScheme;
+use crate::lockout::{clear_login_failures, locked_out, lockout_remaining, record_login_failure};
+use crate::login_events::{record_login_event, LoginEventOutcome, LoginSubject};
+use crate::notifications::LoginNotifier;
+use crate::sessions::{create_session, revoke_session, revoke_user_sessions, touch_session};
+use crate::tokens::{generate_opaque_token, hash_token, issue_access_token};
+use crate::validation::{is_valid_email, is_valid_name, Validator, MAX_NAME_LEN};
+use crate::web_sessions::{clear_web_session_cookies, create_web_session, set_web_session_cookies, SessionDelivery};
+use super::credentials::NONCE_LEN;
+use super::devices::{register_device, validate_device, DeviceRequest};
+use super::two_factor::create_two_factor_challenge;
+#[cfg(feature = "opaque")]
+use opaque_ke::RegistrationUpload;
+#[cfg(feature = "opaque")]
+use crate::opaque::{registration_record, OpaqueSuite};
+#[cfg(feature = "opaque")]
+use super::opaque::OpaqueMessage;
+
+/// The length of the client-side password hash (SHA-256).
+pub(super) const PASSWORD_HASH_LEN: usize = 32;
+/// The accepted Argon2 salt lengths.
+const SALT_LENS: &[usize] = &[16, 32];
+/// The length of a user's public key (X25519).
+pub(super) const PUBLIC_KEY_LEN: usize = 32;
+/// An upper bound for encrypted keys: a key plus the cipher's overhead fits easily.
+const MAX_ENCRYPTED_KEY_LEN: usize = 1024;
+
+/// Argon2 memory cost, in KiB, assumed when the client doesn't send one.
+pub(crate) const DEFAULT_KDF_MEMORY_KIB: i32 = 65536;
+/// Argon2 iteration count assumed when the client doesn't send one.
+pub(crate) const DEFAULT_KDF_ITERATIONS: i32 = 3;
+/// Argon2 parallelism assumed when the client doesn't send one.
+pub(crate) const DEFAULT_KDF_PARALLELISM: i32 = 4;
 
 
 // --- Request DTOs ---
@@ -18,6 +58,7 @@ use crate::DatabasePool;
 /// which are expected to be received as Base64-encoded strings and are automatically
 /// decoded into byte vectors (`Vec<u8>`).
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct RegisterRequest {
     /// A unique code required to allow registration.
     pub invite_code: String,
@@ -26,13 +67,28 @@ pub struct RegisterRequest {
     /// The display name of the user.
     pub name: String,
     /// The SHA256 of the Argon2 hash of the user's password.
-    /// Encoded as Base64 in JSON.
-    #[serde(deserialize_with = "deserialize_base64")]
-    pub password_hash: Vec<u8>,
-    /// The random salt used during the password hashing process.
+    /// Encoded as Base64 in JSON. Left out by accounts signing up for OPAQUE.
+    #[serde(default, deserialize_with = "deserialize_optional_base64")]
+    pub password_hash: Option<Vec<u8>>,
+    /// The client's final OPAQUE registration message, answering
+    /// `/auth/signup/opaque/start`, for accounts that log in through
+    /// `/auth/login/opaque` instead of sending a password hash. Encoded as Base64 in JSON.
+    #[cfg(feature = "opaque")]
+    pub opaque_registration: Option<OpaqueMessage<RegistrationUpload<OpaqueSuite>>>,
+    /// The random salt used during the password hashing process. Still needed with
+    /// OPAQUE, since the master key is derived from the password the same way.
     /// Encoded as Base64 in JSON.
     #[serde(deserialize_with = "deserialize_base64")]
     pub password_salt: Vec<u8>,
+    /// The Argon2 memory cost, in KiB, used to derive the master key.
+    #[serde(default = "default_kdf_memory_kib")]
+    pub kdf_memory_kib: i32,
+    /// The Argon2 iteration count used to derive the master key.
+    #[serde(default = "default_kdf_iterations")]
+    pub kdf_iterations: i32,
+    /// The Argon2 parallelism used to derive the master key.
+    #[serde(default = "default_kdf_parallelism")]
+    pub kdf_parallelism: i32,
     /// The user's public key, used for asymmetric encryption within the system.
     /// Encoded as Base64 in JSON.
     #[serde(deserialize_with = "deserialize_base64")]
@@ -56,47 +112,738 @@ pub struct RegisterRequest {
     pub personal_key_nonce: Vec<u8>,
 }
 
+impl RegisterRequest {
+    /// The OPAQUE registration record to store for the account, if it signs up for OPAQUE.
+    fn opaque_registration_record(&self) -> Option<Vec<u8>> {
+        #[cfg(feature = "opaque")]
+        return self.opaque_registration.as_ref().map(|upload| registration_record(upload.0.clone()));
+        #[cfg(not(feature = "opaque"))]
+        None
+    }
+}
+
+fn default_kdf_memory_kib() -> i32 {
+    DEFAULT_KDF_MEMORY_KIB
+}
+
+fn default_kdf_iterations() -> i32 {
+    DEFAULT_KDF_ITERATIONS
+}
+
+fn default_kdf_parallelism() -> i32 {
+    DEFAULT_KDF_PARALLELISM
+}
+
 /// Custom Serde deserializer to convert a Base64-encoded string into a `Vec<u8>`.
 ///
 /// By default, Serde expects `Vec<u8>` to be a JSON array of numbers. Since our API
 /// transmits binary data as Base64 strings, this helper function is used with
 /// `#[serde(deserialize_with = "...")]` to perform the conversion during deserialization.
-fn deserialize_base64<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+///
+/// Standard padded Base64 is tried first. Some clients emit the URL-safe alphabet
+/// and/or leave out the padding, so those forms are accepted as a fallback.
+pub(crate) fn deserialize_base64<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
 where
     D: Deserializer<'de>,
 {
+    use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+
     // First, deserialize the input into a standard String.
     let s: String = Deserialize::deserialize(deserializer)?;
-    // Use the base64 crate to decode the string using the standard engine.
-    base64::engine::general_purpose::STANDARD
-        .decode(s)
-        .map_err(rocket::serde::de::Error::custom)
+    // Use the base64 crate to decode the string, preferring the standard engine.
+    STANDARD.decode(&s).or_else(|standard_error| {
+        let unpadded = s.trim_end_matches('=');
+        URL_SAFE_NO_PAD
+            .decode(unpadded)
+            .or_else(|_| STANDARD_NO_PAD.decode(unpadded))
+            .map_err(|_| rocket::serde::de::Error::custom(format!("invalid base64: {}", standard_error)))
+    })
+}
+
+/// Like [`deserialize_base64`], for optional fields. Use with `#[serde(default)]` so
+/// the field may also be left out.
+pub(crate) fn deserialize_optional_base64<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Base64(#[serde(deserialize_with = "deserialize_base64")] Vec<u8>);
+
+    Ok(Option::<Base64>::deserialize(deserializer)?.map(|bytes| bytes.0))
+}
+
+/// Deserializes a field that may be set to `null` as `Some(None)`. Use with
+/// `#[serde(default)]`, so leaving the field out gives `None` instead.
+pub(crate) fn deserialize_nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Custom Serde serializer emitting bytes as standard padded Base64, the counterpart of
+/// [`deserialize_base64`] for response DTOs.
+pub(crate) fn serialize_base64<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// The body of `POST /auth/salt`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SaltRequest {
+    /// The email address to look up.
+    pub email: String,
+}
+
+/// Represents the credentials submitted by a user attempting to log in.
+///
+/// The password hash is derived client-side exactly as during registration
+/// and is expected to be received as a Base64-encoded string.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginRequest {
+    /// The email address the account was registered with.
+    pub email: String,
+    /// The SHA256 of the Argon2 hash of the user's password.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_hash: Vec<u8>,
+    /// The device logging in. Required while `require_device_approval` is set.
+    pub device: Option<DeviceRequest>,
+}
+
+/// Request DTO for exchanging a refresh token for a new token pair.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Represents the data required to change the caller's password.
+///
+/// Changing the password changes the master key, so the client re-encrypts the same
+/// private key under the new master key and submits it together with the new hash and salt.
+/// All binary fields are expected as Base64-encoded strings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChangePasswordRequest {
+    /// The hash of the current password, proving the caller knows it.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub old_password_hash: Vec<u8>,
+    /// The SHA256 of the Argon2 hash of the new password.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub new_password_hash: Vec<u8>,
+    /// The salt used to derive the new password hash.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub new_password_salt: Vec<u8>,
+    /// The Argon2 memory cost, in KiB, used with the new salt; the current one is kept
+    /// if omitted.
+    pub kdf_memory_kib: Option<i32>,
+    /// The Argon2 iteration count used with the new salt; the current one is kept if omitted.
+    pub kdf_iterations: Option<i32>,
+    /// The Argon2 parallelism used with the new salt; the current one is kept if omitted.
+    pub kdf_parallelism: Option<i32>,
+    /// The private key, re-encrypted with the new master key.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    /// The nonce required to decrypt the new `encrypted_private_key`.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+}
+
+/// Request DTO for starting the password reset flow.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
 }
 
-/// Simple request DTO for verifying or using an invite code.
+/// Request DTO for confirming an email address.
 #[derive(Deserialize)]
-pub struct InviteRequest {
-    pub code: String,
+#[serde(deny_unknown_fields)]
+pub struct VerifyEmailRequest {
+    /// The token from the verification email.
+    pub token: String,
+}
+
+/// Request DTO for sending a new verification email.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+/// Represents the data required to reset a forgotten password.
+///
+/// Without the old password the old private key can't be decrypted, so the client
+/// generates a brand-new key pair and submits it alongside the new hash and salt.
+/// All binary fields are expected as Base64-encoded strings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResetPasswordRequest {
+    /// The one-time token that was emailed to the user.
+    pub token: String,
+    /// The SHA256 of the Argon2 hash of the new password.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_hash: Vec<u8>,
+    /// The salt used to derive the new password hash.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_salt: Vec<u8>,
+    /// The Argon2 memory cost, in KiB, used with the new salt; the current one is kept
+    /// if omitted.
+    pub kdf_memory_kib: Option<i32>,
+    /// The Argon2 iteration count used with the new salt; the current one is kept if omitted.
+    pub kdf_iterations: Option<i32>,
+    /// The Argon2 parallelism used with the new salt; the current one is kept if omitted.
+    pub kdf_parallelism: Option<i32>,
+    /// The newly generated public key.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub public_key: Vec<u8>,
+    /// The newly generated private key, encrypted with the new master key.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_private_key`.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+}
+
+// --- Response DTOs ---
+
+/// The identifiers of a newly registered account, so the client doesn't need another
+/// round trip to learn them.
+#[derive(Serialize)]
+pub struct SignupResponse {
+    pub user_id: Uuid,
+    /// The team created to hold the user's own credentials.
+    pub personal_team_id: Uuid,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The parameters a client needs to derive a user's master key.
+#[derive(Serialize)]
+pub struct SaltResponse {
+    /// The Argon2 salt, encoded as Base64 in JSON.
+    #[serde(serialize_with = "serialize_base64")]
+    pub salt: Vec<u8>,
+    pub kdf_memory_kib: i32,
+    pub kdf_iterations: i32,
+    pub kdf_parallelism: i32,
+    /// How the account logs in: `password_hash` through `/auth/login`, or `opaque`
+    /// through `/auth/login/opaque/start`.
+    pub auth_scheme: AuthScheme,
+}
+
+/// A salt lookup response, which must never be cached by the client or a proxy.
+pub struct SaltLookup {
+    salt: SaltResponse,
+    /// Whether the lookup came through the deprecated `GET /auth/salt`.
+    deprecated: bool,
+}
+
+impl<'r> Responder<'r, 'static> for SaltLookup {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Json(self.salt).respond_to(request)?;
+        response.set_header(Header::new("Cache-Control", "no-store"));
+        if self.deprecated {
+            response.set_header(Header::new("Deprecation", "true"));
+        }
+        Ok(response)
+    }
+}
+
+/// A freshly issued access/refresh token pair.
+#[derive(Serialize)]
+pub struct TokenResponse {
+    /// A signed JWT to be sent as `Authorization: Bearer <token>`.
+    pub access_token: String,
+    /// An opaque, single-use token for obtaining the next pair via `/auth/refresh`.
+    pub refresh_token: String,
+    /// The token type, always `Bearer`.
+    pub token_type: &'static str,
+    /// Seconds until the access token expires.
+    pub expires_in: i64,
+}
+
+/// The result of the password step of logging in.
+///
+/// Users without two-factor authentication get a session straight away; everyone
+/// else gets a challenge that must be completed through `/auth/login/2fa`.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    Complete(LoginResponse),
+    TwoFactorRequired(TwoFactorChallengeResponse),
+}
+
+/// Returned by `/auth/login` when the account requires a second factor.
+#[derive(Serialize)]
+pub struct TwoFactorChallengeResponse {
+    /// Always `true`; lets clients tell this apart from a completed login.
+    pub two_factor_required: bool,
+    /// The second factors the user can complete the login with (`totp`, `webauthn`).
+    pub methods: Vec<&'static str>,
+    /// A short-lived, opaque token to submit along with the second factor.
+    pub two_factor_token: String,
+    /// Seconds until the `two_factor_token` expires.
+    pub expires_in: i64,
+}
+
+/// The session tokens and key material returned after a successful login.
+///
+/// This is everything the client needs to unlock the user's vault: the private key
+/// is still encrypted with the master key, which never leaves the client.
+/// All binary fields are encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct LoginResponse {
+    /// The access/refresh token pair, flattened into the top-level object. Absent when
+    /// the session was delivered as a cookie.
+    #[serde(flatten)]
+    pub tokens: Option<TokenResponse>,
+    /// The token to send in `X-CSRF-Token` with state-changing requests, when the
+    /// session was delivered as a cookie. It is also set as the `homedesk_csrf` cookie.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
+    /// The user's public key.
+    #[serde(serialize_with = "serialize_base64")]
+    pub public_key: Vec<u8>,
+    /// The user's private key, encrypted with their master key.
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_private_key`.
+    #[serde(serialize_with = "serialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+    /// Whether the user must change their password before anything but
+    /// `/auth/change-password`, `GET /auth/me`, and `/auth/logout` will work.
+    pub must_change_password: bool,
+    /// Whether the account was recovered with its recovery key and must store a new one
+    /// before anything but `/auth/recovery-key`, `GET /auth/me`, and `/auth/logout` will work.
+    pub recovery_key_required: bool,
+    /// The id of the device the login was made from, if the client sent one.
+    pub device_id: Option<Uuid>,
+    /// Whether the device must be approved from another one before anything but
+    /// `GET /auth/me`, `GET /auth/devices`, and `/auth/logout` will work.
+    pub device_approval_required: bool,
+}
+
+/// One of the caller's active login sessions.
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: Uuid,
+    /// The `User-Agent` the session was last used from.
+    pub user_agent: Option<String>,
+    /// The IP address the session was last used from.
+    pub ip_address: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    /// Whether this is the session the request was made with ("this device").
+    pub current: bool,
+}
+
+/// One of the caller's past login attempts.
+#[derive(Serialize)]
+pub struct LoginEventResponse {
+    pub id: i64,
+    /// How the attempt ended, e.g. `success`, `wrong_password`, or `two_factor_failed`.
+    pub outcome: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// One page of the caller's login history, newest first.
+#[derive(Serialize)]
+pub struct LoginHistoryPage {
+    pub events: Vec<LoginEventResponse>,
+    /// The total number of events across all pages.
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Login events returned per page when no `limit` is given.
+const DEFAULT_HISTORY_PAGE_SIZE: i64 = 20;
+/// The largest `limit` accepted for login history.
+const MAX_HISTORY_PAGE_SIZE: i64 = 100;
+
+// --- Helpers ---
+
+/// Creates and stores a new refresh token in the given session, returning its id and plaintext.
+///
+/// Only the SHA256 hash of the token is persisted.
+async fn create_refresh_token(
+    conn: &mut sqlx::PgConnection,
+    config: &AppConfig,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.refresh_token_ttl_secs);
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO refresh_tokens (user_id, session_id, token_hash, expires_at) VALUES ($1, $2, $3, $4) RETURNING id",
+        user_id,
+        session_id,
+        hash_token(&token),
+        expires_at
+    )
+        .fetch_one(conn)
+        .await?;
+
+    Ok((id, token))
+}
+
+/// Checks every field of a registration, reporting all problems at once.
+fn validate_registration(reg: &RegisterRequest, opaque_registration: Option<&[u8]>) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    match (&reg.password_hash, opaque_registration) {
+        (Some(password_hash), None) => {
+            v.check_len("password_hash", password_hash, &[PASSWORD_HASH_LEN]);
+        }
+        (Some(_), Some(_)) => {
+            v.check("opaque_registration", false, "can't be sent along with password_hash");
+        }
+        (None, Some(_)) => {}
+        (None, None) => {
+            v.check("password_hash", false, "is required");
+        }
+    }
+    v.check("email", is_valid_email(&reg.email), "must be a valid email address")
+        .check("name", is_valid_name(&reg.name), format!("must be between 1 and {} characters", MAX_NAME_LEN))
+        .check_len("public_key", &reg.public_key, &[PUBLIC_KEY_LEN])
+        .check_max_len("wrapped_personal_key", &reg.wrapped_personal_key, MAX_ENCRYPTED_KEY_LEN)
+        .check_len("personal_key_nonce", &reg.personal_key_nonce, &[NONCE_LEN]);
+    check_kdf_params(&mut v, Some(reg.kdf_memory_kib), Some(reg.kdf_iterations), Some(reg.kdf_parallelism));
+    check_key_material(&mut v, Some(("password_salt", &reg.password_salt)), &reg.encrypted_private_key, &reg.private_key_nonce);
+    v.finish()
+}
+
+/// Checks the Argon2 parameters a client derives its master key with; a `None` isn't
+/// being changed.
+pub(super) fn check_kdf_params(
+    v: &mut Validator,
+    memory_kib: Option<i32>,
+    iterations: Option<i32>,
+    parallelism: Option<i32>,
+) -> &mut Validator {
+    v.check(
+        "kdf_memory_kib",
+        memory_kib.is_none_or(|m| (8 * 1024..=4 * 1024 * 1024).contains(&m)),
+        "must be between 8 MiB and 4 GiB",
+    )
+        .check("kdf_iterations", iterations.is_none_or(|i| (1..=100).contains(&i)), "must be between 1 and 100")
+        .check("kdf_parallelism", parallelism.is_none_or(|p| (1..=64).contains(&p)), "must be between 1 and 64")
+}
+
+/// Checks a private key encrypted under the master key, and the salt of the password it
+/// is derived from when that changes too, the way signup does.
+pub(super) fn check_key_material<'v>(
+    v: &'v mut Validator,
+    password_salt: Option<(&'static str, &[u8])>,
+    encrypted_private_key: &[u8],
+    private_key_nonce: &[u8],
+) -> &'v mut Validator {
+    if let Some((field, salt)) = password_salt {
+        v.check_len(field, salt, SALT_LENS);
+    }
+    v.check_max_len("encrypted_private_key", encrypted_private_key, MAX_ENCRYPTED_KEY_LEN)
+        .check_len("private_key_nonce", private_key_nonce, &[NONCE_LEN])
+}
+
+/// An account whose password was just verified, with what decides how its login
+/// goes on.
+pub(crate) struct VerifiedAccount {
+    pub id: Uuid,
+    pub totp_enabled: bool,
+    pub email_verified: bool,
+    pub suspended: bool,
+    pub has_webauthn: bool,
+}
+
+/// Takes a login on from a verified password: refuses suspended and (if required)
+/// unverified accounts, then either issues a second factor challenge or completes the
+/// login through [`complete_login`].
+pub(crate) async fn continue_login(
+    db: &mut Connection<DatabasePool>,
+    config: &AppConfig,
+    client: &ClientInfo,
+    notifier: &LoginNotifier,
+    account: VerifiedAccount,
+    device: Option<&DeviceRequest>,
+    delivery: SessionDelivery<'_>,
+) -> Result<LoginOutcome, ApiError> {
+    // Only checked once the password is known to be right, so they reveal nothing new.
+    if account.suspended {
+        record_login_event(db.as_mut(), LoginSubject::User(account.id), client, LoginEventOutcome::AccountSuspended)
+            .await;
+        return Err(account_suspended());
+    }
+    if config.require_verified_email && !account.email_verified {
+        record_login_event(db.as_mut(), LoginSubject::User(account.id), client, LoginEventOutcome::EmailUnverified)
+            .await;
+        return Err(ApiError::new(Status::Forbidden, "email_unverified"));
+    }
+
+    let mut methods = Vec::new();
+    if account.totp_enabled {
+        methods.push("totp");
+    }
+    if account.has_webauthn {
+        methods.push("webauthn");
+    }
+
+    if !methods.is_empty() {
+        let two_factor_token = create_two_factor_challenge(db.as_mut(), config, account.id, device)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+
+        return Ok(LoginOutcome::TwoFactorRequired(TwoFactorChallengeResponse {
+            two_factor_required: true,
+            methods,
+            two_factor_token,
+            expires_in: config.auth.two_factor_token_ttl_secs,
+        }));
+    }
+
+    let response = complete_login(db, config, client, notifier, account.id, device, delivery).await?;
+    Ok(LoginOutcome::Complete(response))
+}
+
+/// Starts a new session for a user who has passed every login step, returning the
+/// session's tokens and the user's key material. Registers the device the login was
+/// made from, clears the user's failed login count, promotes the user if they are the
+/// configured bootstrap admin logging in for the first time with their email verified,
+/// and records when and from where they logged in, also in
+/// the login history. If the login came from a new device, the user is told about it
+/// in the background. The session is handed over as `delivery` asks: as a token pair
+/// in the response, or as web session cookies.
+///
+/// Fails with `403 Forbidden` and `{"error": "account_suspended"}` if the account was
+/// suspended while a second factor was pending.
+pub(crate) async fn complete_login(
+    db: &mut Connection<DatabasePool>,
+    config: &AppConfig,
+    client: &ClientInfo,
+    notifier: &LoginNotifier,
+    user_id: Uuid,
+    device: Option<&DeviceRequest>,
+    delivery: SessionDelivery<'_>,
+) -> Result<LoginResponse, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut **db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let keys = sqlx::query!(
+        "SELECT email, public_key, encrypted_private_key, private_key_nonce, must_change_password,
+                recovery_key_required, suspended_at IS NOT NULL AS \"suspended!\"
+         FROM users WHERE id = $1",
+        user_id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if keys.suspended {
+        // Dropping the transaction rolls it back before the event is written.
+        drop(tx);
+        record_login_event(db.as_mut(), LoginSubject::User(user_id), client, LoginEventOutcome::AccountSuspended)
+            .await;
+        return Err(account_suspended());
+    }
+
+    clear_login_failures(&mut tx, &keys.email)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Only the first login, and only with the address proven, so anyone else signing up
+    // with it can't take over, and demoting the account later sticks.
+    if config.bootstrap_admin_email.as_deref() == Some(keys.email.as_str()) {
+        sqlx::query!(
+            "UPDATE users SET is_admin = true
+             WHERE id = $1 AND NOT is_admin AND last_login_at IS NULL AND email_verified_at IS NOT NULL",
+            user_id
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    let device = match device {
+        Some(device) => Some(
+            register_device(&mut tx, config, user_id, device)
+                .await
+                .map_err(|_| Status::InternalServerError)?,
+        ),
+        None => None,
+    };
+
+    let session_id = create_session(&mut tx, user_id, device.as_ref().map(|d| d.id), client)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let (refresh_token, web_session) = match delivery {
+        SessionDelivery::Bearer => {
+            let (_, refresh_token) = create_refresh_token(&mut tx, config, user_id, session_id)
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            (Some(refresh_token), None)
+        }
+        SessionDelivery::Cookie(cookies) => {
+            let web_session = create_web_session(&mut tx, config, session_id)
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            (None, Some((cookies, web_session)))
+        }
+    };
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    let csrf_token = web_session.map(|(cookies, (token, csrf_token))| {
+        set_web_session_cookies(cookies, config, token, csrf_token.clone());
+        csrf_token
+    });
+
+    // Outside the transaction: failing to record this must not fail the login.
+    if let Err(e) = sqlx::query!(
+        "UPDATE users SET last_login_at = NOW(), last_login_ip = $2 WHERE id = $1",
+        user_id,
+        client.ip_address()
+    )
+        .execute(db.as_mut())
+        .await
+    {
+        warn!("Failed to record last login of user {}: {}", user_id, e);
+    }
+    record_login_event(db.as_mut(), LoginSubject::User(user_id), client, LoginEventOutcome::Success).await;
+    notifier.new_device_login(user_id, session_id, device.as_ref().map(|d| d.is_new), client);
+
+    Ok(LoginResponse {
+        tokens: refresh_token
+            .map(|refresh_token| token_response(config, user_id, session_id, refresh_token))
+            .transpose()?,
+        csrf_token,
+        public_key: keys.public_key,
+        encrypted_private_key: keys.encrypted_private_key,
+        private_key_nonce: keys.private_key_nonce,
+        must_change_password: keys.must_change_password,
+        recovery_key_required: keys.recovery_key_required,
+        device_id: device.as_ref().map(|d| d.id),
+        device_approval_required: config.require_device_approval && device.is_some_and(|d| !d.approved),
+    })
+}
+
+/// The error returned when a suspended account tries to log in.
+fn account_suspended() -> ApiError {
+    ApiError::new(Status::Forbidden, "account_suspended")
+}
+
+/// The error returned when an account using OPAQUE is asked for its password hash,
+/// which the server doesn't have.
+pub(crate) fn opaque_account() -> ApiError {
+    ApiError::new(Status::Conflict, "opaque_account")
+}
+
+/// Stores a new email verification token for a user, replacing any earlier one, and
+/// returns it in plain text for emailing.
+async fn create_verification_token(
+    conn: &mut sqlx::PgConnection,
+    config: &AppConfig,
+    user_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.email_verification_ttl_secs);
+
+    sqlx::query!(
+        "INSERT INTO email_verification_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)
+         ON CONFLICT (user_id) DO UPDATE
+         SET token_hash = EXCLUDED.token_hash, expires_at = EXCLUDED.expires_at, created_at = NOW()",
+        user_id,
+        hash_token(&token),
+        expires_at
+    )
+        .execute(conn)
+        .await?;
+
+    Ok(token)
+}
+
+/// The email carrying a verification token to `to`.
+fn verification_email(config: &AppConfig, to: &str, token: &str) -> EmailMessage {
+    EmailMessage {
+        to: to.to_string(),
+        subject: "Verify your HomeDesk email address".to_string(),
+        body: format!(
+            "Welcome to HomeDesk! Please confirm this is your email address.\n\n\
+             Your verification token is: {}\n\n\
+             It expires in {} hours. If you didn't create an account, you can ignore this email.",
+            token,
+            config.auth.email_verification_ttl_secs / 3600
+        ),
+    }
+}
+
+/// Issues a new access token alongside an already-stored refresh token.
+fn token_response(
+    config: &AppConfig,
+    user_id: Uuid,
+    session_id: Uuid,
+    refresh_token: String,
+) -> Result<TokenResponse, Status> {
+    let access_token = issue_access_token(&config.auth, user_id, session_id)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(TokenResponse {
+        access_token,
+        refresh_token,
+        token_type: "Bearer",
+        expires_in: config.auth.access_token_ttl_secs,
+    })
 }
 
 // --- Routes ---
 
-/// Signs up a new user using a one-time invite code.
+/// Signs up a new user using an invite code.
 ///
 /// This endpoint performs several atomic operations within a single database transaction:
-/// 1. Validates the provided invite code and marks it as used.
+/// 1. Validates the provided invite code and counts a use of it.
 /// 2. Creates a new entry in the `users` table.
 /// 3. Automatically creates a "Personal Team" for the user.
 /// 4. Adds the user to this team with an 'admin' role.
 /// 5. Stores the user's access to the personal team's key.
+/// 6. Issues an email verification token, which is emailed once the transaction commits.
+///
+/// The account can be used straight away unless `require_verified_email` is set, in
+/// which case logging in waits for `/auth/verify-email`.
 ///
-/// Returns `201 Created` on success, `403 Forbidden` if the invite code is invalid/used,
-/// or `500 Internal Server Error` if any database operation fails.
+/// When built with the `opaque` feature, an account can sign up with an
+/// `opaque_registration` instead of a `password_hash` and then logs in through
+/// `/auth/login/opaque/start` and `/finish`, so nothing derived from the password
+/// reaches the server. Which of the two an account uses is its `auth_scheme`.
+///
+/// Returns `201 Created` with the new user and personal team ids on success,
+/// `422 Unprocessable Entity` listing every invalid field as `{ field, message }`,
+/// `403 Forbidden` if the invite code is invalid, revoked, expired, used up, or bound
+/// to a different email, `409 Conflict` with `{"error": "email_taken"}` if the email
+/// already has an account (the invite is not consumed), another `409`/`422` naming the
+/// constraint if the data violates one, or `500 Internal Server Error` if any other
+/// database operation fails.
 #[post("/signup", data = "<reg_data>")]
 pub async fn signup(
     mut db: Connection<DatabasePool>,
-    reg_data: Json<RegisterRequest>,
-) -> Result<Status, Status> {
+    config: &State<AppConfig>,
+    emailer: &State<SharedEmailer>,
+    reg_data: JsonBody<RegisterRequest>,
+) -> Result<(Status, Json<SignupResponse>), ApiError> {
+    // Reject malformed data before touching the invite code.
+    let opaque_registration = reg_data.opaque_registration_record();
+    validate_registration(&reg_data, opaque_registration.as_deref())?;
+    let auth_scheme = match opaque_registration {
+        Some(_) => AuthScheme::Opaque,
+        None => AuthScheme::PasswordHash,
+    };
 
     // Start a transaction to ensure all-or-nothing success.
     // If any step fails, the transaction is rolled back and no partial data is stored.
@@ -105,57 +852,75 @@ pub async fn signup(
         .map_err(|_| Status::InternalServerError)?;
 
     // 1. Validate and consume the invite code.
-    // We attempt to update the code to 'used' in one atomic query. If zero rows are returned,
-    // the code was either incorrect or already used.
+    // We attempt to count a use of the code in one atomic query. If zero rows are returned,
+    // the code was either incorrect, expired, has no uses left, or is bound to another email.
     let invite = sqlx::query!(
-        "UPDATE invite_codes SET is_used = true WHERE code = $1 AND is_used = false RETURNING id",
-        reg_data.invite_code
+        "UPDATE invite_codes SET use_count = use_count + 1, is_used = (use_count + 1 >= max_uses)
+         WHERE code = $1 AND NOT revoked AND use_count < max_uses
+           AND (expires_at IS NULL OR expires_at > NOW())
+           AND (email IS NULL OR lower(email) = lower($2))
+         RETURNING id",
+        reg_data.invite_code,
+        reg_data.email
     )
         .fetch_optional(&mut *tx)
         .await
         .map_err(|_| Status::InternalServerError)?;
 
     if invite.is_none() {
-        return Err(Status::Forbidden);
+        return Err(Status::Forbidden.into());
     }
 
     // 2. Create the User.
     // Insert the user's core profile and cryptographic materials into the database.
-    let user_id = sqlx::query_scalar!(
-        "INSERT INTO users (email, name, password_hash, password_salt, public_key, encrypted_private_key, private_key_nonce)
-         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+    let user = sqlx::query!(
+        "INSERT INTO users (email, name, auth_scheme, password_hash, opaque_registration, password_salt,
+                            kdf_memory_kib, kdf_iterations, kdf_parallelism, public_key, encrypted_private_key,
+                            private_key_nonce)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id, created_at",
         reg_data.email,
-        reg_data.name,
+        reg_data.name.trim(),
+        auth_scheme as AuthScheme,
         reg_data.password_hash,
+        opaque_registration,
         reg_data.password_salt,
+        reg_data.kdf_memory_kib,
+        reg_data.kdf_iterations,
+        reg_data.kdf_parallelism,
         reg_data.public_key,
         reg_data.encrypted_private_key,
         reg_data.private_key_nonce
     )
         .fetch_one(&mut *tx)
         .await
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(|e| match &e {
+            // Returning early drops the transaction, so the invite use is rolled back too.
+            sqlx::Error::Database(db_error) if db_error.constraint() == Some("users_email_key") => {
+                ApiError::new(Status::Conflict, "email_taken").with("field", "email")
+            }
+            _ => ApiError::from_db(e),
+        })?;
 
     // 3. Create the Personal Team.
     // Every user has a default personal team that only they belong to initially.
     let team_id = sqlx::query_scalar!(
         "INSERT INTO teams (name, is_personal) VALUES ($1, true) RETURNING id",
-        format!("{}'s Personal Team", reg_data.name)
+        format!("{}'s Personal Team", reg_data.name.trim())
     )
         .fetch_one(&mut *tx)
         .await
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(ApiError::from_db)?;
 
     // 4. Join User to Team as Admin.
     // Link the user to the newly created team.
     sqlx::query!(
         "INSERT INTO team_members (team_id, user_id, role) VALUES ($1, $2, 'admin')",
         team_id,
-        user_id
+        user.id
     )
         .execute(&mut *tx)
         .await
-        .map_err(|_| Status::InternalServerError)?;
+        .map_err(ApiError::from_db)?;
 
     // 5. Store the wrapped Personal Team Key.
     // The client generates a personal team key, wraps it for the user's public key,
@@ -163,71 +928,931 @@ pub async fn signup(
     sqlx::query!(
         "INSERT INTO team_key_access (team_id, user_id, encrypted_team_key, nonce) VALUES ($1, $2, $3, $4)",
         team_id,
-        user_id,
+        user.id,
         reg_data.wrapped_personal_key,
         reg_data.personal_key_nonce
     )
         .execute(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    // 6. Issue the email verification token.
+    let verification_token = create_verification_token(&mut tx, config, user.id)
         .await
         .map_err(|_| Status::InternalServerError)?;
 
     // Commit the transaction to persist all changes.
     tx.commit().await.map_err(|_| Status::InternalServerError)?;
 
-    Ok(Status::Created)
+    send_in_background(emailer, verification_email(config, &reg_data.email, &verification_token));
+
+    Ok((Status::Created, Json(SignupResponse {
+        user_id: user.id,
+        personal_team_id: team_id,
+        created_at: user.created_at,
+    })))
+}
+
+/// Looks up the salt and Argon2 parameters for a given email address.
+///
+/// Returns the salt and KDF parameters used for the user's password hashing.
+/// If the user does not exist, it returns a fake salt derived from the email with a
+/// server secret, along with the default parameters, to prevent timing attacks or user
+/// enumeration via salt requests. Unknown emails are reported as using `password_hash`,
+/// so an `opaque` answer does show that an account exists; accounts opting into OPAQUE
+/// give that up.
+///
+/// Both paths do the same work: the lookup always runs, the fake salt is always computed,
+/// and the response is only chosen at the end. The response may then be held back until
+/// `auth.salt_response_floor_ms` has passed.
+async fn lookup_salt(
+    db: &mut sqlx::PgConnection,
+    config: &AppConfig,
+    salt_secret: &SaltSecret,
+    email: &str,
+) -> Result<SaltResponse, Status> {
+    let started = std::time::Instant::now();
+
+    let params = sqlx::query!(
+        "SELECT password_salt, kdf_memory_kib, kdf_iterations, kdf_parallelism, auth_scheme AS \"auth_scheme: AuthScheme\"
+         FROM users WHERE email = $1",
+        email
+    ).fetch_optional(db)
+    .await.map_err(|_| Status::InternalServerError)?;
+    // black_box keeps the compiler from moving the HMAC into the unknown-email branch.
+    let fake = SaltResponse {
+        salt: std::hint::black_box(fake_salt(salt_secret, email)).to_vec(),
+        kdf_memory_kib: DEFAULT_KDF_MEMORY_KIB,
+        kdf_iterations: DEFAULT_KDF_ITERATIONS,
+        kdf_parallelism: DEFAULT_KDF_PARALLELISM,
+        auth_scheme: AuthScheme::PasswordHash,
+    };
+
+    let response = match params {
+        Some(params) => SaltResponse {
+            salt: params.password_salt,
+            kdf_memory_kib: params.kdf_memory_kib,
+            kdf_iterations: params.kdf_iterations,
+            kdf_parallelism: params.kdf_parallelism,
+            auth_scheme: params.auth_scheme,
+        },
+        None => fake,
+    };
+
+    let floor_ms = config.auth.salt_response_floor_ms;
+    if floor_ms > 0 {
+        let jitter_ms = rand::random::<u64>() % (floor_ms / 2 + 1);
+        let target = std::time::Duration::from_millis(floor_ms + jitter_ms);
+        if let Some(remaining) = target.checked_sub(started.elapsed()) {
+            rocket::tokio::time::sleep(remaining).await;
+        }
+    }
+
+    Ok(response)
+}
+
+/// Fetch the salt and Argon2 parameters for a given email address.
+///
+/// See [`lookup_salt`] for how unknown emails are handled. The response carries
+/// `Cache-Control: no-store`.
+#[post("/salt", data = "<salt_data>")]
+pub async fn post_salt(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    salt_secret: &State<SaltSecret>,
+    salt_data: JsonBody<SaltRequest>,
+) -> Result<SaltLookup, Status> {
+    let salt = lookup_salt(&mut db, config, salt_secret, &salt_data.email).await?;
+    Ok(SaltLookup { salt, deprecated: false })
 }
 
-/// Generates a new unique invite code and stores it in the database.
+/// Fetch the salt and Argon2 parameters for a given email address.
 ///
-/// This endpoint currently does not require authentication (marked as ToDo).
-/// It generates a UUID v4 string and inserts it into the `invite_codes` table.
-#[post("/invite")]
-pub async fn generate_invite(
+/// Deprecated: the email ends up in access logs and browser history through the query
+/// string. Use `POST /auth/salt` instead; responses here carry a `Deprecation` header.
+#[get("/salt?<email>")]
+pub async fn get_salt(
     mut db: Connection<DatabasePool>,
-) -> Result<String, Status> {
-    // Generate a unique random UUID v4 for the code.
-    let new_code = Uuid::new_v4().to_string();
+    config: &State<AppConfig>,
+    salt_secret: &State<SaltSecret>,
+    email: String,
+) -> Result<SaltLookup, Status> {
+    let salt = lookup_salt(&mut db, config, salt_secret, &email).await?;
+    Ok(SaltLookup { salt, deprecated: true })
+}
+
+/// Logs a user in by verifying their password hash.
+///
+/// The submitted hash is compared against `users.password_hash` in constant time.
+/// If the email is unknown, a comparison against a dummy hash is still performed so
+/// both failure paths do the same amount of work.
+///
+/// If the user has two-factor authentication enabled (TOTP or a WebAuthn credential),
+/// no session is created yet; instead a short-lived `two_factor_token` is returned, to be
+/// exchanged through `/auth/login/2fa` or the WebAuthn login endpoints. Otherwise, returns an access/refresh token pair and the user's
+/// key material straight away.
+///
+/// Failed attempts are recorded per email and per IP; after repeated failures further
+/// attempts are refused for an escalating period, even with the right password. The
+/// count is only reset once a login fully completes, including any second factor.
+///
+/// With `?session=cookie`, the session is set as HttpOnly web session cookies instead
+/// of being returned as tokens, and the response carries the `csrf_token` to send with
+/// state-changing requests. The routes completing a second factor take the same parameter.
+///
+/// Clients should identify the device they log in from; while `require_device_approval`
+/// is set this is mandatory, and a login from a device the user hasn't approved yet
+/// succeeds with `device_approval_required` set and a session that can only do the
+/// basics until the device is approved from another one.
+///
+/// Accounts that signed up for OPAQUE log in through `/auth/login/opaque/start` instead
+/// and are refused here like a wrong password.
+///
+/// Returns `401 Unauthorized` if the email or password is wrong (without revealing which),
+/// `403 Forbidden` with `{"error": "account_suspended"}` if an admin suspended the account
+/// or `{"error": "email_unverified"}` if `require_verified_email` is set and the address
+/// hasn't been verified, or `429 Too Many Requests` with a `Retry-After`
+/// header while locked out.
+#[post("/login", data = "<login_data>")]
+pub async fn login(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    client: ClientInfo,
+    notifier: LoginNotifier,
+    delivery: SessionDelivery<'_>,
+    login_data: JsonBody<LoginRequest>,
+) -> Result<Json<LoginOutcome>, ApiError> {
+    validate_device(login_data.device.as_ref(), config.require_device_approval)?;
+
+    let ip = client.ip_address();
+    let locked = lockout_remaining(db.as_mut(), &login_data.email, ip.as_deref())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if let Some(retry_after) = locked {
+        let subject = LoginSubject::Email(&login_data.email);
+        record_login_event(db.as_mut(), subject, &client, LoginEventOutcome::LockedOut).await;
+        return Err(locked_out(retry_after));
+    }
+
+    let user = sqlx::query!(
+        "SELECT id, password_hash, totp_enabled, email_verified_at IS NOT NULL AS \"email_verified!\",
+                suspended_at IS NOT NULL AS \"suspended!\",
+                EXISTS(SELECT 1 FROM webauthn_credentials w WHERE w.user_id = users.id) AS \"has_webauthn!\"
+         FROM users WHERE email = $1",
+        login_data.email
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let user = match user {
+        // Accounts using OPAQUE have no hash, so they can't log in here.
+        Some(user) if user.password_hash.as_deref().is_some_and(|hash| constant_time_eq(hash, &login_data.password_hash)) => {
+            Ok(user)
+        }
+        Some(_) => Err(LoginEventOutcome::WrongPassword),
+        None => {
+            // Burn the same comparison as the real path before rejecting.
+            let _ = constant_time_eq(&login_data.password_hash, &[0u8; 32]);
+            Err(LoginEventOutcome::UnknownEmail)
+        }
+    };
+
+    let user = match user {
+        Ok(user) => user,
+        Err(outcome) => {
+            record_login_failure(db.as_mut(), &login_data.email, ip.as_deref())
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            record_login_event(db.as_mut(), LoginSubject::Email(&login_data.email), &client, outcome).await;
+            return Err(Status::Unauthorized.into());
+        }
+    };
+
+    let account = VerifiedAccount {
+        id: user.id,
+        totp_enabled: user.totp_enabled,
+        email_verified: user.email_verified,
+        suspended: user.suspended,
+        has_webauthn: user.has_webauthn,
+    };
+    let outcome = continue_login(&mut db, config, &client, &notifier, account, login_data.device.as_ref(), delivery).await?;
+    Ok(Json(outcome))
+}
+
+/// Exchanges a refresh token for a new access/refresh token pair.
+///
+/// Refresh tokens are single use: the presented token is marked as replaced by the
+/// newly issued one, which stays in the same session. If a token that was already
+/// rotated is presented again, it has most likely been stolen, so the whole session
+/// (every token in the rotation chain and every access token issued from it) is revoked
+/// and the request fails. This forces both the legitimate client and the attacker to log in again.
+///
+/// Returns `401 Unauthorized` for unknown, expired, revoked, or reused tokens, or if the
+/// session has been logged out.
+#[post("/refresh", data = "<refresh_data>")]
+pub async fn refresh(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    client: ClientInfo,
+    refresh_data: JsonBody<RefreshRequest>,
+) -> Result<Json<TokenResponse>, Status> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Lock the row so two concurrent refreshes with the same token can't both succeed.
+    let current = sqlx::query!(
+        "SELECT t.id, t.user_id, t.session_id, t.expires_at, t.revoked, t.replaced_by,
+                s.revoked_at IS NOT NULL AS \"session_revoked!\"
+         FROM refresh_tokens t JOIN sessions s ON s.id = t.session_id
+         WHERE t.token_hash = $1 FOR UPDATE OF t",
+        hash_token(&refresh_data.refresh_token)
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    // Reuse detection: the rotation chain is exactly the session, so revoke all of it.
+    if current.replaced_by.is_some() {
+        revoke_session(&mut tx, current.session_id, current.user_id)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+
+        tx.commit().await.map_err(|_| Status::InternalServerError)?;
+        warn!("Refresh token reuse detected for user {}; revoked session {}", current.user_id, current.session_id);
+        return Err(Status::Unauthorized);
+    }
+
+    if current.revoked || current.session_revoked || current.expires_at <= chrono::Utc::now() {
+        return Err(Status::Unauthorized);
+    }
+
+    // Rotate: issue the successor and link it to the presented token.
+    let (new_id, refresh_token) = create_refresh_token(&mut tx, config, current.user_id, current.session_id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
 
-    // Insert the newly generated code into the database.
     sqlx::query!(
-        "INSERT INTO invite_codes (code) VALUES ($1)",
-        new_code
+        "UPDATE refresh_tokens SET replaced_by = $1 WHERE id = $2",
+        new_id,
+        current.id
     )
-        .execute(db.as_mut())
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    touch_session(&mut tx, current.session_id, &client)
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    // Return the generated code to the requester.
-    Ok(new_code)
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(token_response(config, current.user_id, current.session_id, refresh_token)?))
+}
+
+/// Logs the caller out by revoking their current session.
+///
+/// With `?all=true`, every session of the user is revoked instead ("log out everywhere").
+/// Revocation takes effect immediately: refresh tokens can no longer be exchanged, and
+/// access tokens from revoked sessions are rejected by the `AuthenticatedUser` guard
+/// for the rest of their lifetime. Web session cookies are cleared as well. Personal
+/// access tokens aren't sessions, so even `?all=true` leaves them working.
+///
+/// Returns `204 No Content` on success, or `400 Bad Request` with `{"error":
+/// "personal_access_token"}` for a plain logout made with a personal access token, which
+/// has no session to end; delete it through `DELETE /auth/tokens/<id>` instead.
+#[post("/logout?<all>")]
+pub async fn logout(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    cookies: &CookieJar<'_>,
+    all: Option<bool>,
+) -> Result<Status, ApiError> {
+    let revoked = match (all.unwrap_or(false), user.session_id) {
+        (true, _) => revoke_user_sessions(db.as_mut(), user.id, None).await,
+        (false, Some(session_id)) => revoke_session(db.as_mut(), session_id, user.id).await.map(|_| ()),
+        (false, None) => return Err(ApiError::new(Status::BadRequest, "personal_access_token")),
+    };
+    revoked.map_err(|_| Status::InternalServerError)?;
+    clear_web_session_cookies(cookies);
+
+    Ok(Status::NoContent)
 }
 
+/// Lists the caller's active sessions, most recently used first.
+///
+/// A session is active while it hasn't been revoked and its latest refresh token or
+/// its web session is still valid. The session the request was made with is flagged as `current`.
+#[get("/sessions")]
+pub async fn list_sessions(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Vec<SessionResponse>>, Status> {
+    let sessions = sqlx::query!(
+        "SELECT s.id, s.user_agent, s.ip_address, s.created_at, s.last_seen_at
+         FROM sessions s
+         WHERE s.user_id = $1 AND s.revoked_at IS NULL
+           AND (EXISTS (
+               SELECT 1 FROM refresh_tokens t
+               WHERE t.session_id = s.id AND t.replaced_by IS NULL AND NOT t.revoked AND t.expires_at > NOW()
+           ) OR EXISTS (SELECT 1 FROM web_sessions w WHERE w.session_id = s.id AND w.expires_at > NOW()))
+         ORDER BY s.last_seen_at DESC",
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(sessions.into_iter().map(|s| SessionResponse {
+        current: Some(s.id) == user.session_id,
+        id: s.id,
+        user_agent: s.user_agent,
+        ip_address: s.ip_address,
+        created_at: s.created_at,
+        last_seen_at: s.last_seen_at,
+    }).collect()))
+}
 
-/// Fetch the salt for a given email address.
+/// Lists the caller's recent login attempts, newest first, so they can spot logins
+/// they don't recognise.
 ///
-/// This endpoint returns the salt used for the user's password hashing.
-/// If the user does not exist, it returns a deterministic random salt based on the email
-/// to prevent timing attacks or user enumeration via salt requests.
-#[get("/salt?<email>")]
-pub async fn get_salt(mut db: Connection<DatabasePool>, email: String) -> Result<String, Status> {
-    let salt = sqlx::query_scalar!(
-        "SELECT password_salt FROM users WHERE email = $1",
-        email
-    ).fetch_optional(db.as_mut())
-    .await.map_err(|_| Status::InternalServerError)?;
+/// Paginated with `limit` (default 20, at most 100) and `offset`. Events are kept for
+/// `auth.login_event_retention_days`.
+#[get("/login-history?<limit>&<offset>")]
+pub async fn login_history(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Json<LoginHistoryPage>, Status> {
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_PAGE_SIZE).clamp(1, MAX_HISTORY_PAGE_SIZE);
+    let offset = offset.unwrap_or(0).max(0);
 
-    match salt {
-        Some(salt_bytes) => Ok(base64::engine::general_purpose::STANDARD.encode(salt_bytes)),
-        None => {
-            use std::collections::hash_map::DefaultHasher;
-            use std::hash::{Hash, Hasher};
+    let events = sqlx::query_as!(
+        LoginEventResponse,
+        "SELECT id, outcome, ip_address, user_agent, created_at
+         FROM login_events WHERE user_id = $1
+         ORDER BY created_at DESC, id DESC
+         LIMIT $2 OFFSET $3",
+        user.id,
+        limit,
+        offset
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let total = sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"count!\" FROM login_events WHERE user_id = $1",
+        user.id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(LoginHistoryPage { events, total, limit, offset }))
+}
+
+/// Revokes one of the caller's sessions, e.g. to sign out a lost device.
+///
+/// The session's refresh tokens stop working immediately, as do its access tokens.
+/// Returns `204 No Content` on success or `404 Not Found` if the caller has no
+/// active session with that id.
+#[delete("/sessions/<id>")]
+pub async fn delete_session(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, Status> {
+    let revoked = revoke_session(db.as_mut(), id, user.id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if revoked { Ok(Status::NoContent) } else { Err(Status::NotFound) }
+}
+
+/// Changes the caller's password and re-wrapped private key.
+///
+/// The old password hash is verified first; the hash, salt, encrypted private key, and
+/// its nonce, plus any Argon2 parameters sent, are then replaced in a single transaction. Every other session of the user
+/// is revoked so other devices have to log in with the new password; the calling
+/// session stays valid. A password change required by an admin is thereby completed.
+///
+/// Returns `204 No Content` on success, `403 Forbidden` if the old hash doesn't match,
+/// `409 Conflict` with `{"error": "opaque_account"}` for accounts using OPAQUE, which have
+/// no hash to check, or `422 Unprocessable Entity` listing invalid fields.
+#[post("/change-password", data = "<change_data>")]
+pub async fn change_password(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    change_data: JsonBody<ChangePasswordRequest>,
+) -> Result<Status, ApiError> {
+    let mut v = Validator::new();
+    v.check_len("new_password_hash", &change_data.new_password_hash, &[PASSWORD_HASH_LEN]);
+    check_kdf_params(&mut v, change_data.kdf_memory_kib, change_data.kdf_iterations, change_data.kdf_parallelism);
+    check_key_material(
+        &mut v,
+        Some(("new_password_salt", &change_data.new_password_salt)),
+        &change_data.encrypted_private_key,
+        &change_data.private_key_nonce,
+    );
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Lock the row so a concurrent change can't slip in between verification and update.
+    let current_hash = sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE id = $1 FOR UPDATE",
+        user.id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or_else(opaque_account)?;
+
+    if !constant_time_eq(&current_hash, &change_data.old_password_hash) {
+        return Err(Status::Forbidden.into());
+    }
+
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1, password_salt = $2, encrypted_private_key = $3, private_key_nonce = $4,
+                          kdf_memory_kib = COALESCE($5, kdf_memory_kib), kdf_iterations = COALESCE($6, kdf_iterations),
+                          kdf_parallelism = COALESCE($7, kdf_parallelism), must_change_password = false
+         WHERE id = $8",
+        change_data.new_password_hash,
+        change_data.new_password_salt,
+        change_data.encrypted_private_key,
+        change_data.private_key_nonce,
+        change_data.kdf_memory_kib,
+        change_data.kdf_iterations,
+        change_data.kdf_parallelism,
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    revoke_user_sessions(&mut tx, user.id, user.session_id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Starts the password reset flow by emailing a one-time reset token.
+///
+/// Any previously issued, unused tokens for the user are discarded, and the new token
+/// is stored only as a hash. The email is sent in the background, and the endpoint
+/// returns `200 OK` whether or not the email belongs to an account, so it can't be
+/// used to enumerate users.
+#[post("/forgot-password", data = "<forgot_data>")]
+pub async fn forgot_password(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    emailer: &State<SharedEmailer>,
+    forgot_data: JsonBody<ForgotPasswordRequest>,
+) -> Result<Status, Status> {
+    let user_id = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE email = $1",
+        forgot_data.email
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let Some(user_id) = user_id else {
+        return Ok(Status::Ok);
+    };
 
-            let mut hasher = DefaultHasher::new();
-            email.hash(&mut hasher);
-            let seed = hasher.finish();
+    let token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.password_reset_ttl_secs);
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+        user_id,
+        hash_token(&token),
+        expires_at
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    send_in_background(emailer, EmailMessage {
+        to: forgot_data.email.clone(),
+        subject: "Reset your HomeDesk password".to_string(),
+        body: format!(
+            "Someone requested a password reset for your HomeDesk account.\n\n\
+             Your reset token is: {}\n\n\
+             It expires in {} minutes. If you didn't request this, you can ignore this email.",
+            token,
+            config.auth.password_reset_ttl_secs / 60
+        ),
+    });
+
+    Ok(Status::Ok)
+}
 
-            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
-            let random_salt: [u8; 16] = rng.r#gen();
-            Ok(base64::engine::general_purpose::STANDARD.encode(random_salt))
+/// Confirms the user's email address using a token from the verification email.
+///
+/// The token is consumed and the address marked verified. Returns `204 No Content` on
+/// success or `403 Forbidden` if the token is invalid or expired.
+#[post("/verify-email", data = "<verify_data>")]
+pub async fn verify_email(
+    mut db: Connection<DatabasePool>,
+    verify_data: JsonBody<VerifyEmailRequest>,
+) -> Result<Status, Status> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let user_id = sqlx::query_scalar!(
+        "DELETE FROM email_verification_tokens WHERE token_hash = $1 AND expires_at > NOW()
+         RETURNING user_id",
+        hash_token(&verify_data.token)
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Forbidden)?;
+
+    sqlx::query!(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+        user_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Sends a fresh verification email, invalidating the previous token.
+///
+/// Like `/auth/forgot-password`, this returns `200 OK` whether or not the email belongs
+/// to an unverified account, so it can't be used to enumerate users. It is rate limited
+/// per IP through the `verify-email/resend` limit.
+#[post("/verify-email/resend", data = "<resend_data>")]
+pub async fn resend_verification(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    emailer: &State<SharedEmailer>,
+    resend_data: JsonBody<ResendVerificationRequest>,
+) -> Result<Status, Status> {
+    let user_id = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE email = $1 AND email_verified_at IS NULL",
+        resend_data.email
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let Some(user_id) = user_id else {
+        return Ok(Status::Ok);
+    };
+
+    let token = create_verification_token(db.as_mut(), config, user_id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    send_in_background(emailer, verification_email(config, &resend_data.email, &token));
+
+    Ok(Status::Ok)
+}
+
+/// Resets a forgotten password using a token from `/auth/forgot-password`.
+///
+/// The token is consumed, and the user's password hash, salt, any Argon2 parameters
+/// sent, and key pair are replaced in a single transaction. Because every `team_key_access` row was wrapped for the old
+/// public key, those rows are deleted: the user keeps their team memberships, but a team
+/// admin has to re-wrap each team key for the new public key before it can be used again.
+/// The recovery key, which unlocked the old private key, is deleted too. All of the
+/// user's sessions are revoked. An account using OPAQUE is moved back to
+/// `password_hash`, since the new password arrives as a hash.
+///
+/// Returns `204 No Content` on success, `403 Forbidden` if the token is invalid,
+/// expired, or already used, or `422 Unprocessable Entity` listing invalid fields.
+#[post("/reset-password", data = "<reset_data>")]
+pub async fn reset_password(
+    mut db: Connection<DatabasePool>,
+    reset_data: JsonBody<ResetPasswordRequest>,
+) -> Result<Status, ApiError> {
+    let mut v = Validator::new();
+    v.check_len("password_hash", &reset_data.password_hash, &[PASSWORD_HASH_LEN])
+        .check_len("public_key", &reset_data.public_key, &[PUBLIC_KEY_LEN]);
+    check_kdf_params(&mut v, reset_data.kdf_memory_kib, reset_data.kdf_iterations, reset_data.kdf_parallelism);
+    check_key_material(
+        &mut v,
+        Some(("password_salt", &reset_data.password_salt)),
+        &reset_data.encrypted_private_key,
+        &reset_data.private_key_nonce,
+    );
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Consume the token atomically; zero rows means it is unknown, expired, or used.
+    let user_id = sqlx::query_scalar!(
+        "UPDATE password_reset_tokens SET used_at = NOW()
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+         RETURNING user_id",
+        hash_token(&reset_data.token)
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Forbidden)?;
+
+    sqlx::query!(
+        "UPDATE users SET auth_scheme = 'password_hash', password_hash = $1, opaque_registration = NULL,
+                          password_salt = $2, public_key = $3, encrypted_private_key = $4, private_key_nonce = $5,
+                          kdf_memory_kib = COALESCE($6, kdf_memory_kib), kdf_iterations = COALESCE($7, kdf_iterations),
+                          kdf_parallelism = COALESCE($8, kdf_parallelism), must_change_password = false
+         WHERE id = $9",
+        reset_data.password_hash,
+        reset_data.password_salt,
+        reset_data.public_key,
+        reset_data.encrypted_private_key,
+        reset_data.private_key_nonce,
+        reset_data.kdf_memory_kib,
+        reset_data.kdf_iterations,
+        reset_data.kdf_parallelism,
+        user_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Team keys wrapped for the old public key are useless now; admins must re-wrap them.
+    sqlx::query!("DELETE FROM team_key_access WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!("DELETE FROM recovery_keys WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    revoke_user_sessions(&mut tx, user_id, None)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use rocket::http::ContentType;
+    use rocket::serde::json::{json, Value};
+    use rocket_db_pools::sqlx::PgPool;
+    use crate::testing;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(crate = "rocket::serde")]
+    struct Body {
+        #[serde(deserialize_with = "deserialize_base64")]
+        value: Vec<u8>,
+    }
+
+    fn decode(value: &str) -> Result<Vec<u8>, String> {
+        rocket::serde::json::from_str::<Body>(&format!(r#"{{"value": "{}"}}"#, value))
+            .map(|body| body.value)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn base64_accepts_standard_padded_input() {
+        assert_eq!(decode("aGk=").unwrap(), b"hi");
+        assert_eq!(decode("+/8=").unwrap(), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn base64_accepts_unpadded_input() {
+        assert_eq!(decode("aGk").unwrap(), b"hi");
+        assert_eq!(decode("+/8").unwrap(), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn base64_accepts_url_safe_input() {
+        assert_eq!(decode("-_8").unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode("-_8=").unwrap(), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn base64_rejects_invalid_input() {
+        for value in ["a", "aGk*", "a G k=", "+_8="] {
+            assert!(decode(value).unwrap_err().starts_with("invalid base64"), "{}", value);
         }
+        assert!(decode("").unwrap().is_empty());
+    }
+
+    #[sqlx::test]
+    async fn salt_lookups_query_the_database_for_known_and_unknown_emails(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        testing::signup(&client, "known@example.com").await;
+        let paths = ["/auth/salt?email=known@example.com", "/auth/salt?email=unknown@example.com"];
+        for path in paths {
+            assert_eq!(client.get(path).dispatch().await.status(), Status::Ok, "{}", path);
+        }
+
+        // Without the table, a path that skipped the lookup would still answer.
+        sqlx::query("ALTER TABLE users RENAME TO users_hidden").execute(&pool).await.unwrap();
+        for path in paths {
+            assert_eq!(client.get(path).dispatch().await.status(), Status::InternalServerError, "{}", path);
+        }
+    }
+
+    #[sqlx::test]
+    async fn signup_with_a_taken_email_leaves_the_invite_unused(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        testing::signup(&client, "taken@example.com").await;
+        sqlx::query("INSERT INTO invite_codes (code, max_uses) VALUES ('second', 5)").execute(&pool).await.unwrap();
+
+        let response = client.post("/auth/signup")
+            .header(ContentType::JSON)
+            .body(testing::signup_body("second", "taken@example.com"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"], "email_taken");
+
+        let use_count: i32 = sqlx::query_scalar("SELECT use_count FROM invite_codes WHERE code = 'second'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(use_count, 0);
     }
-}
\ No newline at end of file
+
+    #[sqlx::test]
+    async fn logout_ends_only_the_current_session(pool: PgPool) {
+        let client = testing::client(pool).await;
+        testing::signup(&client, "user@example.com").await;
+        let laptop = testing::access_token(&client, "user@example.com").await;
+        let phone = testing::access_token(&client, "user@example.com").await;
+
+        let response = client.post("/auth/logout").header(testing::bearer(&laptop)).dispatch().await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        let response = client.get("/auth/sessions").header(testing::bearer(&laptop)).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client.get("/auth/sessions").header(testing::bearer(&phone)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let sessions: Value = response.into_json().await.unwrap();
+        assert_eq!(sessions.as_array().unwrap().len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn logout_all_rejects_access_tokens_from_every_session(pool: PgPool) {
+        let client = testing::client(pool).await;
+        testing::signup(&client, "user@example.com").await;
+        let laptop = testing::access_token(&client, "user@example.com").await;
+        let phone = testing::access_token(&client, "user@example.com").await;
+
+        let response = client.post("/auth/logout?all=true").header(testing::bearer(&laptop)).dispatch().await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        for token in [&laptop, &phone] {
+            let response = client.get("/auth/sessions").header(testing::bearer(token)).dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+    }
+
+    #[sqlx::test]
+    async fn logout_with_a_personal_access_token_is_refused(pool: PgPool) {
+        let client = testing::client(pool).await;
+        testing::signup(&client, "user@example.com").await;
+        let session = testing::access_token(&client, "user@example.com").await;
+        let token = &testing::api_token(&client, &session, json!({ "name": "nightly backup" })).await;
+
+        let response = client.post("/auth/logout").header(testing::bearer(token)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"], "personal_access_token");
+        let response = client.get("/auth/sessions").header(testing::bearer(token)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    async fn is_admin(pool: &PgPool) -> bool {
+        sqlx::query_scalar("SELECT is_admin FROM users WHERE email = 'admin@example.com'").fetch_one(pool).await.unwrap()
+    }
+
+    #[sqlx::test]
+    async fn the_bootstrap_admin_is_promoted_once_at_a_verified_first_login(pool: PgPool) {
+        let client = testing::client_with(pool.clone(), |figment| {
+            figment.merge(("bootstrap_admin_email", "admin@example.com"))
+        }).await;
+        testing::signup(&client, "admin@example.com").await;
+        assert!(!is_admin(&pool).await, "promoted at signup");
+
+        sqlx::query("UPDATE users SET email_verified_at = NOW()").execute(&pool).await.unwrap();
+        testing::login(&client, "admin@example.com").await;
+        assert!(is_admin(&pool).await);
+
+        // A demotion sticks through later logins.
+        sqlx::query("UPDATE users SET is_admin = false").execute(&pool).await.unwrap();
+        testing::login(&client, "admin@example.com").await;
+        assert!(!is_admin(&pool).await);
+    }
+
+    #[sqlx::test]
+    async fn the_bootstrap_admin_is_not_promoted_before_verifying(pool: PgPool) {
+        let client = testing::client_with(pool.clone(), |figment| {
+            figment.merge(("bootstrap_admin_email", "admin@example.com"))
+        }).await;
+        testing::signup(&client, "admin@example.com").await;
+        testing::login(&client, "admin@example.com").await;
+        assert!(!is_admin(&pool).await);
+    }
+
+    #[sqlx::test]
+    async fn change_password_checks_the_new_key_material(pool: PgPool) {
+        let client = testing::client(pool).await;
+        testing::signup(&client, "user@example.com").await;
+        let token = testing::access_token(&client, "user@example.com").await;
+
+        let response = client.post("/auth/change-password")
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(json!({
+                "old_password_hash": STANDARD.encode(testing::PASSWORD_HASH),
+                "new_password_hash": STANDARD.encode([8; 32]),
+                "new_password_salt": STANDARD.encode([9; 8]),
+                "encrypted_private_key": STANDARD.encode([10; 48]),
+                "private_key_nonce": STANDARD.encode([11; 12]),
+            }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().await.unwrap();
+        let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, ["new_password_salt", "private_key_nonce"]);
+
+        // The old password still works.
+        testing::login(&client, "user@example.com").await;
+    }
+
+    #[sqlx::test]
+    async fn change_password_stores_new_argon2_parameters(pool: PgPool) {
+        let client = testing::client(pool).await;
+        testing::signup(&client, "user@example.com").await;
+        let token = testing::access_token(&client, "user@example.com").await;
+        let change = |kdf_iterations: i32| json!({
+            "old_password_hash": STANDARD.encode(testing::PASSWORD_HASH),
+            "new_password_hash": STANDARD.encode(testing::PASSWORD_HASH),
+            "new_password_salt": STANDARD.encode([9; 16]),
+            "kdf_iterations": kdf_iterations,
+            "encrypted_private_key": STANDARD.encode([10; 48]),
+            "private_key_nonce": STANDARD.encode([11; 24]),
+        }).to_string();
+
+        let response = client.post("/auth/change-password")
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(change(0))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let response = client.post("/auth/change-password")
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(change(5))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        let salt: Value = client.get("/auth/salt?email=user@example.com").dispatch().await.into_json().await.unwrap();
+        assert_eq!(salt["kdf_iterations"], 5);
+        assert_eq!(salt["kdf_memory_kib"], DEFAULT_KDF_MEMORY_KIB);
+        assert_eq!(salt["salt"], STANDARD.encode([9; 16]));
+    }
+}
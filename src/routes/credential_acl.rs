@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::validation::Validator;
+use super::access_log::require_team_admin;
+
+/// The most members a credential can be restricted to.
+const MAX_ACL_ENTRIES: usize = 200;
+
+// --- Request DTOs ---
+
+/// The members allowed to access a credential, replacing any listed before. No entries
+/// lift the restriction.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetCredentialAclRequest {
+    pub entries: Vec<CredentialAclEntryRequest>,
+}
+
+/// What one member may do with a restricted credential.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CredentialAclEntryRequest {
+    pub user_id: Uuid,
+    pub can_read: bool,
+    /// Needs `can_read`.
+    pub can_write: bool,
+}
+
+// --- Response DTOs ---
+
+/// Who may access a credential besides the admins of its team.
+#[derive(Serialize)]
+pub struct CredentialAclResponse {
+    /// Whether the credential has entries, so only admins and the members listed may see
+    /// it. Otherwise the whole team can, as usual.
+    pub restricted: bool,
+    pub entries: Vec<CredentialAclEntry>,
+}
+
+/// What one user may do with a restricted credential.
+#[derive(Serialize)]
+pub struct CredentialAclEntry {
+    pub user_id: Uuid,
+    pub email: String,
+    pub can_read: bool,
+    pub can_write: bool,
+    /// `false` for a user who has left the team since, whose entry applies again if
+    /// they rejoin.
+    pub is_member: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+// --- Helpers ---
+
+/// The entries of a credential, by email.
+async fn fetch_acl(conn: &mut sqlx::PgConnection, credential_id: Uuid) -> Result<CredentialAclResponse, Status> {
+    let entries = sqlx::query_as!(
+        CredentialAclEntry,
+        "SELECT a.user_id, u.email, a.can_read, a.can_write,
+                EXISTS(SELECT 1 FROM team_members m WHERE m.team_id = c.team_id AND m.user_id = a.user_id)
+                    AS \"is_member!\",
+                a.created_at
+         FROM credential_acl a
+         JOIN credentials c ON c.id = a.credential_id
+         JOIN users u ON u.id = a.user_id
+         WHERE a.credential_id = $1
+         ORDER BY lower(u.email), a.user_id",
+        credential_id
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(CredentialAclResponse { restricted: !entries.is_empty(), entries })
+}
+
+// --- Routes ---
+
+/// Lists who may access a credential, for an admin of its team.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team, or `403 Forbidden` for a token not scoped to the
+/// team or a member who isn't a team admin.
+#[get("/<id>/acl")]
+pub async fn get_credential_acl(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<CredentialAclResponse>, ApiError> {
+    let team_id = sqlx::query_scalar!("SELECT team_id FROM credentials WHERE id = $1 AND deleted_at IS NULL", id)
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+    require_team_admin(db.as_mut(), team_id, user.id).await?;
+
+    Ok(Json(fetch_acl(db.as_mut(), id).await?))
+}
+
+/// Restricts a credential to some members of its team, for an admin of the team, and
+/// returns the new list. Once it has entries, only the team's admins and the members
+/// listed with `can_read` can see it, in listings as well as by id, and only admins and
+/// those with `can_write` can change it; other members get `404 Not Found` as if it
+/// didn't exist. Admins needn't be listed. The entries replace those before, and an
+/// empty list opens the credential to the whole team again.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team, `403 Forbidden` for a token not scoped to the team
+/// or a member who isn't a team admin, or `422 Unprocessable Entity` listing invalid
+/// entries, such as a user who isn't a member of the team.
+#[put("/<id>/acl", data = "<acl_data>")]
+pub async fn set_credential_acl(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+    acl_data: JsonBody<SetCredentialAclRequest>,
+) -> Result<Json<CredentialAclResponse>, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // The row lock keeps concurrent changes to the list from interleaving.
+    let team_id = sqlx::query_scalar!(
+        "SELECT team_id FROM credentials WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+    require_team_admin(&mut tx, team_id, user.id).await?;
+
+    let user_ids: Vec<Uuid> = acl_data.entries.iter().map(|entry| entry.user_id).collect();
+    let members: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT user_id FROM team_members WHERE team_id = $1 AND user_id = ANY($2)",
+        team_id,
+        &user_ids
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut v = Validator::new();
+    v.check(
+        "entries",
+        acl_data.entries.len() <= MAX_ACL_ENTRIES,
+        format!("must list at most {} members", MAX_ACL_ENTRIES),
+    );
+    for (index, entry) in acl_data.entries.iter().enumerate() {
+        v.check_entry("entries", index, members.contains(&entry.user_id), "must be a member of the team")
+            .check_entry("entries", index, seen.insert(entry.user_id), "must not repeat a member")
+            .check_entry("entries", index, entry.can_read || !entry.can_write, "must have can_read for can_write");
+    }
+    v.finish()?;
+
+    sqlx::query!(
+        "DELETE FROM credential_acl WHERE credential_id = $1 AND user_id <> ALL($2)",
+        id,
+        &user_ids
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let can_read: Vec<bool> = acl_data.entries.iter().map(|entry| entry.can_read).collect();
+    let can_write: Vec<bool> = acl_data.entries.iter().map(|entry| entry.can_write).collect();
+    sqlx::query!(
+        "INSERT INTO credential_acl (credential_id, user_id, can_read, can_write)
+         SELECT $1, i.user_id, i.can_read, i.can_write
+         FROM UNNEST($2::uuid[], $3::bool[], $4::bool[]) AS i(user_id, can_read, can_write)
+         ON CONFLICT (credential_id, user_id) DO UPDATE SET can_read = EXCLUDED.can_read, can_write = EXCLUDED.can_write",
+        id,
+        &user_ids,
+        &can_read,
+        &can_write
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let acl = fetch_acl(&mut tx, id).await?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(acl))
+}
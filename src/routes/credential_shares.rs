@@ -0,0 +1,210 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::validation::Validator;
+use super::access_log::require_team_admin;
+use super::auth::deserialize_base64;
+use super::credentials::{MAX_SECRET_LEN, NONCE_LEN};
+
+// --- Request DTOs ---
+
+/// The credential's secret for another team to read.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShareCredentialRequest {
+    /// The secret, encrypted with the target team's key.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_secret`.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+// --- Response DTOs ---
+
+/// A team a credential is shared into.
+#[derive(Serialize)]
+pub struct CredentialShareEntry {
+    pub target_team_id: Uuid,
+    pub target_team_name: String,
+    /// Who last shared it, if they still have an account.
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Whether the secret has changed since it was last shared, so the target team reads
+    /// the old one until it is shared again.
+    pub stale: bool,
+}
+
+// --- Helpers ---
+
+/// The team of a credential outside the trash, for an admin of it whose token may
+/// `write` to it or only read it.
+async fn admin_credential_team(
+    conn: &mut sqlx::PgConnection,
+    user: &AuthenticatedUser,
+    credential_id: Uuid,
+    write: bool,
+) -> Result<Uuid, ApiError> {
+    let team_id = sqlx::query_scalar!(
+        "SELECT team_id FROM credentials WHERE id = $1 AND deleted_at IS NULL",
+        credential_id
+    )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let allowed = if write {
+        user.permissions.can_write_team(team_id)
+    } else {
+        user.permissions.can_read_team(team_id)
+    };
+    if !allowed {
+        return Err(insufficient_scope());
+    }
+    require_team_admin(conn, team_id, user.id).await?;
+    Ok(team_id)
+}
+
+// --- Routes ---
+
+/// Lists the teams a credential is shared into, by name, for an admin of its team.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team, or `403 Forbidden` for a token not scoped to the
+/// team or a member who isn't a team admin.
+#[get("/<id>/shares")]
+pub async fn list_credential_shares(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<Vec<CredentialShareEntry>>, ApiError> {
+    admin_credential_team(db.as_mut(), &user, id, false).await?;
+
+    let shares = sqlx::query_as!(
+        CredentialShareEntry,
+        "SELECT s.target_team_id, t.name AS target_team_name, s.created_by, s.created_at, s.updated_at,
+                c.secret_changed_at > s.updated_at AS \"stale!\"
+         FROM credential_shares s
+         JOIN credentials c ON c.id = s.credential_id
+         JOIN teams t ON t.id = s.target_team_id
+         WHERE s.credential_id = $1
+         ORDER BY lower(t.name), t.id",
+        id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(shares))
+}
+
+/// Shares a credential read-only into another team the caller belongs to, for an admin
+/// of the credential's team, or replaces the secret shared before. The client encrypts
+/// the secret again with the target team's key. Members of the target team then see the
+/// credential in its listings and by id, flagged with `shared_from_team`, without its
+/// extra fields or folder, and get `403 Forbidden` trying to change it. As the secret is
+/// a copy, it goes stale when the credential's secret changes until it is shared again.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team, `403 Forbidden` for a token not scoped to both
+/// teams or a member who isn't an admin of the credential's team, or
+/// `422 Unprocessable Entity` for the credential's own team or a team the caller isn't
+/// a member of, or a malformed secret.
+#[put("/<id>/shares/<team_id>", data = "<share_data>")]
+pub async fn share_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+    team_id: Uuid,
+    share_data: JsonBody<ShareCredentialRequest>,
+) -> Result<Json<CredentialShareEntry>, ApiError> {
+    let source_team_id = admin_credential_team(db.as_mut(), &user, id, true).await?;
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let is_member = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
+        team_id,
+        user.id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut v = Validator::new();
+    v.check("team_id", team_id != source_team_id, "must not be the credential's own team")
+        .check("team_id", is_member == Some(true), "must be a team you are a member of")
+        .check_max_len("encrypted_secret", &share_data.encrypted_secret, MAX_SECRET_LEN)
+        .check_len("nonce", &share_data.nonce, &[NONCE_LEN]);
+    v.finish()?;
+
+    let share = sqlx::query_as!(
+        CredentialShareEntry,
+        "WITH s AS (
+             INSERT INTO credential_shares (credential_id, target_team_id, encrypted_secret, nonce, created_by)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (credential_id, target_team_id) DO UPDATE
+                 SET encrypted_secret = EXCLUDED.encrypted_secret, nonce = EXCLUDED.nonce,
+                     created_by = EXCLUDED.created_by
+             RETURNING *
+         )
+         SELECT s.target_team_id, t.name AS target_team_name, s.created_by, s.created_at, s.updated_at,
+                c.secret_changed_at > s.updated_at AS \"stale!\"
+         FROM s
+         JOIN credentials c ON c.id = s.credential_id
+         JOIN teams t ON t.id = s.target_team_id",
+        id,
+        team_id,
+        share_data.encrypted_secret,
+        share_data.nonce,
+        user.id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(share))
+}
+
+/// Stops sharing a credential into a team, for an admin of the credential's team. The
+/// target team loses sight of it straight away.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, the
+/// caller isn't a member of its team, or it isn't shared into the team, or
+/// `403 Forbidden` for a token not scoped to the credential's team or a member who
+/// isn't a team admin.
+#[delete("/<id>/shares/<team_id>")]
+pub async fn unshare_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+    team_id: Uuid,
+) -> Result<Status, ApiError> {
+    admin_credential_team(db.as_mut(), &user, id, true).await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM credential_shares WHERE credential_id = $1 AND target_team_id = $2",
+        id,
+        team_id
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if result.rows_affected() == 0 {
+        return Err(Status::NotFound.into());
+    }
+    Ok(Status::NoContent)
+}
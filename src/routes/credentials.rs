@@ -0,0 +1,4388 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use chrono::{DateTime, Utc};
+use rocket::serde::json::serde_json;
+use sqlx::FromRow;
+use base64::Engine;
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::{self, JsonBody};
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::access_log::AccessAction;
+use crate::guards::{insufficient_scope, AuthenticatedUser, ClientInfo};
+use crate::models::{FieldType, SecretKind, SshKeyType, TeamRole, TotpAlgorithm, UriMatchType};
+use crate::ssh;
+use crate::uri_match::{parse_uri, specificity, PageUrl};
+use crate::usage::UsageRecorder;
+use crate::validation::{FieldError, Validator};
+use super::admin::escape_like;
+use super::auth::{deserialize_base64, deserialize_nullable, deserialize_optional_base64, serialize_base64};
+use super::folders::check_can_edit;
+use super::templates::{apply_template, expand_title_pattern, template_to_apply};
+
+/// The longest credential title accepted, in characters.
+pub(super) const MAX_TITLE_LEN: usize = 200;
+/// The length of the `" (copy)"` that titles a copied credential, in characters.
+const COPY_SUFFIX_LEN: usize = 7;
+/// The longest hostname accepted, in bytes (the DNS limit).
+const MAX_HOSTNAME_LEN: usize = 253;
+/// The longest username accepted, in characters.
+const MAX_USERNAME_LEN: usize = 255;
+/// The longest SSH public key accepted, in bytes; large RSA keys fit easily.
+const MAX_PUBLIC_KEY_LEN: usize = 16 * 1024;
+/// The largest encrypted secret accepted, in bytes.
+pub(super) const MAX_SECRET_LEN: usize = 64 * 1024;
+/// The most tags a credential can have.
+const MAX_TAGS: usize = 20;
+/// The longest tag accepted, in characters.
+const MAX_TAG_LEN: usize = 50;
+/// The largest metadata object accepted, in bytes of JSON.
+const MAX_METADATA_LEN: usize = 4 * 1024;
+/// The longest notes accepted, in bytes.
+const MAX_NOTES_LEN: usize = 10 * 1024;
+/// The most custom fields a credential can have.
+pub(super) const MAX_FIELDS: usize = 50;
+/// The longest custom field label accepted, in characters.
+pub(super) const MAX_FIELD_LABEL_LEN: usize = 100;
+/// The largest encrypted custom field value accepted, in bytes.
+const MAX_FIELD_VALUE_LEN: usize = 8 * 1024;
+/// The longest SSH key comment accepted, in characters.
+const MAX_SSH_COMMENT_LEN: usize = 255;
+/// The longest icon URL accepted, in bytes.
+const MAX_ICON_URL_LEN: usize = 2048;
+/// The most URIs a credential can have.
+const MAX_URIS: usize = 20;
+/// The longest URI accepted, in bytes.
+const MAX_URI_LEN: usize = 2048;
+/// The length of the nonces used by the client cipher (XChaCha20-Poly1305).
+pub(super) const NONCE_LEN: usize = 24;
+/// How many digits a `totp_secret` credential's codes have when not given.
+const DEFAULT_TOTP_DIGITS: i16 = 6;
+/// How long a `totp_secret` credential's codes last when not given, in seconds.
+const DEFAULT_TOTP_PERIOD: i32 = 30;
+/// The hash a `totp_secret` credential's codes are made with when not given.
+const DEFAULT_TOTP_ALGORITHM: TotpAlgorithm = TotpAlgorithm::Sha1;
+/// The most credentials a bulk delete, restore, or move takes at once.
+const MAX_BULK_IDS: usize = 500;
+/// The longest a TOTP code can last, in seconds.
+const MAX_TOTP_PERIOD: i32 = 300;
+/// The highest strength score a client can report, as zxcvbn rates passwords.
+const MAX_STRENGTH_SCORE: i16 = 4;
+/// The longest reused-secret group a client can report.
+const MAX_REUSED_GROUP_LEN: usize = 64;
+/// Credentials returned per page when no `limit` is given.
+const DEFAULT_PAGE_SIZE: i64 = 50;
+/// The largest `limit` accepted.
+const MAX_PAGE_SIZE: i64 = 200;
+/// How many days ahead `GET /credentials/expiring` looks when no `within_days` is given.
+const DEFAULT_EXPIRING_WITHIN_DAYS: i64 = 30;
+/// How many credentials the caller's recent list holds.
+const RECENT_LIMIT: i64 = 20;
+/// The most credentials `GET /credentials/match` returns.
+const MAX_MATCHES: usize = 50;
+
+/// The columns of [`CredentialSummary`] in `credentials c`, joined with [`AUTHOR_JOINS`].
+const SUMMARY_COLUMNS: &str =
+    "c.id, c.title, c.hostname, c.username, c.kind, c.public_key, c.ssh_key_type, c.ssh_fingerprint, c.ssh_comment,
+     c.totp_digits, c.totp_period, c.totp_algorithm, c.metadata, c.notes, c.icon_url, c.tags, c.folder_id, c.expires_at,
+     COALESCE(c.expires_at <= NOW(), FALSE) AS expired, c.secret_changed_at,
+     date_part('day', NOW() - c.secret_changed_at)::int AS secret_age_days, c.created_at, c.updated_at,
+     c.archived_at, c.created_by, created_by_user.name AS created_by_name, c.updated_by,
+     updated_by_user.name AS updated_by_name";
+
+/// Joins the users who created and last edited `credentials c`, for [`SUMMARY_COLUMNS`].
+const AUTHOR_JOINS: &str = "LEFT JOIN users created_by_user ON created_by_user.id = c.created_by
+         LEFT JOIN users updated_by_user ON updated_by_user.id = c.updated_by";
+
+/// The columns of [`CredentialSummary`] in `credentials c` for a credential shared into
+/// another team, where it is outside any folder and its authors, who belong to its own
+/// team, are left out.
+const SHARED_SUMMARY_COLUMNS: &str =
+    "c.id, c.title, c.hostname, c.username, c.kind, c.public_key, c.ssh_key_type, c.ssh_fingerprint, c.ssh_comment,
+     c.totp_digits, c.totp_period, c.totp_algorithm, c.metadata, c.notes, c.icon_url, c.tags, NULL::uuid AS folder_id,
+     c.expires_at,
+     COALESCE(c.expires_at <= NOW(), FALSE) AS expired, c.secret_changed_at,
+     date_part('day', NOW() - c.secret_changed_at)::int AS secret_age_days, c.created_at, c.updated_at,
+     c.archived_at, NULL::uuid AS created_by, NULL::text AS created_by_name, NULL::uuid AS updated_by,
+     NULL::text AS updated_by_name";
+
+/// The credentials listed for the team `$1`, as `c`: its own, bar those restricted from
+/// the user `$10`, and those shared into it, with the team they are shared from.
+/// Shared credentials sit unpinned at the top level.
+const TEAM_CREDENTIALS: &str = "(
+         SELECT c.id, c.title, c.hostname, c.username, c.kind, c.public_key, c.ssh_key_type, c.ssh_fingerprint,
+                c.ssh_comment, c.totp_digits, c.totp_period, c.totp_algorithm, c.metadata, c.notes, c.icon_url, c.tags,
+                c.folder_id, c.position, c.expires_at,
+                c.secret_changed_at, c.last_used_at, c.created_at, c.updated_at, c.archived_at, c.deleted_at,
+                c.created_by, c.updated_by, NULL::uuid AS shared_from_team
+         FROM credentials c
+         WHERE c.team_id = $1 AND credential_acl_allows(c.id, $10, FALSE)
+         UNION ALL
+         SELECT c.id, c.title, c.hostname, c.username, c.kind, c.public_key, c.ssh_key_type, c.ssh_fingerprint,
+                c.ssh_comment, c.totp_digits, c.totp_period, c.totp_algorithm, c.metadata, c.notes, c.icon_url, c.tags,
+                NULL, NULL, c.expires_at,
+                c.secret_changed_at, c.last_used_at, c.created_at, c.updated_at, c.archived_at, c.deleted_at,
+                NULL, NULL, c.team_id
+         FROM credential_shares s
+         JOIN credentials c ON c.id = s.credential_id
+         WHERE s.target_team_id = $1
+     ) c";
+
+// --- Request DTOs ---
+
+/// A new credential, encrypted by the client with the team key.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateCredentialRequest {
+    pub title: String,
+    /// Taken from the first of `uris` instead if any are given, so it can be left out.
+    #[serde(default)]
+    pub hostname: String,
+    pub username: String,
+    /// `password`, `ssh_key`, `api_token`, `secure_note`, `totp_secret`, or `credit_card`.
+    pub kind: SecretKind,
+    /// The public half of an `ssh_key` credential, in OpenSSH format. Stored in plaintext.
+    pub public_key: Option<String>,
+    /// `ed25519`, `rsa`, or `ecdsa`, for `ssh_key` credentials only.
+    pub ssh_key_type: Option<SshKeyType>,
+    /// The key's fingerprint as `ssh-keygen -l` prints it, `SHA256:` and unpadded
+    /// Base64, for `ssh_key` credentials only.
+    pub ssh_fingerprint: Option<String>,
+    /// The key's comment, such as `user@host`, for `ssh_key` credentials only.
+    pub ssh_comment: Option<String>,
+    /// How many digits codes have, 6 (the default) or 8, for `totp_secret` credentials
+    /// only.
+    pub totp_digits: Option<i16>,
+    /// How many seconds each code lasts, 30 by default, for `totp_secret` credentials
+    /// only.
+    pub totp_period: Option<i32>,
+    /// `sha1` (the default), `sha256`, or `sha512`, for `totp_secret` credentials only.
+    pub totp_algorithm: Option<TotpAlgorithm>,
+    /// Plaintext hints for listings, such as a card's brand and masked last digits.
+    /// Never anything sensitive. Defaults to an empty object.
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Plaintext notes for the team, such as who owns it, up to 10 KB. See
+    /// [`CredentialSummary::notes`].
+    #[serde(default)]
+    pub notes: String,
+    /// An `http` or `https` image URL for listings, such as the site's favicon.
+    pub icon_url: Option<String>,
+    /// Set `icon_url` to `https://<host>/favicon.ico` for the `hostname` instead.
+    #[serde(default)]
+    pub derive_icon: bool,
+    /// Up to 20 labels for filtering. Tags differing only in case count as one.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// A folder of the same team to put it in; top level if left out.
+    pub folder_id: Option<Uuid>,
+    /// When the credential should be rotated, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The secret, encrypted with the team key.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_secret`.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+    /// Extra fields besides the main secret, in the order they are shown.
+    #[serde(default)]
+    pub fields: Vec<CredentialFieldRequest>,
+    /// The addresses to fill it in on, in order. The host of the first becomes the
+    /// `hostname`.
+    #[serde(default)]
+    pub uris: Vec<CredentialUriRequest>,
+    /// How strong the secret is and what it is reused with, as the client worked out.
+    pub health: Option<CredentialHealthRequest>,
+}
+
+/// Credentials to add to a team at once, each like a single new credential.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportCredentialsRequest {
+    pub credentials: Vec<CreateCredentialRequest>,
+    /// Only validate the credentials, without adding them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A custom field of a credential, its value encrypted by the client with the team key.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CredentialFieldRequest {
+    /// A plaintext label to show, e.g. "PIN".
+    pub label: String,
+    pub field_type: FieldType,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_value: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_value`. Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+/// An address a credential applies to, e.g. `https://login.example.com` or `10.0.0.1`.
+/// Addresses without a scheme are read as `https://`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CredentialUriRequest {
+    pub uri: String,
+    /// `exact`, `host`, `domain` (the default), or `starts_with`.
+    #[serde(default)]
+    pub match_type: UriMatchType,
+}
+
+/// What a client worked out about a credential's secret, which the server can't read.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CredentialHealthRequest {
+    /// From 0, trivially guessable, to 4, very strong, as zxcvbn scores passwords.
+    pub strength_score: i16,
+    /// An opaque value equal for credentials of the team with the same secret, such as a
+    /// prefix of an HMAC of the plaintext keyed with the team key, if the client groups
+    /// them.
+    pub reused_group: Option<String>,
+}
+
+/// Changes to a credential. Omitted fields are left as they are.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateCredentialRequest {
+    pub title: Option<String>,
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    /// Switching to any kind but `ssh_key` also removes any `public_key` and SSH key details.
+    pub kind: Option<SecretKind>,
+    /// The public half of an `ssh_key` credential, in OpenSSH format.
+    pub public_key: Option<String>,
+    /// Like `public_key`, only for `ssh_key` credentials and removed on switching kinds.
+    pub ssh_key_type: Option<SshKeyType>,
+    pub ssh_fingerprint: Option<String>,
+    pub ssh_comment: Option<String>,
+    /// Only for `totp_secret` credentials, and removed on switching kinds. Switching to
+    /// `totp_secret` without them sets the defaults.
+    pub totp_digits: Option<i16>,
+    pub totp_period: Option<i32>,
+    pub totp_algorithm: Option<TotpAlgorithm>,
+    /// Replaces the plaintext hints for listings as a whole.
+    pub metadata: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Replaces the plaintext notes; `""` removes them.
+    pub notes: Option<String>,
+    /// An `http` or `https` image URL for listings, or `null` to remove it.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub icon_url: Option<Option<String>>,
+    /// Set `icon_url` to `https://<host>/favicon.ico` for the hostname it ends up with.
+    #[serde(default)]
+    pub derive_icon: bool,
+    /// Replaces the tags as a whole.
+    pub tags: Option<Vec<String>>,
+    /// A folder of the same team to move it to, or `null` for the top level.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub folder_id: Option<Option<Uuid>>,
+    /// When the credential should be rotated, or `null` for never.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    /// The new secret, encrypted with the team key. Must come with its `nonce`.
+    /// Encoded as Base64 in JSON.
+    #[serde(default, deserialize_with = "deserialize_optional_base64")]
+    pub encrypted_secret: Option<Vec<u8>>,
+    /// The nonce required to decrypt the new `encrypted_secret`.
+    /// Encoded as Base64 in JSON.
+    #[serde(default, deserialize_with = "deserialize_optional_base64")]
+    pub nonce: Option<Vec<u8>>,
+    /// Replaces the custom fields as a whole.
+    pub fields: Option<Vec<CredentialFieldRequest>>,
+    /// Replaces the URIs as a whole. The host of the first one replaces the `hostname`.
+    pub uris: Option<Vec<CredentialUriRequest>>,
+    /// Replaces what the client worked out about the secret, or `null` to remove it. A
+    /// new secret clears it unless given along.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub health: Option<Option<CredentialHealthRequest>>,
+    /// The `updated_at` the changes were made against. If the credential has changed
+    /// since, nothing is updated.
+    pub expected_updated_at: Option<DateTime<Utc>>,
+}
+
+/// The order of the credentials in one folder of a team, or its top level.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReorderCredentialsRequest {
+    /// The folder, or `null` for the top level.
+    pub folder_id: Option<Uuid>,
+    /// Credentials of the folder, first to last. Those left out sort after them by title.
+    pub ids: Vec<Uuid>,
+}
+
+/// Credentials to fetch with their secrets at once, e.g. to fill in several logins.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchCredentialsRequest {
+    pub ids: Vec<Uuid>,
+}
+
+/// Credentials of a team to move into its trash, or out of it, at once.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkCredentialsRequest {
+    pub ids: Vec<Uuid>,
+}
+
+/// Credentials of a team to move into one folder at once.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkMoveCredentialsRequest {
+    pub ids: Vec<Uuid>,
+    /// A folder of the same team, or `null` for the top level.
+    pub folder_id: Option<Uuid>,
+}
+
+/// A credential moving to another team, re-encrypted by the client with that team's key.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MoveCredentialRequest {
+    pub target_team_id: Uuid,
+    /// The secret, encrypted with the target team's key.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_secret`.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+    /// Every earlier version of the credential, re-encrypted the same way.
+    #[serde(default)]
+    pub versions: Vec<ReencryptedVersion>,
+    /// The key of every attachment of the credential, re-wrapped with the target team's
+    /// key.
+    #[serde(default)]
+    pub attachments: Vec<RewrappedAttachmentKey>,
+    /// The value of every custom field of the credential, re-encrypted with the target
+    /// team's key.
+    #[serde(default)]
+    pub fields: Vec<ReencryptedField>,
+}
+
+/// The secret of an earlier version, re-encrypted for a move.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReencryptedVersion {
+    pub version: i32,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+/// The value of a custom field, re-encrypted for a move.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReencryptedField {
+    pub position: i32,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_value: Vec<u8>,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+/// The key of an attachment, re-wrapped for a move.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewrappedAttachmentKey {
+    pub id: Uuid,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_key: Vec<u8>,
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub key_nonce: Vec<u8>,
+}
+
+/// The query parameters of a search across teams.
+#[derive(FromForm)]
+pub struct CredentialSearchQuery<'r> {
+    pub q: Option<&'r str>,
+    pub kind: Option<&'r str>,
+    pub tag: Vec<&'r str>,
+    pub include_archived: Option<bool>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// The query parameters of a team's credential listing.
+#[derive(FromForm)]
+pub struct CredentialListQuery<'r> {
+    pub q: Option<&'r str>,
+    pub kind: Option<&'r str>,
+    pub tag: Vec<&'r str>,
+    pub folder: Option<&'r str>,
+    pub expiring_within_days: Option<i64>,
+    pub updated_since: Option<&'r str>,
+    pub include_archived: Option<bool>,
+    pub sort: Option<&'r str>,
+    pub order: Option<&'r str>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// --- Response DTOs ---
+
+/// A credential as listed and returned on creation. The encrypted secret and its nonce
+/// are left out on purpose, so listings never ship the whole vault.
+#[derive(Serialize, FromRow)]
+pub struct CredentialSummary {
+    pub id: Uuid,
+    pub title: String,
+    pub hostname: String,
+    pub username: String,
+    pub kind: SecretKind,
+    /// The public half of an `ssh_key` credential, if one was stored.
+    pub public_key: Option<String>,
+    /// The algorithm of an `ssh_key` credential's key, if known.
+    pub ssh_key_type: Option<SshKeyType>,
+    /// The SHA256 fingerprint of an `ssh_key` credential's key, as `ssh-keygen -l` prints it.
+    pub ssh_fingerprint: Option<String>,
+    /// The comment of an `ssh_key` credential's key, such as `user@host`.
+    pub ssh_comment: Option<String>,
+    /// How many digits a `totp_secret` credential's codes have.
+    pub totp_digits: Option<i16>,
+    /// How many seconds each code of a `totp_secret` credential lasts.
+    pub totp_period: Option<i32>,
+    /// The hash a `totp_secret` credential's codes are made with.
+    pub totp_algorithm: Option<TotpAlgorithm>,
+    /// Plaintext hints set by the client, such as a card's brand and masked last digits.
+    pub metadata: serde_json::Value,
+    /// Notes such as "rotate quarterly, owned by infra", `""` if there are none. They are
+    /// stored in plaintext, so the server can read and search them, and are meant for
+    /// annotations only: anything secret belongs in a custom field, which is encrypted.
+    pub notes: String,
+    /// An image for listings, such as the site's favicon; clients load it themselves.
+    pub icon_url: Option<String>,
+    pub tags: Vec<String>,
+    /// The folder it is in, or `None` at the top level.
+    pub folder_id: Option<Uuid>,
+    /// When the credential should be rotated, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether `expires_at` has passed.
+    pub expired: bool,
+    /// When the encrypted secret last changed, unlike `updated_at`, which any edit bumps.
+    pub secret_changed_at: DateTime<Utc>,
+    /// How many whole days ago `secret_changed_at` was.
+    pub secret_age_days: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the credential was archived, leaving it out of most listings, if it is.
+    pub archived_at: Option<DateTime<Utc>>,
+    /// Who created the credential, unless it predates this being recorded or their
+    /// account has been deleted since, and their name. Left out of credentials shared
+    /// in from another team.
+    pub created_by: Option<Uuid>,
+    pub created_by_name: Option<String>,
+    /// Who last edited the credential, by an update, a version restore, or a move, on
+    /// the same terms.
+    pub updated_by: Option<Uuid>,
+    pub updated_by_name: Option<String>,
+}
+
+/// A new credential, with any others of its team it may be a duplicate of.
+#[derive(Serialize)]
+pub struct CreatedCredentialResponse {
+    #[serde(flatten)]
+    pub credential: CredentialSummary,
+    /// Credentials of the team with the same normalized hostname and username, unless
+    /// the check was turned off. Left out if there are none.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub possible_duplicate_of: Vec<Uuid>,
+}
+
+/// Credentials of a team with the same normalized hostname and username.
+#[derive(Serialize)]
+pub struct DuplicateGroup {
+    pub hostname: String,
+    pub username: String,
+    /// Oldest first.
+    pub credentials: Vec<CredentialSummary>,
+}
+
+/// A credential with its encrypted secret. Binary fields are encoded as Base64 in JSON.
+#[derive(Serialize, FromRow)]
+pub struct CredentialResponse {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub credential: CredentialSummary,
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub nonce: Vec<u8>,
+    /// For a credential shared into another team, the team it belongs to, which alone
+    /// may change it.
+    #[sqlx(default)]
+    pub shared_from_team: Option<Uuid>,
+    /// For a credential shared into another team, that team, whose key the secret is
+    /// encrypted with. Shared credentials come without their custom fields, which only
+    /// the team they belong to can decrypt.
+    #[sqlx(default)]
+    pub shared_into_team: Option<Uuid>,
+    /// Its custom fields, in order.
+    #[sqlx(skip)]
+    pub fields: Vec<CredentialField>,
+    /// The addresses it applies to, in order.
+    #[sqlx(skip)]
+    pub uris: Vec<CredentialUri>,
+}
+
+/// An address a credential applies to.
+#[derive(Serialize, Deserialize)]
+pub struct CredentialUri {
+    pub uri: String,
+    pub match_type: UriMatchType,
+}
+
+/// A custom field of a credential. Binary fields are encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct CredentialField {
+    pub label: String,
+    pub field_type: FieldType,
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_value: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+/// The outcome of a bulk import.
+#[derive(Serialize)]
+pub struct ImportCredentialsResponse {
+    pub dry_run: bool,
+    /// How many credentials were added, or would be without `dry_run`.
+    pub count: usize,
+    /// The ids of the added credentials, in the order they were given; empty for a dry run.
+    pub ids: Vec<Uuid>,
+}
+
+/// A credential of a bulk import that failed validation.
+#[derive(Serialize)]
+pub struct RejectedImportItem {
+    /// Its position in the request, counting from 0.
+    pub index: usize,
+    pub errors: Vec<FieldError>,
+}
+
+/// What a bulk delete or restore did with one credential.
+#[derive(Serialize)]
+pub struct BulkCredentialOutcome {
+    pub id: Uuid,
+    pub outcome: BulkOutcome,
+}
+
+/// The outcome for one credential of a bulk delete or restore.
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkOutcome {
+    /// Moved into the trash.
+    Deleted,
+    /// Left alone, as it was in the trash already.
+    AlreadyDeleted,
+    /// Moved out of the trash.
+    Restored,
+    /// Left alone, as it wasn't in the trash.
+    NotDeleted,
+    /// There is no such credential, or the caller's access restrictions keep them from
+    /// changing it.
+    NotFound,
+}
+
+/// The outcome of a bulk move.
+#[derive(Serialize)]
+pub struct BulkMoveResponse {
+    /// How many credentials changed folder; those already in it are left alone.
+    pub moved: u64,
+}
+
+/// A credential after an update, with when it was last updated before, so clients can
+/// tell whether someone else changed it in between.
+#[derive(Serialize)]
+pub struct UpdatedCredentialResponse {
+    #[serde(flatten)]
+    pub credential: CredentialSummary,
+    pub previous_updated_at: DateTime<Utc>,
+}
+
+/// A credential after restoring an earlier version of it.
+#[derive(Serialize)]
+pub struct RestoredCredentialResponse {
+    #[serde(flatten)]
+    pub credential: CredentialSummary,
+    /// The version number of the credential as it is now, one past its latest earlier
+    /// version.
+    pub version: i32,
+    /// The version that was restored.
+    pub restored_from: i32,
+}
+
+/// A credential as listed for the caller, with whether they have marked it a favorite.
+#[derive(Serialize, FromRow)]
+pub struct ListedCredential {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub credential: CredentialSummary,
+    pub is_favorite: bool,
+    /// For a credential shared into the team read-only, the team it belongs to.
+    pub shared_from_team: Option<Uuid>,
+}
+
+/// One page of a team's credentials.
+#[derive(Serialize)]
+pub struct CredentialPage {
+    pub items: Vec<ListedCredential>,
+    /// The total number of credentials in the team across all pages.
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// A credential found by a search across teams, with the team it belongs to.
+#[derive(Serialize, FromRow)]
+pub struct CredentialSearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub credential: CredentialSummary,
+    pub team_id: Uuid,
+    pub team_name: String,
+    pub is_favorite: bool,
+}
+
+/// A credential the caller used recently, with when they last used it.
+#[derive(Serialize, FromRow)]
+pub struct RecentCredential {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub credential: CredentialSearchHit,
+    pub used_at: DateTime<Utc>,
+}
+
+/// A credential matching a page being filled in, with the URI that matched it best.
+#[derive(Serialize)]
+pub struct CredentialMatch {
+    #[serde(flatten)]
+    pub credential: CredentialSearchHit,
+    /// The credential's URI that matched, or its hostname if it has none.
+    pub matched_uri: String,
+    pub match_type: UriMatchType,
+}
+
+/// One page of search hits across teams.
+#[derive(Serialize)]
+pub struct CredentialSearchPage {
+    pub items: Vec<CredentialSearchHit>,
+    /// The total number of hits across all pages.
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// An earlier version of a credential as listed, without its encrypted secret.
+#[derive(Serialize)]
+pub struct CredentialVersionSummary {
+    pub version: i32,
+    pub title: String,
+    pub hostname: String,
+    pub username: String,
+    pub kind: SecretKind,
+    pub public_key: Option<String>,
+    pub metadata: serde_json::Value,
+    pub tags: Vec<String>,
+    /// When this version was written.
+    pub written_at: DateTime<Utc>,
+    /// Who replaced this version with the next one, unless their account has been
+    /// deleted since, their name, and when. The next version's author, as the
+    /// credential's `updated_by` records it for the latest.
+    pub changed_by: Option<Uuid>,
+    pub changed_by_name: Option<String>,
+    pub changed_at: DateTime<Utc>,
+    /// The version the next one was restored from, if it was a restore.
+    pub restored_from: Option<i32>,
+}
+
+/// An earlier version of a credential with its encrypted secret. Binary fields are
+/// encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct CredentialVersionResponse {
+    #[serde(flatten)]
+    pub version: CredentialVersionSummary,
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+/// A tag in use in a team, with how many credentials have it.
+#[derive(Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+/// A credential in a team's trash.
+#[derive(Serialize)]
+pub struct TrashedCredentialResponse {
+    #[serde(flatten)]
+    pub credential: CredentialSummary,
+    pub deleted_at: DateTime<Utc>,
+    /// Who deleted the credential, unless their account has been deleted since.
+    pub deleted_by: Option<Uuid>,
+    pub deleted_by_email: Option<String>,
+}
+
+// --- Helpers ---
+
+/// A listed credential with the number of credentials matched across all pages.
+#[derive(FromRow)]
+struct CredentialPageRow {
+    #[sqlx(flatten)]
+    credential: ListedCredential,
+    total: i64,
+}
+
+/// A search hit with the number of hits across all pages.
+#[derive(FromRow)]
+struct CredentialSearchRow {
+    #[sqlx(flatten)]
+    hit: CredentialSearchHit,
+    total: i64,
+}
+
+/// A credential listed for a bulk change, held until the change commits.
+struct LockedCredential {
+    deleted: bool,
+    /// Whether the caller's access restrictions let them change it.
+    writable: bool,
+}
+
+/// Matches credentials whose title, hostname, username, or notes contain `$2`, a `LIKE`
+/// pattern, case-insensitively; a NULL pattern matches everything.
+const SEARCH_FILTER: &str =
+    "($2::text IS NULL OR c.title ILIKE $2 OR c.hostname ILIKE $2 OR c.username ILIKE $2 OR c.notes ILIKE $2)";
+
+/// Matches credentials whose kind is one of `$3`, an array of kind names; a NULL array
+/// matches every kind.
+const KIND_FILTER: &str = "($3::text[] IS NULL OR c.kind::text = ANY($3))";
+
+/// Matches credentials that have all of `$4`, an array of lowercase tags.
+const TAG_FILTER: &str = "ARRAY(SELECT lower(tag) FROM unnest(c.tags) tag) @> $4::text[]";
+
+/// Matches credentials in the folder `$5` if `$6` holds, where a NULL folder means the
+/// top level.
+const FOLDER_FILTER: &str = "($6::bool IS NOT TRUE OR c.folder_id IS NOT DISTINCT FROM $5::uuid)";
+
+/// Matches credentials expiring within `$7` days from now, including those that have
+/// already expired; a NULL number matches everything.
+const EXPIRY_FILTER: &str = "($7::int IS NULL OR c.expires_at <= NOW() + make_interval(days => $7))";
+
+/// Matches credentials changed after `$8`; a NULL time matches everything.
+const UPDATED_FILTER: &str = "($8::timestamptz IS NULL OR c.updated_at > $8)";
+
+/// Matches credentials that aren't archived, or all of them if `$9` holds.
+const ARCHIVED_FILTER: &str = "($9::bool OR c.archived_at IS NULL)";
+
+/// Parses a `folder` parameter for [`FOLDER_FILTER`], either a folder id or `none` for
+/// the top level, recording an error if it is neither.
+fn parse_folder(v: &mut Validator, folder: &str) -> Option<Uuid> {
+    if folder == "none" {
+        return None;
+    }
+    let id = folder.parse().ok();
+    v.check("folder", id.is_some(), "must be a folder id or none");
+    id
+}
+
+/// Parses a comma-separated list of kinds for [`KIND_FILTER`], recording an error if
+/// any of them is unknown.
+fn kind_names(v: &mut Validator, kind: &str) -> Vec<&'static str> {
+    let kinds: Option<Vec<_>> = kind.split(',').map(|name| SecretKind::from_name(name.trim())).collect();
+    let allowed: Vec<_> = SecretKind::ALL.iter().map(SecretKind::as_str).collect();
+    v.check("kind", kinds.is_some(), format!("must be a comma-separated list of {}", allowed.join(", ")));
+    kinds.unwrap_or_default().iter().map(SecretKind::as_str).collect()
+}
+
+/// Turns a search term into a pattern for [`SEARCH_FILTER`], recording an error if it
+/// is blank.
+fn search_pattern(v: &mut Validator, q: &str) -> String {
+    let q = q.trim();
+    v.check("q", !q.is_empty(), "must not be blank");
+    format!("%{}%", escape_like(q))
+}
+
+/// Maps a `sort` parameter to the column it orders by. Text columns sort
+/// case-insensitively.
+fn sort_column(sort: &str) -> Option<&'static str> {
+    match sort {
+        "title" => Some("lower(c.title)"),
+        "hostname" => Some("lower(c.hostname)"),
+        "created_at" => Some("c.created_at"),
+        "updated_at" => Some("c.updated_at"),
+        "last_used_at" => Some("c.last_used_at"),
+        "expires_at" => Some("c.expires_at"),
+        _ => None,
+    }
+}
+
+/// Maps an `order` parameter to its SQL direction.
+fn sort_direction(order: &str) -> Option<&'static str> {
+    match order {
+        "asc" => Some("ASC"),
+        "desc" => Some("DESC"),
+        _ => None,
+    }
+}
+
+/// Whether `value`, once trimmed, is 1 to `max` characters long.
+pub(super) fn is_valid_text(value: &str, max: usize) -> bool {
+    let len = value.trim().chars().count();
+    (1..=max).contains(&len)
+}
+
+/// Checks the labels of a credential that is (or becomes) of `kind`: the title if it is
+/// given, and the hostname and username it ends up with. Secure notes may leave the
+/// hostname and username empty, and credit cards have no hostname and an optional
+/// username.
+fn check_labels(v: &mut Validator, kind: &SecretKind, title: Option<&str>, hostname: &str, username: &str) {
+    if let Some(title) = title {
+        v.check("title", is_valid_text(title, MAX_TITLE_LEN), format!("must be between 1 and {} characters", MAX_TITLE_LEN));
+    }
+
+    let hostname_len = hostname.trim().len();
+    match kind {
+        SecretKind::CreditCard => {
+            v.check("hostname", hostname_len == 0, "must be empty for credit_card credentials");
+        }
+        SecretKind::SecureNote => {
+            v.check("hostname", hostname_len <= MAX_HOSTNAME_LEN, format!("must be at most {} bytes", MAX_HOSTNAME_LEN));
+        }
+        _ => {
+            v.check(
+                "hostname",
+                (1..=MAX_HOSTNAME_LEN).contains(&hostname_len),
+                format!("must be between 1 and {} bytes", MAX_HOSTNAME_LEN),
+            );
+        }
+    }
+
+    if matches!(kind, SecretKind::SecureNote | SecretKind::CreditCard) {
+        v.check(
+            "username",
+            username.trim().chars().count() <= MAX_USERNAME_LEN,
+            format!("must be at most {} characters", MAX_USERNAME_LEN),
+        );
+    } else {
+        v.check(
+            "username",
+            is_valid_text(username, MAX_USERNAME_LEN),
+            format!("must be between 1 and {} characters", MAX_USERNAME_LEN),
+        );
+    }
+}
+
+/// Trims the tags given for a credential and drops those repeating an earlier one but
+/// for case, recording an error if any is blank or too long, or there are too many.
+pub(super) fn normalize_tags(v: &mut Validator, tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|tag| tag.trim()) {
+        if !normalized.iter().any(|kept| kept.to_lowercase() == tag.to_lowercase()) {
+            normalized.push(tag.to_string());
+        }
+    }
+    v.check(
+        "tags",
+        normalized.iter().all(|tag| is_valid_text(tag, MAX_TAG_LEN)),
+        format!("must each be between 1 and {} characters", MAX_TAG_LEN),
+    );
+    v.check("tags", normalized.len() <= MAX_TAGS, format!("must be at most {} tags", MAX_TAGS));
+    normalized
+}
+
+/// Checks the length of the notes given for a credential.
+fn check_notes(v: &mut Validator, notes: Option<&str>) {
+    if let Some(notes) = notes {
+        v.check("notes", notes.len() <= MAX_NOTES_LEN, format!("must be at most {} bytes", MAX_NOTES_LEN));
+    }
+}
+
+/// Checks the size of the metadata given for a credential.
+fn check_metadata(v: &mut Validator, metadata: Option<&serde_json::Map<String, serde_json::Value>>) {
+    if let Some(metadata) = metadata {
+        let len = serde_json::to_vec(metadata).map_or(usize::MAX, |json| json.len());
+        v.check("metadata", len <= MAX_METADATA_LEN, format!("must be at most {} bytes of JSON", MAX_METADATA_LEN));
+    }
+}
+
+/// Checks a public key given for a credential that is (or becomes) of `kind`. It must
+/// be an OpenSSH public key whose blob matches its declared algorithm.
+fn check_public_key(v: &mut Validator, kind: &SecretKind, public_key: Option<&str>) {
+    match (kind, public_key) {
+        (SecretKind::SshKey, Some(public_key)) => {
+            let len = public_key.trim().len();
+            if !(1..=MAX_PUBLIC_KEY_LEN).contains(&len) {
+                v.check("public_key", false, format!("must be between 1 and {} bytes", MAX_PUBLIC_KEY_LEN));
+            } else if let Err(reason) = ssh::parse_public_key(public_key) {
+                v.check("public_key", false, reason);
+            }
+        }
+        (_, Some(_)) => {
+            v.check("public_key", false, "is only allowed for ssh_key credentials");
+        }
+        (_, None) => {}
+    }
+}
+
+/// Checks the SSH key details given for a credential that is (or becomes) of `kind`.
+/// Given with a `public_key`, the type and fingerprint must be the key's own.
+fn check_ssh_details(
+    v: &mut Validator,
+    kind: &SecretKind,
+    public_key: Option<&str>,
+    key_type: Option<&SshKeyType>,
+    fingerprint: Option<&str>,
+    comment: Option<&str>,
+) {
+    if !matches!(kind, SecretKind::SshKey) {
+        v.check("ssh_key_type", key_type.is_none(), "is only allowed for ssh_key credentials");
+        v.check("ssh_fingerprint", fingerprint.is_none(), "is only allowed for ssh_key credentials");
+        v.check("ssh_comment", comment.is_none(), "is only allowed for ssh_key credentials");
+        return;
+    }
+    if let Some(fingerprint) = fingerprint {
+        let digest = fingerprint
+            .trim()
+            .strip_prefix("SHA256:")
+            .and_then(|digest| base64::engine::general_purpose::STANDARD_NO_PAD.decode(digest).ok());
+        v.check(
+            "ssh_fingerprint",
+            digest.is_some_and(|digest| digest.len() == 32),
+            "must be SHA256: followed by the unpadded Base64 of a SHA-256 digest",
+        );
+    }
+    if let Some(comment) = comment {
+        v.check(
+            "ssh_comment",
+            is_valid_text(comment, MAX_SSH_COMMENT_LEN),
+            format!("must be between 1 and {} characters", MAX_SSH_COMMENT_LEN),
+        );
+    }
+    if let Some(key) = public_key.and_then(|public_key| ssh::parse_public_key(public_key).ok()) {
+        if let Some(key_type) = key_type {
+            v.check("ssh_key_type", *key_type == key.key_type, "doesn't match public_key");
+        }
+        if let Some(fingerprint) = fingerprint {
+            v.check("ssh_fingerprint", fingerprint.trim() == key.fingerprint, "doesn't match public_key");
+        }
+    }
+}
+
+/// The SSH key details to store for a credential, read from its `public_key` when one
+/// is given. A comment given alongside takes the place of the key's own.
+struct SshDetails {
+    key_type: Option<SshKeyType>,
+    fingerprint: Option<String>,
+    comment: Option<String>,
+}
+
+impl SshDetails {
+    fn new(
+        public_key: Option<&str>,
+        key_type: Option<SshKeyType>,
+        fingerprint: Option<&str>,
+        comment: Option<&str>,
+    ) -> Self {
+        let comment = comment.map(|comment| comment.trim().to_string());
+        match public_key.and_then(|public_key| ssh::parse_public_key(public_key).ok()) {
+            Some(key) => SshDetails {
+                key_type: Some(key.key_type),
+                fingerprint: Some(key.fingerprint),
+                comment: comment.or(key.comment),
+            },
+            None => SshDetails {
+                key_type,
+                fingerprint: fingerprint.map(|fingerprint| fingerprint.trim().to_string()),
+                comment,
+            },
+        }
+    }
+}
+
+/// Checks the TOTP parameters given for a credential that is (or becomes) of `kind`.
+fn check_totp_details(
+    v: &mut Validator,
+    kind: &SecretKind,
+    digits: Option<i16>,
+    period: Option<i32>,
+    algorithm: Option<&TotpAlgorithm>,
+) {
+    if !matches!(kind, SecretKind::TotpSecret) {
+        v.check("totp_digits", digits.is_none(), "is only allowed for totp_secret credentials");
+        v.check("totp_period", period.is_none(), "is only allowed for totp_secret credentials");
+        v.check("totp_algorithm", algorithm.is_none(), "is only allowed for totp_secret credentials");
+        return;
+    }
+    if let Some(digits) = digits {
+        v.check("totp_digits", matches!(digits, 6 | 8), "must be 6 or 8");
+    }
+    if let Some(period) = period {
+        v.check(
+            "totp_period",
+            (1..=MAX_TOTP_PERIOD).contains(&period),
+            format!("must be between 1 and {} seconds", MAX_TOTP_PERIOD),
+        );
+    }
+}
+
+/// The TOTP parameters to store for a credential of `kind`: those given, or the
+/// defaults, for a `totp_secret` credential, and none for any other.
+struct TotpDetails {
+    digits: Option<i16>,
+    period: Option<i32>,
+    algorithm: Option<TotpAlgorithm>,
+}
+
+impl TotpDetails {
+    fn new(kind: &SecretKind, digits: Option<i16>, period: Option<i32>, algorithm: Option<TotpAlgorithm>) -> Self {
+        if !matches!(kind, SecretKind::TotpSecret) {
+            return TotpDetails { digits: None, period: None, algorithm: None };
+        }
+        TotpDetails {
+            digits: Some(digits.unwrap_or(DEFAULT_TOTP_DIGITS)),
+            period: Some(period.unwrap_or(DEFAULT_TOTP_PERIOD)),
+            algorithm: Some(algorithm.unwrap_or(DEFAULT_TOTP_ALGORITHM)),
+        }
+    }
+}
+
+/// Checks the icon given for a credential: `icon_url` is `None` if left out and
+/// `Some(None)` if removed. A URL must be `http` or `https`, and can't come with
+/// `derive_icon`, which needs a `hostname` to derive from.
+fn check_icon(v: &mut Validator, icon_url: Option<Option<&str>>, derive_icon: bool, hostname: &str) {
+    if let Some(Some(icon_url)) = icon_url {
+        let url = url::Url::parse(icon_url.trim()).ok();
+        let valid = icon_url.trim().len() <= MAX_ICON_URL_LEN
+            && url.is_some_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+        v.check("icon_url", valid, format!("must be an http or https URL of at most {} bytes", MAX_ICON_URL_LEN));
+    }
+    if derive_icon {
+        v.check("derive_icon", icon_url.is_none(), "can't be combined with icon_url");
+        v.check("derive_icon", derived_icon_url(hostname).is_some(), "needs a hostname to derive the icon from");
+    }
+}
+
+/// The conventional favicon location for `hostname`, ignoring any port.
+fn derived_icon_url(hostname: &str) -> Option<String> {
+    let url = parse_uri(hostname)?;
+    Some(format!("https://{}/favicon.ico", url.host_str()?))
+}
+
+/// The icon a new credential gets: its `icon_url` as given, or derived if asked for.
+fn new_icon_url(credential: &CreateCredentialRequest, hostname: &str) -> Option<String> {
+    if credential.derive_icon {
+        derived_icon_url(hostname)
+    } else {
+        credential.icon_url.as_deref().map(|icon_url| icon_url.trim().to_string())
+    }
+}
+
+/// Checks the custom fields given for a credential.
+fn check_fields(v: &mut Validator, fields: &[CredentialFieldRequest]) {
+    v.check("fields", fields.len() <= MAX_FIELDS, format!("must be at most {} fields", MAX_FIELDS));
+    for field in fields {
+        v.check(
+            "fields",
+            is_valid_text(&field.label, MAX_FIELD_LABEL_LEN),
+            format!("labels must be between 1 and {} characters", MAX_FIELD_LABEL_LEN),
+        )
+            .check_max_len("fields", &field.encrypted_value, MAX_FIELD_VALUE_LEN)
+            .check_len("fields", &field.nonce, &[NONCE_LEN]);
+    }
+}
+
+/// The host of `uri`, lowercased, or `None` if it isn't a URI with a host. A URI without
+/// a scheme is read as `https://`.
+fn uri_host(uri: &str) -> Option<String> {
+    Some(parse_uri(uri)?.host_str()?.trim_matches(['[', ']']).to_string())
+}
+
+/// The hostname a credential ends up with: the host of the first of `uris`, or
+/// `hostname` if there are none.
+pub(super) fn effective_hostname(hostname: &str, uris: &[CredentialUriRequest]) -> String {
+    match uris.first() {
+        Some(first) => uri_host(&first.uri).unwrap_or_default(),
+        None => hostname.trim().to_string(),
+    }
+}
+
+/// Checks the health a client reported for a credential.
+fn check_health(v: &mut Validator, health: Option<&CredentialHealthRequest>) {
+    let Some(health) = health else {
+        return;
+    };
+    v.check(
+        "health.strength_score",
+        (0..=MAX_STRENGTH_SCORE).contains(&health.strength_score),
+        format!("must be between 0 and {}", MAX_STRENGTH_SCORE),
+    );
+    if let Some(reused_group) = &health.reused_group {
+        v.check(
+            "health.reused_group",
+            (1..=MAX_REUSED_GROUP_LEN).contains(&reused_group.len()) && reused_group.chars().all(|c| c.is_ascii_graphic()),
+            format!("must be between 1 and {} printable ASCII characters", MAX_REUSED_GROUP_LEN),
+        );
+    }
+}
+
+/// Checks the URIs given for a credential of `kind`, reporting each invalid one by its
+/// index. Credit cards, having no hostname, can't have any.
+pub(super) fn check_uris(v: &mut Validator, kind: &SecretKind, uris: &[CredentialUriRequest]) {
+    if matches!(kind, SecretKind::CreditCard) {
+        v.check("uris", uris.is_empty(), "must be empty for credit_card credentials");
+        return;
+    }
+    v.check("uris", uris.len() <= MAX_URIS, format!("must be at most {} URIs", MAX_URIS));
+    for (index, entry) in uris.iter().enumerate() {
+        let uri = entry.uri.trim();
+        v.check_entry(
+            "uris",
+            index,
+            (1..=MAX_URI_LEN).contains(&uri.len()) && uri_host(uri).is_some(),
+            format!("must be a URI with a host, of at most {} bytes", MAX_URI_LEN),
+        );
+    }
+}
+
+/// Checks a new credential, returning its normalized tags.
+pub(super) fn check_new_credential(v: &mut Validator, credential: &CreateCredentialRequest) -> Vec<String> {
+    let hostname = effective_hostname(&credential.hostname, &credential.uris);
+    check_labels(v, &credential.kind, Some(&credential.title), &hostname, &credential.username);
+    check_uris(v, &credential.kind, &credential.uris);
+    check_icon(v, credential.icon_url.as_deref().map(Some), credential.derive_icon, &hostname);
+    v.check_max_len("encrypted_secret", &credential.encrypted_secret, MAX_SECRET_LEN)
+        .check_len("nonce", &credential.nonce, &[NONCE_LEN]);
+    check_public_key(v, &credential.kind, credential.public_key.as_deref());
+    check_ssh_details(
+        v,
+        &credential.kind,
+        credential.public_key.as_deref(),
+        credential.ssh_key_type.as_ref(),
+        credential.ssh_fingerprint.as_deref(),
+        credential.ssh_comment.as_deref(),
+    );
+    check_totp_details(
+        v,
+        &credential.kind,
+        credential.totp_digits,
+        credential.totp_period,
+        credential.totp_algorithm.as_ref(),
+    );
+    check_metadata(v, credential.metadata.as_ref());
+    check_notes(v, Some(&credential.notes));
+    check_fields(v, &credential.fields);
+    check_health(v, credential.health.as_ref());
+    normalize_tags(v, &credential.tags)
+}
+
+/// A hostname or URL reduced to a bare lowercase host, so the same site compares equal
+/// however it was entered: `https://user@www.Example.com:8443/login` becomes
+/// `example.com`. Values without a scheme are read as starting with the host. Returns
+/// `""` if there is no host.
+pub(super) fn normalize_hostname(hostname: &str) -> String {
+    let hostname = hostname.trim();
+    let rest = hostname.split_once("://").map_or(hostname, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    // Keep IPv6 literals whole; otherwise drop any port.
+    let host = match host.strip_prefix('[') {
+        Some(literal) => literal.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    match host.strip_prefix("www.") {
+        Some(rest) if !rest.is_empty() => rest.to_string(),
+        _ => host,
+    }
+}
+
+/// The most specific URI of a credential matching a page, with what the credential is
+/// ranked by after that.
+struct UriMatch {
+    match_type: UriMatchType,
+    uri: String,
+    last_used_at: Option<DateTime<Utc>>,
+    /// Lowercased.
+    title: String,
+}
+
+/// Whether a member with `role` may change the team's credentials.
+pub(super) fn can_edit_credentials(role: &TeamRole) -> bool {
+    matches!(role, TeamRole::Member | TeamRole::Admin)
+}
+
+/// The error for a member who may read a restricted credential but not change it.
+pub(super) fn restricted_credential() -> ApiError {
+    ApiError::new(Status::Forbidden, "restricted_credential")
+}
+
+/// Adds validated credentials to a team with one statement as created by `user_id`,
+/// returning their ids in the order given. `tags` holds the normalized tags of each
+/// credential.
+pub(super) async fn insert_credentials(
+    conn: &mut sqlx::PgConnection,
+    team_id: Uuid,
+    user_id: Uuid,
+    credentials: &[CreateCredentialRequest],
+    tags: Vec<Vec<String>>,
+) -> Result<Vec<Uuid>, ApiError> {
+    // One row per credential, unnested from a column array each. Ids are made here so
+    // they come back in the order given. Postgres has no ragged arrays, so the tags of
+    // each credential travel as a JSON array.
+    let ids: Vec<Uuid> = credentials.iter().map(|_| Uuid::new_v4()).collect();
+    let titles: Vec<String> = credentials.iter().map(|c| c.title.trim().to_string()).collect();
+    let hostnames: Vec<String> = credentials.iter().map(|c| effective_hostname(&c.hostname, &c.uris)).collect();
+    let icon_urls: Vec<Option<String>> =
+        credentials.iter().zip(&hostnames).map(|(c, hostname)| new_icon_url(c, hostname)).collect();
+    let usernames: Vec<String> = credentials.iter().map(|c| c.username.trim().to_string()).collect();
+    let kinds: Vec<String> = credentials.iter().map(|c| c.kind.as_str().to_string()).collect();
+    let public_keys: Vec<Option<String>> =
+        credentials.iter().map(|c| c.public_key.as_deref().map(|key| key.trim().to_string())).collect();
+    let mut ssh_key_types: Vec<Option<String>> = Vec::with_capacity(credentials.len());
+    let mut ssh_fingerprints: Vec<Option<String>> = Vec::with_capacity(credentials.len());
+    let mut ssh_comments: Vec<Option<String>> = Vec::with_capacity(credentials.len());
+    for c in credentials {
+        let ssh = SshDetails::new(
+            c.public_key.as_deref(),
+            c.ssh_key_type,
+            c.ssh_fingerprint.as_deref(),
+            c.ssh_comment.as_deref(),
+        );
+        ssh_key_types.push(ssh.key_type.map(|key_type| key_type.as_str().to_string()));
+        ssh_fingerprints.push(ssh.fingerprint);
+        ssh_comments.push(ssh.comment);
+    }
+    let mut totp_digits: Vec<Option<i16>> = Vec::with_capacity(credentials.len());
+    let mut totp_periods: Vec<Option<i32>> = Vec::with_capacity(credentials.len());
+    let mut totp_algorithms: Vec<Option<String>> = Vec::with_capacity(credentials.len());
+    for c in credentials {
+        let totp = TotpDetails::new(&c.kind, c.totp_digits, c.totp_period, c.totp_algorithm);
+        totp_digits.push(totp.digits);
+        totp_periods.push(totp.period);
+        totp_algorithms.push(totp.algorithm.map(|algorithm| algorithm.as_str().to_string()));
+    }
+    let metadata: Vec<serde_json::Value> =
+        credentials.iter().map(|c| serde_json::Value::Object(c.metadata.clone().unwrap_or_default())).collect();
+    let notes: Vec<String> = credentials.iter().map(|c| c.notes.trim().to_string()).collect();
+    let tags: Vec<serde_json::Value> = tags.into_iter().map(serde_json::Value::from).collect();
+    let folder_ids: Vec<Option<Uuid>> = credentials.iter().map(|c| c.folder_id).collect();
+    let expires_at: Vec<Option<DateTime<Utc>>> = credentials.iter().map(|c| c.expires_at).collect();
+    let secrets: Vec<Vec<u8>> = credentials.iter().map(|c| c.encrypted_secret.clone()).collect();
+    let nonces: Vec<Vec<u8>> = credentials.iter().map(|c| c.nonce.clone()).collect();
+    let strength_scores: Vec<Option<i16>> =
+        credentials.iter().map(|c| c.health.as_ref().map(|health| health.strength_score)).collect();
+    let reused_groups: Vec<Option<String>> =
+        credentials.iter().map(|c| c.health.as_ref().and_then(|health| health.reused_group.clone())).collect();
+    sqlx::query!(
+        "INSERT INTO credentials
+             (id, team_id, title, hostname, username, kind, public_key, metadata, tags, folder_id, expires_at,
+              encrypted_secret, nonce, icon_url, ssh_key_type, ssh_fingerprint, ssh_comment,
+              health_strength_score, health_reused_group, health_reported_at, totp_digits, totp_period, totp_algorithm,
+              notes, created_by, updated_by)
+         SELECT i.id, $1, i.title, i.hostname, i.username, i.kind::secret_kind, i.public_key, i.metadata,
+                ARRAY(SELECT jsonb_array_elements_text(i.tags)), i.folder_id, i.expires_at, i.encrypted_secret,
+                i.nonce, i.icon_url, i.ssh_key_type::ssh_key_type, i.ssh_fingerprint, i.ssh_comment,
+                i.strength_score, i.reused_group, CASE WHEN i.strength_score IS NOT NULL THEN NOW() END,
+                i.totp_digits, i.totp_period, i.totp_algorithm::totp_algorithm, i.notes, $24, $24
+         FROM UNNEST($2::uuid[], $3::text[], $4::text[], $5::text[], $6::text[], $7::text[], $8::jsonb[],
+                     $9::jsonb[], $10::uuid[], $11::timestamptz[], $12::bytea[], $13::bytea[], $14::text[],
+                     $15::text[], $16::text[], $17::text[], $18::smallint[], $19::text[], $20::smallint[],
+                     $21::int[], $22::text[], $23::text[])
+              AS i(id, title, hostname, username, kind, public_key, metadata, tags, folder_id, expires_at,
+                   encrypted_secret, nonce, icon_url, ssh_key_type, ssh_fingerprint, ssh_comment, strength_score,
+                   reused_group, totp_digits, totp_period, totp_algorithm, notes)",
+        team_id,
+        &ids,
+        &titles,
+        &hostnames,
+        &usernames,
+        &kinds,
+        // The macro only takes nullable array elements as slices of options.
+        &public_keys as &[Option<String>],
+        &metadata,
+        &tags,
+        &folder_ids as &[Option<Uuid>],
+        &expires_at as &[Option<DateTime<Utc>>],
+        &secrets,
+        &nonces,
+        &icon_urls as &[Option<String>],
+        &ssh_key_types as &[Option<String>],
+        &ssh_fingerprints as &[Option<String>],
+        &ssh_comments as &[Option<String>],
+        &strength_scores as &[Option<i16>],
+        &reused_groups as &[Option<String>],
+        &totp_digits as &[Option<i16>],
+        &totp_periods as &[Option<i32>],
+        &totp_algorithms as &[Option<String>],
+        &notes,
+        user_id
+    )
+        .execute(&mut *conn)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    let fields: Vec<(Uuid, &[CredentialFieldRequest])> =
+        ids.iter().zip(credentials).map(|(id, credential)| (*id, credential.fields.as_slice())).collect();
+    insert_fields(conn, &fields).await?;
+    let uris: Vec<(Uuid, &[CredentialUriRequest])> =
+        ids.iter().zip(credentials).map(|(id, credential)| (*id, credential.uris.as_slice())).collect();
+    insert_uris(conn, &uris).await?;
+
+    Ok(ids)
+}
+
+/// Adds custom fields to credentials that have none, numbering each credential's
+/// fields from 0 in the order given.
+async fn insert_fields(conn: &mut sqlx::PgConnection, fields: &[(Uuid, &[CredentialFieldRequest])]) -> Result<(), Status> {
+    let rows = fields.iter().flat_map(|(id, fields)| fields.iter().enumerate().map(move |(position, field)| (*id, position, field)));
+    let (mut credential_ids, mut positions, mut labels, mut types, mut values, mut nonces) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (id, position, field) in rows {
+        credential_ids.push(id);
+        positions.push(position as i32);
+        labels.push(field.label.trim().to_string());
+        types.push(field.field_type.as_str().to_string());
+        values.push(field.encrypted_value.clone());
+        nonces.push(field.nonce.clone());
+    }
+    if credential_ids.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "INSERT INTO credential_fields (credential_id, position, label, field_type, encrypted_value, nonce)
+         SELECT i.credential_id, i.position, i.label, i.field_type::credential_field_type, i.encrypted_value, i.nonce
+         FROM UNNEST($1::uuid[], $2::int[], $3::text[], $4::text[], $5::bytea[], $6::bytea[])
+              AS i(credential_id, position, label, field_type, encrypted_value, nonce)",
+        &credential_ids,
+        &positions,
+        &labels,
+        &types,
+        &values,
+        &nonces
+    )
+        .execute(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(())
+}
+
+/// Adds URIs to credentials that have none, numbering each credential's URIs from 0 in
+/// the order given.
+async fn insert_uris(conn: &mut sqlx::PgConnection, uris: &[(Uuid, &[CredentialUriRequest])]) -> Result<(), Status> {
+    let rows = uris.iter().flat_map(|(id, uris)| uris.iter().enumerate().map(move |(position, uri)| (*id, position, uri)));
+    let (mut credential_ids, mut positions, mut values, mut match_types) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (id, position, uri) in rows {
+        credential_ids.push(id);
+        positions.push(position as i32);
+        values.push(uri.uri.trim().to_string());
+        match_types.push(uri.match_type.as_str().to_string());
+    }
+    if credential_ids.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "INSERT INTO credential_uris (credential_id, position, uri, match_type)
+         SELECT i.credential_id, i.position, i.uri, i.match_type::uri_match_type
+         FROM UNNEST($1::uuid[], $2::int[], $3::text[], $4::text[]) AS i(credential_id, position, uri, match_type)",
+        &credential_ids,
+        &positions,
+        &values,
+        &match_types
+    )
+        .execute(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(())
+}
+
+/// Saves a credential as it is now as its next earlier version, returning the number of
+/// the saved version.
+///
+/// Must run in a transaction holding a lock on the credential row, which keeps version
+/// numbers from clashing.
+async fn save_version(
+    conn: &mut sqlx::PgConnection,
+    credential_id: Uuid,
+    changed_by: Uuid,
+    restored_from: Option<i32>,
+) -> Result<i32, Status> {
+    sqlx::query_scalar!(
+        "INSERT INTO credential_versions
+             (credential_id, version, title, hostname, username, kind, public_key, metadata, tags,
+              encrypted_secret, nonce, written_at, changed_by, restored_from)
+         SELECT id, COALESCE((SELECT MAX(version) FROM credential_versions WHERE credential_id = $1), 0) + 1,
+                title, hostname, username, kind, public_key, metadata, tags, encrypted_secret, nonce,
+                updated_at, $2, $3
+         FROM credentials WHERE id = $1
+         RETURNING version",
+        credential_id,
+        changed_by,
+        restored_from
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Deletes the earlier versions of a credential beyond the `max_versions` most recent,
+/// counting back from `latest`.
+async fn prune_versions(
+    conn: &mut sqlx::PgConnection,
+    credential_id: Uuid,
+    latest: i32,
+    max_versions: u32,
+) -> Result<(), Status> {
+    sqlx::query!(
+        "DELETE FROM credential_versions WHERE credential_id = $1 AND version <= $2",
+        credential_id,
+        latest - max_versions.min(i32::MAX as u32) as i32
+    )
+        .execute(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(())
+}
+
+/// Reads the summary of a credential, e.g. once a statement has changed it.
+async fn fetch_summary(conn: &mut sqlx::PgConnection, id: Uuid) -> Result<CredentialSummary, Status> {
+    sqlx::query_as(&format!("SELECT {} FROM credentials c {} WHERE c.id = $1", SUMMARY_COLUMNS, AUTHOR_JOINS))
+        .bind(id)
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// The team of a credential outside the trash, or `None` if there is no such credential
+/// or the user isn't a member of its team allowed to read it.
+pub(super) async fn member_credential_team(
+    conn: &mut sqlx::PgConnection,
+    credential_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Uuid>, Status> {
+    sqlx::query_scalar!(
+        "SELECT c.team_id
+         FROM credentials c
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $2
+         WHERE c.id = $1 AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)",
+        credential_id,
+        user_id
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// Reads a credential shared into one of the user's teams, as `GET /credentials/<id>`
+/// does, with the secret encrypted for that team. The read is recorded in the
+/// credential's access log. `None` if there is no such share the user's token covers, or
+/// the credential is in the trash.
+async fn read_shared_credential(
+    conn: &mut sqlx::PgConnection,
+    user: &AuthenticatedUser,
+    client: &ClientInfo,
+    credential_id: Uuid,
+) -> Result<Option<CredentialResponse>, Status> {
+    // With shares into several of the user's teams, the oldest is read.
+    let credential = sqlx::query_as::<_, CredentialResponse>(&format!(
+        "WITH read AS (
+             SELECT {}, s.encrypted_secret, s.nonce, c.team_id AS shared_from_team,
+                    s.target_team_id AS shared_into_team
+             FROM credential_shares s
+             JOIN credentials c ON c.id = s.credential_id
+             JOIN team_members m ON m.team_id = s.target_team_id AND m.user_id = $2
+             WHERE s.credential_id = $1 AND c.deleted_at IS NULL
+               AND ($5::uuid[] IS NULL OR s.target_team_id = ANY($5))
+             ORDER BY s.created_at, s.target_team_id
+             LIMIT 1
+         ), logged AS (
+             INSERT INTO credential_access_log (credential_id, user_id, action, ip_address)
+             SELECT id, $2, $3, $4 FROM read
+         )
+         SELECT * FROM read",
+        SHARED_SUMMARY_COLUMNS
+    ))
+        .bind(credential_id)
+        .bind(user.id)
+        .bind(AccessAction::View.as_str())
+        .bind(client.ip_address())
+        .bind(user.permissions.team_ids.as_deref())
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let Some(mut credential) = credential else {
+        return Ok(None);
+    };
+
+    credential.uris = sqlx::query_as!(
+        CredentialUri,
+        "SELECT uri, match_type AS \"match_type: UriMatchType\"
+         FROM credential_uris WHERE credential_id = $1
+         ORDER BY position",
+        credential_id
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Some(credential))
+}
+
+/// The error for a credential the user can't reach through its own team: `403
+/// Forbidden` if it is shared into one of their teams, which may only read it, or else
+/// `404 Not Found`.
+pub(super) async fn not_found_unless_shared(
+    conn: &mut sqlx::PgConnection,
+    credential_id: Uuid,
+    user_id: Uuid,
+) -> Result<ApiError, Status> {
+    let shared = sqlx::query_scalar!(
+        "SELECT EXISTS(
+             SELECT 1 FROM credential_shares s
+             JOIN credentials c ON c.id = s.credential_id
+             JOIN team_members m ON m.team_id = s.target_team_id AND m.user_id = $2
+             WHERE s.credential_id = $1 AND c.deleted_at IS NULL
+         ) AS \"shared!\"",
+        credential_id,
+        user_id
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(if shared { ApiError::new(Status::Forbidden, "shared_credential") } else { Status::NotFound.into() })
+}
+
+/// Whether the user belongs to the team.
+pub(super) async fn is_team_member(conn: &mut sqlx::PgConnection, team_id: Uuid, user_id: Uuid) -> Result<bool, Status> {
+    let member = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
+        team_id,
+        user_id
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(member.unwrap_or(false))
+}
+
+/// The other credentials of a team outside the trash with the same normalized hostname
+/// and username as `credential`, oldest first, of those the user may read. Credentials
+/// without a hostname, such as cards, have no duplicates.
+async fn find_duplicates(
+    conn: &mut sqlx::PgConnection,
+    team_id: Uuid,
+    user_id: Uuid,
+    credential: &CredentialSummary,
+) -> Result<Vec<Uuid>, Status> {
+    let hostname = normalize_hostname(&credential.hostname);
+    if hostname.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let candidates = sqlx::query!(
+        "SELECT id, hostname FROM credentials
+         WHERE team_id = $1 AND id <> $2 AND deleted_at IS NULL AND lower(username) = lower($3)
+           AND credential_acl_allows(id, $4, FALSE)
+         ORDER BY created_at, id",
+        team_id,
+        credential.id,
+        credential.username,
+        user_id
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(candidates
+        .into_iter()
+        .filter(|candidate| normalize_hostname(&candidate.hostname) == hostname)
+        .map(|candidate| candidate.id)
+        .collect())
+}
+
+// --- Routes ---
+
+/// Adds a credential to a team the caller belongs to.
+///
+/// With `template_id`, the credential starts from that template of the team: its kind,
+/// tags, folder, URIs, and title pattern apply to whatever the body leaves out, and each
+/// of the body's `fields` may leave out its label and type to take those of the
+/// template's field at the same position. The credential doesn't refer to the template
+/// afterwards.
+///
+/// Returns `201 Created` with the credential's summary and, unless `check_duplicates` is
+/// `false`, a `possible_duplicate_of` hint listing credentials of the team with the same
+/// normalized hostname and username; the credential is added either way. Returns `404
+/// Not Found` if the team doesn't exist or the caller isn't a member, so outsiders can't
+/// tell the two apart, `403 Forbidden` for a token not scoped to the team, or `422
+/// Unprocessable Entity` listing invalid fields, or with `{"error":
+/// "invalid_reference"}` if `folder_id` isn't a folder of the team.
+#[post("/<team_id>/credentials?<check_duplicates>&<template_id>", data = "<credential_data>")]
+pub async fn create_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    check_duplicates: Option<bool>,
+    template_id: Option<Uuid>,
+    credential_data: JsonBody<serde_json::Map<String, serde_json::Value>>,
+) -> Result<(Status, Json<CreatedCredentialResponse>), ApiError> {
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    // The template fills in the body before it is read, so the body wins field by field.
+    let mut body = credential_data.0;
+    let mut title_from_template = false;
+    if let Some(template) = template_to_apply(db.as_mut(), team_id, template_id, user.id).await? {
+        title_from_template = apply_template(&template, &mut body);
+    }
+    let mut credential_data: CreateCredentialRequest = body::from_value(serde_json::Value::Object(body))?;
+    if title_from_template {
+        let hostname = effective_hostname(&credential_data.hostname, &credential_data.uris);
+        credential_data.title = expand_title_pattern(&credential_data.title, &hostname, &credential_data.username);
+    }
+
+    let mut v = Validator::new();
+    let tags = check_new_credential(&mut v, &credential_data);
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Inserting only for members makes the membership check and the insert one statement.
+    let hostname = effective_hostname(&credential_data.hostname, &credential_data.uris);
+    let ssh = SshDetails::new(
+        credential_data.public_key.as_deref(),
+        credential_data.ssh_key_type,
+        credential_data.ssh_fingerprint.as_deref(),
+        credential_data.ssh_comment.as_deref(),
+    );
+    let totp = TotpDetails::new(
+        &credential_data.kind,
+        credential_data.totp_digits,
+        credential_data.totp_period,
+        credential_data.totp_algorithm,
+    );
+    let id = sqlx::query_scalar!(
+        "INSERT INTO credentials
+             (team_id, title, hostname, username, kind, public_key, metadata, tags, folder_id, expires_at,
+              encrypted_secret, nonce, icon_url, ssh_key_type, ssh_fingerprint, ssh_comment,
+              health_strength_score, health_reused_group, health_reported_at, totp_digits, totp_period, totp_algorithm,
+              notes, created_by, updated_by)
+         SELECT $1, $2, $3, $4, $5, $6, COALESCE($7::jsonb, '{}'), $8, $9, $10, $11, $12, $14, $15, $16, $17,
+                $18, $19, CASE WHEN $18::smallint IS NOT NULL THEN NOW() END, $20, $21, $22, $23, $13, $13
+         WHERE EXISTS (SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $13)
+         RETURNING id",
+        team_id,
+        credential_data.title.trim(),
+        hostname,
+        credential_data.username.trim(),
+        &credential_data.kind as &SecretKind,
+        credential_data.public_key.as_deref().map(str::trim),
+        credential_data.metadata.clone().map(serde_json::Value::Object),
+        &tags,
+        credential_data.folder_id,
+        credential_data.expires_at,
+        credential_data.encrypted_secret,
+        credential_data.nonce,
+        user.id,
+        new_icon_url(&credential_data, &hostname),
+        ssh.key_type as Option<SshKeyType>,
+        ssh.fingerprint,
+        ssh.comment,
+        credential_data.health.as_ref().map(|health| health.strength_score),
+        credential_data.health.as_ref().and_then(|health| health.reused_group.as_deref()),
+        totp.digits,
+        totp.period,
+        totp.algorithm as Option<TotpAlgorithm>,
+        credential_data.notes.trim()
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?
+        .ok_or(Status::NotFound)?;
+    let credential = fetch_summary(&mut tx, id).await?;
+
+    insert_fields(&mut tx, &[(id, &credential_data.fields)]).await?;
+    insert_uris(&mut tx, &[(id, &credential_data.uris)]).await?;
+
+    let possible_duplicate_of = if check_duplicates.unwrap_or(true) {
+        find_duplicates(&mut tx, team_id, user.id, &credential).await?
+    } else {
+        Vec::new()
+    };
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok((Status::Created, Json(CreatedCredentialResponse { credential, possible_duplicate_of })))
+}
+
+/// Adds many credentials to a team the caller belongs to in one transaction, all or
+/// none. Every credential is validated before any is added; with `dry_run`, nothing is.
+/// Each credential takes the same fields as a single `POST /teams/<id>/credentials`.
+///
+/// Returns `201 Created` with the new ids in order, `200 OK` for a dry run that passes,
+/// `404 Not Found` if the team doesn't exist or the caller isn't a member, `403
+/// Forbidden` for a token not scoped to the team, `413 Payload Too Large` with
+/// `{"error": "batch_too_large", "max": ...}` beyond `credentials.max_import_batch`, or
+/// `422 Unprocessable Entity` with `{"error": "validation_failed", "items": [...]}`
+/// giving the index and errors of each invalid credential.
+#[post("/<team_id>/credentials/import", data = "<import_data>")]
+pub async fn import_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    team_id: Uuid,
+    import_data: JsonBody<ImportCredentialsRequest>,
+) -> Result<(Status, Json<ImportCredentialsResponse>), ApiError> {
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let max = config.credentials.max_import_batch;
+    if import_data.credentials.len() > max {
+        return Err(ApiError::new(Status::PayloadTooLarge, "batch_too_large").with("max", max));
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !is_team_member(&mut tx, team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    // Folders are checked up front too, so a bad one is blamed on its credential rather
+    // than failing the insert. The lock keeps them from being deleted in between.
+    let folder_ids: Vec<Uuid> = import_data.credentials.iter().filter_map(|credential| credential.folder_id).collect();
+    let folders = sqlx::query_scalar!(
+        "SELECT id FROM folders WHERE team_id = $1 AND id = ANY($2) FOR SHARE",
+        team_id,
+        &folder_ids
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut tags = Vec::with_capacity(import_data.credentials.len());
+    let mut rejected = Vec::new();
+    for (index, credential) in import_data.credentials.iter().enumerate() {
+        let mut v = Validator::new();
+        tags.push(check_new_credential(&mut v, credential));
+        if let Some(folder_id) = credential.folder_id {
+            v.check("folder_id", folders.contains(&folder_id), "must be a folder of the team");
+        }
+        let errors = v.into_errors();
+        if !errors.is_empty() {
+            rejected.push(RejectedImportItem { index, errors });
+        }
+    }
+    if !rejected.is_empty() {
+        return Err(ApiError::new(Status::UnprocessableEntity, "validation_failed").with("items", rejected));
+    }
+
+    let count = import_data.credentials.len();
+    if import_data.dry_run {
+        return Ok((Status::Ok, Json(ImportCredentialsResponse { dry_run: true, count, ids: Vec::new() })));
+    }
+
+    let ids = insert_credentials(&mut tx, team_id, user.id, &import_data.credentials, tags).await?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok((Status::Created, Json(ImportCredentialsResponse { dry_run: false, count, ids })))
+}
+
+/// Lists the credentials of a team the caller belongs to, without their encrypted
+/// secrets but with whether the caller has marked each a favorite. Credentials in the
+/// trash or restricted from the caller by `PUT /credentials/<id>/acl` are left out, as
+/// are archived ones unless `include_archived` is `true`. Credentials other teams share
+/// into the team with `PUT /credentials/<id>/shares/<team_id>` are listed too, with
+/// `shared_from_team` set and at the top level.
+///
+/// `q` limits the list to credentials whose title, hostname, username, or notes contain
+/// it, case-insensitively, `kind` to a comma-separated list of kinds, each `tag` to
+/// credentials with that tag, ignoring case, `folder` to a folder's id or `none` for
+/// the top level, `expiring_within_days` to credentials expiring within that many
+/// days, including expired ones, and `updated_since`, an RFC 3339 time, to credentials
+/// changed after it: syncing from the latest `updated_at` seen picks up every change
+/// since, though credentials moved to the trash are listed by `GET
+/// /teams/<id>/credentials/trash` instead. `sort` is one of `title` (the default), `hostname`,
+/// `created_at`, `updated_at`, `last_used_at`, or `expires_at`, and `order` is `asc` (the
+/// default) or `desc`. Filtered to a folder without a `sort`, credentials pinned with
+/// `POST /teams/<id>/credentials/reorder` come first in their order, then the rest by
+/// title, and `order` reverses both.
+/// Paginated with `limit` (default 50, at most 200) and `offset`; an offset past the end
+/// gives an empty page. Returns `404 Not Found` if the team doesn't exist or the caller
+/// isn't a member, `403 Forbidden` for a token not scoped to the team, or `422
+/// Unprocessable Entity` for a blank `q`, an unknown `kind`, an invalid `folder`, a
+/// negative `expiring_within_days`, an invalid `updated_since`, or any other `sort` or
+/// `order`.
+#[get("/<team_id>/credentials?<query..>")]
+pub async fn list_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    query: CredentialListQuery<'_>,
+) -> Result<Json<CredentialPage>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let column = sort_column(query.sort.unwrap_or("title"));
+    let direction = sort_direction(query.order.unwrap_or("asc"));
+    let mut v = Validator::new();
+    let pattern = query.q.map(|q| search_pattern(&mut v, q));
+    let kinds = query.kind.map(|kind| kind_names(&mut v, kind));
+    let tags: Vec<String> = query.tag.iter().map(|tag| tag.trim().to_lowercase()).collect();
+    let folder = query.folder.map(|folder| parse_folder(&mut v, folder));
+    let expiring_within_days = query.expiring_within_days.map(|days| {
+        v.check("expiring_within_days", (0..=i32::MAX as i64).contains(&days), "must not be negative");
+        days.clamp(0, i32::MAX as i64) as i32
+    });
+    let updated_since = query.updated_since.and_then(|since| {
+        let since = DateTime::parse_from_rfc3339(since.trim()).ok();
+        v.check("updated_since", since.is_some(), "must be an RFC 3339 time");
+        since.map(|since| since.with_timezone(&Utc))
+    });
+    v.check(
+        "sort",
+        column.is_some(),
+        "must be one of title, hostname, created_at, updated_at, last_used_at, expires_at",
+    );
+    v.check("order", direction.is_some(), "must be one of asc, desc");
+    v.finish()?;
+    let (Some(column), Some(direction)) = (column, direction) else {
+        unreachable!("validated above");
+    };
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let include_archived = query.include_archived.unwrap_or(false);
+
+    // Both parts of the ORDER BY come from the allowlists above. Credentials never used
+    // or without an expiry sort last either way, and the id breaks ties so pages stay
+    // stable.
+    let ordering = if query.folder.is_some() && query.sort.is_none() {
+        format!("c.position {} NULLS LAST, {} {}", direction, column, direction)
+    } else {
+        format!("{} {} NULLS LAST", column, direction)
+    };
+    let filter = format!(
+        "WHERE c.deleted_at IS NULL AND {} AND {} AND {} AND {} AND {} AND {} AND {}",
+        SEARCH_FILTER, KIND_FILTER, TAG_FILTER, FOLDER_FILTER, EXPIRY_FILTER, UPDATED_FILTER, ARCHIVED_FILTER
+    );
+    let rows: Vec<CredentialPageRow> = sqlx::query_as(&format!(
+        "SELECT {}, f.user_id IS NOT NULL AS is_favorite, c.shared_from_team, COUNT(*) OVER () AS total
+         FROM {}
+         {}
+         LEFT JOIN credential_favorites f ON f.credential_id = c.id AND f.user_id = $10
+         {}
+         ORDER BY {}, c.id {} LIMIT $11 OFFSET $12",
+        SUMMARY_COLUMNS, TEAM_CREDENTIALS, AUTHOR_JOINS, filter, ordering, direction
+    ))
+        .bind(team_id)
+        .bind(&pattern)
+        .bind(&kinds)
+        .bind(&tags)
+        .bind(folder.flatten())
+        .bind(folder.is_some())
+        .bind(expiring_within_days)
+        .bind(updated_since)
+        .bind(include_archived)
+        .bind(user.id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // The window count comes with the rows, so a page past the end has to count apart.
+    let total = match rows.first() {
+        Some(row) => row.total,
+        None if offset == 0 => 0,
+        None => sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {} {}", TEAM_CREDENTIALS, filter))
+            .bind(team_id)
+            .bind(&pattern)
+            .bind(&kinds)
+            .bind(&tags)
+            .bind(folder.flatten())
+            .bind(folder.is_some())
+            .bind(expiring_within_days)
+            .bind(updated_since)
+            .bind(include_archived)
+            .bind(user.id)
+            .fetch_one(db.as_mut())
+            .await
+            .map_err(|_| Status::InternalServerError)?,
+    };
+
+    let items = rows.into_iter().map(|row| row.credential).collect();
+
+    Ok(Json(CredentialPage { items, total, limit, offset }))
+}
+
+/// Pins credentials of a team's folder, or its top level, in the order given. Those
+/// left out lose their place and sort after them by title. A listing filtered to the
+/// folder follows this order unless a `sort` is asked for.
+///
+/// Positions are rewritten from 0 in one transaction that holds the folder's credentials,
+/// so of two concurrent reorders the later one wins in full.
+///
+/// Returns `204 No Content`, `404 Not Found` if the team doesn't exist or the caller
+/// isn't a member, `403 Forbidden` for a token not scoped to the team or a member whose
+/// role may not edit, or `422 Unprocessable Entity` if `folder_id` isn't a folder of the
+/// team, or `ids` repeats a credential or lists one that isn't in the folder or is in the
+/// trash.
+#[post("/<team_id>/credentials/reorder", data = "<reorder_data>")]
+pub async fn reorder_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    reorder_data: JsonBody<ReorderCredentialsRequest>,
+) -> Result<Status, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, &user, team_id).await?;
+
+    let folder_exists = match reorder_data.folder_id {
+        Some(folder_id) => sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM folders WHERE id = $1 AND team_id = $2) AS \"exists!\"",
+            folder_id,
+            team_id
+        )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?,
+        None => true,
+    };
+
+    // Trashed credentials are held too, as they lose their place below and would clash
+    // once restored.
+    let in_folder: HashSet<Uuid> = sqlx::query!(
+        "SELECT id, deleted_at FROM credentials
+         WHERE team_id = $1 AND folder_id IS NOT DISTINCT FROM $2
+         ORDER BY id
+         FOR UPDATE",
+        team_id,
+        reorder_data.folder_id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .filter(|row| row.deleted_at.is_none())
+        .map(|row| row.id)
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut v = Validator::new();
+    v.check("folder_id", folder_exists, "must be a folder of the team");
+    v.check("ids", reorder_data.ids.iter().all(|id| seen.insert(*id)), "must not repeat a credential");
+    v.check(
+        "ids",
+        reorder_data.ids.iter().all(|id| in_folder.contains(id)),
+        "must only list credentials in the folder",
+    );
+    v.finish()?;
+
+    sqlx::query!(
+        "UPDATE credentials c SET position = pinned.position - 1
+         FROM (
+             SELECT c.id, ordered.position
+             FROM credentials c
+             LEFT JOIN UNNEST($3::uuid[]) WITH ORDINALITY AS ordered(id, position) ON ordered.id = c.id
+             WHERE c.team_id = $1 AND c.folder_id IS NOT DISTINCT FROM $2
+         ) pinned
+         WHERE c.id = pinned.id AND c.position IS DISTINCT FROM pinned.position - 1",
+        team_id,
+        reorder_data.folder_id,
+        &reorder_data.ids
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Searches the credentials of every team the caller belongs to, or every team their
+/// token is scoped to, for `q` in the title, hostname, username, or notes,
+/// case-insensitively.
+/// Hits come ordered by title with the team they belong to and whether the caller has
+/// marked them a favorite, without their encrypted secrets; credentials in the trash or
+/// restricted from the caller are left out, as are archived ones unless
+/// `include_archived` is `true`.
+///
+/// `kind` limits the hits to a comma-separated list of kinds, e.g. `ssh_key`, and each
+/// `tag` to credentials with that tag. Paginated
+/// with `limit` (default 50, at most 200) and `offset`. Returns `422 Unprocessable
+/// Entity` if `q` is missing or blank, or `kind` names an unknown kind.
+#[get("/search?<query..>")]
+pub async fn search_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    query: CredentialSearchQuery<'_>,
+) -> Result<Json<CredentialSearchPage>, ApiError> {
+    let mut v = Validator::new();
+    let pattern = search_pattern(&mut v, query.q.unwrap_or(""));
+    let kinds = query.kind.map(|kind| kind_names(&mut v, kind));
+    let tags: Vec<String> = query.tag.iter().map(|tag| tag.trim().to_lowercase()).collect();
+    v.finish()?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let include_archived = query.include_archived.unwrap_or(false);
+    let team_ids = user.permissions.team_ids.as_deref();
+
+    let filter = format!(
+        "WHERE c.team_id IN (SELECT team_id FROM team_members WHERE user_id = $1)
+           AND ($5::uuid[] IS NULL OR c.team_id = ANY($5))
+           AND ($6::bool OR c.archived_at IS NULL)
+           AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $1, FALSE) AND {} AND {} AND {}",
+        SEARCH_FILTER, KIND_FILTER, TAG_FILTER
+    );
+    let rows: Vec<CredentialSearchRow> = sqlx::query_as(&format!(
+        "SELECT {}, t.id AS team_id, t.name AS team_name, f.user_id IS NOT NULL AS is_favorite,
+                COUNT(*) OVER () AS total
+         FROM credentials c
+         {}
+         JOIN teams t ON t.id = c.team_id
+         LEFT JOIN credential_favorites f ON f.credential_id = c.id AND f.user_id = $1
+         {}
+         ORDER BY lower(c.title), c.id LIMIT $7 OFFSET $8",
+        SUMMARY_COLUMNS, AUTHOR_JOINS, filter
+    ))
+        .bind(user.id)
+        .bind(&pattern)
+        .bind(&kinds)
+        .bind(&tags)
+        .bind(team_ids)
+        .bind(include_archived)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // As for team listings, only a page past the end needs its own count.
+    let total = match rows.first() {
+        Some(row) => row.total,
+        None if offset == 0 => 0,
+        None => sqlx::query_scalar(&format!("SELECT COUNT(*) FROM credentials c {}", filter))
+            .bind(user.id)
+            .bind(&pattern)
+            .bind(&kinds)
+            .bind(&tags)
+            .bind(team_ids)
+            .bind(include_archived)
+            .fetch_one(db.as_mut())
+            .await
+            .map_err(|_| Status::InternalServerError)?,
+    };
+
+    let items = rows.into_iter().map(|row| row.hit).collect();
+
+    Ok(Json(CredentialSearchPage { items, total, limit, offset }))
+}
+
+/// Lists the credentials the caller has marked as favorites across all their teams, or
+/// every team their token is scoped to, ordered by title with the team they belong to.
+/// Favorites in the trash are left out until they are restored.
+#[get("/favorites")]
+pub async fn list_favorites(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Vec<CredentialSearchHit>>, Status> {
+    let favorites = sqlx::query_as(&format!(
+        "SELECT {}, t.id AS team_id, t.name AS team_name, TRUE AS is_favorite
+         FROM credential_favorites f
+         JOIN credentials c ON c.id = f.credential_id
+         {}
+         JOIN teams t ON t.id = c.team_id
+         WHERE f.user_id = $1 AND c.deleted_at IS NULL AND ($2::uuid[] IS NULL OR c.team_id = ANY($2))
+           AND credential_acl_allows(c.id, $1, FALSE)
+         ORDER BY lower(c.title), c.id",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(user.id)
+        .bind(user.permissions.team_ids.as_deref())
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(favorites))
+}
+
+/// Lists the 20 credentials the caller used most recently, across all their teams or
+/// every team their token is scoped to, most recent first, with the team they belong to.
+/// Only the caller's own uses count, unlike `last_used_at`, which is the latest use by
+/// anyone on the team. Credentials in the trash, and those of teams the caller has left,
+/// are left out.
+#[get("/recent")]
+pub async fn list_recent(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Vec<RecentCredential>>, Status> {
+    let recent = sqlx::query_as(&format!(
+        "SELECT {}, t.id AS team_id, t.name AS team_name, f.user_id IS NOT NULL AS is_favorite, u.used_at
+         FROM credential_usage u
+         JOIN credentials c ON c.id = u.credential_id
+         {}
+         JOIN teams t ON t.id = c.team_id
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = u.user_id
+         LEFT JOIN credential_favorites f ON f.credential_id = c.id AND f.user_id = u.user_id
+         WHERE u.user_id = $1 AND c.deleted_at IS NULL AND ($2::uuid[] IS NULL OR c.team_id = ANY($2))
+           AND credential_acl_allows(c.id, $1, FALSE)
+         ORDER BY u.used_at DESC, c.id LIMIT $3",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(user.id)
+        .bind(user.permissions.team_ids.as_deref())
+        .bind(RECENT_LIMIT)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(recent))
+}
+
+/// Finds the credentials of every team the caller belongs to, or every team their token
+/// is scoped to, that apply to the page at `url`, e.g. for a browser extension to offer
+/// them. Each URI of a credential is compared by its match type: `exact` takes the same
+/// URL, `host` the same host and port, `domain` the same registrable domain by the public
+/// suffix list (so `a.example.co.uk` and `b.example.co.uk` match, but not
+/// `other.co.uk`), and `starts_with` a URL starting with it. Credentials without URIs
+/// match on their hostname by domain.
+///
+/// Returns summaries without secrets, at most 50, with the URI that matched best: the
+/// most specific matches first, from `exact` down to `domain`, then the most recently
+/// used, then by title. Archived and trashed credentials are left out. Returns `422 Unprocessable
+/// Entity` unless `url` is a URL with a host.
+#[get("/match?<url>")]
+pub async fn match_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    url: &str,
+) -> Result<Json<Vec<CredentialMatch>>, ApiError> {
+    let page = PageUrl::parse(url);
+    let mut v = Validator::new();
+    v.check("url", page.is_some(), "must be a URL with a host");
+    v.finish()?;
+    let Some(page) = page else {
+        unreachable!("validated above");
+    };
+
+    let team_ids = user.permissions.team_ids.as_deref();
+    let candidates = sqlx::query!(
+        "SELECT c.id, c.hostname, lower(c.title) AS \"title!\", c.last_used_at,
+                u.uri AS \"uri?\", u.match_type AS \"match_type?: UriMatchType\"
+         FROM credentials c
+         LEFT JOIN credential_uris u ON u.credential_id = c.id
+         WHERE c.team_id IN (SELECT team_id FROM team_members WHERE user_id = $1)
+           AND ($2::uuid[] IS NULL OR c.team_id = ANY($2)) AND c.deleted_at IS NULL AND c.archived_at IS NULL
+           AND credential_acl_allows(c.id, $1, FALSE)",
+        user.id,
+        team_ids
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut best: HashMap<Uuid, UriMatch> = HashMap::new();
+    for candidate in candidates {
+        let (uri, match_type) = match (candidate.uri, candidate.match_type) {
+            (Some(uri), Some(match_type)) => (uri, match_type),
+            _ => (candidate.hostname, UriMatchType::Domain),
+        };
+        if !page.matches(&uri, match_type) {
+            continue;
+        }
+        let better = best
+            .get(&candidate.id)
+            .is_none_or(|kept| specificity(match_type) > specificity(kept.match_type));
+        if better {
+            let found = UriMatch { match_type, uri, last_used_at: candidate.last_used_at, title: candidate.title };
+            best.insert(candidate.id, found);
+        }
+    }
+
+    let mut ranked: Vec<(Uuid, UriMatch)> = best.into_iter().collect();
+    ranked.sort_by(|(a_id, a), (b_id, b)| {
+        specificity(b.match_type)
+            .cmp(&specificity(a.match_type))
+            .then_with(|| b.last_used_at.cmp(&a.last_used_at))
+            .then_with(|| a.title.cmp(&b.title))
+            .then_with(|| a_id.cmp(b_id))
+    });
+    ranked.truncate(MAX_MATCHES);
+
+    let ids: Vec<Uuid> = ranked.iter().map(|(id, _)| *id).collect();
+    let hits: Vec<CredentialSearchHit> = sqlx::query_as(&format!(
+        "SELECT {}, t.id AS team_id, t.name AS team_name, f.user_id IS NOT NULL AS is_favorite
+         FROM credentials c
+         {}
+         JOIN teams t ON t.id = c.team_id
+         LEFT JOIN credential_favorites f ON f.credential_id = c.id AND f.user_id = $1
+         WHERE c.id = ANY($2)",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(user.id)
+        .bind(&ids)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut hits: HashMap<Uuid, CredentialSearchHit> = hits.into_iter().map(|hit| (hit.credential.id, hit)).collect();
+    let matches = ranked
+        .into_iter()
+        .filter_map(|(id, found)| {
+            hits.remove(&id).map(|credential| CredentialMatch {
+                credential,
+                matched_uri: found.uri,
+                match_type: found.match_type,
+            })
+        })
+        .collect();
+
+    Ok(Json(matches))
+}
+
+/// Lists the credentials expiring within `within_days` (default 30) across all the
+/// caller's teams, or every team their token is scoped to, soonest first, with the team
+/// they belong to. Credentials that have already expired come first, flagged `expired`;
+/// those archived or in the trash are left out.
+///
+/// Returns `422 Unprocessable Entity` for a negative `within_days`.
+#[get("/expiring?<within_days>")]
+pub async fn list_expiring(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    within_days: Option<i64>,
+) -> Result<Json<Vec<CredentialSearchHit>>, ApiError> {
+    let within_days = within_days.unwrap_or(DEFAULT_EXPIRING_WITHIN_DAYS);
+    let mut v = Validator::new();
+    v.check("within_days", (0..=i32::MAX as i64).contains(&within_days), "must not be negative");
+    v.finish()?;
+
+    let expiring = sqlx::query_as(&format!(
+        "SELECT {}, t.id AS team_id, t.name AS team_name, f.user_id IS NOT NULL AS is_favorite
+         FROM credentials c
+         {}
+         JOIN teams t ON t.id = c.team_id
+         LEFT JOIN credential_favorites f ON f.credential_id = c.id AND f.user_id = $1
+         WHERE c.team_id IN (SELECT team_id FROM team_members WHERE user_id = $1)
+           AND ($2::uuid[] IS NULL OR c.team_id = ANY($2))
+           AND c.deleted_at IS NULL AND c.archived_at IS NULL AND credential_acl_allows(c.id, $1, FALSE)
+           AND c.expires_at <= NOW() + make_interval(days => $3)
+         ORDER BY c.expires_at, c.id",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(user.id)
+        .bind(user.permissions.team_ids.as_deref())
+        .bind(within_days as i32)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(expiring))
+}
+
+/// Marks a credential of a team the caller belongs to as one of their favorites. Marking
+/// a favorite again changes nothing.
+///
+/// Returns `204 No Content`, or `404 Not Found` if the credential doesn't exist or is in
+/// the trash, the caller isn't a member of its team, or their token isn't scoped to the
+/// team.
+#[put("/<id>/favorite")]
+pub async fn favorite_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, Status> {
+    // Checking visibility in the statement that inserts keeps a 404 apart from a repeat.
+    let visible = sqlx::query_scalar!(
+        "WITH visible AS (
+             SELECT id, team_id FROM credentials
+             WHERE id = $1 AND deleted_at IS NULL
+               AND team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+               AND ($3::uuid[] IS NULL OR team_id = ANY($3)) AND credential_acl_allows(id, $2, FALSE)
+         ), inserted AS (
+             INSERT INTO credential_favorites (user_id, credential_id, team_id)
+             SELECT $2, id, team_id FROM visible
+             ON CONFLICT DO NOTHING
+         )
+         SELECT EXISTS(SELECT 1 FROM visible)",
+        id,
+        user.id,
+        user.permissions.team_ids.as_deref()
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .unwrap_or(false);
+
+    if visible { Ok(Status::NoContent) } else { Err(Status::NotFound) }
+}
+
+/// Removes a credential of a team the caller belongs to from their favorites, if it is
+/// one.
+///
+/// Returns `204 No Content`, or `404 Not Found` if the credential doesn't exist or is in
+/// the trash, the caller isn't a member of its team, or their token isn't scoped to the
+/// team.
+#[delete("/<id>/favorite")]
+pub async fn unfavorite_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, Status> {
+    let visible = sqlx::query_scalar!(
+        "WITH visible AS (
+             SELECT id FROM credentials
+             WHERE id = $1 AND deleted_at IS NULL
+               AND team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+               AND ($3::uuid[] IS NULL OR team_id = ANY($3)) AND credential_acl_allows(id, $2, FALSE)
+         ), deleted AS (
+             DELETE FROM credential_favorites
+             WHERE user_id = $2 AND credential_id IN (SELECT id FROM visible)
+         )
+         SELECT EXISTS(SELECT 1 FROM visible)",
+        id,
+        user.id,
+        user.permissions.team_ids.as_deref()
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .unwrap_or(false);
+
+    if visible { Ok(Status::NoContent) } else { Err(Status::NotFound) }
+}
+
+/// Updates a credential of a team the caller belongs to and returns its summary, along
+/// with the `updated_at` it had before.
+///
+/// The credential as it was is kept as an earlier version, listed by `GET
+/// /credentials/<id>/versions`; beyond `credentials.max_versions` the oldest versions
+/// are pruned. A new `encrypted_secret` must come with a new `nonce`, and vice versa;
+/// `secret_changed_at` only moves if the `encrypted_secret` differs from the stored one.
+/// New `uris` replace the `hostname` with the host of the first one; switching to
+/// `credit_card` removes them. Like custom fields, URIs aren't kept in earlier versions.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team or may not read it, `403 Forbidden` for a token
+/// not scoped to the team or a member whose role may not edit, `403 Forbidden` with
+/// `{"error": "restricted_credential"}` for a member it is restricted to reading or
+/// `{"error": "shared_credential"}` for a credential shared into one of the caller's
+/// teams, `409 Conflict` with `{"error": "edit_conflict"}` and the credential's current `updated_at` if it has changed since
+/// `expected_updated_at`, or `422 Unprocessable Entity` listing invalid fields, each
+/// invalid URI with its `index`, or with `{"error": "invalid_reference"}` if `folder_id`
+/// isn't a folder of the team.
+#[patch("/<id>", data = "<update_data>")]
+pub async fn update_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    id: Uuid,
+    update_data: JsonBody<UpdateCredentialRequest>,
+) -> Result<Json<UpdatedCredentialResponse>, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Lock the row so the fields checked below are still current when the update lands.
+    let current = sqlx::query!(
+        "SELECT c.team_id, c.hostname, c.username, c.kind AS \"kind: SecretKind\", c.updated_at,
+                c.totp_digits, c.totp_period, c.totp_algorithm AS \"totp_algorithm: TotpAlgorithm\",
+                m.role AS \"role: TeamRole\", credential_acl_allows(c.id, $2, TRUE) AS \"writable!\"
+         FROM credentials c
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $2
+         WHERE c.id = $1 AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)
+         FOR UPDATE OF c",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let Some(current) = current else {
+        return Err(not_found_unless_shared(&mut tx, id, user.id).await?);
+    };
+
+    if !user.permissions.can_write_team(current.team_id) {
+        return Err(insufficient_scope());
+    }
+    if !can_edit_credentials(&current.role) {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+    if !current.writable {
+        return Err(restricted_credential());
+    }
+    if update_data.expected_updated_at.is_some_and(|expected| expected != current.updated_at) {
+        return Err(ApiError::new(Status::Conflict, "edit_conflict").with("updated_at", current.updated_at));
+    }
+
+    let kind = update_data.kind.as_ref().unwrap_or(&current.kind);
+    let hostname = match update_data.uris.as_deref() {
+        Some(uris) if !uris.is_empty() => Some(effective_hostname("", uris)),
+        _ => update_data.hostname.as_deref().map(|hostname| hostname.trim().to_string()),
+    };
+    let mut v = Validator::new();
+    // The hostname and username are checked even if unchanged, as a new kind may need them.
+    check_labels(
+        &mut v,
+        kind,
+        update_data.title.as_deref(),
+        hostname.as_deref().unwrap_or(&current.hostname),
+        update_data.username.as_deref().unwrap_or(&current.username),
+    );
+    match (&update_data.encrypted_secret, &update_data.nonce) {
+        (Some(encrypted_secret), Some(nonce)) => {
+            v.check_max_len("encrypted_secret", encrypted_secret, MAX_SECRET_LEN)
+                .check_len("nonce", nonce, &[NONCE_LEN]);
+        }
+        (Some(_), None) => {
+            v.check("nonce", false, "is required with encrypted_secret");
+        }
+        (None, Some(_)) => {
+            v.check("encrypted_secret", false, "is required with nonce");
+        }
+        (None, None) => {}
+    }
+    check_public_key(&mut v, kind, update_data.public_key.as_deref());
+    check_ssh_details(
+        &mut v,
+        kind,
+        update_data.public_key.as_deref(),
+        update_data.ssh_key_type.as_ref(),
+        update_data.ssh_fingerprint.as_deref(),
+        update_data.ssh_comment.as_deref(),
+    );
+    check_metadata(&mut v, update_data.metadata.as_ref());
+    check_notes(&mut v, update_data.notes.as_deref());
+    if let Some(fields) = &update_data.fields {
+        check_fields(&mut v, fields);
+    }
+    if let Some(uris) = &update_data.uris {
+        check_uris(&mut v, kind, uris);
+    }
+    check_health(&mut v, update_data.health.as_ref().and_then(Option::as_ref));
+    check_totp_details(
+        &mut v,
+        kind,
+        update_data.totp_digits,
+        update_data.totp_period,
+        update_data.totp_algorithm.as_ref(),
+    );
+    let new_hostname = hostname.as_deref().unwrap_or(&current.hostname);
+    let icon_url = update_data.icon_url.as_ref().map(|icon_url| icon_url.as_deref().map(str::trim));
+    check_icon(&mut v, icon_url, update_data.derive_icon, new_hostname);
+    let icon_url = match update_data.derive_icon {
+        true => Some(derived_icon_url(new_hostname)),
+        false => icon_url.map(|icon_url| icon_url.map(str::to_string)),
+    };
+    let tags = update_data.tags.as_deref().map(|tags| normalize_tags(&mut v, tags));
+    v.finish()?;
+    let health = update_data.health.as_ref().and_then(Option::as_ref);
+    let ssh = SshDetails::new(
+        update_data.public_key.as_deref(),
+        update_data.ssh_key_type,
+        update_data.ssh_fingerprint.as_deref(),
+        update_data.ssh_comment.as_deref(),
+    );
+    // Parameters left out keep their current values, or the defaults for a new totp_secret.
+    let totp = TotpDetails::new(
+        kind,
+        update_data.totp_digits.or(current.totp_digits),
+        update_data.totp_period.or(current.totp_period),
+        update_data.totp_algorithm.or(current.totp_algorithm),
+    );
+
+    let saved = save_version(&mut tx, id, user.id, None).await?;
+    prune_versions(&mut tx, id, saved, config.credentials.max_versions).await?;
+
+    sqlx::query!(
+        "UPDATE credentials SET
+             title = COALESCE($1, title),
+             hostname = COALESCE($2, hostname),
+             username = COALESCE($3, username),
+             kind = $4::secret_kind,
+             public_key = CASE WHEN $4::secret_kind = 'ssh_key' THEN COALESCE($5, public_key) END,
+             ssh_key_type = CASE WHEN $4::secret_kind = 'ssh_key'
+                 THEN COALESCE($17, CASE WHEN $5::text IS NULL THEN ssh_key_type END) END,
+             ssh_fingerprint = CASE WHEN $4::secret_kind = 'ssh_key'
+                 THEN COALESCE($18, CASE WHEN $5::text IS NULL THEN ssh_fingerprint END) END,
+             ssh_comment = CASE WHEN $4::secret_kind = 'ssh_key'
+                 THEN COALESCE($19, CASE WHEN $5::text IS NULL THEN ssh_comment END) END,
+             metadata = COALESCE($6, metadata),
+             icon_url = CASE WHEN $15 THEN $16 ELSE icon_url END,
+             tags = COALESCE($7, tags),
+             folder_id = CASE WHEN $8 THEN $9 ELSE folder_id END,
+             position = CASE WHEN $8 AND $9 IS DISTINCT FROM folder_id THEN NULL ELSE position END,
+             expires_at = CASE WHEN $10 THEN $11 ELSE expires_at END,
+             expiry_notice_days = CASE WHEN $10 AND $11 IS DISTINCT FROM expires_at THEN NULL ELSE expiry_notice_days END,
+             encrypted_secret = COALESCE($12, encrypted_secret),
+             nonce = COALESCE($13, nonce),
+             secret_changed_at = CASE WHEN $12 <> encrypted_secret THEN NOW() ELSE secret_changed_at END,
+             -- What a client reported about the old secret no longer holds for a new one.
+             health_strength_score = CASE WHEN $20 THEN $21 WHEN $12 <> encrypted_secret THEN NULL
+                 ELSE health_strength_score END,
+             health_reused_group = CASE WHEN $20 THEN $22 WHEN $12 <> encrypted_secret THEN NULL
+                 ELSE health_reused_group END,
+             health_reported_at = CASE WHEN $20 THEN CASE WHEN $21::smallint IS NOT NULL THEN NOW() END
+                 WHEN $12 <> encrypted_secret THEN NULL ELSE health_reported_at END,
+             totp_digits = $23,
+             totp_period = $24,
+             totp_algorithm = $25,
+             notes = COALESCE($26, notes),
+             updated_by = $27
+         WHERE id = $14",
+        update_data.title.as_deref().map(str::trim),
+        hostname,
+        update_data.username.as_deref().map(str::trim),
+        kind as &SecretKind,
+        update_data.public_key.as_deref().map(str::trim),
+        update_data.metadata.clone().map(serde_json::Value::Object),
+        tags.as_deref(),
+        update_data.folder_id.is_some(),
+        update_data.folder_id.flatten(),
+        update_data.expires_at.is_some(),
+        update_data.expires_at.flatten(),
+        update_data.encrypted_secret.as_deref(),
+        update_data.nonce.as_deref(),
+        id,
+        icon_url.is_some(),
+        icon_url.flatten(),
+        ssh.key_type as Option<SshKeyType>,
+        ssh.fingerprint,
+        ssh.comment,
+        update_data.health.is_some(),
+        health.map(|health| health.strength_score),
+        health.and_then(|health| health.reused_group.as_deref()),
+        totp.digits,
+        totp.period,
+        totp.algorithm as Option<TotpAlgorithm>,
+        update_data.notes.as_deref().map(str::trim),
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    let credential = fetch_summary(&mut tx, id).await?;
+
+    if let Some(fields) = &update_data.fields {
+        sqlx::query!("DELETE FROM credential_fields WHERE credential_id = $1", id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        insert_fields(&mut tx, &[(id, fields)]).await?;
+    }
+    // Credit cards have no addresses, so switching to one drops them.
+    if update_data.uris.is_some() || matches!(kind, SecretKind::CreditCard) {
+        sqlx::query!("DELETE FROM credential_uris WHERE credential_id = $1", id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        insert_uris(&mut tx, &[(id, update_data.uris.as_deref().unwrap_or_default())]).await?;
+    }
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(UpdatedCredentialResponse { credential, previous_updated_at: current.updated_at }))
+}
+
+/// Returns a credential of a team the caller belongs to with its encrypted secret and
+/// nonce, its custom fields, and its URIs, e.g. to fill in a login. The read is recorded
+/// in the credential's access log, and shortly after as a use of the credential. A
+/// credential shared into one of the caller's teams comes with `shared_from_team` and
+/// `shared_into_team` set and the secret shared with it, without custom fields.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team or is kept from it by its access restrictions, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<id>")]
+pub async fn get_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    usage: &State<UsageRecorder>,
+    id: Uuid,
+) -> Result<Json<CredentialResponse>, ApiError> {
+    let Some(team_id) = member_credential_team(db.as_mut(), id, user.id).await? else {
+        let credential = read_shared_credential(db.as_mut(), &user, &client, id)
+            .await?
+            .ok_or(Status::NotFound)?;
+        usage.record(user.id, &[id]);
+        return Ok(Json(credential));
+    };
+
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    // Logging in the same statement means no secret leaves without its entry.
+    let mut credential = sqlx::query_as::<_, CredentialResponse>(&format!(
+        "WITH read AS (
+             SELECT {}, c.encrypted_secret, c.nonce
+             FROM credentials c {} WHERE c.id = $1 AND c.deleted_at IS NULL
+         ), logged AS (
+             INSERT INTO credential_access_log (credential_id, user_id, action, ip_address)
+             SELECT id, $2, $3, $4 FROM read
+         )
+         SELECT * FROM read",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(id)
+        .bind(user.id)
+        .bind(AccessAction::View.as_str())
+        .bind(client.ip_address())
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    credential.fields = sqlx::query_as!(
+        CredentialField,
+        "SELECT label, field_type AS \"field_type: FieldType\", encrypted_value, nonce
+         FROM credential_fields WHERE credential_id = $1
+         ORDER BY position",
+        id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    credential.uris = sqlx::query_as!(
+        CredentialUri,
+        "SELECT uri, match_type AS \"match_type: UriMatchType\"
+         FROM credential_uris WHERE credential_id = $1
+         ORDER BY position",
+        id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    usage.record(user.id, &[id]);
+
+    Ok(Json(credential))
+}
+
+/// Returns the credentials with the given ids, of up to 200, from teams the caller
+/// belongs to, with their encrypted secrets, nonces, custom fields, and URIs, in the order
+/// asked for. Ids of credentials that don't exist, are in the trash, are restricted from
+/// the caller, or belong to a team the caller isn't a member of or their token isn't
+/// scoped to are left out, unless shared into one of the caller's teams, as
+/// `GET /credentials/<id>` returns them. Each credential is returned once however often
+/// it is asked for. Each read is
+/// recorded in the credential's access log, and shortly after as a use of the credential.
+///
+/// Returns `422 Unprocessable Entity` for no ids or more than 200.
+#[post("/batch", data = "<batch_data>")]
+pub async fn batch_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    usage: &State<UsageRecorder>,
+    batch_data: JsonBody<BatchCredentialsRequest>,
+) -> Result<Json<Vec<CredentialResponse>>, ApiError> {
+    let mut v = Validator::new();
+    v.check(
+        "ids",
+        !batch_data.ids.is_empty() && batch_data.ids.len() <= MAX_PAGE_SIZE as usize,
+        format!("must list between 1 and {} credentials", MAX_PAGE_SIZE),
+    );
+    v.finish()?;
+
+    let mut credentials = sqlx::query_as::<_, CredentialResponse>(&format!(
+        "WITH readable AS (
+             SELECT {}, c.encrypted_secret, c.nonce, NULL::uuid AS shared_from_team,
+                    NULL::uuid AS shared_into_team, asked.position
+             FROM UNNEST($1::uuid[]) WITH ORDINALITY AS asked(id, position)
+             JOIN credentials c ON c.id = asked.id
+             {}
+             WHERE c.deleted_at IS NULL
+               AND c.team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+               AND ($3::uuid[] IS NULL OR c.team_id = ANY($3)) AND credential_acl_allows(c.id, $2, FALSE)
+             UNION ALL
+             SELECT {}, s.encrypted_secret, s.nonce, c.team_id, s.target_team_id, asked.position
+             FROM UNNEST($1::uuid[]) WITH ORDINALITY AS asked(id, position)
+             JOIN credential_shares s ON s.credential_id = asked.id
+             JOIN credentials c ON c.id = s.credential_id
+             WHERE c.deleted_at IS NULL
+               AND s.target_team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+               AND ($3::uuid[] IS NULL OR s.target_team_id = ANY($3))
+         ), read AS (
+             -- A credential of the caller's own team comes as it is rather than shared.
+             SELECT DISTINCT ON (id) * FROM readable
+             ORDER BY id, shared_into_team IS NOT NULL, position, shared_into_team
+         ), logged AS (
+             INSERT INTO credential_access_log (credential_id, user_id, action, ip_address)
+             SELECT id, $2, $4, $5 FROM read
+         )
+         SELECT * FROM read ORDER BY position",
+        SUMMARY_COLUMNS, AUTHOR_JOINS, SHARED_SUMMARY_COLUMNS
+    ))
+        .bind(&batch_data.ids)
+        .bind(user.id)
+        .bind(user.permissions.team_ids.as_deref())
+        .bind(AccessAction::View.as_str())
+        .bind(client.ip_address())
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let ids: Vec<Uuid> = credentials.iter().map(|c| c.credential.id).collect();
+    let own_ids: Vec<Uuid> = credentials
+        .iter()
+        .filter(|c| c.shared_into_team.is_none())
+        .map(|c| c.credential.id)
+        .collect();
+    let rows = sqlx::query!(
+        "SELECT credential_id, label, field_type AS \"field_type: FieldType\", encrypted_value, nonce
+         FROM credential_fields WHERE credential_id = ANY($1)
+         ORDER BY credential_id, position",
+        &own_ids
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut fields: HashMap<Uuid, Vec<CredentialField>> = HashMap::new();
+    for row in rows {
+        fields.entry(row.credential_id).or_default().push(CredentialField {
+            label: row.label,
+            field_type: row.field_type,
+            encrypted_value: row.encrypted_value,
+            nonce: row.nonce,
+        });
+    }
+    let rows = sqlx::query!(
+        "SELECT credential_id, uri, match_type AS \"match_type: UriMatchType\"
+         FROM credential_uris WHERE credential_id = ANY($1)
+         ORDER BY credential_id, position",
+        &ids
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut uris: HashMap<Uuid, Vec<CredentialUri>> = HashMap::new();
+    for row in rows {
+        uris.entry(row.credential_id).or_default().push(CredentialUri { uri: row.uri, match_type: row.match_type });
+    }
+    for credential in &mut credentials {
+        credential.fields = fields.remove(&credential.credential.id).unwrap_or_default();
+        credential.uris = uris.remove(&credential.credential.id).unwrap_or_default();
+    }
+
+    usage.record(user.id, &ids);
+
+    Ok(Json(credentials))
+}
+
+/// Lists the earlier versions of a credential of a team the caller belongs to, newest
+/// first, without their encrypted secrets.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team, or `403 Forbidden` for a token not scoped to the
+/// team.
+#[get("/<id>/versions")]
+pub async fn list_versions(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<Vec<CredentialVersionSummary>>, ApiError> {
+    let team_id = member_credential_team(db.as_mut(), id, user.id)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let versions = sqlx::query_as!(
+        CredentialVersionSummary,
+        "SELECT v.version, v.title, v.hostname, v.username, v.kind AS \"kind: SecretKind\", v.public_key, v.metadata,
+                v.tags, v.written_at, v.changed_by, u.name AS \"changed_by_name?\", v.changed_at, v.restored_from
+         FROM credential_versions v
+         LEFT JOIN users u ON u.id = v.changed_by
+         WHERE v.credential_id = $1
+         ORDER BY v.version DESC",
+        id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(versions))
+}
+
+/// Returns an earlier version of a credential of a team the caller belongs to, with
+/// its encrypted secret and nonce. The read is recorded in the credential's access log.
+///
+/// Returns `404 Not Found` if there is no such version, the credential is in the trash,
+/// or the caller isn't a member of its team, or `403 Forbidden` for a token not scoped
+/// to the team.
+#[get("/<id>/versions/<version>")]
+pub async fn get_version(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    id: Uuid,
+    version: i32,
+) -> Result<Json<CredentialVersionResponse>, ApiError> {
+    let team_id = member_credential_team(db.as_mut(), id, user.id)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    // Logging in the same statement means no secret leaves without its entry.
+    let row = sqlx::query!(
+        "WITH v AS (
+             SELECT v.version, v.title, v.hostname, v.username, v.kind, v.public_key, v.metadata, v.tags,
+                    v.encrypted_secret, v.nonce, v.written_at, v.changed_by, u.name AS changed_by_name, v.changed_at,
+                    v.restored_from
+             FROM credential_versions v
+             LEFT JOIN users u ON u.id = v.changed_by
+             WHERE v.credential_id = $1 AND v.version = $2
+         ), logged AS (
+             INSERT INTO credential_access_log (credential_id, user_id, action, ip_address)
+             SELECT $1, $3, $4, $5 FROM v
+         )
+         SELECT version AS \"version!\", title AS \"title!\", hostname AS \"hostname!\",
+                username AS \"username!\", kind AS \"kind!: SecretKind\", public_key,
+                metadata AS \"metadata!\", tags AS \"tags!\", encrypted_secret AS \"encrypted_secret!\",
+                nonce AS \"nonce!\", written_at AS \"written_at!\", changed_by, changed_by_name AS \"changed_by_name?\",
+                changed_at AS \"changed_at!\", restored_from
+         FROM v",
+        id,
+        version,
+        user.id,
+        AccessAction::ViewVersion.as_str(),
+        client.ip_address()
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(CredentialVersionResponse {
+        version: CredentialVersionSummary {
+            version: row.version,
+            title: row.title,
+            hostname: row.hostname,
+            username: row.username,
+            kind: row.kind,
+            public_key: row.public_key,
+            metadata: row.metadata,
+            tags: row.tags,
+            written_at: row.written_at,
+            changed_by: row.changed_by,
+            changed_by_name: row.changed_by_name,
+            changed_at: row.changed_at,
+            restored_from: row.restored_from,
+        },
+        encrypted_secret: row.encrypted_secret,
+        nonce: row.nonce,
+    }))
+}
+
+/// Restores an earlier version of a credential of a team the caller belongs to,
+/// copying all its fields back. Like any update, the credential as it was before is kept
+/// as a new earlier version, which records the version restored. Returns the credential
+/// with its new version number.
+///
+/// Returns `404 Not Found` if there is no such version, the credential is in the trash,
+/// or the caller isn't a member of its team or may not read it, or `403 Forbidden` for
+/// a token not scoped to the team, a member whose role may not edit, or with `{"error":
+/// "restricted_credential"}` for a member it is restricted to reading or `{"error":
+/// "shared_credential"}` for a credential shared into one of the caller's teams.
+#[post("/<id>/versions/<version>/restore")]
+pub async fn restore_version(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    id: Uuid,
+    version: i32,
+) -> Result<Json<RestoredCredentialResponse>, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let current = sqlx::query!(
+        "SELECT c.team_id, m.role AS \"role: TeamRole\", credential_acl_allows(c.id, $2, TRUE) AS \"writable!\"
+         FROM credentials c
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $2
+         WHERE c.id = $1 AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)
+         FOR UPDATE OF c",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let Some(current) = current else {
+        return Err(not_found_unless_shared(&mut tx, id, user.id).await?);
+    };
+
+    if !user.permissions.can_write_team(current.team_id) {
+        return Err(insufficient_scope());
+    }
+    if !can_edit_credentials(&current.role) {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+    if !current.writable {
+        return Err(restricted_credential());
+    }
+
+    let restored_key = sqlx::query_scalar!(
+        "SELECT public_key FROM credential_versions WHERE credential_id = $1 AND version = $2",
+        id,
+        version
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+    // Versions don't keep the SSH details, so they are read again from the restored key,
+    // as an update replacing the key would; other kinds have none.
+    let ssh = SshDetails::new(restored_key.as_deref(), None, None, None);
+
+    let saved = save_version(&mut tx, id, user.id, Some(version)).await?;
+
+    sqlx::query!(
+        "UPDATE credentials c SET
+             title = v.title,
+             hostname = v.hostname,
+             username = v.username,
+             kind = v.kind,
+             public_key = v.public_key,
+             -- The same key keeps the details it has, which may have been entered by hand.
+             ssh_key_type = CASE WHEN v.kind <> 'ssh_key' THEN NULL
+                 WHEN v.public_key IS NOT DISTINCT FROM c.public_key AND c.kind = 'ssh_key' THEN c.ssh_key_type
+                 ELSE $7 END,
+             ssh_fingerprint = CASE WHEN v.kind <> 'ssh_key' THEN NULL
+                 WHEN v.public_key IS NOT DISTINCT FROM c.public_key AND c.kind = 'ssh_key' THEN c.ssh_fingerprint
+                 ELSE $8 END,
+             ssh_comment = CASE WHEN v.kind <> 'ssh_key' THEN NULL
+                 WHEN v.public_key IS NOT DISTINCT FROM c.public_key AND c.kind = 'ssh_key' THEN c.ssh_comment
+                 ELSE $9 END,
+             metadata = v.metadata,
+             tags = v.tags,
+             encrypted_secret = v.encrypted_secret,
+             nonce = v.nonce,
+             secret_changed_at = CASE WHEN v.encrypted_secret <> c.encrypted_secret THEN NOW() ELSE c.secret_changed_at END,
+             health_strength_score = CASE WHEN v.encrypted_secret <> c.encrypted_secret THEN NULL
+                 ELSE c.health_strength_score END,
+             health_reused_group = CASE WHEN v.encrypted_secret <> c.encrypted_secret THEN NULL
+                 ELSE c.health_reused_group END,
+             health_reported_at = CASE WHEN v.encrypted_secret <> c.encrypted_secret THEN NULL
+                 ELSE c.health_reported_at END,
+             -- Versions don't keep TOTP parameters, so they stay, or default on becoming a totp_secret.
+             totp_digits = CASE WHEN v.kind = 'totp_secret' THEN COALESCE(c.totp_digits, $3) END,
+             totp_period = CASE WHEN v.kind = 'totp_secret' THEN COALESCE(c.totp_period, $4) END,
+             totp_algorithm = CASE WHEN v.kind = 'totp_secret' THEN COALESCE(c.totp_algorithm, $5) END,
+             updated_by = $6
+         FROM credential_versions v
+         WHERE c.id = $1 AND v.credential_id = c.id AND v.version = $2",
+        id,
+        version,
+        DEFAULT_TOTP_DIGITS,
+        DEFAULT_TOTP_PERIOD,
+        DEFAULT_TOTP_ALGORITHM as TotpAlgorithm,
+        user.id,
+        ssh.key_type as Option<SshKeyType>,
+        ssh.fingerprint,
+        ssh.comment
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let credential = fetch_summary(&mut tx, id).await?;
+
+    // Pruning only now keeps the restored version around to copy, even if it is the
+    // oldest one kept.
+    prune_versions(&mut tx, id, saved, config.credentials.max_versions).await?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(RestoredCredentialResponse { credential, version: saved + 1, restored_from: version }))
+}
+
+/// Moves a credential to another team, along with its earlier versions, attachments,
+/// and custom fields. Since it is encrypted with the team key, the client re-encrypts
+/// the secret and those of all earlier versions and the value of every custom field, and
+/// re-wraps the key of every attachment, with the target team's key first; the server
+/// swaps them in with the team in one transaction and records the move. The credential
+/// leaves its folder. Re-encrypting doesn't count as changing the secret, so
+/// `secret_changed_at` stays, as does its reported strength; its reused-secret group,
+/// keyed for the old team, doesn't.
+///
+/// The caller must be able to edit credentials in both teams. Access restrictions go
+/// along, applying to those listed who are members of the target team. Returns the moved
+/// credential, `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of either team or may not read it, `403 Forbidden` for a token
+/// not scoped to both teams, a member whose role may not edit, or with `{"error":
+/// "restricted_credential"}` for a member it is restricted to reading or `{"error":
+/// "shared_credential"}` for a credential shared into one of the caller's teams, which
+/// only its own team can move, `409 Conflict` with `{"error":
+/// "versions_mismatch", "versions": [...]}` unless `versions` re-encrypts exactly the
+/// listed versions, `409 Conflict` with `{"error": "attachments_mismatch", "attachments":
+/// [...]}` unless `attachments` covers exactly the listed attachment ids, `409 Conflict`
+/// with `{"error": "fields_mismatch", "fields": [...]}` unless `fields` re-encrypts
+/// exactly the listed field positions, or `422 Unprocessable Entity` listing invalid
+/// fields.
+#[post("/<id>/move", data = "<move_data>")]
+pub async fn move_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+    move_data: JsonBody<MoveCredentialRequest>,
+) -> Result<Json<CredentialSummary>, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // The row lock also keeps versions from being added while they are re-encrypted.
+    let current = sqlx::query!(
+        "SELECT c.team_id, m.role AS \"role: TeamRole\", credential_acl_allows(c.id, $2, TRUE) AS \"writable!\"
+         FROM credentials c
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $2
+         WHERE c.id = $1 AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)
+         FOR UPDATE OF c",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let Some(current) = current else {
+        return Err(not_found_unless_shared(&mut tx, id, user.id).await?);
+    };
+
+    let target_team_id = move_data.target_team_id;
+    let target_role = sqlx::query_scalar!(
+        "SELECT role AS \"role: TeamRole\" FROM team_members WHERE team_id = $1 AND user_id = $2",
+        target_team_id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if !user.permissions.can_write_team(current.team_id) || !user.permissions.can_write_team(target_team_id) {
+        return Err(insufficient_scope());
+    }
+    if !can_edit_credentials(&current.role) || !can_edit_credentials(&target_role) {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+    if !current.writable {
+        return Err(restricted_credential());
+    }
+
+    let mut v = Validator::new();
+    v.check("target_team_id", target_team_id != current.team_id, "must be another team");
+    v.check_max_len("encrypted_secret", &move_data.encrypted_secret, MAX_SECRET_LEN)
+        .check_len("nonce", &move_data.nonce, &[NONCE_LEN]);
+    for version in &move_data.versions {
+        v.check_max_len("versions", &version.encrypted_secret, MAX_SECRET_LEN)
+            .check_len("versions", &version.nonce, &[NONCE_LEN]);
+    }
+    for attachment in &move_data.attachments {
+        v.check_max_len("attachments", &attachment.encrypted_key, MAX_SECRET_LEN)
+            .check_len("attachments", &attachment.key_nonce, &[NONCE_LEN]);
+    }
+    for field in &move_data.fields {
+        v.check_max_len("fields", &field.encrypted_value, MAX_FIELD_VALUE_LEN)
+            .check_len("fields", &field.nonce, &[NONCE_LEN]);
+    }
+    v.finish()?;
+
+    let versions = sqlx::query_scalar!(
+        "SELECT version FROM credential_versions WHERE credential_id = $1 ORDER BY version",
+        id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut given: Vec<i32> = move_data.versions.iter().map(|version| version.version).collect();
+    given.sort_unstable();
+    if given != versions {
+        return Err(ApiError::new(Status::Conflict, "versions_mismatch").with("versions", versions));
+    }
+
+    let attachments = sqlx::query_scalar!(
+        "SELECT id FROM attachments WHERE credential_id = $1 ORDER BY id",
+        id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut given: Vec<Uuid> = move_data.attachments.iter().map(|attachment| attachment.id).collect();
+    given.sort_unstable();
+    if given != attachments {
+        return Err(ApiError::new(Status::Conflict, "attachments_mismatch").with("attachments", attachments));
+    }
+
+    let fields = sqlx::query_scalar!(
+        "SELECT position FROM credential_fields WHERE credential_id = $1 ORDER BY position",
+        id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut given: Vec<i32> = move_data.fields.iter().map(|field| field.position).collect();
+    given.sort_unstable();
+    if given != fields {
+        return Err(ApiError::new(Status::Conflict, "fields_mismatch").with("fields", fields));
+    }
+
+    for version in &move_data.versions {
+        sqlx::query!(
+            "UPDATE credential_versions SET encrypted_secret = $3, nonce = $4
+             WHERE credential_id = $1 AND version = $2",
+            id,
+            version.version,
+            version.encrypted_secret,
+            version.nonce
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    for attachment in &move_data.attachments {
+        sqlx::query!(
+            "UPDATE attachments SET encrypted_key = $2, key_nonce = $3 WHERE id = $1",
+            attachment.id,
+            attachment.encrypted_key,
+            attachment.key_nonce
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    for field in &move_data.fields {
+        sqlx::query!(
+            "UPDATE credential_fields SET encrypted_value = $3, nonce = $4
+             WHERE credential_id = $1 AND position = $2",
+            id,
+            field.position,
+            field.encrypted_value,
+            field.nonce
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    // A share into the target team has nothing left to share once the credential is there.
+    sqlx::query!(
+        "DELETE FROM credential_shares WHERE credential_id = $1 AND target_team_id = $2",
+        id,
+        target_team_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Favorites follow the credential to the target team, except those of users who
+    // aren't members there.
+    sqlx::query!(
+        "DELETE FROM credential_favorites f
+         WHERE f.credential_id = $1
+           AND NOT EXISTS (SELECT 1 FROM team_members m WHERE m.team_id = $2 AND m.user_id = f.user_id)",
+        id,
+        target_team_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "UPDATE credentials SET team_id = $2, folder_id = NULL, position = NULL, encrypted_secret = $3, nonce = $4,
+             health_reused_group = NULL, updated_by = $5
+         WHERE id = $1",
+        id,
+        target_team_id,
+        move_data.encrypted_secret,
+        move_data.nonce,
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let credential = fetch_summary(&mut tx, id).await?;
+
+    sqlx::query!(
+        "INSERT INTO credential_moves (credential_id, from_team_id, to_team_id, moved_by) VALUES ($1, $2, $3, $4)",
+        id,
+        current.team_id,
+        target_team_id,
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(credential))
+}
+
+/// Copies a credential within its team and returns the copy with its encrypted secret,
+/// like `GET /credentials/<id>`. The copy is titled `"<title> (copy)"` and starts fresh,
+/// and the read is recorded in the original's access log. It keeps the same ciphertext,
+/// since the team key is the same, along with the custom fields, URIs, tags, folder, and
+/// access restrictions, but no earlier versions, favorites, or use. Attachments are
+/// copied too with `with_attachments=true`.
+///
+/// A credential can't be copied to another team this way, as its secret is encrypted
+/// with this team's key: `target_team_id` is only accepted if it is the same team.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, or the
+/// caller isn't a member of its team or may not read it, `403 Forbidden` for a token not
+/// scoped to the team, a member whose role may not edit, or with `{"error":
+/// "shared_credential"}` for a credential shared into one of the caller's teams, or
+/// `422 Unprocessable Entity` with `{"error": "cross_team_copy"}` for another
+/// `target_team_id`, which needs `POST /credentials/<id>/move` and the client
+/// re-encrypting it instead.
+#[post("/<id>/copy?<target_team_id>&<with_attachments>")]
+pub async fn copy_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    id: Uuid,
+    target_team_id: Option<Uuid>,
+    with_attachments: Option<bool>,
+) -> Result<Json<CredentialResponse>, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // The share lock keeps the original's fields and attachments as they are while they
+    // are copied.
+    let current = sqlx::query!(
+        "SELECT c.team_id, m.role AS \"role: TeamRole\"
+         FROM credentials c
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $2
+         WHERE c.id = $1 AND c.deleted_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)
+         FOR SHARE OF c",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let Some(current) = current else {
+        return Err(not_found_unless_shared(&mut tx, id, user.id).await?);
+    };
+
+    if target_team_id.is_some_and(|team_id| team_id != current.team_id) {
+        return Err(ApiError::new(Status::UnprocessableEntity, "cross_team_copy")
+            .with("field", "target_team_id")
+            .with("message", format!("must be the credential's own team; use POST /credentials/{}/move", id)));
+    }
+    if !user.permissions.can_write_team(current.team_id) {
+        return Err(insufficient_scope());
+    }
+    if !can_edit_credentials(&current.role) {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+
+    // Shortened as needed so the suffix fits within the longest title allowed.
+    let copy_id = sqlx::query_scalar!(
+        "INSERT INTO credentials
+             (team_id, title, hostname, username, kind, public_key, ssh_key_type, ssh_fingerprint, ssh_comment,
+              metadata, icon_url, tags, folder_id, expires_at, encrypted_secret, nonce, secret_changed_at,
+              health_strength_score, health_reused_group, health_reported_at, totp_digits, totp_period, totp_algorithm,
+              notes, created_by, updated_by)
+         SELECT team_id, left(title, $2) || ' (copy)', hostname, username, kind, public_key, ssh_key_type,
+                ssh_fingerprint, ssh_comment, metadata, icon_url, tags, folder_id, expires_at, encrypted_secret,
+                nonce, secret_changed_at, health_strength_score, health_reused_group, health_reported_at,
+                totp_digits, totp_period, totp_algorithm, notes, $3, $3
+         FROM credentials WHERE id = $1
+         RETURNING id",
+        id,
+        (MAX_TITLE_LEN - COPY_SUFFIX_LEN) as i32,
+        user.id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "INSERT INTO credential_fields (credential_id, position, label, field_type, encrypted_value, nonce)
+         SELECT $2, position, label, field_type, encrypted_value, nonce FROM credential_fields WHERE credential_id = $1",
+        id,
+        copy_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "INSERT INTO credential_uris (credential_id, position, uri, match_type)
+         SELECT $2, position, uri, match_type FROM credential_uris WHERE credential_id = $1",
+        id,
+        copy_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "INSERT INTO credential_acl (credential_id, user_id, can_read, can_write)
+         SELECT $2, user_id, can_read, can_write FROM credential_acl WHERE credential_id = $1",
+        id,
+        copy_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if with_attachments.unwrap_or(false) {
+        // Each attachment keeps its wrapped key, so its chunks are copied as they are.
+        sqlx::query!(
+            "WITH copied AS (
+                 SELECT id, gen_random_uuid() AS copy_id FROM attachments WHERE credential_id = $1
+             ), attached AS (
+                 INSERT INTO attachments (id, credential_id, filename, size, chunk_size, encrypted_key, key_nonce, created_by)
+                 SELECT copied.copy_id, $2, a.filename, a.size, a.chunk_size, a.encrypted_key, a.key_nonce, $3
+                 FROM attachments a JOIN copied ON copied.id = a.id
+             )
+             INSERT INTO attachment_chunks (attachment_id, seq, data)
+             SELECT copied.copy_id, k.seq, k.data FROM attachment_chunks k JOIN copied ON copied.id = k.attachment_id",
+            id,
+            copy_id,
+            user.id
+        )
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    sqlx::query!(
+        "INSERT INTO credential_access_log (credential_id, user_id, action, ip_address) VALUES ($1, $2, $3, $4)",
+        id,
+        user.id,
+        AccessAction::Copy.as_str(),
+        client.ip_address()
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut credential = sqlx::query_as::<_, CredentialResponse>(&format!(
+        "SELECT {}, c.encrypted_secret, c.nonce FROM credentials c {} WHERE c.id = $1",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(copy_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    credential.fields = sqlx::query_as!(
+        CredentialField,
+        "SELECT label, field_type AS \"field_type: FieldType\", encrypted_value, nonce
+         FROM credential_fields WHERE credential_id = $1
+         ORDER BY position",
+        copy_id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    credential.uris = sqlx::query_as!(
+        CredentialUri,
+        "SELECT uri, match_type AS \"match_type: UriMatchType\"
+         FROM credential_uris WHERE credential_id = $1
+         ORDER BY position",
+        copy_id
+    )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(credential))
+}
+
+/// Moves a credential of a team the caller belongs to into the team's trash, from which
+/// it can be restored through `POST /credentials/<id>/restore` or purged for good
+/// through `DELETE /credentials/<id>/purge`.
+///
+/// Returns `204 No Content`, or `404 Not Found` if the credential doesn't exist or is
+/// already in the trash, the caller isn't a member of its team or its access
+/// restrictions keep them from changing it, or their token isn't scoped to the team, or
+/// `403 Forbidden` with `{"error": "shared_credential"}` for a credential shared into
+/// one of the caller's teams.
+#[delete("/<id>")]
+pub async fn delete_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, ApiError> {
+    // Membership and token scope are checked by the statement that deletes, so nothing
+    // can change in between.
+    let deleted = sqlx::query!(
+        "UPDATE credentials SET deleted_at = NOW(), deleted_by = $2
+         WHERE id = $1 AND deleted_at IS NULL
+           AND team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+           AND ($3::uuid[] IS NULL OR team_id = ANY($3)) AND credential_acl_allows(id, $2, TRUE)",
+        id,
+        user.id,
+        user.permissions.team_ids.as_deref()
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected();
+
+    if deleted == 0 {
+        return Err(not_found_unless_shared(db.as_mut(), id, user.id).await?);
+    }
+    Ok(Status::NoContent)
+}
+
+/// Lists the tags in use on the credentials of a team the caller belongs to, with how
+/// many credentials have each. Tags differing only in case count as one. Archived
+/// credentials and those in the trash are left out.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/tags")]
+pub async fn list_tags(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<Vec<TagCount>>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let tags = sqlx::query_as!(
+        TagCount,
+        "SELECT MIN(tag) AS \"tag!\", COUNT(*) AS \"count!\"
+         FROM credentials c, unnest(c.tags) tag
+         WHERE c.team_id = $1 AND c.deleted_at IS NULL AND c.archived_at IS NULL
+           AND credential_acl_allows(c.id, $2, FALSE)
+         GROUP BY lower(tag)
+         ORDER BY lower(tag)",
+        team_id,
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(tags))
+}
+
+/// Lists the credentials of a team the caller belongs to whose secret is older than the
+/// team's `max_secret_age_days`, oldest first. Empty if the team sets no limit.
+/// Archived credentials and those in the trash are left out.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/credentials/stale")]
+pub async fn list_stale(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<Vec<CredentialSummary>>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let stale = sqlx::query_as(&format!(
+        "SELECT {}
+         FROM credentials c
+         {}
+         JOIN teams t ON t.id = c.team_id
+         WHERE c.team_id = $1 AND c.deleted_at IS NULL AND c.archived_at IS NULL
+           AND credential_acl_allows(c.id, $2, FALSE)
+           AND c.secret_changed_at < NOW() - make_interval(days => t.max_secret_age_days)
+         ORDER BY c.secret_changed_at, c.id",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(team_id)
+        .bind(user.id)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(stale))
+}
+
+/// Lists groups of credentials of a team the caller belongs to that share a hostname
+/// and username, e.g. to clean up after an import. Hostnames are compared normalized,
+/// so `https://www.example.com/` matches `example.com`, and usernames ignoring case.
+/// Credentials without a hostname, archived ones, and those in the trash are left out.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/credentials/duplicates")]
+pub async fn list_duplicates(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<Vec<DuplicateGroup>>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    // Grouped here rather than in SQL so the hostname is normalized exactly as on import.
+    let credentials: Vec<CredentialSummary> = sqlx::query_as(&format!(
+        "SELECT {}
+         FROM credentials c
+         {}
+         WHERE c.team_id = $1 AND c.deleted_at IS NULL AND c.archived_at IS NULL AND c.hostname <> ''
+           AND credential_acl_allows(c.id, $2, FALSE)
+         ORDER BY c.created_at, c.id",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(team_id)
+        .bind(user.id)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut groups: BTreeMap<(String, String), Vec<CredentialSummary>> = BTreeMap::new();
+    for credential in credentials {
+        let hostname = normalize_hostname(&credential.hostname);
+        if !hostname.is_empty() {
+            groups.entry((hostname, credential.username.to_lowercase())).or_default().push(credential);
+        }
+    }
+
+    let duplicates = groups
+        .into_iter()
+        .filter(|(_, credentials)| credentials.len() > 1)
+        .map(|((hostname, username), credentials)| DuplicateGroup { hostname, username, credentials })
+        .collect();
+
+    Ok(Json(duplicates))
+}
+
+/// Lists the trash of a team the caller belongs to, most recently deleted first, with
+/// who deleted each credential and when.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/credentials/trash")]
+pub async fn list_trash(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<Vec<TrashedCredentialResponse>>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let rows = sqlx::query!(
+        "SELECT c.id, c.title, c.hostname, c.username, c.kind AS \"kind: SecretKind\", c.public_key,
+                c.ssh_key_type AS \"ssh_key_type: SshKeyType\", c.ssh_fingerprint, c.ssh_comment, c.totp_digits,
+                c.totp_period, c.totp_algorithm AS \"totp_algorithm: TotpAlgorithm\", c.metadata,
+                c.notes, c.icon_url, c.tags, c.folder_id, c.expires_at,
+                COALESCE(c.expires_at <= NOW(), FALSE) AS \"expired!\",
+                c.secret_changed_at, date_part('day', NOW() - c.secret_changed_at)::int AS \"secret_age_days!\",
+                c.created_at, c.updated_at, c.archived_at, c.deleted_at AS \"deleted_at!\", c.deleted_by,
+                u.email AS \"deleted_by_email?\", c.created_by, created_by_user.name AS \"created_by_name?\",
+                c.updated_by, updated_by_user.name AS \"updated_by_name?\"
+         FROM credentials c
+         LEFT JOIN users u ON u.id = c.deleted_by
+         LEFT JOIN users created_by_user ON created_by_user.id = c.created_by
+         LEFT JOIN users updated_by_user ON updated_by_user.id = c.updated_by
+         WHERE c.team_id = $1 AND c.deleted_at IS NOT NULL AND credential_acl_allows(c.id, $2, FALSE)
+         ORDER BY c.deleted_at DESC, c.id",
+        team_id,
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let trash = rows
+        .into_iter()
+        .map(|row| TrashedCredentialResponse {
+            credential: CredentialSummary {
+                id: row.id,
+                title: row.title,
+                hostname: row.hostname,
+                username: row.username,
+                kind: row.kind,
+                public_key: row.public_key,
+                ssh_key_type: row.ssh_key_type,
+                ssh_fingerprint: row.ssh_fingerprint,
+                ssh_comment: row.ssh_comment,
+                totp_digits: row.totp_digits,
+                totp_period: row.totp_period,
+                totp_algorithm: row.totp_algorithm,
+                metadata: row.metadata,
+                notes: row.notes,
+                icon_url: row.icon_url,
+                tags: row.tags,
+                folder_id: row.folder_id,
+                expires_at: row.expires_at,
+                expired: row.expired,
+                secret_changed_at: row.secret_changed_at,
+                secret_age_days: row.secret_age_days,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+                archived_at: row.archived_at,
+                created_by: row.created_by,
+                created_by_name: row.created_by_name,
+                updated_by: row.updated_by,
+                updated_by_name: row.updated_by_name,
+            },
+            deleted_at: row.deleted_at,
+            deleted_by: row.deleted_by,
+            deleted_by_email: row.deleted_by_email,
+        })
+        .collect();
+
+    Ok(Json(trash))
+}
+
+/// Lists the archived credentials of a team the caller belongs to, most recently
+/// archived first. Those in the trash are left out.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/credentials/archived")]
+pub async fn list_archived(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<Vec<CredentialSummary>>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let archived = sqlx::query_as(&format!(
+        "SELECT {}
+         FROM credentials c
+         {}
+         WHERE c.team_id = $1 AND c.deleted_at IS NULL AND c.archived_at IS NOT NULL
+           AND credential_acl_allows(c.id, $2, FALSE)
+         ORDER BY c.archived_at DESC, c.id",
+        SUMMARY_COLUMNS, AUTHOR_JOINS
+    ))
+        .bind(team_id)
+        .bind(user.id)
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(archived))
+}
+
+/// Restores a credential from the trash of a team the caller belongs to and returns
+/// its summary.
+///
+/// Returns `404 Not Found` if the credential isn't in the trash, the caller isn't a
+/// member of its team or its access restrictions keep them from changing it, or their
+/// token isn't scoped to the team.
+#[post("/<id>/restore")]
+pub async fn restore_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<CredentialSummary>, Status> {
+    let found = sqlx::query!(
+        "UPDATE credentials SET deleted_at = NULL, deleted_by = NULL
+         WHERE id = $1 AND deleted_at IS NOT NULL
+           AND team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+           AND ($3::uuid[] IS NULL OR team_id = ANY($3)) AND credential_acl_allows(id, $2, TRUE)",
+        id,
+        user.id,
+        user.permissions.team_ids.as_deref()
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+    if !found {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Json(fetch_summary(db.as_mut(), id).await?))
+}
+
+/// Checks the ids of a bulk change: at least one, at most [`MAX_BULK_IDS`], and no
+/// repeats.
+fn check_bulk_ids(v: &mut Validator, ids: &[Uuid]) {
+    let mut seen = HashSet::new();
+    v.check(
+        "ids",
+        !ids.is_empty() && ids.len() <= MAX_BULK_IDS,
+        format!("must list between 1 and {} credentials", MAX_BULK_IDS),
+    );
+    v.check("ids", ids.iter().all(|id| seen.insert(*id)), "must not repeat a credential");
+}
+
+/// Locks the credentials listed for a bulk change to a team until the transaction ends,
+/// keyed by id; those that don't exist are missing. Returns `422 Unprocessable Entity`
+/// with `{"error": "foreign_credentials"}` and their `ids` if any belong to another
+/// team, so a list mixing them in is refused whole rather than applied in part.
+async fn lock_bulk_credentials(
+    conn: &mut sqlx::PgConnection,
+    user_id: Uuid,
+    team_id: Uuid,
+    ids: &[Uuid],
+) -> Result<HashMap<Uuid, LockedCredential>, ApiError> {
+    let rows = sqlx::query!(
+        "SELECT id, team_id, deleted_at IS NOT NULL AS \"deleted!\",
+                credential_acl_allows(id, $2, TRUE) AS \"writable!\"
+         FROM credentials
+         WHERE id = ANY($1)
+         ORDER BY id
+         FOR UPDATE",
+        ids,
+        user_id
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let foreign: Vec<Uuid> = rows.iter().filter(|row| row.team_id != team_id).map(|row| row.id).collect();
+    if !foreign.is_empty() {
+        return Err(ApiError::new(Status::UnprocessableEntity, "foreign_credentials").with("ids", foreign));
+    }
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id, LockedCredential { deleted: row.deleted, writable: row.writable }))
+        .collect())
+}
+
+/// Moves credentials of a team into its trash, with `delete`, or out of it, all in one
+/// transaction, returning what happened to each in the order given. Credentials already
+/// where they are going are left alone, and so are those the caller may not change as
+/// far as access restrictions go, which count as not found. Credentials of any other
+/// team, including those shared into this one, fail the whole request.
+async fn bulk_trash(
+    db: &mut sqlx::PgConnection,
+    user: &AuthenticatedUser,
+    team_id: Uuid,
+    ids: &[Uuid],
+    delete: bool,
+) -> Result<Vec<BulkCredentialOutcome>, ApiError> {
+    let mut v = Validator::new();
+    check_bulk_ids(&mut v, ids);
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, user, team_id).await?;
+    let rows = lock_bulk_credentials(&mut tx, user.id, team_id, ids).await?;
+
+    let outcomes: Vec<BulkCredentialOutcome> = ids
+        .iter()
+        .map(|id| {
+            let outcome = match rows.get(id) {
+                Some(row) if !row.writable => BulkOutcome::NotFound,
+                Some(row) if row.deleted == delete => match delete {
+                    true => BulkOutcome::AlreadyDeleted,
+                    false => BulkOutcome::NotDeleted,
+                },
+                Some(_) if delete => BulkOutcome::Deleted,
+                Some(_) => BulkOutcome::Restored,
+                None => BulkOutcome::NotFound,
+            };
+            BulkCredentialOutcome { id: *id, outcome }
+        })
+        .collect();
+
+    let changed: Vec<Uuid> = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome.outcome, BulkOutcome::Deleted | BulkOutcome::Restored))
+        .map(|outcome| outcome.id)
+        .collect();
+    sqlx::query!(
+        "UPDATE credentials SET
+             deleted_at = CASE WHEN $2 THEN NOW() END,
+             deleted_by = CASE WHEN $2 THEN $3::uuid END
+         WHERE id = ANY($1)",
+        &changed,
+        delete,
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(outcomes)
+}
+
+/// Moves up to 500 credentials of a team the caller belongs to into its trash at once,
+/// e.g. to undo an import, returning for each, in the order given, whether it was
+/// `deleted`, `already_deleted`, or `not_found`, which includes those its access
+/// restrictions keep the caller from changing. Nothing is deleted unless all of them
+/// can be considered.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member,
+/// `403 Forbidden` for a token not scoped to the team or a member whose role may not
+/// edit, or `422 Unprocessable Entity` for no ids, more than 500, or repeated ones, or
+/// with `{"error": "foreign_credentials"}` and their `ids` if any belong to another
+/// team, including credentials shared into this one.
+#[post("/<team_id>/credentials/bulk-delete", data = "<bulk_data>")]
+pub async fn bulk_delete_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    bulk_data: JsonBody<BulkCredentialsRequest>,
+) -> Result<Json<Vec<BulkCredentialOutcome>>, ApiError> {
+    let outcomes = bulk_trash(&mut db, &user, team_id, &bulk_data.ids, true).await?;
+    Ok(Json(outcomes))
+}
+
+/// Restores up to 500 credentials from the trash of a team the caller belongs to at
+/// once, returning for each, in the order given, whether it was `restored`,
+/// `not_deleted`, or `not_found`, which includes those its access restrictions keep the
+/// caller from changing. Nothing is restored unless all of them can be considered.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member,
+/// `403 Forbidden` for a token not scoped to the team or a member whose role may not
+/// edit, or `422 Unprocessable Entity` for no ids, more than 500, or repeated ones, or
+/// with `{"error": "foreign_credentials"}` and their `ids` if any belong to another
+/// team, including credentials shared into this one.
+#[post("/<team_id>/credentials/bulk-restore", data = "<bulk_data>")]
+pub async fn bulk_restore_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    bulk_data: JsonBody<BulkCredentialsRequest>,
+) -> Result<Json<Vec<BulkCredentialOutcome>>, ApiError> {
+    let outcomes = bulk_trash(&mut db, &user, team_id, &bulk_data.ids, false).await?;
+    Ok(Json(outcomes))
+}
+
+/// Moves up to 500 credentials of a team the caller belongs to into one of its folders,
+/// or to its top level for a `folder_id` of `null`, in one statement, returning how many
+/// changed folder. Moved credentials lose their place in the old folder's order.
+/// Nothing is moved unless all of them can be, so a selection never moves in part.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member,
+/// `403 Forbidden` for a token not scoped to the team or a member whose role may not
+/// edit, or `422 Unprocessable Entity` for no ids, more than 500, or repeated ones, or a
+/// `folder_id` that isn't a folder of the team, with `{"error": "foreign_credentials"}`
+/// and their `ids` if any belong to another team, including credentials shared into
+/// this one, or with `{"error": "unmovable_credentials"}` listing the `trashed` ones and
+/// those `not_found`, which includes those its access restrictions keep the caller from
+/// changing.
+#[post("/<team_id>/credentials/bulk-move", data = "<move_data>")]
+pub async fn bulk_move_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    move_data: JsonBody<BulkMoveCredentialsRequest>,
+) -> Result<Json<BulkMoveResponse>, ApiError> {
+    let mut v = Validator::new();
+    check_bulk_ids(&mut v, &move_data.ids);
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, &user, team_id).await?;
+
+    let folder_exists = match move_data.folder_id {
+        Some(folder_id) => sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM folders WHERE id = $1 AND team_id = $2) AS \"exists!\"",
+            folder_id,
+            team_id
+        )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?,
+        None => true,
+    };
+    let mut v = Validator::new();
+    v.check("folder_id", folder_exists, "must be a folder of the team");
+    v.finish()?;
+
+    let rows = lock_bulk_credentials(&mut tx, user.id, team_id, &move_data.ids).await?;
+    let (mut trashed, mut not_found) = (Vec::new(), Vec::new());
+    for id in &move_data.ids {
+        match rows.get(id) {
+            Some(row) if !row.writable => not_found.push(*id),
+            Some(row) if row.deleted => trashed.push(*id),
+            Some(_) => {}
+            None => not_found.push(*id),
+        }
+    }
+    if !trashed.is_empty() || !not_found.is_empty() {
+        return Err(ApiError::new(Status::UnprocessableEntity, "unmovable_credentials")
+            .with("trashed", trashed)
+            .with("not_found", not_found));
+    }
+
+    let moved = sqlx::query!(
+        "UPDATE credentials SET folder_id = $2, position = NULL, updated_by = $3
+         WHERE id = ANY($1) AND folder_id IS DISTINCT FROM $2",
+        &move_data.ids,
+        move_data.folder_id,
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?
+        .rows_affected();
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(BulkMoveResponse { moved }))
+}
+
+/// Permanently deletes a credential from the trash of a team the caller belongs to.
+/// Credentials that aren't in the trash must be deleted first.
+///
+/// Returns `204 No Content`, or `404 Not Found` if the credential isn't in the trash,
+/// the caller isn't a member of its team or its access restrictions keep them from
+/// changing it, or their token isn't scoped to the team.
+#[delete("/<id>/purge")]
+pub async fn purge_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, Status> {
+    let purged = sqlx::query!(
+        "DELETE FROM credentials
+         WHERE id = $1 AND deleted_at IS NOT NULL
+           AND team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+           AND ($3::uuid[] IS NULL OR team_id = ANY($3)) AND credential_acl_allows(id, $2, TRUE)",
+        id,
+        user.id,
+        user.permissions.team_ids.as_deref()
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected();
+
+    if purged > 0 { Ok(Status::NoContent) } else { Err(Status::NotFound) }
+}
+
+/// Archives a credential of a team the caller belongs to and returns its summary. An
+/// archived credential stays readable through `GET /credentials/<id>` and in exports,
+/// but is left out of listings, searches, and URL matches unless asked for, as for a
+/// retired server's password. Archiving an archived credential keeps its `archived_at`
+/// and `updated_by`.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, the
+/// caller isn't a member of its team or its access restrictions keep them from changing
+/// it, or their token isn't scoped to the team, or `403 Forbidden` with `{"error":
+/// "shared_credential"}` for a credential shared into one of the caller's teams.
+#[post("/<id>/archive")]
+pub async fn archive_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<CredentialSummary>, ApiError> {
+    let found = sqlx::query!(
+        "UPDATE credentials SET archived_at = COALESCE(archived_at, NOW()),
+                                updated_by = CASE WHEN archived_at IS NULL THEN $2 ELSE updated_by END
+         WHERE id = $1 AND deleted_at IS NULL
+           AND team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+           AND ($3::uuid[] IS NULL OR team_id = ANY($3)) AND credential_acl_allows(id, $2, TRUE)",
+        id,
+        user.id,
+        user.permissions.team_ids.as_deref()
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+    if !found {
+        return Err(not_found_unless_shared(db.as_mut(), id, user.id).await?);
+    }
+
+    Ok(Json(fetch_summary(db.as_mut(), id).await?))
+}
+
+/// Takes a credential of a team the caller belongs to out of the archive and returns
+/// its summary, with the caller as `updated_by`. Credentials that aren't archived are
+/// returned as they are.
+///
+/// Returns `404 Not Found` if the credential doesn't exist or is in the trash, the
+/// caller isn't a member of its team or its access restrictions keep them from changing
+/// it, or their token isn't scoped to the team, or `403 Forbidden` with `{"error":
+/// "shared_credential"}` for a credential shared into one of the caller's teams.
+#[post("/<id>/unarchive")]
+pub async fn unarchive_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<CredentialSummary>, ApiError> {
+    let found = sqlx::query!(
+        "UPDATE credentials SET archived_at = NULL,
+                                updated_by = CASE WHEN archived_at IS NULL THEN updated_by ELSE $2 END
+         WHERE id = $1 AND deleted_at IS NULL
+           AND team_id IN (SELECT team_id FROM team_members WHERE user_id = $2)
+           AND ($3::uuid[] IS NULL OR team_id = ANY($3)) AND credential_acl_allows(id, $2, TRUE)",
+        id,
+        user.id,
+        user.permissions.team_ids.as_deref()
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+    if !found {
+        return Err(not_found_unless_shared(db.as_mut(), id, user.id).await?);
+    }
+
+    Ok(Json(fetch_summary(db.as_mut(), id).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use chrono::{DateTime, Utc};
+    use rocket::serde::json::{json, Value};
+    use sqlx::PgPool;
+    use uuid::Uuid;
+    use crate::testing;
+
+    /// Signs up `email` and returns their access token and personal team id.
+    async fn member(client: &Client, email: &str) -> (String, String) {
+        let signup = testing::signup(client, email).await;
+        let team_id = signup["personal_team_id"].as_str().unwrap().to_string();
+        (testing::access_token(client, email).await, team_id)
+    }
+
+    async fn count(pool: &PgPool, query: &str, id: Uuid) -> i64 {
+        sqlx::query_scalar(query).bind(id).fetch_one(pool).await.unwrap()
+    }
+
+    /// Sets a credential's `updated_at` far into the past, which the trigger leaves be
+    /// since nothing else changes, and returns it.
+    async fn backdate(pool: &PgPool, id: Uuid) -> DateTime<Utc> {
+        sqlx::query_scalar("UPDATE credentials SET updated_at = '2000-01-01Z' WHERE id = $1 RETURNING updated_at")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn updated_at(pool: &PgPool, id: Uuid) -> DateTime<Utc> {
+        sqlx::query_scalar("SELECT updated_at FROM credentials WHERE id = $1").bind(id).fetch_one(pool).await.unwrap()
+    }
+
+    #[sqlx::test]
+    async fn purging_a_credential_removes_its_versions_attachments_and_fields(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (token, team_id) = member(&client, "owner@example.com").await;
+        let credential = testing::create_credential(&client, &token, &team_id).await;
+        let id: Uuid = credential["id"].as_str().unwrap().parse().unwrap();
+
+        let response = client.patch(format!("/credentials/{}", id))
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(json!({ "title": "Old router" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.post(format!("/credentials/{}/attachments?filename=backup.bin", id))
+            .header(testing::bearer(&token))
+            .header(Header::new("X-Encrypted-Key", "a2V5a2V5a2V5a2V5"))
+            .header(Header::new("X-Key-Nonce", "bm9uY2Vub25jZW5vbmNlbm9uY2Vub25j"))
+            .body(vec![16; 4096])
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let attachment: Value = response.into_json().await.unwrap();
+        let attachment_id: Uuid = attachment["id"].as_str().unwrap().parse().unwrap();
+
+        let dependents = [
+            ("SELECT COUNT(*) FROM credential_versions WHERE credential_id = $1", id),
+            ("SELECT COUNT(*) FROM credential_fields WHERE credential_id = $1", id),
+            ("SELECT COUNT(*) FROM attachments WHERE credential_id = $1", id),
+            ("SELECT COUNT(*) FROM attachment_chunks WHERE attachment_id = $1", attachment_id),
+        ];
+        for (query, id) in dependents {
+            assert!(count(&pool, query, id).await > 0, "{}", query);
+        }
+
+        let response = client.delete(format!("/credentials/{}", id)).header(testing::bearer(&token)).dispatch().await;
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.delete(format!("/credentials/{}/purge", id)).header(testing::bearer(&token)).dispatch().await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM credentials WHERE id = $1", id).await, 0);
+        for (query, id) in dependents {
+            assert_eq!(count(&pool, query, id).await, 0, "{}", query);
+        }
+    }
+
+    #[sqlx::test]
+    async fn deleting_another_teams_credential_is_not_found(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (owner, team_id) = member(&client, "owner@example.com").await;
+        let (outsider, _) = member(&client, "outsider@example.com").await;
+        let credential = testing::create_credential(&client, &owner, &team_id).await;
+        let id = credential["id"].as_str().unwrap();
+
+        let response = client.delete(format!("/credentials/{}", id)).header(testing::bearer(&outsider)).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let trashed: bool = sqlx::query_scalar("SELECT deleted_at IS NOT NULL FROM credentials WHERE id = $1::uuid")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(!trashed);
+
+        let response = client.delete(format!("/credentials/{}", id)).header(testing::bearer(&owner)).dispatch().await;
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.delete(format!("/credentials/{}/purge", id)).header(testing::bearer(&outsider)).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM credentials WHERE id = $1", id.parse().unwrap()).await, 1);
+    }
+
+    #[sqlx::test]
+    async fn restoring_the_version_a_restore_replaced_undoes_it(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (token, team_id) = member(&client, "owner@example.com").await;
+        let credential = testing::create_credential(&client, &token, &team_id).await;
+        let id: Uuid = credential["id"].as_str().unwrap().parse().unwrap();
+        let response = client.patch(format!("/credentials/{}", id))
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(json!({ "title": "Renamed router" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // Version 1 is the credential as created; restoring it keeps the renamed one as 2.
+        let response = client.post(format!("/credentials/{}/versions/1/restore", id))
+            .header(testing::bearer(&token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let restored: Value = response.into_json().await.unwrap();
+        assert_eq!((restored["title"].as_str(), restored["version"].as_i64()), (Some("Router"), Some(3)));
+
+        let response = client.post(format!("/credentials/{}/versions/2/restore", id))
+            .header(testing::bearer(&token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let restored: Value = response.into_json().await.unwrap();
+        assert_eq!(restored["title"], "Renamed router");
+        assert_eq!((restored["version"].as_i64(), restored["restored_from"].as_i64()), (Some(4), Some(2)));
+
+        let live: String = sqlx::query_scalar("SELECT title FROM credentials WHERE id = $1")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(live, "Renamed router");
+        let history: Vec<(i32, String, Option<i32>)> = sqlx::query_as(
+            "SELECT version, title, restored_from FROM credential_versions WHERE credential_id = $1 ORDER BY version"
+        )
+            .bind(id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(history, [
+            (1, "Router".to_string(), None),
+            (2, "Renamed router".to_string(), Some(1)),
+            (3, "Router".to_string(), Some(2)),
+        ]);
+    }
+
+    #[sqlx::test]
+    async fn moving_to_a_team_the_caller_isnt_in_is_not_found(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (token, team_id) = member(&client, "owner@example.com").await;
+        let (_, other_team_id) = member(&client, "other@example.com").await;
+        let credential = testing::create_credential(&client, &token, &team_id).await;
+        let id: Uuid = credential["id"].as_str().unwrap().parse().unwrap();
+        let snapshot = "SELECT c.team_id, c.encrypted_secret, c.updated_at, f.encrypted_value
+                        FROM credentials c JOIN credential_fields f ON f.credential_id = c.id
+                        WHERE c.id = $1";
+        type Snapshot = (Uuid, Vec<u8>, DateTime<Utc>, Vec<u8>);
+        let before: Snapshot = sqlx::query_as(snapshot).bind(id).fetch_one(&pool).await.unwrap();
+
+        let response = client.post(format!("/credentials/{}/move", id))
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(json!({
+                "target_team_id": other_team_id,
+                "encrypted_secret": "c2VjcmV0",
+                "nonce": "bm9uY2Vub25jZW5vbmNlbm9uY2Vub25j",
+                "fields": [{ "position": 0, "encrypted_value": "cGlu", "nonce": "bm9uY2Vub25jZW5vbmNlbm9uY2Vub25j" }],
+            }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let after: Snapshot = sqlx::query_as(snapshot).bind(id).fetch_one(&pool).await.unwrap();
+        assert_eq!(after, before);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM credential_moves WHERE credential_id = $1", id).await, 0);
+    }
+
+    #[sqlx::test]
+    async fn updating_a_credential_bumps_updated_at(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (token, team_id) = member(&client, "owner@example.com").await;
+        let credential = testing::create_credential(&client, &token, &team_id).await;
+        let id: Uuid = credential["id"].as_str().unwrap().parse().unwrap();
+        let before = backdate(&pool, id).await;
+
+        let response = client.patch(format!("/credentials/{}", id))
+            .header(testing::bearer(&token))
+            .header(ContentType::JSON)
+            .body(json!({ "notes": "Reset in March" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(updated_at(&pool, id).await > before);
+    }
+
+    #[sqlx::test]
+    async fn recording_a_use_leaves_updated_at(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (token, team_id) = member(&client, "owner@example.com").await;
+        let credential = testing::create_credential(&client, &token, &team_id).await;
+        let id: Uuid = credential["id"].as_str().unwrap().parse().unwrap();
+        let before = backdate(&pool, id).await;
+
+        let response = client.get(format!("/credentials/{}", id)).header(testing::bearer(&token)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        // Uses are written in the background a couple of seconds later.
+        let mut used = false;
+        for _ in 0..50 {
+            used = count(&pool, "SELECT COUNT(*) FROM credentials WHERE id = $1 AND last_used_at IS NOT NULL", id).await > 0;
+            if used {
+                break;
+            }
+            rocket::tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        assert!(used, "the use was never written");
+        assert_eq!(updated_at(&pool, id).await, before);
+    }
+
+    #[sqlx::test]
+    async fn expiry_notices_leave_updated_at(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (token, team_id) = member(&client, "owner@example.com").await;
+        let credential = testing::create_credential(&client, &token, &team_id).await;
+        let id: Uuid = credential["id"].as_str().unwrap().parse().unwrap();
+        sqlx::query("UPDATE credentials SET expires_at = NOW() + interval '3 days' WHERE id = $1")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let before = backdate(&pool, id).await;
+
+        let emailer: crate::email::SharedEmailer = std::sync::Arc::new(crate::email::LogEmailer);
+        let notified = crate::expiry::notify_expiring_credentials(&pool, &emailer, &[14]).await.unwrap();
+        assert_eq!(notified, 1);
+        assert_eq!(updated_at(&pool, id).await, before);
+    }
+
+    #[sqlx::test]
+    async fn archiving_records_who_did_it(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let (owner, team_id) = member(&client, "owner@example.com").await;
+        let (archiver, _) = member(&client, "archiver@example.com").await;
+        let archiver_id: Uuid = sqlx::query_scalar("SELECT id FROM users WHERE email = 'archiver@example.com'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO team_members (team_id, user_id, role) VALUES ($1::uuid, $2, 'member')")
+            .bind(&team_id)
+            .bind(archiver_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let credential = testing::create_credential(&client, &owner, &team_id).await;
+
+        for action in ["archive", "unarchive"] {
+            let response = client.post(format!("/credentials/{}/{}", credential["id"].as_str().unwrap(), action))
+                .header(testing::bearer(&archiver))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok, "{}", action);
+            let summary: Value = response.into_json().await.unwrap();
+            assert_eq!(summary["updated_by"], archiver_id.to_string(), "{}", action);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::guards::AuthenticatedUser;
+use crate::sessions::revoke_device_sessions;
+use crate::validation::{is_valid_name, Validator, MAX_NAME_LEN};
+
+/// The longest device identifier accepted, in bytes.
+const MAX_DEVICE_IDENTIFIER_LEN: usize = 128;
+/// The longest platform accepted, in characters.
+const MAX_PLATFORM_LEN: usize = 50;
+
+// --- Request DTOs ---
+
+/// The device a client logs in from, sent along with the login.
+///
+/// Also stored with a pending second factor login, so the device carries over to
+/// the session the second factor completes.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceRequest {
+    /// An identifier the client generated for itself, e.g. a random UUID kept in local
+    /// storage. It should be hard to guess, since it is what marks the device as known.
+    pub id: String,
+    /// A label for the device, e.g. "Firefox on my laptop". Only used the first time the
+    /// device is seen; afterwards it is changed through `PATCH /auth/devices/<id>`.
+    pub name: String,
+    /// The client's platform, e.g. "web", "android", or "cli".
+    pub platform: Option<String>,
+}
+
+/// The new name for a device.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenameDeviceRequest {
+    pub name: String,
+}
+
+// --- Response DTOs ---
+
+/// One of the caller's devices.
+#[derive(Serialize)]
+pub struct DeviceResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub platform: Option<String>,
+    /// Whether the device is trusted while `require_device_approval` is set.
+    pub approved: bool,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// Whether this is the device the request was made from.
+    pub current: bool,
+}
+
+/// A device a login was made from, as recorded by [`register_device`].
+pub(crate) struct RegisteredDevice {
+    pub id: Uuid,
+    pub approved: bool,
+    /// Whether this login was the first from the device.
+    pub is_new: bool,
+}
+
+// --- Helpers ---
+
+/// Checks the device sent with a login, which must be present if `required`.
+pub(crate) fn validate_device(device: Option<&DeviceRequest>, required: bool) -> Result<(), ApiError> {
+    let mut v = Validator::new();
+    v.check("device", !required || device.is_some(), "is required");
+    if let Some(device) = device {
+        v.check(
+            "device.id",
+            !device.id.is_empty() && device.id.len() <= MAX_DEVICE_IDENTIFIER_LEN,
+            format!("must be between 1 and {} bytes", MAX_DEVICE_IDENTIFIER_LEN),
+        )
+            .check("device.name", is_valid_name(&device.name), format!("must be between 1 and {} characters", MAX_NAME_LEN))
+            .check(
+                "device.platform",
+                device.platform.as_ref().is_none_or(|p| p.chars().count() <= MAX_PLATFORM_LEN),
+                format!("must be at most {} characters", MAX_PLATFORM_LEN),
+            );
+    }
+    v.finish()
+}
+
+/// Records a login from a device, adding it to the user's devices the first time it
+/// is seen and bumping its `last_seen_at` otherwise.
+///
+/// A new device is approved straight away unless `require_device_approval` is set. A
+/// user's first device is always approved, since there would be nothing to approve it from.
+pub(crate) async fn register_device(
+    conn: &mut sqlx::PgConnection,
+    config: &AppConfig,
+    user_id: Uuid,
+    device: &DeviceRequest,
+) -> Result<RegisteredDevice, sqlx::Error> {
+    sqlx::query_as!(
+        RegisteredDevice,
+        "INSERT INTO devices (user_id, device_identifier, name, platform, approved)
+         VALUES ($1, $2, $3, $4, NOT $5 OR NOT EXISTS (SELECT 1 FROM devices WHERE user_id = $1 AND approved))
+         ON CONFLICT (user_id, device_identifier) DO UPDATE
+         SET platform = EXCLUDED.platform, last_seen_at = NOW()
+         RETURNING id, approved, xmax = 0 AS \"is_new!\"",
+        user_id,
+        device.id,
+        device.name.trim(),
+        device.platform,
+        config.require_device_approval
+    )
+        .fetch_one(conn)
+        .await
+}
+
+/// Loads one of the user's devices, flagging it as `current` if `session_id` is on it.
+async fn fetch_device(
+    conn: &mut sqlx::PgConnection,
+    user_id: Uuid,
+    session_id: Option<Uuid>,
+    device_id: Uuid,
+) -> Result<Option<DeviceResponse>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceResponse,
+        "SELECT id, name, platform, approved, first_seen_at, last_seen_at,
+                COALESCE(id = (SELECT device_id FROM sessions WHERE id = $2), false) AS \"current!\"
+         FROM devices WHERE user_id = $1 AND id = $3",
+        user_id,
+        session_id,
+        device_id
+    )
+        .fetch_optional(conn)
+        .await
+}
+
+// --- Routes ---
+
+/// Lists the devices the caller has logged in from, most recently used first.
+///
+/// The device the request was made from is flagged as `current`.
+#[get("/devices")]
+pub async fn list_devices(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Vec<DeviceResponse>>, Status> {
+    let devices = sqlx::query_as!(
+        DeviceResponse,
+        "SELECT id, name, platform, approved, first_seen_at, last_seen_at,
+                COALESCE(id = (SELECT device_id FROM sessions WHERE id = $2), false) AS \"current!\"
+         FROM devices WHERE user_id = $1
+         ORDER BY last_seen_at DESC",
+        user.id,
+        user.session_id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(devices))
+}
+
+/// Renames one of the caller's devices and returns it.
+///
+/// Returns `404 Not Found` if the caller has no device with that id, or
+/// `422 Unprocessable Entity` if the name isn't 1–100 characters.
+#[patch("/devices/<id>", data = "<rename_data>")]
+pub async fn rename_device(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+    rename_data: JsonBody<RenameDeviceRequest>,
+) -> Result<Json<DeviceResponse>, ApiError> {
+    let mut v = Validator::new();
+    v.check("name", is_valid_name(&rename_data.name), format!("must be between 1 and {} characters", MAX_NAME_LEN));
+    v.finish()?;
+
+    let renamed = sqlx::query!(
+        "UPDATE devices SET name = $1 WHERE id = $2 AND user_id = $3",
+        rename_data.name.trim(),
+        id,
+        user.id
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+    if !renamed {
+        return Err(Status::NotFound.into());
+    }
+
+    let device = fetch_device(db.as_mut(), user.id, user.session_id, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(device))
+}
+
+/// Approves one of the caller's devices, lifting the restrictions on its sessions
+/// under `require_device_approval`, and returns it.
+///
+/// Only a logged-in session can approve devices, and while approval is required only
+/// one on an approved device gets this far. Requests made with a personal access token
+/// fail with `403 Forbidden` and `{"error": "session_required"}`. Returns
+/// `404 Not Found` if the caller has no device with that id.
+#[post("/devices/<id>/approve")]
+pub async fn approve_device(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<DeviceResponse>, ApiError> {
+    if user.session_id.is_none() {
+        return Err(ApiError::new(Status::Forbidden, "session_required"));
+    }
+
+    let approved = sqlx::query!("UPDATE devices SET approved = true WHERE id = $1 AND user_id = $2", id, user.id)
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+    if !approved {
+        return Err(Status::NotFound.into());
+    }
+
+    let device = fetch_device(db.as_mut(), user.id, user.session_id, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(device))
+}
+
+/// Removes one of the caller's devices and signs it out: every session on it is
+/// revoked along with its refresh tokens. Logging in from it again registers it as a
+/// new device.
+///
+/// Returns `204 No Content`, or `404 Not Found` if the caller has no device with that id.
+#[delete("/devices/<id>")]
+pub async fn delete_device(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, Status> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let owned = sqlx::query_scalar!(
+        "SELECT id FROM devices WHERE id = $1 AND user_id = $2 FOR UPDATE",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .is_some();
+    if !owned {
+        return Err(Status::NotFound);
+    }
+
+    revoke_device_sessions(&mut tx, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!("DELETE FROM devices WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,540 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::{http::Status, State};
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::email::{send_in_background, EmailMessage, SharedEmailer};
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser, ClientInfo};
+use crate::models::EmergencyGrantStatus;
+use crate::notifications::describe_user_agent;
+use crate::validation::Validator;
+use super::auth::{deserialize_base64, serialize_base64};
+
+/// The longest waiting period a grant may have, in days.
+const MAX_WAIT_DAYS: i32 = 90;
+/// An upper bound for wrapped keys: a key sealed for a public key fits easily.
+const MAX_WRAPPED_KEY_LEN: usize = 1024;
+
+// --- Request DTOs ---
+
+/// The settings for a new emergency grant.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateGrantRequest {
+    /// The email address of the trusted contact.
+    pub grantee_email: String,
+    /// The caller's private key, wrapped for the grantee's public key.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub wrapped_private_key: Vec<u8>,
+    /// How many days a request must go unrejected before the grantee gets the key.
+    pub wait_days: i32,
+}
+
+// --- Response DTOs ---
+
+/// An emergency grant the caller gave or received.
+#[derive(Serialize)]
+pub struct GrantResponse {
+    pub id: Uuid,
+    pub grantor_id: Uuid,
+    pub grantor_email: String,
+    pub grantee_id: Uuid,
+    pub grantee_email: String,
+    pub wait_days: i32,
+    pub status: EmergencyGrantStatus,
+    /// When the grantee last requested access, while the request stands.
+    pub requested_at: Option<DateTime<Utc>>,
+    /// When a running request unlocks the grant unless it is rejected first.
+    pub unlocks_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The emergency grants the caller gave and received.
+#[derive(Serialize)]
+pub struct GrantListResponse {
+    pub granted: Vec<GrantResponse>,
+    pub received: Vec<GrantResponse>,
+}
+
+/// The grantor's private key, for the grantee of an unlocked grant.
+#[derive(Serialize)]
+pub struct GrantKeyResponse {
+    /// The grantor's private key, wrapped for the grantee's public key.
+    #[serde(serialize_with = "serialize_base64")]
+    pub wrapped_private_key: Vec<u8>,
+}
+
+/// One change to an emergency grant.
+#[derive(Serialize)]
+pub struct GrantEventResponse {
+    /// One of `created`, `requested`, `rejected`, `unlocked`, or `revoked`.
+    pub action: String,
+    /// The user who made the change.
+    pub actor_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+// --- Helpers ---
+
+/// A change to an emergency grant, as stored in `emergency_grant_events.action`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GrantAction {
+    Created,
+    Requested,
+    Rejected,
+    Unlocked,
+    Revoked,
+}
+
+impl GrantAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            GrantAction::Created => "created",
+            GrantAction::Requested => "requested",
+            GrantAction::Rejected => "rejected",
+            GrantAction::Unlocked => "unlocked",
+            GrantAction::Revoked => "revoked",
+        }
+    }
+}
+
+/// Appends a change to a grant's history. Called within the transaction making the
+/// change, so a change is never left unrecorded.
+async fn record_grant_event(
+    conn: &mut sqlx::PgConnection,
+    grant_id: Uuid,
+    grantor_id: Uuid,
+    grantee_id: Uuid,
+    actor_id: Uuid,
+    action: GrantAction,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO emergency_grant_events (grant_id, grantor_id, grantee_id, actor_id, action)
+         VALUES ($1, $2, $3, $4, $5)",
+        grant_id,
+        grantor_id,
+        grantee_id,
+        actor_id,
+        action.as_str()
+    )
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Loads the grants the user gave or received, or only the one with `grant_id`.
+async fn fetch_grants(
+    conn: &mut sqlx::PgConnection,
+    user_id: Uuid,
+    grant_id: Option<Uuid>,
+) -> Result<Vec<GrantResponse>, sqlx::Error> {
+    sqlx::query_as!(
+        GrantResponse,
+        "SELECT g.id, g.grantor_id, r.email AS grantor_email, g.grantee_id, e.email AS grantee_email,
+                g.wait_days, g.status AS \"status: EmergencyGrantStatus\",
+                CASE WHEN g.status = 'requested' THEN g.requested_at END AS requested_at,
+                CASE WHEN g.status = 'requested' THEN g.requested_at + make_interval(days => g.wait_days) END
+                    AS unlocks_at,
+                g.created_at
+         FROM emergency_grants g
+         JOIN users r ON r.id = g.grantor_id
+         JOIN users e ON e.id = g.grantee_id
+         WHERE (g.grantor_id = $1 OR g.grantee_id = $1) AND ($2::uuid IS NULL OR g.id = $2)
+         ORDER BY g.created_at",
+        user_id,
+        grant_id
+    )
+        .fetch_all(conn)
+        .await
+}
+
+/// Loads a single grant the user is a party to.
+async fn fetch_grant(conn: &mut sqlx::PgConnection, user_id: Uuid, grant_id: Uuid) -> Result<GrantResponse, Status> {
+    fetch_grants(conn, user_id, Some(grant_id))
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .pop()
+        .ok_or(Status::NotFound)
+}
+
+// --- Routes ---
+
+/// Grants a trusted contact emergency access to the caller's account.
+///
+/// The client wraps the caller's private key for the grantee's public key; the server
+/// only ever stores the wrapped key. The grantee can ask for it through
+/// `POST /emergency/<id>/request`, and gets it once `wait_days` pass without the caller
+/// rejecting the request. A grant must be revoked and created again after either user's
+/// key pair changes, since the wrapped key no longer fits.
+///
+/// Requires a login session: requests made with a personal access token fail with
+/// `403 Forbidden` and `{"error": "session_required"}`. Returns `201 Created`, `404 Not
+/// Found` if no account has the grantee's email, `409 Conflict` if the grantee already
+/// has a grant from the caller, or `422 Unprocessable Entity` listing invalid fields.
+#[post("/", data = "<grant_data>")]
+pub async fn create_grant(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    grant_data: JsonBody<CreateGrantRequest>,
+) -> Result<(Status, Json<GrantResponse>), ApiError> {
+    if user.session_id.is_none() {
+        return Err(ApiError::new(Status::Forbidden, "session_required"));
+    }
+
+    let mut v = Validator::new();
+    v.check(
+        "wait_days",
+        (1..=MAX_WAIT_DAYS).contains(&grant_data.wait_days),
+        format!("must be between 1 and {}", MAX_WAIT_DAYS),
+    )
+        .check(
+            "wrapped_private_key",
+            !grant_data.wrapped_private_key.is_empty() && grant_data.wrapped_private_key.len() <= MAX_WRAPPED_KEY_LEN,
+            format!("must be between 1 and {} bytes", MAX_WRAPPED_KEY_LEN),
+        );
+    v.finish()?;
+
+    let grantee_id = sqlx::query_scalar!("SELECT id FROM users WHERE email = $1", grant_data.grantee_email)
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let mut v = Validator::new();
+    v.check("grantee_email", grantee_id != user.id, "must not be your own");
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let grant_id = sqlx::query_scalar!(
+        "INSERT INTO emergency_grants (grantor_id, grantee_id, wrapped_private_key, wait_days)
+         VALUES ($1, $2, $3, $4) RETURNING id",
+        user.id,
+        grantee_id,
+        grant_data.wrapped_private_key,
+        grant_data.wait_days
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    record_grant_event(&mut tx, grant_id, user.id, grantee_id, user.id, GrantAction::Created)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    let grant = fetch_grant(db.as_mut(), user.id, grant_id).await?;
+    Ok((Status::Created, Json(grant)))
+}
+
+/// Lists the emergency grants the caller gave (`granted`) and received (`received`),
+/// oldest first. Wrapped keys are never included.
+#[get("/")]
+pub async fn list_grants(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<GrantListResponse>, Status> {
+    let (granted, received) = fetch_grants(db.as_mut(), user.id, None)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .partition(|grant| grant.grantor_id == user.id);
+
+    Ok(Json(GrantListResponse { granted, received }))
+}
+
+/// Revokes an emergency grant the caller gave, whatever state it is in. Its history
+/// stays available through `GET /emergency/<id>/events`.
+///
+/// Returns `204 No Content`, `403 Forbidden` for a token limited to some teams, or
+/// `404 Not Found` if the caller gave no such grant.
+#[delete("/<id>")]
+pub async fn revoke_grant(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, ApiError> {
+    if !user.permissions.covers_all_teams() {
+        return Err(insufficient_scope());
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let grantee_id = sqlx::query_scalar!(
+        "DELETE FROM emergency_grants WHERE id = $1 AND grantor_id = $2 RETURNING grantee_id",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    record_grant_event(&mut tx, id, user.id, grantee_id, user.id, GrantAction::Revoked)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Requests emergency access through a grant the caller received, starting its
+/// waiting period. The grantor is notified and emailed, and can reject the request
+/// through `POST /emergency/<id>/reject` until it unlocks.
+///
+/// Returns the updated grant, `403 Forbidden` for a token limited to some teams, `404
+/// Not Found` if the caller received no such grant, or `409 Conflict` with
+/// `{"error": "already_requested"}` if a request is running or the grant is unlocked.
+#[post("/<id>/request")]
+pub async fn request_access(
+    mut db: Connection<DatabasePool>,
+    emailer: &State<SharedEmailer>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    id: Uuid,
+) -> Result<Json<GrantResponse>, ApiError> {
+    if !user.permissions.covers_all_teams() {
+        return Err(insufficient_scope());
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let requested = sqlx::query!(
+        "UPDATE emergency_grants g SET status = 'requested', requested_at = NOW()
+         FROM users r, users e
+         WHERE g.id = $1 AND g.grantee_id = $2 AND g.status IN ('pending', 'rejected')
+           AND r.id = g.grantor_id AND e.id = g.grantee_id
+         RETURNING g.grantor_id, g.wait_days, r.email AS grantor_email, e.email AS grantee_email,
+                   g.requested_at + make_interval(days => g.wait_days) AS \"unlocks_at!\"",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let Some(requested) = requested else {
+        drop(tx);
+        let grant = fetch_grant(db.as_mut(), user.id, id).await?;
+        if grant.grantee_id != user.id {
+            return Err(Status::NotFound.into());
+        }
+        return Err(ApiError::new(Status::Conflict, "already_requested"));
+    };
+
+    record_grant_event(&mut tx, id, requested.grantor_id, user.id, user.id, GrantAction::Requested)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let client_description = client.user_agent.as_deref().map(describe_user_agent);
+    sqlx::query!(
+        "INSERT INTO notifications (user_id, kind, ip_address, user_agent)
+         VALUES ($1, 'emergency_access_requested', $2, $3)",
+        requested.grantor_id,
+        client.ip_address(),
+        client_description
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    send_in_background(emailer, EmailMessage {
+        to: requested.grantor_email,
+        subject: "Emergency access to your HomeDesk account was requested".to_string(),
+        body: format!(
+            "{} has requested emergency access to your HomeDesk account.\n\n\
+             Unless you reject the request, they will be able to decrypt your data from {} \
+             (after the {}-day waiting period you chose).\n\n\
+             If you didn't expect this, reject the request from your emergency access settings.",
+            requested.grantee_email,
+            requested.unlocks_at.format("%Y-%m-%d %H:%M UTC"),
+            requested.wait_days,
+        ),
+    });
+
+    let grant = fetch_grant(db.as_mut(), user.id, id).await?;
+    Ok(Json(grant))
+}
+
+/// Rejects the running request on an emergency grant the caller gave. The grant stays
+/// in place, and a new request starts the waiting period over.
+///
+/// Returns the updated grant, `403 Forbidden` for a token limited to some teams, `404
+/// Not Found` if the caller gave no such grant, or `409 Conflict` with
+/// `{"error": "not_requested"}` if no request is running.
+#[post("/<id>/reject")]
+pub async fn reject_request(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<GrantResponse>, ApiError> {
+    if !user.permissions.covers_all_teams() {
+        return Err(insufficient_scope());
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let grantee_id = sqlx::query_scalar!(
+        "UPDATE emergency_grants SET status = 'rejected', requested_at = NULL
+         WHERE id = $1 AND grantor_id = $2 AND status = 'requested'
+         RETURNING grantee_id",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let Some(grantee_id) = grantee_id else {
+        drop(tx);
+        let grant = fetch_grant(db.as_mut(), user.id, id).await?;
+        if grant.grantor_id != user.id {
+            return Err(Status::NotFound.into());
+        }
+        return Err(ApiError::new(Status::Conflict, "not_requested"));
+    };
+
+    record_grant_event(&mut tx, id, user.id, grantee_id, user.id, GrantAction::Rejected)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    let grant = fetch_grant(db.as_mut(), user.id, id).await?;
+    Ok(Json(grant))
+}
+
+/// Returns the grantor's wrapped private key to the grantee of an emergency grant.
+///
+/// A request whose waiting period has passed unlocks the grant here, on first access.
+///
+/// Requires a login session, like creating a grant: requests made with a personal
+/// access token fail with `403 Forbidden` and `{"error": "session_required"}`. Returns
+/// `403 Forbidden` with `{"error": "emergency_access_locked"}` and the grant's
+/// `unlocks_at` (`null` without a running request) while it is locked, or `404 Not
+/// Found` if the caller received no such grant.
+#[get("/<id>/key")]
+pub async fn get_grant_key(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<GrantKeyResponse>, ApiError> {
+    if user.session_id.is_none() {
+        return Err(ApiError::new(Status::Forbidden, "session_required"));
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let unlocked_grantor = sqlx::query_scalar!(
+        "UPDATE emergency_grants SET status = 'unlocked'
+         WHERE id = $1 AND grantee_id = $2 AND status = 'requested'
+           AND requested_at + make_interval(days => wait_days) <= NOW()
+         RETURNING grantor_id",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if let Some(grantor_id) = unlocked_grantor {
+        record_grant_event(&mut tx, id, grantor_id, user.id, user.id, GrantAction::Unlocked)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
+    let grant = sqlx::query!(
+        "SELECT status AS \"status: EmergencyGrantStatus\", wrapped_private_key,
+                CASE WHEN status = 'requested' THEN requested_at + make_interval(days => wait_days) END
+                    AS unlocks_at
+         FROM emergency_grants WHERE id = $1 AND grantee_id = $2",
+        id,
+        user.id
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    if grant.status != EmergencyGrantStatus::Unlocked {
+        return Err(ApiError::new(Status::Forbidden, "emergency_access_locked").with("unlocks_at", grant.unlocks_at));
+    }
+
+    Ok(Json(GrantKeyResponse { wrapped_private_key: grant.wrapped_private_key }))
+}
+
+/// Lists every change to an emergency grant the caller gave or received, oldest first.
+/// The history of a revoked grant stays available.
+///
+/// Returns `404 Not Found` if the caller was never a party to such a grant.
+#[get("/<id>/events")]
+pub async fn list_grant_events(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Json<Vec<GrantEventResponse>>, Status> {
+    let events = sqlx::query_as!(
+        GrantEventResponse,
+        "SELECT action, actor_id, created_at FROM emergency_grant_events
+         WHERE grant_id = $1 AND (grantor_id = $2 OR grantee_id = $2)
+         ORDER BY created_at, id",
+        id,
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if events.is_empty() {
+        return Err(Status::NotFound);
+    }
+
+    Ok(Json(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use rocket::serde::json::{json, Value};
+    use sqlx::PgPool;
+    use uuid::Uuid;
+    use crate::testing;
+
+    #[sqlx::test]
+    async fn grant_keys_need_a_login_session(pool: PgPool) {
+        let client = testing::client(pool).await;
+        testing::signup(&client, "grantee@example.com").await;
+        let session = testing::access_token(&client, "grantee@example.com").await;
+        let token = testing::api_token(&client, &session, json!({ "name": "every team" })).await;
+
+        let response = client.get(format!("/emergency/{}/key", Uuid::new_v4()))
+            .header(testing::bearer(&token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["error"], "session_required");
+    }
+}
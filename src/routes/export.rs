@@ -0,0 +1,557 @@
+use base64::Engine;
+use rocket_db_pools::{sqlx, Connection};
+use rocket::futures::StreamExt;
+use rocket::futures::stream::Stream;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::response::stream::ByteStream;
+use rocket::serde::json::serde_json;
+use rocket::serde::Serialize;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::access_log::AccessAction;
+use crate::crypto::constant_time_eq;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser, ClientInfo};
+use crate::models::{FieldType, TeamRole, UriMatchType};
+use super::account::{fetch_me, MeResponse};
+use super::auth::{opaque_account, serialize_base64};
+
+/// The header carrying the password hash that confirms an export, as standard Base64.
+const PASSWORD_HASH_HEADER: &str = "X-Password-Hash";
+
+// --- Response DTOs ---
+
+/// A team the user belongs to, with the team key wrapped for them.
+#[derive(Serialize)]
+pub struct ExportedTeam {
+    pub id: Uuid,
+    pub name: String,
+    pub is_personal: bool,
+    pub role: TeamRole,
+    /// Whether the user is the team's only member, in which case its credentials are
+    /// part of the export.
+    pub sole_member: bool,
+    pub updated_at: DateTime<Utc>,
+    /// The team key wrapped for the user's public key, unless a team admin has yet to
+    /// re-wrap it after a password reset.
+    pub team_key: Option<ExportedTeamKey>,
+}
+
+/// A team key wrapped for the user. Binary fields are encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct ExportedTeamKey {
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_team_key: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+/// A credential from a team the user is the only member of, still encrypted with the
+/// team key. Binary fields are encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct ExportedCredential {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub title: String,
+    pub hostname: String,
+    pub username: String,
+    pub kind: String,
+    pub public_key: Option<String>,
+    pub ssh_key_type: Option<String>,
+    pub ssh_fingerprint: Option<String>,
+    pub ssh_comment: Option<String>,
+    pub totp_digits: Option<i16>,
+    pub totp_period: Option<i32>,
+    pub totp_algorithm: Option<String>,
+    pub metadata: serde_json::Value,
+    pub notes: String,
+    pub icon_url: Option<String>,
+    pub tags: Vec<String>,
+    pub folder_id: Option<Uuid>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub secret_changed_at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub nonce: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the credential was archived, if it is.
+    pub archived_at: Option<DateTime<Utc>>,
+    /// When the credential was moved to the trash, if it is there.
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// For a credential another team shares into `team_id`, that team. Its secret is the
+    /// copy encrypted with this team's key, and its fields, addresses and attachments
+    /// aren't exported.
+    pub shared_from_team: Option<Uuid>,
+}
+
+/// A custom field of an exported credential, its value still encrypted with the team
+/// key. Binary fields are encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct ExportedField {
+    pub credential_id: Uuid,
+    pub position: i32,
+    pub label: String,
+    pub field_type: FieldType,
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_value: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+/// An address an exported credential applies to.
+#[derive(Serialize)]
+pub struct ExportedUri {
+    pub credential_id: Uuid,
+    pub position: i32,
+    pub uri: String,
+    pub match_type: UriMatchType,
+}
+
+/// A file attached to an exported credential, without its content, which is streamed
+/// after these fields. Binary fields are encoded as Base64 in JSON.
+#[derive(Serialize)]
+pub struct ExportedAttachment {
+    pub id: Uuid,
+    pub credential_id: Uuid,
+    pub filename: String,
+    pub size: i64,
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_key: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub key_nonce: Vec<u8>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An account export, streamed as a JSON attachment named after the time it was made.
+pub struct AccountExport<S> {
+    stream: ByteStream<S>,
+    filename: String,
+}
+
+impl<'r, S> Responder<'r, 'r> for AccountExport<S>
+where
+    S: Stream<Item = Vec<u8>> + Send + 'r,
+{
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        let mut response = self.stream.respond_to(request)?;
+        response.set_header(ContentType::JSON);
+        response.set_header(Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", self.filename),
+        ));
+        Ok(response)
+    }
+}
+
+// --- Helpers ---
+
+/// The password hash sent in [`PASSWORD_HASH_HEADER`], or `None` if the header is
+/// missing or isn't Base64.
+pub struct PasswordHashHeader(Option<Vec<u8>>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PasswordHashHeader {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let hash = request
+            .headers()
+            .get_one(PASSWORD_HASH_HEADER)
+            .and_then(|value| base64::engine::general_purpose::STANDARD.decode(value).ok());
+        request::Outcome::Success(PasswordHashHeader(hash))
+    }
+}
+
+/// Serializes a part of the export, which only fails for types serde_json can't represent.
+fn to_json<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).expect("export parts serialize to JSON")
+}
+
+/// Encodes content arriving in chunks as one Base64 string. Chunks needn't be multiples
+/// of three bytes, so up to two bytes carry over to the next.
+#[derive(Default)]
+struct Base64Chunks {
+    carry: Vec<u8>,
+}
+
+impl Base64Chunks {
+    /// Encodes as much of `chunk` as can be without knowing what follows.
+    fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.carry.extend_from_slice(chunk);
+        let whole = self.carry.len() / 3 * 3;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&self.carry[..whole]);
+        self.carry.drain(..whole);
+        encoded.into_bytes()
+    }
+
+    /// Encodes what is left, with padding.
+    fn finish(self) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD.encode(&self.carry).into_bytes()
+    }
+}
+
+// --- Routes ---
+
+/// Exports everything attributable solely to the caller as a JSON attachment: the
+/// `profile` as returned by `GET /auth/me`, every team membership with its wrapped
+/// team key, and all `credentials` the caller may read of teams they are the only
+/// member of with their custom `fields`, `uris`, and `attachments`, each attachment with
+/// its `content`.
+/// Binary fields stay encrypted and are encoded as Base64.
+///
+/// Requires the current password hash, Base64-encoded, in the `X-Password-Hash` header.
+/// Credentials are streamed row by row and attachments chunk by chunk, so large vaults
+/// never sit in memory at once; only the attachments' other fields are read up front. A
+/// database error after streaming has started can only cut the document short, leaving
+/// it invalid JSON. Every exported credential gets an entry in its access log.
+/// Credentials other teams share into those teams are included with `shared_from_team`
+/// set, carrying the shared copy of the secret.
+///
+/// Returns `400 Bad Request` with `{"error": "password_confirmation_required"}` without
+/// the header, `403 Forbidden` with `{"error": "invalid_password"}` if the hash is wrong,
+/// `403 Forbidden` for a token limited to some teams, or `409 Conflict` with
+/// `{"error": "opaque_account"}` for accounts using OPAQUE, which have no hash to check.
+#[get("/export")]
+pub async fn export_account(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    password_hash: PasswordHashHeader,
+) -> Result<AccountExport<impl Stream<Item = Vec<u8>>>, ApiError> {
+    if !user.permissions.covers_all_teams() {
+        return Err(insufficient_scope());
+    }
+
+    let Some(password_hash) = password_hash.0 else {
+        return Err(ApiError::new(Status::BadRequest, "password_confirmation_required"));
+    };
+
+    let current_hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", user.id)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or_else(opaque_account)?;
+
+    if !constant_time_eq(&current_hash, &password_hash) {
+        return Err(ApiError::new(Status::Forbidden, "invalid_password"));
+    }
+
+    let profile: MeResponse = fetch_me(db.as_mut(), &user).await?;
+
+    let teams: Vec<ExportedTeam> = sqlx::query!(
+        "SELECT t.id, t.name, t.is_personal IS TRUE AS \"is_personal!\", m.role AS \"role: TeamRole\", t.updated_at,
+                NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = t.id AND o.user_id <> $1)
+                    AS \"sole_member!\",
+                k.encrypted_team_key AS \"encrypted_team_key?\", k.nonce AS \"nonce?\"
+         FROM team_members m
+         JOIN teams t ON t.id = m.team_id
+         LEFT JOIN team_key_access k ON k.team_id = m.team_id AND k.user_id = m.user_id
+         WHERE m.user_id = $1
+         ORDER BY t.created_at, t.id",
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(|row| ExportedTeam {
+            id: row.id,
+            name: row.name,
+            is_personal: row.is_personal,
+            role: row.role,
+            sole_member: row.sole_member,
+            updated_at: row.updated_at,
+            team_key: row.encrypted_team_key.zip(row.nonce).map(|(encrypted_team_key, nonce)| ExportedTeamKey {
+                encrypted_team_key,
+                nonce,
+            }),
+        })
+        .collect();
+
+    // Logged before streaming starts, while a failure can still be reported.
+    sqlx::query!(
+        "INSERT INTO credential_access_log (credential_id, user_id, action, ip_address)
+         SELECT c.id, $1, $2, $3
+         FROM credentials c
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $1
+         WHERE NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = c.team_id AND o.user_id <> $1)
+           AND credential_acl_allows(c.id, $1, FALSE)
+         UNION ALL
+         SELECT s.credential_id, $1, $2, $3
+         FROM credential_shares s
+         JOIN credentials c ON c.id = s.credential_id
+         JOIN team_members m ON m.team_id = s.target_team_id AND m.user_id = $1
+         WHERE NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = s.target_team_id AND o.user_id <> $1)
+           AND c.deleted_at IS NULL",
+        user.id,
+        AccessAction::Export.as_str(),
+        client.ip_address()
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let exported_at = Utc::now();
+    let filename = format!("homedesk-export-{}.json", exported_at.format("%Y%m%dT%H%M%SZ"));
+    let user_id = user.id;
+
+    let stream = ByteStream! {
+        let mut head = b"{\"exported_at\":".to_vec();
+        head.extend(to_json(&exported_at));
+        head.extend(b",\"profile\":");
+        head.extend(to_json(&profile));
+        head.extend(b",\"teams\":");
+        head.extend(to_json(&teams));
+        head.extend(b",\"credentials\":[");
+        yield head;
+
+        let mut credentials = sqlx::query_as!(
+            ExportedCredential,
+            "SELECT c.id AS \"id!\", c.team_id AS \"team_id!\", c.title AS \"title!\", c.hostname AS \"hostname!\",
+                    c.username AS \"username!\", c.kind::text AS \"kind!\", c.public_key, c.ssh_key_type::text,
+                    c.ssh_fingerprint, c.ssh_comment, c.totp_digits, c.totp_period, c.totp_algorithm::text,
+                    c.metadata AS \"metadata!\", c.notes AS \"notes!\", c.icon_url, c.tags AS \"tags!\", c.folder_id,
+                    c.expires_at, c.secret_changed_at AS \"secret_changed_at!\", c.encrypted_secret AS \"encrypted_secret!\",
+                    c.nonce AS \"nonce!\", c.created_at AS \"created_at!\", c.updated_at AS \"updated_at!\", c.archived_at,
+                    c.deleted_at, NULL::uuid AS shared_from_team
+             FROM credentials c
+             JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $1
+             WHERE NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = c.team_id AND o.user_id <> $1)
+               AND credential_acl_allows(c.id, $1, FALSE)
+             UNION ALL
+             SELECT c.id, s.target_team_id, c.title, c.hostname, c.username, c.kind::text,
+                    c.public_key, c.ssh_key_type::text, c.ssh_fingerprint, c.ssh_comment, c.totp_digits, c.totp_period,
+                    c.totp_algorithm::text, c.metadata, c.notes, c.icon_url, c.tags, NULL, c.expires_at,
+                    c.secret_changed_at,
+                    s.encrypted_secret, s.nonce, c.created_at, c.updated_at, c.archived_at, c.deleted_at,
+                    c.team_id
+             FROM credential_shares s
+             JOIN credentials c ON c.id = s.credential_id
+             JOIN team_members m ON m.team_id = s.target_team_id AND m.user_id = $1
+             WHERE NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = s.target_team_id AND o.user_id <> $1)
+               AND c.deleted_at IS NULL
+             ORDER BY 2, 23, 1",
+            user_id
+        )
+            .fetch(db.as_mut());
+
+        let mut first = true;
+        while let Some(credential) = credentials.next().await {
+            let credential = match credential {
+                Ok(credential) => credential,
+                Err(e) => {
+                    warn!("Failed to export credentials of user {}: {}", user_id, e);
+                    return;
+                }
+            };
+            let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+            chunk.extend(to_json(&credential));
+            first = false;
+            yield chunk;
+        }
+        drop(credentials);
+
+        yield b"],\"fields\":[".to_vec();
+
+        let mut fields = sqlx::query_as!(
+            ExportedField,
+            "SELECT f.credential_id, f.position, f.label, f.field_type AS \"field_type: FieldType\",
+                    f.encrypted_value, f.nonce
+             FROM credential_fields f
+             JOIN credentials c ON c.id = f.credential_id
+             JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $1
+             WHERE NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = c.team_id AND o.user_id <> $1)
+               AND credential_acl_allows(c.id, $1, FALSE)
+             ORDER BY c.team_id, c.created_at, c.id, f.position",
+            user_id
+        )
+            .fetch(db.as_mut());
+
+        let mut first = true;
+        while let Some(field) = fields.next().await {
+            let field = match field {
+                Ok(field) => field,
+                Err(e) => {
+                    warn!("Failed to export custom fields of user {}: {}", user_id, e);
+                    return;
+                }
+            };
+            let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+            chunk.extend(to_json(&field));
+            first = false;
+            yield chunk;
+        }
+        drop(fields);
+
+        yield b"],\"uris\":[".to_vec();
+
+        let mut uris = sqlx::query_as!(
+            ExportedUri,
+            "SELECT u.credential_id, u.position, u.uri, u.match_type AS \"match_type: UriMatchType\"
+             FROM credential_uris u
+             JOIN credentials c ON c.id = u.credential_id
+             JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $1
+             WHERE NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = c.team_id AND o.user_id <> $1)
+               AND credential_acl_allows(c.id, $1, FALSE)
+             ORDER BY c.team_id, c.created_at, c.id, u.position",
+            user_id
+        )
+            .fetch(db.as_mut());
+
+        let mut first = true;
+        while let Some(uri) = uris.next().await {
+            let uri = match uri {
+                Ok(uri) => uri,
+                Err(e) => {
+                    warn!("Failed to export URIs of user {}: {}", user_id, e);
+                    return;
+                }
+            };
+            let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+            chunk.extend(to_json(&uri));
+            first = false;
+            yield chunk;
+        }
+        drop(uris);
+
+        yield b"],\"attachments\":[".to_vec();
+
+        let attachments = sqlx::query_as!(
+            ExportedAttachment,
+            "SELECT a.id, a.credential_id, a.filename, a.size, a.encrypted_key, a.key_nonce, a.created_by,
+                    a.created_at
+             FROM attachments a
+             JOIN credentials c ON c.id = a.credential_id
+             JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $1
+             WHERE NOT EXISTS (SELECT 1 FROM team_members o WHERE o.team_id = c.team_id AND o.user_id <> $1)
+               AND credential_acl_allows(c.id, $1, FALSE)
+             ORDER BY c.team_id, c.created_at, c.id, a.created_at, a.id",
+            user_id
+        )
+            .fetch_all(db.as_mut())
+            .await;
+        let attachments = match attachments {
+            Ok(attachments) => attachments,
+            Err(e) => {
+                warn!("Failed to export attachments of user {}: {}", user_id, e);
+                return;
+            }
+        };
+
+        for (i, attachment) in attachments.iter().enumerate() {
+            // The content goes last, in place of the closing brace.
+            let mut head = if i == 0 { Vec::new() } else { b",".to_vec() };
+            head.extend(to_json(attachment));
+            head.pop();
+            head.extend(b",\"content\":\"");
+            yield head;
+
+            let mut chunks = sqlx::query_scalar!(
+                "SELECT data FROM attachment_chunks WHERE attachment_id = $1 ORDER BY seq",
+                attachment.id
+            )
+                .fetch(db.as_mut());
+            let mut content = Base64Chunks::default();
+            while let Some(chunk) = chunks.next().await {
+                match chunk {
+                    Ok(chunk) => yield content.push(&chunk),
+                    Err(e) => {
+                        warn!("Failed to export attachment {} of user {}: {}", attachment.id, user_id, e);
+                        return;
+                    }
+                }
+            }
+
+            let mut tail = content.finish();
+            tail.extend(b"\"}");
+            yield tail;
+        }
+
+        yield b"]}".to_vec();
+    };
+
+    Ok(AccountExport { stream, filename })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Write};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use rocket::http::{Header, Status};
+    use rocket::serde::Deserialize;
+    use rocket::tokio::io::AsyncReadExt;
+    use sqlx::PgPool;
+    use crate::testing;
+
+    /// The peak resident set size of the test process, in KiB, since it was last reset.
+    fn peak_rss_kib() -> u64 {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmHWM:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[derive(Deserialize)]
+    #[serde(crate = "rocket::serde")]
+    struct Export {
+        credentials: Vec<rocket::serde::de::IgnoredAny>,
+    }
+
+    // Slow, and the memory reading is only meaningful with no other test running
+    // alongside: `cargo test large_exports -- --ignored`. Linux only.
+    #[sqlx::test]
+    #[ignore]
+    async fn large_exports_stream_in_bounded_memory(pool: PgPool) {
+        let client = testing::client(pool.clone()).await;
+        let signup = testing::signup(&client, "owner@example.com").await;
+        let token = testing::access_token(&client, "owner@example.com").await;
+        // 10,000 credentials with 4 KiB secrets make an export of over 50 MiB.
+        sqlx::query(
+            "INSERT INTO credentials (team_id, title, hostname, username, encrypted_secret, nonce)
+             SELECT $1::uuid, 'Server ' || n, 'host' || n || '.example.com', 'admin', decode(repeat('ab', 4096), 'hex'),
+                    decode(repeat('cd', 24), 'hex')
+             FROM generate_series(1, 10000) n"
+        )
+            .bind(signup["personal_team_id"].as_str().unwrap())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("homedesk-export-test-{}.json", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        std::fs::write("/proc/self/clear_refs", "5").unwrap();
+        let baseline = peak_rss_kib();
+
+        let mut response = client.get("/auth/export")
+            .header(testing::bearer(&token))
+            .header(Header::new("X-Password-Hash", STANDARD.encode(testing::PASSWORD_HASH)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let mut chunk = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let read = response.read(&mut chunk).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            file.write_all(&chunk[..read]).unwrap();
+            size += read;
+        }
+        let growth_kib = peak_rss_kib().saturating_sub(baseline);
+        drop(file);
+
+        let export: Export = rocket::serde::json::serde_json::from_reader(BufReader::new(
+            std::fs::File::open(&path).unwrap(),
+        )).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(export.credentials.len(), 10_000);
+        assert!(size > 50 << 20, "the export is {} bytes", size);
+        assert!(growth_kib < 16 << 10, "memory grew by {} KiB while streaming", growth_kib);
+    }
+}
@@ -0,0 +1,353 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::models::TeamRole;
+use crate::validation::Validator;
+use super::auth::deserialize_nullable;
+use super::credentials::{can_edit_credentials, is_team_member};
+
+/// The longest folder name accepted, in characters.
+pub(super) const MAX_NAME_LEN: usize = 100;
+/// How deeply folders can nest; top-level folders are at depth 1.
+pub(super) const MAX_DEPTH: i32 = 5;
+
+// --- Request DTOs ---
+
+/// A new folder in a team.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateFolderRequest {
+    pub name: String,
+    /// The folder to create it in; top level if left out.
+    pub parent_id: Option<Uuid>,
+}
+
+/// Changes to a folder. Omitted fields are left as they are.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateFolderRequest {
+    pub name: Option<String>,
+    /// The folder to move it into, or `null` to move it to the top level.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub parent_id: Option<Option<Uuid>>,
+}
+
+// --- Response DTOs ---
+
+/// A folder of a team.
+#[derive(Serialize)]
+pub struct FolderResponse {
+    pub id: Uuid,
+    pub name: String,
+    /// The folder it is in, or `None` at the top level.
+    pub parent_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+// --- Helpers ---
+
+/// Checks the scope of the caller's token and their role in the team before they
+/// change its folders or the order of the credentials in them.
+pub(super) async fn check_can_edit(conn: &mut sqlx::PgConnection, user: &AuthenticatedUser, team_id: Uuid) -> Result<(), ApiError> {
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let role = sqlx::query_scalar!(
+        "SELECT role AS \"role: TeamRole\" FROM team_members WHERE team_id = $1 AND user_id = $2",
+        team_id,
+        user.id
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if !can_edit_credentials(&role) {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+    Ok(())
+}
+
+/// Locks the folders of a team, so concurrent moves can't build a cycle or a tree
+/// deeper than [`MAX_DEPTH`] between checking and writing.
+pub(super) async fn lock_folders(conn: &mut sqlx::PgConnection, team_id: Uuid) -> Result<(), Status> {
+    sqlx::query!("SELECT id FROM folders WHERE team_id = $1 FOR UPDATE", team_id)
+        .fetch_all(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(())
+}
+
+/// The ids of a folder of the team and all folders above it, starting with the folder
+/// itself. Empty if the team has no such folder.
+async fn folder_path(conn: &mut sqlx::PgConnection, team_id: Uuid, id: Uuid) -> Result<Vec<Uuid>, Status> {
+    sqlx::query_scalar!(
+        "WITH RECURSIVE path AS (
+             SELECT id, parent_id, 1 AS depth FROM folders WHERE id = $1 AND team_id = $2
+             UNION ALL
+             SELECT f.id, f.parent_id, p.depth + 1 FROM folders f JOIN path p ON f.id = p.parent_id
+         )
+         SELECT id AS \"id!\" FROM path ORDER BY depth",
+        id,
+        team_id
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// How many levels a folder and the folders below it span, counting the folder itself.
+async fn subtree_height(conn: &mut sqlx::PgConnection, id: Uuid) -> Result<i32, Status> {
+    let height = sqlx::query_scalar!(
+        "WITH RECURSIVE subtree AS (
+             SELECT id, 1 AS depth FROM folders WHERE id = $1
+             UNION ALL
+             SELECT f.id, s.depth + 1 FROM folders f JOIN subtree s ON f.parent_id = s.id
+         )
+         SELECT MAX(depth) FROM subtree",
+        id
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    Ok(height.unwrap_or(1))
+}
+
+fn check_name(v: &mut Validator, name: &str) {
+    let len = name.trim().chars().count();
+    v.check("name", (1..=MAX_NAME_LEN).contains(&len), format!("must be between 1 and {} characters", MAX_NAME_LEN));
+}
+
+// --- Routes ---
+
+/// Lists the folders of a team the caller belongs to, ordered by name. Nesting is given
+/// by each folder's `parent_id`.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/folders")]
+pub async fn list_folders(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<Vec<FolderResponse>>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let folders = sqlx::query_as!(
+        FolderResponse,
+        "SELECT id, name, parent_id, created_at FROM folders WHERE team_id = $1 ORDER BY lower(name), id",
+        team_id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(folders))
+}
+
+/// Creates a folder in a team the caller belongs to, at the top level or inside
+/// `parent_id`. Folders nest at most 5 deep, and sibling names must differ ignoring case.
+///
+/// Returns `201 Created` with the folder, `404 Not Found` if the team doesn't exist or
+/// the caller isn't a member, `403 Forbidden` for a token not scoped to the team or a
+/// member whose role may not edit, `409 Conflict` if a sibling has the same name, or
+/// `422 Unprocessable Entity` listing invalid fields.
+#[post("/<team_id>/folders", data = "<folder_data>")]
+pub async fn create_folder(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    folder_data: JsonBody<CreateFolderRequest>,
+) -> Result<(Status, Json<FolderResponse>), ApiError> {
+    let mut v = Validator::new();
+    check_name(&mut v, &folder_data.name);
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, &user, team_id).await?;
+
+    if let Some(parent_id) = folder_data.parent_id {
+        lock_folders(&mut tx, team_id).await?;
+        let path = folder_path(&mut tx, team_id, parent_id).await?;
+        let mut v = Validator::new();
+        if path.is_empty() {
+            v.check("parent_id", false, "must be a folder of the same team");
+        } else {
+            v.check("parent_id", path.len() < MAX_DEPTH as usize, format!("folders nest at most {} deep", MAX_DEPTH));
+        }
+        v.finish()?;
+    }
+
+    let folder = sqlx::query_as!(
+        FolderResponse,
+        "INSERT INTO folders (team_id, name, parent_id) VALUES ($1, $2, $3)
+         RETURNING id, name, parent_id, created_at",
+        team_id,
+        folder_data.name.trim(),
+        folder_data.parent_id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok((Status::Created, Json(folder)))
+}
+
+/// Renames a folder of a team the caller belongs to, or moves it with everything in it.
+/// A folder can't move into itself or a folder below it, nor so that anything ends up
+/// more than 5 deep.
+///
+/// Returns the folder, `404 Not Found` if there is no such folder or the caller isn't a
+/// member of the team, `403 Forbidden` for a token not scoped to the team or a member
+/// whose role may not edit, `409 Conflict` if a sibling has the same name, or `422
+/// Unprocessable Entity` listing invalid fields.
+#[patch("/<team_id>/folders/<id>", data = "<update_data>")]
+pub async fn update_folder(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    id: Uuid,
+    update_data: JsonBody<UpdateFolderRequest>,
+) -> Result<Json<FolderResponse>, ApiError> {
+    let mut v = Validator::new();
+    if let Some(name) = &update_data.name {
+        check_name(&mut v, name);
+    }
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, &user, team_id).await?;
+    lock_folders(&mut tx, team_id).await?;
+
+    if folder_path(&mut tx, team_id, id).await?.is_empty() {
+        return Err(Status::NotFound.into());
+    }
+
+    if let Some(Some(parent_id)) = update_data.parent_id {
+        let path = folder_path(&mut tx, team_id, parent_id).await?;
+        let height = subtree_height(&mut tx, id).await?;
+        let mut v = Validator::new();
+        if path.is_empty() {
+            v.check("parent_id", false, "must be a folder of the same team");
+        } else if path.contains(&id) {
+            v.check("parent_id", false, "must not be the folder itself or one below it");
+        } else {
+            v.check(
+                "parent_id",
+                path.len() as i32 + height <= MAX_DEPTH,
+                format!("folders nest at most {} deep", MAX_DEPTH),
+            );
+        }
+        v.finish()?;
+    }
+
+    let folder = sqlx::query_as!(
+        FolderResponse,
+        "UPDATE folders SET
+             name = COALESCE($1, name),
+             parent_id = CASE WHEN $2 THEN $3 ELSE parent_id END
+         WHERE id = $4
+         RETURNING id, name, parent_id, created_at",
+        update_data.name.as_deref().map(str::trim),
+        update_data.parent_id.is_some(),
+        update_data.parent_id.flatten(),
+        id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(folder))
+}
+
+/// Deletes a folder of a team the caller belongs to.
+///
+/// A folder holding credentials (including those in the trash) or other folders can only
+/// be deleted with `cascade=children`, which first moves everything in it up to its
+/// parent. Returns `204 No Content`, `404 Not Found` if there is no such folder or the
+/// caller isn't a member of the team, `403 Forbidden` for a token not scoped to the team
+/// or a member whose role may not edit, `409 Conflict` with `{"error":
+/// "folder_not_empty"}`, or `422 Unprocessable Entity` for any other `cascade`.
+#[delete("/<team_id>/folders/<id>?<cascade>")]
+pub async fn delete_folder(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    id: Uuid,
+    cascade: Option<&str>,
+) -> Result<Status, ApiError> {
+    let mut v = Validator::new();
+    v.check("cascade", matches!(cascade, None | Some("children")), "must be children");
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, &user, team_id).await?;
+    lock_folders(&mut tx, team_id).await?;
+
+    let parent_id = sqlx::query_scalar!("SELECT parent_id FROM folders WHERE id = $1 AND team_id = $2", id, team_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if cascade.is_some() {
+        sqlx::query!("UPDATE credentials SET folder_id = $2, position = NULL WHERE folder_id = $1", id, parent_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        // Names only clash if a moved folder meets a namesake at its new level.
+        sqlx::query!("UPDATE folders SET parent_id = $2 WHERE parent_id = $1", id, parent_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(ApiError::from_db)?;
+    } else {
+        let not_empty = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM credentials WHERE folder_id = $1)
+                 OR EXISTS(SELECT 1 FROM folders WHERE parent_id = $1)",
+            id
+        )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|_| Status::InternalServerError)?
+            .unwrap_or(false);
+        if not_empty {
+            return Err(ApiError::new(Status::Conflict, "folder_not_empty"));
+        }
+    }
+
+    sqlx::query!("DELETE FROM folders WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
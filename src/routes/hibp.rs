@@ -0,0 +1,73 @@
+use rocket_db_pools::Connection;
+use rocket::http::{ContentType, Status};
+use rocket::State;
+use crate::DatabasePool;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::guards::AuthenticatedUser;
+use crate::hibp::{self, HibpClient, UpstreamError};
+use crate::validation::Validator;
+
+/// How many hex digits of a SHA-1 hash a range is looked up by.
+const PREFIX_LEN: usize = 5;
+
+// --- Routes ---
+
+/// Returns the Have I Been Pwned password range for the first 5 hex digits of a SHA-1
+/// password hash, as HIBP's range API does: one `SUFFIX:COUNT` line for each breached
+/// hash starting with them, padded with fake entries. The client compares the rest of
+/// its hash locally, so neither this server nor HIBP learns the password, and HIBP
+/// never sees the client's address. Ranges are cached for `hibp.cache_ttl_secs`.
+///
+/// Returns `404 Not Found` with `{"error": "hibp_disabled"}` if `hibp.enabled` is off,
+/// `422 Unprocessable Entity` unless `prefix` is 5 hex digits, or `502 Bad Gateway`
+/// with `{"error": "upstream_unavailable"}` if HIBP can't be reached or fails, with its
+/// `status` if it answered.
+#[get("/range/<prefix>")]
+pub async fn get_range(
+    mut db: Connection<DatabasePool>,
+    _user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    client: &State<HibpClient>,
+    prefix: &str,
+) -> Result<(ContentType, String), ApiError> {
+    if !config.hibp.enabled {
+        return Err(ApiError::new(Status::NotFound, "hibp_disabled"));
+    }
+
+    let mut v = Validator::new();
+    v.check(
+        "prefix",
+        prefix.len() == PREFIX_LEN && prefix.chars().all(|c| c.is_ascii_hexdigit()),
+        format!("must be {} hex digits", PREFIX_LEN),
+    );
+    v.finish()?;
+    let prefix = prefix.to_ascii_uppercase();
+
+    let ttl_secs = config.hibp.cache_ttl_secs;
+    if ttl_secs > 0 {
+        let cached = hibp::cached_range(db.as_mut(), &prefix, ttl_secs)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        if let Some(body) = cached {
+            return Ok((ContentType::Plain, body));
+        }
+    }
+
+    let body = client.fetch_range(&prefix).await.map_err(|e| {
+        warn!("Fetching HIBP range {} failed: {}", prefix, e);
+        match e {
+            UpstreamError::Unreachable(_) => ApiError::new(Status::BadGateway, "upstream_unavailable"),
+            UpstreamError::Status(status) => {
+                ApiError::new(Status::BadGateway, "upstream_unavailable").with("status", status)
+            }
+        }
+    })?;
+
+    // The range is still good to answer with when it can't be cached.
+    if ttl_secs > 0 && let Err(e) = hibp::cache_range(db.as_mut(), &prefix, &body).await {
+        warn!("Caching HIBP range {} failed: {}", prefix, e);
+    }
+
+    Ok((ContentType::Plain, body))
+}
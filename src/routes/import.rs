@@ -0,0 +1,863 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::models::SecretKind;
+use crate::validation::{FieldError, Validator};
+use super::attachments::{check_attachment_key, check_filename, insert_attachment};
+use super::auth::{deserialize_base64, deserialize_optional_base64};
+use super::credentials::{
+    check_new_credential, insert_credentials, is_team_member, normalize_hostname, CreateCredentialRequest,
+    RejectedImportItem,
+};
+use super::folders::{lock_folders, MAX_DEPTH, MAX_NAME_LEN};
+
+// --- Request DTOs ---
+
+/// A Bitwarden JSON export, decrypted by the client, with each item's secrets
+/// re-encrypted under the team key.
+///
+/// Only the plaintext parts of items are read. Passwords, TOTP seeds, card details,
+/// notes, and hidden fields belong in the `encrypted_secret` the client adds to each
+/// item, and are ignored if they are sent as well. Other fields of the export are
+/// ignored too.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenExport {
+    #[serde(default)]
+    pub folders: Vec<BitwardenFolder>,
+    pub items: Vec<BitwardenItem>,
+}
+
+/// A Bitwarden folder. Slashes in its name nest it, as in Bitwarden.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenFolder {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenItem {
+    pub id: Option<String>,
+    pub folder_id: Option<String>,
+    /// 1 for a login, 2 a secure note, 3 a card, 4 an identity, 5 an SSH key.
+    #[serde(rename = "type")]
+    pub kind: u8,
+    pub name: String,
+    #[serde(default)]
+    pub favorite: bool,
+    pub login: Option<BitwardenLogin>,
+    pub card: Option<BitwardenCard>,
+    pub ssh_key: Option<BitwardenSshKey>,
+    pub fields: Option<Vec<BitwardenField>>,
+    /// The item's secrets, encrypted with the team key. Encoded as Base64 in JSON.
+    #[serde(rename = "encrypted_secret", default, deserialize_with = "deserialize_optional_base64")]
+    pub encrypted_secret: Option<Vec<u8>>,
+    /// The nonce required to decrypt the `encrypted_secret`. Encoded as Base64 in JSON.
+    #[serde(default, deserialize_with = "deserialize_optional_base64")]
+    pub nonce: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenLogin {
+    pub username: Option<String>,
+    pub uris: Option<Vec<BitwardenUri>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenUri {
+    pub uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenCard {
+    pub brand: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenSshKey {
+    pub public_key: Option<String>,
+    pub key_fingerprint: Option<String>,
+}
+
+/// A custom field of a Bitwarden item.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenField {
+    pub name: Option<String>,
+    pub value: Option<String>,
+    /// 0 for text, 1 hidden, 2 boolean, 3 linked.
+    #[serde(rename = "type")]
+    pub kind: u8,
+}
+
+/// A KeePass 2.x database, read from its XML by the client, with each entry's secrets
+/// re-encrypted under the team key.
+///
+/// Passwords, notes, and protected strings belong in each entry's `encrypted_secret`;
+/// only its other strings are sent in the clear.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeepassImport {
+    /// The root group, whose name is the database's. Leave the recycle bin out.
+    pub root: KeepassGroup,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeepassGroup {
+    pub name: String,
+    #[serde(default)]
+    pub groups: Vec<KeepassGroup>,
+    #[serde(default)]
+    pub entries: Vec<KeepassEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeepassEntry {
+    /// The entry's `UUID`, as it appears in the XML.
+    pub uuid: Option<String>,
+    pub title: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// When the entry expires, if it is set to.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Custom strings that aren't protected, by name.
+    #[serde(default)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+    /// The entry's secrets, encrypted with the team key. Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_secret`. Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+    /// Files attached to the entry, each encrypted by the client with a key of its own.
+    #[serde(default)]
+    pub attachments: Vec<KeepassAttachment>,
+}
+
+/// A file attached to a KeePass entry. Binary fields are encoded as Base64 in JSON.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeepassAttachment {
+    pub filename: String,
+    /// The encrypted content.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub content: Vec<u8>,
+    /// The key the content is encrypted with, wrapped with the team key.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_key: Vec<u8>,
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub key_nonce: Vec<u8>,
+}
+
+/// Rows of a CSV file, parsed by the client, and which of their columns hold what. This
+/// lets clients import the CSV exports of other password managers without the server
+/// knowing each one's columns.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvImportRequest {
+    pub mapping: CsvMapping,
+    pub rows: Vec<CsvRow>,
+    /// Whether rows that fail validation are left out and reported, rather than failing
+    /// the whole import.
+    #[serde(default)]
+    pub skip_invalid: bool,
+}
+
+/// The column each field of a credential is read from. Missing columns, and fields
+/// without one, get defaults: the hostname as the title, `password` as the kind, and
+/// empty values otherwise.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvMapping {
+    pub title: Option<String>,
+    /// A hostname or URL, of which only the host is kept. Secure notes and cards get
+    /// none, as LastPass gives notes the URL `http://sn`.
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    /// One of the credential kinds, e.g. `password`.
+    pub kind: Option<String>,
+    /// Tags separated by commas or semicolons.
+    pub tags: Option<String>,
+    /// A folder path separated by slashes or backslashes, e.g. LastPass's `grouping`.
+    /// Missing folders are created.
+    pub folder: Option<String>,
+    /// Columns copied into the metadata under their own names, e.g. the full URL.
+    #[serde(default)]
+    pub metadata: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvRow {
+    /// The row's values by column name. Passwords, notes, and other secret columns
+    /// belong in the `encrypted_secret` instead.
+    pub values: HashMap<String, String>,
+    /// The row's secrets, encrypted with the team key. Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_secret: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_secret`. Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub nonce: Vec<u8>,
+}
+
+// --- Response DTOs ---
+
+/// The outcome of an import from another password manager.
+#[derive(Default, Serialize)]
+pub struct ImportResponse {
+    /// How many folders had to be created; existing folders of the same name are reused.
+    pub folders_created: usize,
+    pub imported: Vec<ImportedItem>,
+    pub skipped: Vec<SkippedItem>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ImportWarning>,
+}
+
+/// An item of an import that became a credential.
+#[derive(Serialize)]
+pub struct ImportedItem {
+    /// Its position in the import, counting from 0.
+    pub index: usize,
+    /// Its id in the other password manager, if it had one.
+    pub source_id: Option<String>,
+    pub id: Uuid,
+}
+
+/// An item of an import that was left out, and why: `unsupported_type`, or `invalid`
+/// with the errors found.
+#[derive(Serialize)]
+pub struct SkippedItem {
+    pub index: usize,
+    pub source_id: Option<String>,
+    pub name: String,
+    pub reason: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// Something about an import that was changed to fit. The only `reason` so far is
+/// `folder_too_deep`: a folder nested deeper than folders may be, whose deepest levels
+/// were merged into one folder named after them.
+#[derive(Serialize)]
+pub struct ImportWarning {
+    pub reason: &'static str,
+    /// The folder's path in the import, from the top level down.
+    pub path: Vec<String>,
+    /// The name of the folder its items went into instead.
+    pub folder: String,
+}
+
+// --- Helpers ---
+
+/// The kind a Bitwarden item type maps to, if it is supported.
+fn bitwarden_kind(kind: u8) -> Option<SecretKind> {
+    match kind {
+        1 => Some(SecretKind::Password),
+        2 => Some(SecretKind::SecureNote),
+        3 => Some(SecretKind::CreditCard),
+        5 => Some(SecretKind::SshKey),
+        _ => None,
+    }
+}
+
+/// Maps a supported Bitwarden item to a new credential. URIs, a card's brand, an SSH
+/// key's fingerprint, and visible custom fields go into the metadata.
+fn map_bitwarden_item(item: &BitwardenItem, kind: SecretKind, folder_id: Option<Uuid>) -> CreateCredentialRequest {
+    let mut metadata = serde_json::Map::new();
+    let mut hostname = String::new();
+    let mut username = String::new();
+    let mut public_key = None;
+
+    if let Some(login) = &item.login {
+        let uris: Vec<String> = login
+            .uris
+            .iter()
+            .flatten()
+            .filter_map(|uri| uri.uri.clone())
+            .filter(|uri| !uri.trim().is_empty())
+            .collect();
+        hostname = uris.iter().map(|uri| normalize_hostname(uri)).find(|host| !host.is_empty()).unwrap_or_default();
+        username = login.username.clone().unwrap_or_default();
+        if !uris.is_empty() {
+            metadata.insert("uris".to_string(), uris.into());
+        }
+    }
+    // A card's number, expiry, and cardholder belong in the encrypted secret.
+    if let Some(brand) = item.card.as_ref().and_then(|card| card.brand.clone()) {
+        metadata.insert("brand".to_string(), brand.into());
+    }
+    if let Some(ssh_key) = &item.ssh_key {
+        public_key = ssh_key.public_key.clone();
+        if let Some(fingerprint) = &ssh_key.key_fingerprint {
+            metadata.insert("fingerprint".to_string(), fingerprint.clone().into());
+        }
+    }
+
+    // Hidden and linked fields are left to the encrypted secret.
+    let fields: Vec<serde_json::Value> = item
+        .fields
+        .iter()
+        .flatten()
+        .filter(|field| matches!(field.kind, 0 | 2))
+        .map(|field| serde_json::json!({ "name": field.name, "value": field.value }))
+        .collect();
+    if !fields.is_empty() {
+        metadata.insert("fields".to_string(), fields.into());
+    }
+
+    CreateCredentialRequest {
+        title: item.name.clone(),
+        hostname,
+        username,
+        kind,
+        public_key,
+        metadata: Some(metadata),
+        tags: Vec::new(),
+        folder_id,
+        expires_at: None,
+        encrypted_secret: item.encrypted_secret.clone().unwrap_or_default(),
+        nonce: item.nonce.clone().unwrap_or_default(),
+        fields: Vec::new(),
+        uris: Vec::new(),
+        icon_url: None,
+        derive_icon: false,
+        ssh_key_type: None,
+        ssh_fingerprint: None,
+        ssh_comment: None,
+        totp_digits: None,
+        totp_period: None,
+        totp_algorithm: None,
+        notes: String::new(),
+        health: None,
+    }
+}
+
+/// Finds the folder of a team at a path of folder names, creating any level that is
+/// missing, and records the folders created in `report`. An existing folder with the
+/// same name at a level, ignoring case, is reused; blank names are dropped, and an empty
+/// path means the top level. Levels past [`MAX_DEPTH`] are merged into the deepest
+/// folder allowed, named after them with slashes, and reported as a warning.
+///
+/// Must run in a transaction holding [`lock_folders`].
+async fn resolve_folder_path(
+    conn: &mut sqlx::PgConnection,
+    team_id: Uuid,
+    path: &[&str],
+    report: &mut ImportResponse,
+) -> Result<Option<Uuid>, ApiError> {
+    let mut levels: Vec<String> =
+        path.iter().map(|name| name.trim()).filter(|name| !name.is_empty()).map(String::from).collect();
+    if levels.len() > MAX_DEPTH as usize {
+        let original = levels.clone();
+        let deepest = levels.split_off(MAX_DEPTH as usize - 1).join("/");
+        levels.push(deepest);
+        report.warnings.push(ImportWarning {
+            reason: "folder_too_deep",
+            path: original,
+            folder: levels[levels.len() - 1].chars().take(MAX_NAME_LEN).collect(),
+        });
+    }
+
+    let mut parent_id: Option<Uuid> = None;
+    for name in levels {
+        let name: String = name.chars().take(MAX_NAME_LEN).collect();
+        let existing = sqlx::query_scalar!(
+            "SELECT id FROM folders
+             WHERE team_id = $1 AND parent_id IS NOT DISTINCT FROM $2 AND lower(name) = lower($3)",
+            team_id,
+            parent_id,
+            name
+        )
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                report.folders_created += 1;
+                sqlx::query_scalar!(
+                    "INSERT INTO folders (team_id, name, parent_id) VALUES ($1, $2, $3) RETURNING id",
+                    team_id,
+                    name,
+                    parent_id
+                )
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(ApiError::from_db)?
+            }
+        };
+        parent_id = Some(id);
+    }
+
+    Ok(parent_id)
+}
+
+/// Maps a KeePass entry to a new `password` credential, with the host of its URL as the
+/// hostname and the URL and custom strings in the metadata.
+fn map_keepass_entry(entry: &KeepassEntry, folder_id: Option<Uuid>) -> CreateCredentialRequest {
+    let mut metadata = serde_json::Map::new();
+    if !entry.url.trim().is_empty() {
+        metadata.insert("uris".to_string(), vec![entry.url.trim()].into());
+    }
+    if !entry.fields.is_empty() {
+        // The same shape as the custom fields of a Bitwarden import.
+        let fields: Vec<serde_json::Value> = entry
+            .fields
+            .iter()
+            .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+            .collect();
+        metadata.insert("fields".to_string(), fields.into());
+    }
+
+    CreateCredentialRequest {
+        title: entry.title.clone(),
+        hostname: normalize_hostname(&entry.url),
+        username: entry.username.clone(),
+        kind: SecretKind::Password,
+        public_key: None,
+        metadata: Some(metadata),
+        tags: entry.tags.clone(),
+        folder_id,
+        expires_at: entry.expires_at,
+        encrypted_secret: entry.encrypted_secret.clone(),
+        nonce: entry.nonce.clone(),
+        fields: Vec::new(),
+        uris: Vec::new(),
+        icon_url: None,
+        derive_icon: false,
+        ssh_key_type: None,
+        ssh_fingerprint: None,
+        ssh_comment: None,
+        totp_digits: None,
+        totp_period: None,
+        totp_algorithm: None,
+        notes: String::new(),
+        health: None,
+    }
+}
+
+/// Checks the attachments of a KeePass entry, each at most `max_size` bytes.
+fn check_keepass_attachments(v: &mut Validator, attachments: &[KeepassAttachment], max_size: u64) {
+    for attachment in attachments {
+        check_filename(v, &attachment.filename);
+        check_attachment_key(v, &attachment.encrypted_key, &attachment.key_nonce);
+        v.check(
+            "content",
+            attachment.content.len() as u64 <= max_size,
+            format!("must be at most {} bytes", max_size),
+        );
+    }
+}
+
+/// The value of `row` in the column mapped to a field, trimmed, or `""` if the field
+/// has no column or the row lacks it.
+fn csv_value<'a>(row: &'a CsvRow, column: &Option<String>) -> &'a str {
+    column.as_ref().and_then(|column| row.values.get(column)).map_or("", |value| value.trim())
+}
+
+/// Maps a CSV row to a new credential, checking the fields only the mapping can get
+/// wrong. The folder is set by the caller.
+fn map_csv_row(v: &mut Validator, row: &CsvRow, mapping: &CsvMapping) -> CreateCredentialRequest {
+    let kind = match csv_value(row, &mapping.kind) {
+        "" => SecretKind::Password,
+        value => SecretKind::from_name(value).unwrap_or_else(|| {
+            let kinds: Vec<&str> = SecretKind::ALL.iter().map(SecretKind::as_str).collect();
+            v.check("kind", false, format!("must be one of {}", kinds.join(", ")));
+            SecretKind::Password
+        }),
+    };
+
+    let hostname = match kind {
+        SecretKind::SecureNote | SecretKind::CreditCard => String::new(),
+        _ => normalize_hostname(csv_value(row, &mapping.hostname)),
+    };
+    let title = match csv_value(row, &mapping.title) {
+        "" => hostname.clone(),
+        title => title.to_string(),
+    };
+    let tags = csv_value(row, &mapping.tags)
+        .split([',', ';'])
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect();
+
+    let mut metadata = serde_json::Map::new();
+    for column in &mapping.metadata {
+        if let Some(value) = row.values.get(column).filter(|value| !value.trim().is_empty()) {
+            metadata.insert(column.clone(), value.trim().into());
+        }
+    }
+
+    CreateCredentialRequest {
+        title,
+        hostname,
+        username: csv_value(row, &mapping.username).to_string(),
+        kind,
+        public_key: None,
+        metadata: Some(metadata),
+        tags,
+        folder_id: None,
+        expires_at: None,
+        encrypted_secret: row.encrypted_secret.clone(),
+        nonce: row.nonce.clone(),
+        fields: Vec::new(),
+        uris: Vec::new(),
+        icon_url: None,
+        derive_icon: false,
+        ssh_key_type: None,
+        ssh_fingerprint: None,
+        ssh_comment: None,
+        totp_digits: None,
+        totp_period: None,
+        totp_algorithm: None,
+        notes: String::new(),
+        health: None,
+    }
+}
+
+// --- Routes ---
+
+/// Imports a Bitwarden JSON export into a team the caller belongs to, in one
+/// transaction. The client decrypts the export and re-encrypts each item's secrets
+/// under the team key as its `encrypted_secret` and `nonce`; the server only maps the
+/// plaintext parts.
+///
+/// Folders become folders of the team, nested by the slashes in their names and merged
+/// with existing ones of the same name. Logins become `password` credentials with the
+/// host of their first URI as the hostname, secure notes `secure_note`, cards
+/// `credit_card`, and SSH keys `ssh_key`. All URIs, text and boolean custom fields, a
+/// card's brand, and an SSH key's fingerprint are kept in the metadata. Items marked as
+/// favorites become favorites of the caller. Identities and other types are skipped, as
+/// are items that fail validation, and reported with the reason.
+///
+/// Returns `201 Created` with the imported and skipped items and any warnings, `404 Not
+/// Found` if the team doesn't exist or the caller isn't a member, `403 Forbidden` for a
+/// token not scoped to the team, or `413 Payload Too Large` with `{"error":
+/// "batch_too_large", "max": ...}` for more items than `credentials.max_import_batch`.
+#[post("/<team_id>/import/bitwarden", data = "<export>")]
+pub async fn import_bitwarden(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    team_id: Uuid,
+    export: JsonBody<BitwardenExport>,
+) -> Result<(Status, Json<ImportResponse>), ApiError> {
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let max = config.credentials.max_import_batch;
+    if export.items.len() > max {
+        return Err(ApiError::new(Status::PayloadTooLarge, "batch_too_large").with("max", max));
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !is_team_member(&mut tx, team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let mut report = ImportResponse::default();
+    lock_folders(&mut tx, team_id).await?;
+    let mut folders = HashMap::new();
+    for folder in &export.folders {
+        let path: Vec<&str> = folder.name.split('/').collect();
+        let id = resolve_folder_path(&mut tx, team_id, &path, &mut report).await?;
+        folders.insert(folder.id.as_str(), id);
+    }
+
+    let mut credentials = Vec::new();
+    let mut tags = Vec::new();
+    let mut sources = Vec::new();
+    for (index, item) in export.items.iter().enumerate() {
+        let skip = |reason, errors| SkippedItem {
+            index,
+            source_id: item.id.clone(),
+            name: item.name.clone(),
+            reason,
+            errors,
+        };
+        let Some(kind) = bitwarden_kind(item.kind) else {
+            report.skipped.push(skip("unsupported_type", Vec::new()));
+            continue;
+        };
+
+        // Items in a folder the export doesn't list end up at the top level.
+        let folder_id = item.folder_id.as_deref().and_then(|id| folders.get(id).copied().flatten());
+        let credential = map_bitwarden_item(item, kind, folder_id);
+        let mut v = Validator::new();
+        let credential_tags = check_new_credential(&mut v, &credential);
+        let errors = v.into_errors();
+        if !errors.is_empty() {
+            report.skipped.push(skip("invalid", errors));
+            continue;
+        }
+
+        credentials.push(credential);
+        tags.push(credential_tags);
+        sources.push((index, item));
+    }
+
+    let ids = insert_credentials(&mut tx, team_id, user.id, &credentials, tags).await?;
+
+    let favorites: Vec<Uuid> = ids
+        .iter()
+        .zip(&sources)
+        .filter(|(_, (_, item))| item.favorite)
+        .map(|(id, _)| *id)
+        .collect();
+    sqlx::query!(
+        "INSERT INTO credential_favorites (user_id, credential_id, team_id)
+         SELECT $1, id, $2 FROM UNNEST($3::uuid[]) id",
+        user.id,
+        team_id,
+        &favorites
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    report.imported = ids
+        .into_iter()
+        .zip(sources)
+        .map(|(id, (index, item))| ImportedItem { index, source_id: item.id.clone(), id })
+        .collect();
+
+    Ok((Status::Created, Json(report)))
+}
+
+/// Imports a KeePass 2.x database into a team the caller belongs to, in one
+/// transaction. The client reads the XML and re-encrypts each entry's secrets under the
+/// team key as its `encrypted_secret` and `nonce`, and each attachment under a key of its
+/// own; the server only maps the plaintext parts.
+///
+/// Groups below the root become folders of the team, merged with existing ones of the
+/// same name; groups nested deeper than folders may be are merged into the deepest
+/// folder allowed and reported as warnings. Entries become `password` credentials with
+/// the host of their URL as the hostname and the URL and custom strings in the metadata,
+/// and keep their tags, expiry, and attachments. Entries that fail validation are
+/// skipped and reported with the errors found. Entries are numbered depth first, a
+/// group's own entries before those of its subgroups.
+///
+/// Returns `201 Created` with the imported and skipped entries and any warnings, `404
+/// Not Found` if the team doesn't exist or the caller isn't a member, `403 Forbidden`
+/// for a token not scoped to the team, or `413 Payload Too Large` with `{"error":
+/// "batch_too_large", "max": ...}` for more entries than `credentials.max_import_batch`.
+#[post("/<team_id>/import/keepass", data = "<database>")]
+pub async fn import_keepass(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    team_id: Uuid,
+    database: JsonBody<KeepassImport>,
+) -> Result<(Status, Json<ImportResponse>), ApiError> {
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    // Every group with its path below the root, parents before their subgroups.
+    let mut groups = Vec::new();
+    let mut pending = vec![(&database.root, Vec::new())];
+    while let Some((group, path)) = pending.pop() {
+        for subgroup in group.groups.iter().rev() {
+            let mut subpath = path.clone();
+            subpath.push(subgroup.name.as_str());
+            pending.push((subgroup, subpath));
+        }
+        groups.push((group, path));
+    }
+
+    let max = config.credentials.max_import_batch;
+    if groups.iter().map(|(group, _)| group.entries.len()).sum::<usize>() > max {
+        return Err(ApiError::new(Status::PayloadTooLarge, "batch_too_large").with("max", max));
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !is_team_member(&mut tx, team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let mut report = ImportResponse::default();
+    lock_folders(&mut tx, team_id).await?;
+    let max_size = u64::from(config.attachments.max_size_mib) * 1024 * 1024;
+    let mut credentials = Vec::new();
+    let mut tags = Vec::new();
+    let mut sources = Vec::new();
+    let mut index = 0;
+    for (group, path) in &groups {
+        let folder_id = resolve_folder_path(&mut tx, team_id, path, &mut report).await?;
+
+        for entry in &group.entries {
+            let credential = map_keepass_entry(entry, folder_id);
+            let mut v = Validator::new();
+            let credential_tags = check_new_credential(&mut v, &credential);
+            check_keepass_attachments(&mut v, &entry.attachments, max_size);
+            let errors = v.into_errors();
+            if errors.is_empty() {
+                credentials.push(credential);
+                tags.push(credential_tags);
+                sources.push((index, entry));
+            } else {
+                report.skipped.push(SkippedItem {
+                    index,
+                    source_id: entry.uuid.clone(),
+                    name: entry.title.clone(),
+                    reason: "invalid",
+                    errors,
+                });
+            }
+            index += 1;
+        }
+    }
+
+    let ids = insert_credentials(&mut tx, team_id, user.id, &credentials, tags).await?;
+
+    for (id, (_, entry)) in ids.iter().zip(&sources) {
+        for attachment in &entry.attachments {
+            insert_attachment(
+                &mut tx,
+                *id,
+                &attachment.filename,
+                &attachment.content,
+                &attachment.encrypted_key,
+                &attachment.key_nonce,
+                user.id,
+            )
+                .await?;
+        }
+    }
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    report.imported = ids
+        .into_iter()
+        .zip(sources)
+        .map(|(id, (index, entry))| ImportedItem { index, source_id: entry.uuid.clone(), id })
+        .collect();
+
+    Ok((Status::Created, Json(report)))
+}
+
+/// Imports the rows of a CSV file into a team the caller belongs to, in one transaction,
+/// reading each field from the column the mapping names. The client parses the file and
+/// encrypts each row's secret columns under the team key as its `encrypted_secret` and
+/// `nonce`.
+///
+/// Hostnames are reduced to the host of a URL, and fields without a value get the
+/// defaults described in [`CsvMapping`]. Rows that fail validation fail the import, or
+/// are skipped and reported with the errors found under `skip_invalid`.
+///
+/// Returns `201 Created` with the imported and skipped rows, `404 Not Found` if the team
+/// doesn't exist or the caller isn't a member, `403 Forbidden` for a token not scoped to
+/// the team, `413 Payload Too Large` with `{"error": "batch_too_large", "max": ...}` for
+/// more rows than `credentials.max_import_batch`, or `422 Unprocessable Entity` with
+/// `{"error": "validation_failed", "items": [{"index": ..., "errors": [...]}]}` for
+/// invalid rows without `skip_invalid`.
+#[post("/<team_id>/import/csv", data = "<import_data>")]
+pub async fn import_csv(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    team_id: Uuid,
+    import_data: JsonBody<CsvImportRequest>,
+) -> Result<(Status, Json<ImportResponse>), ApiError> {
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let max = config.credentials.max_import_batch;
+    if import_data.rows.len() > max {
+        return Err(ApiError::new(Status::PayloadTooLarge, "batch_too_large").with("max", max));
+    }
+
+    let mut report = ImportResponse::default();
+    let mut credentials = Vec::new();
+    let mut tags = Vec::new();
+    let mut sources = Vec::new();
+    let mut rejected = Vec::new();
+    for (index, row) in import_data.rows.iter().enumerate() {
+        let mut v = Validator::new();
+        let credential = map_csv_row(&mut v, row, &import_data.mapping);
+        let credential_tags = check_new_credential(&mut v, &credential);
+        let errors = v.into_errors();
+        if errors.is_empty() {
+            credentials.push(credential);
+            tags.push(credential_tags);
+            sources.push((index, row));
+        } else if import_data.skip_invalid {
+            report.skipped.push(SkippedItem { index, source_id: None, name: credential.title, reason: "invalid", errors });
+        } else {
+            rejected.push(RejectedImportItem { index, errors });
+        }
+    }
+    if !rejected.is_empty() {
+        return Err(ApiError::new(Status::UnprocessableEntity, "validation_failed").with("items", rejected));
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !is_team_member(&mut tx, team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    if import_data.mapping.folder.is_some() {
+        lock_folders(&mut tx, team_id).await?;
+        let mut folders = HashMap::new();
+        for (credential, (_, row)) in credentials.iter_mut().zip(&sources) {
+            let path = csv_value(row, &import_data.mapping.folder);
+            if !folders.contains_key(path) {
+                let levels: Vec<&str> = path.split(['/', '\\']).collect();
+                let id = resolve_folder_path(&mut tx, team_id, &levels, &mut report).await?;
+                folders.insert(path, id);
+            }
+            credential.folder_id = folders[path];
+        }
+    }
+
+    let ids = insert_credentials(&mut tx, team_id, user.id, &credentials, tags).await?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    report.imported = ids
+        .into_iter()
+        .zip(sources)
+        .map(|(id, (index, _))| ImportedItem { index, source_id: None, id })
+        .collect();
+
+    Ok((Status::Created, Json(report)))
+}
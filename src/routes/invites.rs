@@ -0,0 +1,317 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::{self, Json};
+use rocket::{http::Status, State};
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::{self, JsonBody};
+use crate::config::AppConfig;
+use crate::email::{EmailMessage, SharedEmailer};
+use crate::error::ApiError;
+use crate::guards::AdminUser;
+use crate::validation::is_valid_email;
+
+// --- Request DTOs ---
+
+/// Optional settings for a new invite code. An empty body creates a single-use
+/// code that never expires.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CreateInviteRequest {
+    /// When the code stops being accepted, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// How many accounts may sign up with the code. Defaults to 1.
+    pub max_uses: Option<i32>,
+    /// If set, only an account registering with this email may use the code.
+    pub email: Option<String>,
+}
+
+// --- Response DTOs ---
+
+/// An invite code and its metadata.
+#[derive(Serialize)]
+pub struct InviteResponse {
+    pub id: Uuid,
+    /// The code to hand to the invitee.
+    pub code: String,
+    pub created_at: DateTime<Utc>,
+    /// The admin who generated the code.
+    pub created_by: Option<Uuid>,
+    /// The only email allowed to sign up with the code, if it is bound to one.
+    pub email: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: i32,
+    pub use_count: i32,
+    /// Whether the code has no uses left.
+    pub is_used: bool,
+    /// Whether an admin revoked the code before it was used up.
+    pub revoked: bool,
+}
+
+/// One page of invite codes, newest first.
+#[derive(Serialize)]
+pub struct InvitePage {
+    pub invites: Vec<InviteResponse>,
+    /// The total number of invite codes across all pages.
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Invites returned per page when no `limit` is given.
+const DEFAULT_PAGE_SIZE: i64 = 50;
+/// The largest `limit` accepted.
+const MAX_PAGE_SIZE: i64 = 200;
+
+// --- Helpers ---
+
+/// Reads the optional request body, treating an empty one as all defaults. Other bodies
+/// that don't parse fail as a [`JsonBody`] would, naming the offending field.
+fn optional_body(
+    body: Result<JsonBody<CreateInviteRequest>, json::Error<'_>>,
+) -> Result<CreateInviteRequest, ApiError> {
+    match body {
+        Ok(JsonBody(request)) => Ok(request),
+        Err(json::Error::Parse(raw, _)) if raw.trim().is_empty() => Ok(CreateInviteRequest::default()),
+        Err(json::Error::Parse(raw, _)) => body::from_str(raw),
+        Err(e) => Err(ApiError::new(Status::BadRequest, "invalid_body").with("message", e.to_string())),
+    }
+}
+
+/// Validates the invite settings and stores a new code.
+async fn create_invite(
+    conn: &mut sqlx::PgConnection,
+    admin_id: Uuid,
+    invite_data: &CreateInviteRequest,
+) -> Result<InviteResponse, ApiError> {
+    let max_uses = invite_data.max_uses.unwrap_or(1);
+    if max_uses < 1 {
+        return Err(ApiError::new(Status::UnprocessableEntity, "validation_failed")
+            .with("field", "max_uses")
+            .with("message", "must be at least 1"));
+    }
+    if invite_data.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(ApiError::new(Status::UnprocessableEntity, "validation_failed")
+            .with("field", "expires_at")
+            .with("message", "must be in the future"));
+    }
+
+    let email = invite_data.email.as_deref().map(str::trim);
+    if email.is_some_and(|email| !is_valid_email(email)) {
+        return Err(ApiError::new(Status::UnprocessableEntity, "validation_failed")
+            .with("field", "email")
+            .with("message", "must be an email address"));
+    }
+
+    // Generate a unique random UUID v4 for the code.
+    let new_code = Uuid::new_v4().to_string();
+
+    // Insert the newly generated code into the database.
+    let invite = sqlx::query_as!(
+        InviteResponse,
+        "INSERT INTO invite_codes (code, created_by, expires_at, max_uses, email) VALUES ($1, $2, $3, $4, $5)
+         RETURNING id, code, created_at, created_by, expires_at, max_uses, use_count,
+                   COALESCE(is_used, false) AS \"is_used!\", revoked, email",
+        new_code,
+        admin_id,
+        invite_data.expires_at,
+        max_uses,
+        email
+    )
+        .fetch_one(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(invite)
+}
+
+// --- Routes ---
+
+/// Generates a new unique invite code and stores it in the database.
+///
+/// Restricted to admins. It generates a UUID v4 string and inserts it into the
+/// `invite_codes` table, recording who generated it. The body may set `expires_at`
+/// and `max_uses`, so one code can onboard a whole team.
+///
+/// An `email` binds the code to one invitee; signup then only accepts that address.
+///
+/// Returns the code with its metadata, `401 Unauthorized` without a valid session,
+/// `403 Forbidden` if the caller isn't an admin, or `422 Unprocessable Entity` naming
+/// the offending field if `max_uses` isn't positive, `expires_at` is in the past, or
+/// `email` isn't an email address.
+#[post("/invite", data = "<invite_data>")]
+pub async fn generate_invite(
+    mut db: Connection<DatabasePool>,
+    admin: AdminUser,
+    invite_data: Result<JsonBody<CreateInviteRequest>, json::Error<'_>>,
+) -> Result<Json<InviteResponse>, ApiError> {
+    let invite_data = optional_body(invite_data)?;
+
+    let invite = create_invite(db.as_mut(), admin.id, &invite_data).await?;
+
+    info!("Admin {} generated a new invite code", admin.id);
+
+    Ok(Json(invite))
+}
+
+/// Creates an invite bound to `email` and emails the code to that address.
+///
+/// Restricted to admins. Accepts the same settings as `/auth/invite`, but `email` is
+/// required. The invite is only committed once the email has been handed to the mail
+/// server, so a failed delivery doesn't leave behind a code nobody received.
+///
+/// Returns the code with its metadata, `401 Unauthorized`, `403 Forbidden`, or `422
+/// Unprocessable Entity` as for `/auth/invite`, or `502 Bad Gateway` if the email
+/// couldn't be sent.
+#[post("/invite/send", data = "<invite_data>")]
+pub async fn send_invite(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    emailer: &State<SharedEmailer>,
+    admin: AdminUser,
+    invite_data: JsonBody<CreateInviteRequest>,
+) -> Result<Json<InviteResponse>, ApiError> {
+    if invite_data.email.is_none() {
+        return Err(ApiError::new(Status::UnprocessableEntity, "validation_failed")
+            .with("field", "email")
+            .with("message", "is required"));
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let invite = create_invite(&mut tx, admin.id, &invite_data).await?;
+    let to = invite.email.clone().unwrap_or_default();
+
+    let body = config.email.invite_template
+        .replace("{code}", &invite.code)
+        .replace("{server_url}", &config.email.server_url);
+    let sent = emailer.send(EmailMessage {
+        to: to.clone(),
+        subject: config.email.invite_subject.clone(),
+        body,
+    }).await;
+
+    if let Err(e) = sent {
+        // Dropping the transaction discards the invite.
+        error!("{}", e);
+        return Err(ApiError::new(Status::BadGateway, "email_delivery_failed"));
+    }
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    info!("Admin {} sent an invite code to {}", admin.id, to);
+
+    Ok(Json(invite))
+}
+
+/// Lists invite codes, newest first, with their usage, expiry, and bound email.
+///
+/// Restricted to admins. Paginated with `limit` (default 50, at most 200) and `offset`.
+#[get("/invites?<limit>&<offset>")]
+pub async fn list_invites(
+    mut db: Connection<DatabasePool>,
+    _admin: AdminUser,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Json<InvitePage>, Status> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = offset.unwrap_or(0).max(0);
+
+    let invites = sqlx::query_as!(
+        InviteResponse,
+        "SELECT id, code, created_at, created_by, expires_at, max_uses, use_count,
+                COALESCE(is_used, false) AS \"is_used!\", revoked, email
+         FROM invite_codes
+         ORDER BY created_at DESC, id
+         LIMIT $1 OFFSET $2",
+        limit,
+        offset
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let total = sqlx::query_scalar!("SELECT COUNT(*) AS \"count!\" FROM invite_codes")
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(InvitePage { invites, total, limit, offset }))
+}
+
+/// Revokes an invite code so it can no longer be used to sign up.
+///
+/// Restricted to admins. Revoking a code that is already used up or revoked changes
+/// nothing. Returns the code's metadata, or `404 Not Found` if it doesn't exist.
+#[delete("/invites/<id>")]
+pub async fn revoke_invite(
+    mut db: Connection<DatabasePool>,
+    admin: AdminUser,
+    id: Uuid,
+) -> Result<Json<InviteResponse>, Status> {
+    let invite = sqlx::query_as!(
+        InviteResponse,
+        "UPDATE invite_codes
+         SET revoked = revoked OR use_count < max_uses
+         WHERE id = $1
+         RETURNING id, code, created_at, created_by, expires_at, max_uses, use_count,
+                   COALESCE(is_used, false) AS \"is_used!\", revoked, email",
+        id
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    info!("Admin {} revoked invite code {}", admin.id, id);
+
+    Ok(Json(invite))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::serde::json::Value;
+    use sqlx::PgPool;
+    use crate::testing;
+
+    async fn admin_token(client: &Client) -> String {
+        testing::signup(client, "admin@example.com").await;
+        sqlx::query("UPDATE users SET is_admin = true").execute(testing::pool(client)).await.unwrap();
+        testing::access_token(client, "admin@example.com").await
+    }
+
+    async fn post_invite(client: &Client, token: &str, body: &str) -> (Status, Value) {
+        let response = client.post("/auth/invite")
+            .header(testing::bearer(token))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        let status = response.status();
+        (status, response.into_json().await.unwrap())
+    }
+
+    #[sqlx::test]
+    async fn an_empty_invite_body_takes_the_defaults(pool: PgPool) {
+        let client = testing::client(pool).await;
+        let token = admin_token(&client).await;
+        let (status, invite) = post_invite(&client, &token, "").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!((invite["max_uses"].as_i64(), invite["expires_at"].is_null()), (Some(1), true));
+    }
+
+    #[sqlx::test]
+    async fn invite_bodies_name_a_misspelt_field(pool: PgPool) {
+        let client = testing::client(pool).await;
+        let token = admin_token(&client).await;
+        let (status, error) = post_invite(&client, &token, r#"{"max_use": 5}"#).await;
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(error["error"], "invalid_body");
+        assert_eq!(error["field"], "max_use");
+    }
+}
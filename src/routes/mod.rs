@@ -1,5 +1,194 @@
+mod access_log;
+mod account;
+mod admin;
+mod api_tokens;
+mod attachments;
 mod auth;
+mod credential_acl;
+mod credential_shares;
+mod credentials;
+mod devices;
+mod emergency;
+mod export;
+mod folders;
+mod hibp;
+mod import;
+mod invites;
+mod teams;
+mod templates;
+#[cfg(feature = "opaque")]
+mod opaque;
+mod recovery_key;
+mod ssh_keys;
+mod two_factor;
+mod users;
+mod webauthn;
 pub fn auth_routes() -> Vec<rocket::Route> {
-    routes![auth::signup, auth::generate_invite, auth::get_salt]
+    #[allow(unused_mut)]
+    let mut routes = routes![
+        auth::signup,
+        auth::login,
+        auth::refresh,
+        auth::logout,
+        auth::list_sessions,
+        auth::delete_session,
+        auth::login_history,
+        devices::list_devices,
+        devices::rename_device,
+        devices::approve_device,
+        devices::delete_device,
+        api_tokens::create_api_token,
+        api_tokens::list_api_tokens,
+        api_tokens::delete_api_token,
+        auth::change_password,
+        auth::forgot_password,
+        auth::reset_password,
+        auth::verify_email,
+        auth::resend_verification,
+        auth::get_salt,
+        auth::post_salt,
+        account::me,
+        account::update_me,
+        account::update_preferences,
+        account::rotate_keys,
+        account::delete_account,
+        account::request_email_change,
+        account::confirm_email_change,
+        export::export_account,
+        invites::generate_invite,
+        invites::send_invite,
+        invites::list_invites,
+        invites::revoke_invite,
+        recovery_key::generate_recovery_key,
+        recovery_key::store_recovery_key,
+        recovery_key::recover,
+        recovery_key::finish_recovery,
+        two_factor::enroll_totp,
+        two_factor::confirm_totp,
+        two_factor::disable_totp,
+        two_factor::regenerate_recovery_codes,
+        two_factor::login_two_factor,
+        two_factor::login_recovery_code,
+        webauthn::register_begin,
+        webauthn::register_finish,
+        webauthn::login_begin,
+        webauthn::login_finish,
+        webauthn::list_credentials,
+        webauthn::delete_credential,
+    ];
+    #[cfg(feature = "opaque")]
+    routes.extend(routes![
+        opaque::signup_opaque_start,
+        opaque::login_opaque_start,
+        opaque::login_opaque_finish,
+    ]);
+    routes
+}
+
+pub fn emergency_routes() -> Vec<rocket::Route> {
+    routes![
+        emergency::create_grant,
+        emergency::list_grants,
+        emergency::revoke_grant,
+        emergency::request_access,
+        emergency::reject_request,
+        emergency::get_grant_key,
+        emergency::list_grant_events,
+    ]
+}
+
+pub fn team_routes() -> Vec<rocket::Route> {
+    routes![
+        credentials::create_credential,
+        credentials::import_credentials,
+        import::import_bitwarden,
+        import::import_keepass,
+        import::import_csv,
+        credentials::list_credentials,
+        credentials::reorder_credentials,
+        credentials::bulk_delete_credentials,
+        credentials::bulk_restore_credentials,
+        credentials::bulk_move_credentials,
+        credentials::list_trash,
+        credentials::list_archived,
+        credentials::list_stale,
+        credentials::list_duplicates,
+        credentials::list_tags,
+        folders::list_folders,
+        folders::create_folder,
+        folders::update_folder,
+        folders::delete_folder,
+        templates::list_templates,
+        templates::get_template,
+        templates::create_template,
+        templates::update_template,
+        templates::delete_template,
+        teams::get_team_settings,
+        teams::update_team_settings,
+        teams::get_team_stats,
+        teams::get_team_health,
+        access_log::team_access_log,
+    ]
+}
+
+pub fn credential_routes() -> Vec<rocket::Route> {
+    routes![
+        credentials::search_credentials,
+        credentials::list_favorites,
+        credentials::list_expiring,
+        credentials::list_recent,
+        credentials::match_credentials,
+        credentials::get_credential,
+        credentials::batch_credentials,
+        credentials::update_credential,
+        credentials::list_versions,
+        credentials::get_version,
+        credentials::restore_version,
+        credentials::move_credential,
+        credentials::copy_credential,
+        credentials::favorite_credential,
+        credentials::unfavorite_credential,
+        attachments::upload_attachment,
+        attachments::list_attachments,
+        access_log::credential_access_log,
+        credential_acl::get_credential_acl,
+        credential_acl::set_credential_acl,
+        credential_shares::list_credential_shares,
+        credential_shares::share_credential,
+        credential_shares::unshare_credential,
+        credentials::delete_credential,
+        credentials::restore_credential,
+        credentials::purge_credential,
+        credentials::archive_credential,
+        credentials::unarchive_credential,
+    ]
+}
+
+pub fn ssh_key_routes() -> Vec<rocket::Route> {
+    routes![ssh_keys::list_ssh_keys, ssh_keys::get_ssh_key_secret]
+}
+
+pub fn attachment_routes() -> Vec<rocket::Route> {
+    routes![attachments::download_attachment, attachments::delete_attachment]
+}
+
+pub fn hibp_routes() -> Vec<rocket::Route> {
+    routes![hibp::get_range]
+}
+
+pub fn user_routes() -> Vec<rocket::Route> {
+    routes![users::lookup_public_key]
+}
+
+pub fn admin_routes() -> Vec<rocket::Route> {
+    routes![
+        admin::list_users,
+        admin::get_user,
+        admin::promote_user,
+        admin::demote_user,
+        admin::suspend_user,
+        admin::unsuspend_user,
+        admin::require_password_change,
+        admin::purge_trash_now,
+    ]
 }
-mod credentials;
\ No newline at end of file
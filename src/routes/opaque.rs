@@ -0,0 +1,242 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::{http::Status, State};
+use rocket::serde::{Deserialize, Deserializer, Serialize};
+use opaque_ke::{CredentialFinalization, CredentialRequest, RegistrationRequest};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::guards::ClientInfo;
+use crate::lockout::{locked_out, lockout_remaining, record_login_failure};
+use crate::login_events::{record_login_event, LoginEventOutcome, LoginSubject};
+use crate::notifications::LoginNotifier;
+use crate::opaque::{finish_login, ClientMessage, OpaqueServer, OpaqueSuite};
+use crate::web_sessions::SessionDelivery;
+use super::auth::{continue_login, deserialize_base64, serialize_base64, LoginOutcome, VerifiedAccount};
+use super::devices::{validate_device, DeviceRequest};
+
+// --- Request DTOs ---
+
+/// The first message of an OPAQUE registration, sent before signing up.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistrationStartRequest {
+    /// The email address the account will be registered with.
+    pub email: String,
+    /// The client's `RegistrationRequest`, encoded as Base64 in JSON.
+    pub registration_request: OpaqueMessage<RegistrationRequest<OpaqueSuite>>,
+}
+
+/// The first message of an OPAQUE login.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginStartRequest {
+    /// The email address the account was registered with.
+    pub email: String,
+    /// The client's `CredentialRequest`, encoded as Base64 in JSON.
+    pub credential_request: OpaqueMessage<CredentialRequest<OpaqueSuite>>,
+    /// The device logging in. Required while `require_device_approval` is set.
+    pub device: Option<DeviceRequest>,
+}
+
+/// The last message of an OPAQUE login.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginFinishRequest {
+    /// The `login_id` returned by `/auth/login/opaque/start`.
+    pub login_id: Uuid,
+    /// The client's `CredentialFinalization`, encoded as Base64 in JSON.
+    pub credential_finalization: OpaqueMessage<CredentialFinalization<OpaqueSuite>>,
+}
+
+// --- Response DTOs ---
+
+/// The server's answer to the first message of an OPAQUE registration.
+#[derive(Serialize)]
+pub struct RegistrationStartResponse {
+    /// The `RegistrationResponse` to finish the registration with, encoded as Base64 in JSON.
+    #[serde(serialize_with = "serialize_base64")]
+    pub registration_response: Vec<u8>,
+}
+
+/// The server's answer to the first message of an OPAQUE login.
+#[derive(Serialize)]
+pub struct LoginStartResponse {
+    /// Identifies the login to `/auth/login/opaque/finish`.
+    pub login_id: Uuid,
+    /// The `CredentialResponse` to finish the login with, encoded as Base64 in JSON.
+    #[serde(serialize_with = "serialize_base64")]
+    pub credential_response: Vec<u8>,
+    /// Seconds until the login can no longer be finished.
+    pub expires_in: i64,
+}
+
+// --- Helpers ---
+
+/// An OPAQUE protocol message in a request body, sent as Base64. A message that doesn't
+/// decode fails the request with `422 Unprocessable Entity`, naming the field.
+pub struct OpaqueMessage<M>(pub M);
+
+impl<'de, M: ClientMessage> Deserialize<'de> for OpaqueMessage<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserialize_base64(deserializer)?;
+        M::from_bytes(&bytes)
+            .map(OpaqueMessage)
+            .map_err(|_| rocket::serde::de::Error::custom("invalid OPAQUE message"))
+    }
+}
+
+// --- Routes ---
+
+/// Answers the first message of an OPAQUE registration for an email address.
+///
+/// The client finishes the registration locally with the response and signs up through
+/// `/auth/signup`, sending the resulting upload as `opaque_registration`. The server
+/// keeps nothing at this point, and doesn't check whether the email is taken.
+#[post("/signup/opaque/start", data = "<start_data>")]
+pub async fn signup_opaque_start(
+    server: &State<OpaqueServer>,
+    start_data: JsonBody<RegistrationStartRequest>,
+) -> Result<Json<RegistrationStartResponse>, Status> {
+    let RegistrationStartRequest { email, registration_request } = start_data.0;
+    let registration_response = server
+        .start_registration(registration_request.0, &email)
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(RegistrationStartResponse { registration_response }))
+}
+
+/// Starts an OPAQUE login for an account that signed up with an `opaque_registration`.
+///
+/// Returns the server's `credential_response` and a `login_id` to finish the login with
+/// within `auth.opaque_login_ttl_secs`. Unknown emails and accounts using a password
+/// hash get a response too, indistinguishable from a real one, and fail at the finish.
+///
+/// Returns `429 Too Many Requests` with a `Retry-After` header while the email or IP is
+/// locked out after repeated failures, like `/auth/login`.
+#[post("/login/opaque/start", data = "<start_data>")]
+pub async fn login_opaque_start(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    server: &State<OpaqueServer>,
+    client: ClientInfo,
+    start_data: JsonBody<LoginStartRequest>,
+) -> Result<Json<LoginStartResponse>, ApiError> {
+    let LoginStartRequest { email, credential_request, device } = start_data.0;
+    validate_device(device.as_ref(), config.require_device_approval)?;
+
+    let ip = client.ip_address();
+    let locked = lockout_remaining(db.as_mut(), &email, ip.as_deref())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if let Some(retry_after) = locked {
+        record_login_event(db.as_mut(), LoginSubject::Email(&email), &client, LoginEventOutcome::LockedOut).await;
+        return Err(locked_out(retry_after));
+    }
+
+    let account = sqlx::query!("SELECT id, opaque_registration FROM users WHERE email = $1", email)
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let registration = account.as_ref().and_then(|a| a.opaque_registration.as_deref());
+    let (credential_response, state) = server
+        .start_login(registration, credential_request.0, &email)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.opaque_login_ttl_secs);
+    let login_id = sqlx::query_scalar!(
+        "INSERT INTO opaque_login_states (user_id, email, state, device, expires_at)
+         VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        account.map(|a| a.id),
+        email,
+        state,
+        device.map(sqlx::types::Json) as _,
+        expires_at
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(LoginStartResponse {
+        login_id,
+        credential_response,
+        expires_in: config.auth.opaque_login_ttl_secs,
+    }))
+}
+
+/// Finishes an OPAQUE login started through `/auth/login/opaque/start`.
+///
+/// Each `login_id` can be finished once, whether or not the password was right. A
+/// successful login then goes on exactly like one through `/auth/login` and returns the
+/// same responses, including the second factor challenge, the `?session=` parameter, and
+/// the device carried over from the start. Failures count towards the login lockout.
+///
+/// Returns `401 Unauthorized` if the login is unknown, expired, or the client didn't know
+/// the password, or `429 Too Many Requests` with a `Retry-After` header if the email or
+/// IP was locked out since the login started.
+#[post("/login/opaque/finish", data = "<finish_data>")]
+pub async fn login_opaque_finish(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    client: ClientInfo,
+    notifier: LoginNotifier,
+    delivery: SessionDelivery<'_>,
+    finish_data: JsonBody<LoginFinishRequest>,
+) -> Result<Json<LoginOutcome>, ApiError> {
+    let LoginFinishRequest { login_id, credential_finalization } = finish_data.0;
+
+    let login = sqlx::query!(
+        "DELETE FROM opaque_login_states WHERE id = $1 AND expires_at > NOW()
+         RETURNING user_id, email, state, device AS \"device: sqlx::types::Json<DeviceRequest>\"",
+        login_id
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    let ip = client.ip_address();
+    let locked = lockout_remaining(db.as_mut(), &login.email, ip.as_deref())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if let Some(retry_after) = locked {
+        record_login_event(db.as_mut(), LoginSubject::Email(&login.email), &client, LoginEventOutcome::LockedOut)
+            .await;
+        return Err(locked_out(retry_after));
+    }
+
+    let user_id = match (login.user_id, finish_login(&login.state, credential_finalization.0)) {
+        (Some(user_id), Ok(())) => user_id,
+        (user_id, _) => {
+            let outcome = match user_id {
+                Some(_) => LoginEventOutcome::WrongPassword,
+                None => LoginEventOutcome::UnknownEmail,
+            };
+            record_login_failure(db.as_mut(), &login.email, ip.as_deref())
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            record_login_event(db.as_mut(), LoginSubject::Email(&login.email), &client, outcome).await;
+            return Err(Status::Unauthorized.into());
+        }
+    };
+
+    let account = sqlx::query_as!(
+        VerifiedAccount,
+        "SELECT id, totp_enabled, email_verified_at IS NOT NULL AS \"email_verified!\",
+                suspended_at IS NOT NULL AS \"suspended!\",
+                EXISTS(SELECT 1 FROM webauthn_credentials w WHERE w.user_id = users.id) AS \"has_webauthn!\"
+         FROM users WHERE id = $1",
+        user_id
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    let device = login.device.map(|device| device.0);
+    let outcome = continue_login(&mut db, config, &client, &notifier, account, device.as_ref(), delivery).await?;
+    Ok(Json(outcome))
+}
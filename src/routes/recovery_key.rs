@@ -0,0 +1,328 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::{http::Status, State};
+use rocket::serde::{Deserialize, Serialize};
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::crypto::constant_time_eq;
+use crate::error::ApiError;
+use crate::guards::AuthenticatedUser;
+use crate::sessions::revoke_user_sessions;
+use crate::tokens::{generate_opaque_token, hash_token};
+use crate::validation::Validator;
+use super::auth::{check_kdf_params, check_key_material, deserialize_base64, opaque_account, serialize_base64, PASSWORD_HASH_LEN};
+
+// --- Request DTOs ---
+
+/// Represents the confirmation required to generate a recovery key.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenerateRecoveryKeyRequest {
+    /// The hash of the current password.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_hash: Vec<u8>,
+}
+
+/// The copy of the private key a new recovery key unlocks.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreRecoveryKeyRequest {
+    /// The user's private key, encrypted with a key the client derived from the
+    /// recovery key. Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_private_key`.
+    /// Encoded as Base64 in JSON.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+}
+
+/// Represents an attempt to recover an account with its recovery key.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecoverRequest {
+    pub email: String,
+    pub recovery_key: String,
+}
+
+/// Represents the new password set after recovering an account.
+///
+/// The private key stays the same, so the client re-encrypts it under the new master
+/// key. All binary fields are expected as Base64-encoded strings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FinishRecoveryRequest {
+    /// The token returned by `/auth/recover`.
+    pub recovery_token: String,
+    /// The SHA256 of the Argon2 hash of the new password.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_hash: Vec<u8>,
+    /// The salt used to derive the new password hash.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub password_salt: Vec<u8>,
+    /// The Argon2 memory cost, in KiB, used with the new salt; the current one is kept
+    /// if omitted.
+    pub kdf_memory_kib: Option<i32>,
+    /// The Argon2 iteration count used with the new salt; the current one is kept if omitted.
+    pub kdf_iterations: Option<i32>,
+    /// The Argon2 parallelism used with the new salt; the current one is kept if omitted.
+    pub kdf_parallelism: Option<i32>,
+    /// The private key, encrypted with the new master key.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    /// The nonce required to decrypt the new `encrypted_private_key`.
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+}
+
+// --- Response DTOs ---
+
+/// A newly generated recovery key. It is only ever shown here.
+#[derive(Serialize)]
+pub struct RecoveryKeyResponse {
+    /// 32 random bytes, encoded as unpadded URL-safe Base64.
+    pub recovery_key: String,
+}
+
+/// What a client needs to finish recovering an account.
+#[derive(Serialize)]
+pub struct RecoverResponse {
+    /// A short-lived token to submit the new password with.
+    pub recovery_token: String,
+    /// Seconds until the `recovery_token` expires.
+    pub expires_in: i64,
+    /// The private key, encrypted with the key derived from the recovery key.
+    #[serde(serialize_with = "serialize_base64")]
+    pub encrypted_private_key: Vec<u8>,
+    /// The nonce required to decrypt the `encrypted_private_key`.
+    #[serde(serialize_with = "serialize_base64")]
+    pub private_key_nonce: Vec<u8>,
+}
+
+// --- Routes ---
+
+/// Generates a new recovery key for the caller, replacing any earlier one, and returns
+/// it. The key is shown only this once; only its hash is stored.
+///
+/// The key can't recover anything yet: the client derives an encryption key from it,
+/// encrypts the private key with it, and stores the result through
+/// `PUT /auth/recovery-key`. Until then, an earlier recovery key no longer works either.
+///
+/// Requires the current password hash, and a login session: requests made with a
+/// personal access token fail with `403 Forbidden` and `{"error": "session_required"}`.
+/// Returns `201 Created`, `403 Forbidden` with `{"error": "invalid_password"}` if the
+/// password hash is wrong, or `409 Conflict` with `{"error": "opaque_account"}` for
+/// accounts using OPAQUE, which have no hash to check.
+#[post("/recovery-key", data = "<generate_data>")]
+pub async fn generate_recovery_key(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    generate_data: JsonBody<GenerateRecoveryKeyRequest>,
+) -> Result<(Status, Json<RecoveryKeyResponse>), ApiError> {
+    if user.session_id.is_none() {
+        return Err(ApiError::new(Status::Forbidden, "session_required"));
+    }
+
+    let current_hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", user.id)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or_else(opaque_account)?;
+
+    if !constant_time_eq(&current_hash, &generate_data.password_hash) {
+        return Err(ApiError::new(Status::Forbidden, "invalid_password"));
+    }
+
+    let recovery_key = generate_opaque_token();
+    sqlx::query!(
+        "INSERT INTO recovery_keys (user_id, key_hash) VALUES ($1, $2)
+         ON CONFLICT (user_id) DO UPDATE
+         SET key_hash = EXCLUDED.key_hash, encrypted_private_key = NULL, private_key_nonce = NULL,
+             created_at = NOW()",
+        user.id,
+        hash_token(&recovery_key)
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok((Status::Created, Json(RecoveryKeyResponse { recovery_key })))
+}
+
+/// Stores the private key encrypted under the caller's newly generated recovery key,
+/// which makes the key usable. A requirement to replace a used recovery key is thereby
+/// met.
+///
+/// Returns `204 No Content`, `404 Not Found` if no recovery key is waiting for its
+/// private key, or `422 Unprocessable Entity` listing invalid fields.
+#[put("/recovery-key", data = "<store_data>")]
+pub async fn store_recovery_key(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    store_data: JsonBody<StoreRecoveryKeyRequest>,
+) -> Result<Status, ApiError> {
+    let mut v = Validator::new();
+    check_key_material(&mut v, None, &store_data.encrypted_private_key, &store_data.private_key_nonce);
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let stored = sqlx::query!(
+        "UPDATE recovery_keys SET encrypted_private_key = $1, private_key_nonce = $2
+         WHERE user_id = $3 AND encrypted_private_key IS NULL",
+        store_data.encrypted_private_key,
+        store_data.private_key_nonce,
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+    if !stored {
+        return Err(Status::NotFound.into());
+    }
+
+    sqlx::query!("UPDATE users SET recovery_key_required = false WHERE id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Starts recovering an account with its recovery key, for when the password is lost
+/// and email can't be used.
+///
+/// The recovery key is used up: it is deleted, and once recovered the account has to
+/// store a new one before it can do anything else. Returned are the private key
+/// encrypted under the recovery key and a short-lived `recovery_token` to set a new
+/// password with through `/auth/recover/finish`.
+///
+/// Returns `401 Unauthorized` if the email or recovery key is wrong (without revealing
+/// which) or the recovery key was never completed.
+#[post("/recover", data = "<recover_data>")]
+pub async fn recover(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    recover_data: JsonBody<RecoverRequest>,
+) -> Result<Json<RecoverResponse>, Status> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Deleting the key as it is looked up makes it single use, even under concurrent requests.
+    let recovery = sqlx::query!(
+        "DELETE FROM recovery_keys k USING users u
+         WHERE k.user_id = u.id AND u.email = $1 AND k.key_hash = $2 AND k.encrypted_private_key IS NOT NULL
+         RETURNING k.user_id, k.encrypted_private_key AS \"encrypted_private_key!\",
+                   k.private_key_nonce AS \"private_key_nonce!\"",
+        recover_data.email,
+        hash_token(&recover_data.recovery_key)
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    let recovery_token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.account_recovery_ttl_secs);
+    sqlx::query!(
+        "INSERT INTO account_recovery_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+        recovery.user_id,
+        hash_token(&recovery_token),
+        expires_at
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!("UPDATE users SET recovery_key_required = true WHERE id = $1", recovery.user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(RecoverResponse {
+        recovery_token,
+        expires_in: config.auth.account_recovery_ttl_secs,
+        encrypted_private_key: recovery.encrypted_private_key,
+        private_key_nonce: recovery.private_key_nonce,
+    }))
+}
+
+/// Finishes recovering an account by setting a new password, along with any Argon2
+/// parameters sent for it.
+///
+/// Unlike a password reset, the key pair is kept, re-encrypted by the client under the
+/// new master key, so the user keeps access to their teams. An account using OPAQUE is
+/// moved back to `password_hash`. All of the user's sessions are revoked; logging in
+/// again with the new password still goes through any second factor.
+///
+/// Returns `204 No Content` on success, `403 Forbidden` if the token is invalid,
+/// expired, or already used, or `422 Unprocessable Entity` listing invalid fields.
+#[post("/recover/finish", data = "<finish_data>")]
+pub async fn finish_recovery(
+    mut db: Connection<DatabasePool>,
+    finish_data: JsonBody<FinishRecoveryRequest>,
+) -> Result<Status, ApiError> {
+    let mut v = Validator::new();
+    v.check_len("password_hash", &finish_data.password_hash, &[PASSWORD_HASH_LEN]);
+    check_kdf_params(&mut v, finish_data.kdf_memory_kib, finish_data.kdf_iterations, finish_data.kdf_parallelism);
+    check_key_material(
+        &mut v,
+        Some(("password_salt", &finish_data.password_salt)),
+        &finish_data.encrypted_private_key,
+        &finish_data.private_key_nonce,
+    );
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Consume the token atomically; zero rows means it is unknown, expired, or used.
+    let user_id = sqlx::query_scalar!(
+        "UPDATE account_recovery_tokens SET used_at = NOW()
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+         RETURNING user_id",
+        hash_token(&finish_data.recovery_token)
+    )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Forbidden)?;
+
+    sqlx::query!(
+        "UPDATE users SET auth_scheme = 'password_hash', password_hash = $1, opaque_registration = NULL,
+                          password_salt = $2, encrypted_private_key = $3, private_key_nonce = $4,
+                          kdf_memory_kib = COALESCE($5, kdf_memory_kib), kdf_iterations = COALESCE($6, kdf_iterations),
+                          kdf_parallelism = COALESCE($7, kdf_parallelism), must_change_password = false
+         WHERE id = $8",
+        finish_data.password_hash,
+        finish_data.password_salt,
+        finish_data.encrypted_private_key,
+        finish_data.private_key_nonce,
+        finish_data.kdf_memory_kib,
+        finish_data.kdf_iterations,
+        finish_data.kdf_parallelism,
+        user_id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    revoke_user_sessions(&mut tx, user_id, None)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,111 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use rocket::State;
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser, ClientInfo};
+use crate::models::SshKeyType;
+use crate::usage::UsageRecorder;
+use super::credentials::{self, CredentialResponse};
+
+// --- Response DTOs ---
+
+/// The public half of an `ssh_key` credential, e.g. for a CLI to load into an SSH agent.
+#[derive(Serialize)]
+pub struct SshKeyEntry {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub team_name: String,
+    pub title: String,
+    /// In OpenSSH format, if one was saved.
+    pub public_key: Option<String>,
+    pub key_type: Option<SshKeyType>,
+    /// As `ssh-keygen -l` prints it, e.g. `SHA256:q2g5fHyc...`.
+    pub fingerprint: Option<String>,
+    pub comment: Option<String>,
+}
+
+// --- Routes ---
+
+/// Lists the `ssh_key` credentials of every team the caller belongs to, or every team
+/// their token is scoped to, by team name and then title. Only public details are
+/// returned; `/ssh-keys/<id>/secret` has the encrypted private key. Archived and
+/// trashed credentials are left out.
+///
+/// `team` keeps to one team, which lists nothing if the caller isn't a member.
+/// `fingerprint` keeps to keys with that fingerprint, with or without the `SHA256:`
+/// prefix.
+///
+/// Returns `403 Forbidden` for a `team` the caller's token isn't scoped to.
+#[get("/?<team>&<fingerprint>")]
+pub async fn list_ssh_keys(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team: Option<Uuid>,
+    fingerprint: Option<&str>,
+) -> Result<Json<Vec<SshKeyEntry>>, ApiError> {
+    if team.is_some_and(|team_id| !user.permissions.can_read_team(team_id)) {
+        return Err(insufficient_scope());
+    }
+    let fingerprint = fingerprint.map(|fingerprint| {
+        let fingerprint = fingerprint.trim();
+        if fingerprint.starts_with("SHA256:") {
+            fingerprint.to_string()
+        } else {
+            format!("SHA256:{}", fingerprint)
+        }
+    });
+
+    let keys = sqlx::query_as!(
+        SshKeyEntry,
+        "SELECT c.id, t.id AS team_id, t.name AS team_name, c.title, c.public_key,
+                c.ssh_key_type AS \"key_type: SshKeyType\", c.ssh_fingerprint AS fingerprint,
+                c.ssh_comment AS comment
+         FROM credentials c
+         JOIN teams t ON t.id = c.team_id
+         JOIN team_members m ON m.team_id = c.team_id AND m.user_id = $1
+         WHERE c.kind = 'ssh_key' AND c.deleted_at IS NULL AND c.archived_at IS NULL
+           AND ($2::uuid[] IS NULL OR c.team_id = ANY($2))
+           AND ($3::uuid IS NULL OR c.team_id = $3)
+           AND ($4::text IS NULL OR c.ssh_fingerprint = $4) AND credential_acl_allows(c.id, $1, FALSE)
+         ORDER BY lower(t.name), t.id, lower(c.title), c.id",
+        user.id,
+        user.permissions.team_ids.as_deref(),
+        team,
+        fingerprint
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(keys))
+}
+
+/// Returns an `ssh_key` credential with its encrypted private key, as
+/// `GET /credentials/<id>` does, including its access log entry.
+///
+/// Returns `404 Not Found` also if the credential isn't an `ssh_key` one.
+#[get("/<id>/secret")]
+pub async fn get_ssh_key_secret(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    usage: &State<UsageRecorder>,
+    id: Uuid,
+) -> Result<Json<CredentialResponse>, ApiError> {
+    let is_ssh_key = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM credentials WHERE id = $1 AND kind = 'ssh_key')",
+        id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if is_ssh_key != Some(true) {
+        return Err(Status::NotFound.into());
+    }
+
+    credentials::get_credential(db, user, client, usage, id).await
+}
@@ -0,0 +1,338 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::models::{SecretKind, TeamRole};
+use crate::validation::Validator;
+use super::auth::deserialize_nullable;
+use super::credentials::is_team_member;
+
+/// The longest secret age a team can allow, in days.
+const MAX_SECRET_AGE_DAYS: i32 = 10 * 365;
+
+/// How many days ahead `GET /teams/<id>/stats` counts credentials as expiring.
+const STATS_EXPIRING_WITHIN_DAYS: i32 = 30;
+
+/// The highest strength score `GET /teams/<id>/health` counts as weak.
+const WEAK_STRENGTH_SCORE: i16 = 1;
+
+// --- Request DTOs ---
+
+/// Changes to a team's settings. Omitted fields are left as they are.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateTeamSettingsRequest {
+    /// How many days a secret may go unchanged before it is stale, or `null` for no limit.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub max_secret_age_days: Option<Option<i32>>,
+}
+
+// --- Response DTOs ---
+
+/// The settings of a team.
+#[derive(Serialize)]
+pub struct TeamSettingsResponse {
+    /// How many days a secret may go unchanged before it is listed by `GET
+    /// /teams/<id>/credentials/stale`, or `None` for no limit.
+    pub max_secret_age_days: Option<i32>,
+    /// When the team, such as its name or settings, last changed.
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Figures about a team's credentials for a dashboard, of those the caller may read.
+#[derive(Serialize)]
+pub struct TeamStatsResponse {
+    /// Credentials neither archived nor in the trash, by kind, with every kind listed.
+    pub by_kind: Vec<KindCount>,
+    pub archived: i64,
+    pub trashed: i64,
+    /// Credentials neither archived nor in the trash with an `expires_at`.
+    pub with_expiry: i64,
+    /// Of those, the ones expiring within 30 days, including those already expired.
+    pub expiring_within_30_days: i64,
+    /// When the longest unchanged secret of those neither archived nor in the trash was
+    /// last changed, or `None` if there are none.
+    pub oldest_secret_changed_at: Option<DateTime<Utc>>,
+    /// The encrypted size of every attachment, including those of credentials in the
+    /// trash, as they take up space until purged.
+    pub attachment_bytes: i64,
+    pub member_count: i64,
+}
+
+/// How the secrets of a team's credentials fare, going by what clients reported about
+/// them, of those the caller may read.
+#[derive(Serialize)]
+pub struct TeamHealthResponse {
+    /// Credentials neither archived nor in the trash.
+    pub total: i64,
+    /// Of those, the ones a client has reported on since the secret last changed.
+    pub reported: i64,
+    /// Those with a strength score of 1 or less.
+    pub weak: i64,
+    /// Those whose secret another of them shares.
+    pub reused: i64,
+    /// How many different secrets the `reused` ones share between them.
+    pub reused_groups: i64,
+    /// The ones no client has reported on, oldest first.
+    pub unreported: Vec<UnreportedCredential>,
+}
+
+/// A credential no client has reported the health of.
+#[derive(Serialize)]
+pub struct UnreportedCredential {
+    pub id: Uuid,
+    pub title: String,
+    pub kind: SecretKind,
+}
+
+/// How many credentials of a kind a team has.
+#[derive(Serialize)]
+pub struct KindCount {
+    pub kind: SecretKind,
+    pub count: i64,
+}
+
+// --- Routes ---
+
+/// Returns the settings of a team the caller belongs to.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/settings")]
+pub async fn get_team_settings(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<TeamSettingsResponse>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let settings = sqlx::query_as!(
+        TeamSettingsResponse,
+        "SELECT max_secret_age_days, updated_at FROM teams WHERE id = $1",
+        team_id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(settings))
+}
+
+/// Updates the settings of a team the caller is an admin of and returns them.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, `403
+/// Forbidden` for a token not scoped to the team or a member who isn't a team admin, or
+/// `422 Unprocessable Entity` listing invalid fields.
+#[patch("/<team_id>/settings", data = "<settings_data>")]
+pub async fn update_team_settings(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    settings_data: JsonBody<UpdateTeamSettingsRequest>,
+) -> Result<Json<TeamSettingsResponse>, ApiError> {
+    if !user.permissions.can_write_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let mut v = Validator::new();
+    if let Some(Some(days)) = settings_data.max_secret_age_days {
+        v.check(
+            "max_secret_age_days",
+            (1..=MAX_SECRET_AGE_DAYS).contains(&days),
+            format!("must be between 1 and {}", MAX_SECRET_AGE_DAYS),
+        );
+    }
+    v.finish()?;
+
+    let role = sqlx::query_scalar!(
+        "SELECT role AS \"role: TeamRole\" FROM team_members WHERE team_id = $1 AND user_id = $2",
+        team_id,
+        user.id
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if role != TeamRole::Admin {
+        return Err(ApiError::new(Status::Forbidden, "insufficient_role"));
+    }
+
+    let settings = sqlx::query_as!(
+        TeamSettingsResponse,
+        "UPDATE teams SET max_secret_age_days = CASE WHEN $2 THEN $3 ELSE max_secret_age_days END
+         WHERE id = $1
+         RETURNING max_secret_age_days, updated_at",
+        team_id,
+        settings_data.max_secret_age_days.is_some(),
+        settings_data.max_secret_age_days.flatten()
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(settings))
+}
+
+/// Returns figures about the credentials of a team the caller belongs to, such as how
+/// many there are of each kind and how many expire soon. Credentials restricted from the
+/// caller by `PUT /credentials/<id>/acl` aren't counted, so admins see the whole team's
+/// figures and other members those of what they can read. Credentials shared into the
+/// team from another aren't counted either.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/stats")]
+pub async fn get_team_stats(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<TeamStatsResponse>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let totals = sqlx::query!(
+        "SELECT COUNT(*) FILTER (WHERE c.archived_at IS NOT NULL AND c.deleted_at IS NULL) AS \"archived!\",
+                COUNT(*) FILTER (WHERE c.deleted_at IS NOT NULL) AS \"trashed!\",
+                COUNT(*) FILTER (WHERE c.archived_at IS NULL AND c.deleted_at IS NULL AND c.expires_at IS NOT NULL)
+                    AS \"with_expiry!\",
+                COUNT(*) FILTER (
+                    WHERE c.archived_at IS NULL AND c.deleted_at IS NULL
+                      AND c.expires_at <= NOW() + make_interval(days => $3)
+                ) AS \"expiring!\",
+                MIN(c.secret_changed_at) FILTER (WHERE c.archived_at IS NULL AND c.deleted_at IS NULL)
+                    AS oldest_secret_changed_at,
+                COALESCE((
+                    SELECT SUM(a.size) FROM attachments a
+                    JOIN credentials ac ON ac.id = a.credential_id
+                    WHERE ac.team_id = $1 AND credential_acl_allows(ac.id, $2, FALSE)
+                ), 0)::bigint AS \"attachment_bytes!\",
+                (SELECT COUNT(*) FROM team_members WHERE team_id = $1) AS \"member_count!\"
+         FROM credentials c
+         WHERE c.team_id = $1 AND credential_acl_allows(c.id, $2, FALSE)",
+        team_id,
+        user.id,
+        STATS_EXPIRING_WITHIN_DAYS
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let counts = sqlx::query!(
+        "SELECT c.kind AS \"kind: SecretKind\", COUNT(*) AS \"count!\"
+         FROM credentials c
+         WHERE c.team_id = $1 AND c.archived_at IS NULL AND c.deleted_at IS NULL
+           AND credential_acl_allows(c.id, $2, FALSE)
+         GROUP BY c.kind",
+        team_id,
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let by_kind = SecretKind::ALL
+        .iter()
+        .map(|&kind| KindCount {
+            kind,
+            count: counts.iter().find(|row| row.kind == kind).map_or(0, |row| row.count),
+        })
+        .collect();
+
+    Ok(Json(TeamStatsResponse {
+        by_kind,
+        archived: totals.archived,
+        trashed: totals.trashed,
+        with_expiry: totals.with_expiry,
+        expiring_within_30_days: totals.expiring,
+        oldest_secret_changed_at: totals.oldest_secret_changed_at,
+        attachment_bytes: totals.attachment_bytes,
+        member_count: totals.member_count,
+    }))
+}
+
+/// Summarizes how strong and how often reused the secrets of a team the caller belongs
+/// to are, as reported by clients with the `health` of `POST /teams/<id>/credentials`
+/// and `PATCH /credentials/<id>`; the server can't read the secrets itself. Archived and
+/// trashed credentials, and those restricted from the caller by
+/// `PUT /credentials/<id>/acl`, aren't counted. A changed secret counts as unreported
+/// until a client reports on it again.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/health")]
+pub async fn get_team_health(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<TeamHealthResponse>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let totals = sqlx::query!(
+        "SELECT COUNT(*) AS \"total!\",
+                COUNT(h.health_reported_at) AS \"reported!\",
+                COUNT(*) FILTER (WHERE h.health_strength_score <= $3) AS \"weak!\",
+                COUNT(*) FILTER (WHERE h.group_size > 1) AS \"reused!\",
+                COUNT(DISTINCT h.health_reused_group) FILTER (WHERE h.group_size > 1) AS \"reused_groups!\"
+         FROM (
+             SELECT c.health_reported_at, c.health_strength_score, c.health_reused_group,
+                    CASE WHEN c.health_reused_group IS NOT NULL
+                        THEN COUNT(*) OVER (PARTITION BY c.health_reused_group) END AS group_size
+             FROM credentials c
+             WHERE c.team_id = $1 AND c.archived_at IS NULL AND c.deleted_at IS NULL
+               AND credential_acl_allows(c.id, $2, FALSE)
+         ) h",
+        team_id,
+        user.id,
+        WEAK_STRENGTH_SCORE
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let unreported = sqlx::query_as!(
+        UnreportedCredential,
+        "SELECT c.id, c.title, c.kind AS \"kind: SecretKind\"
+         FROM credentials c
+         WHERE c.team_id = $1 AND c.archived_at IS NULL AND c.deleted_at IS NULL
+           AND c.health_reported_at IS NULL AND credential_acl_allows(c.id, $2, FALSE)
+         ORDER BY c.created_at, c.id",
+        team_id,
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(TeamHealthResponse {
+        total: totals.total,
+        reported: totals.reported,
+        weak: totals.weak,
+        reused: totals.reused,
+        reused_groups: totals.reused_groups,
+        unreported,
+    }))
+}
@@ -0,0 +1,473 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::{serde_json, Json};
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use sqlx::types::Json as JsonColumn;
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::error::ApiError;
+use crate::guards::{insufficient_scope, AuthenticatedUser};
+use crate::models::{FieldType, SecretKind};
+use crate::validation::Validator;
+use super::auth::deserialize_nullable;
+use super::credentials::{
+    check_uris, is_team_member, is_valid_text, normalize_tags, CredentialUri, CredentialUriRequest, MAX_FIELDS,
+    MAX_FIELD_LABEL_LEN, MAX_TITLE_LEN,
+};
+use super::folders::check_can_edit;
+
+/// The longest template name accepted, in characters.
+const MAX_NAME_LEN: usize = 100;
+/// The most templates a team can have.
+const MAX_TEMPLATES: i64 = 100;
+
+// --- Request DTOs ---
+
+/// A new template for a team's credentials.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateTemplateRequest {
+    pub name: String,
+    /// The title of credentials created from it, in which `{hostname}` and `{username}`
+    /// are replaced with theirs, e.g. `"Production DB ({hostname})"`.
+    pub title_pattern: Option<String>,
+    pub kind: SecretKind,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// A folder of the same team to put credentials in.
+    pub folder_id: Option<Uuid>,
+    #[serde(default)]
+    pub uris: Vec<CredentialUriRequest>,
+    /// The custom fields credentials get, in order, without values.
+    #[serde(default)]
+    pub fields: Vec<TemplateField>,
+}
+
+/// Changes to a template. Omitted fields are left as they are, and lists are replaced
+/// as a whole.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateTemplateRequest {
+    pub name: Option<String>,
+    /// The title pattern, or `null` to remove it.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub title_pattern: Option<Option<String>>,
+    pub kind: Option<SecretKind>,
+    pub tags: Option<Vec<String>>,
+    /// The folder, or `null` to remove it.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub folder_id: Option<Option<Uuid>>,
+    pub uris: Option<Vec<CredentialUriRequest>>,
+    pub fields: Option<Vec<TemplateField>>,
+}
+
+/// A custom field of a template: what credentials created from it label a field with
+/// and how they show it.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateField {
+    pub label: String,
+    pub field_type: FieldType,
+}
+
+// --- Response DTOs ---
+
+/// A template of a team.
+#[derive(Serialize)]
+pub struct TemplateResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub title_pattern: Option<String>,
+    pub kind: SecretKind,
+    pub tags: Vec<String>,
+    pub folder_id: Option<Uuid>,
+    pub uris: JsonColumn<Vec<CredentialUri>>,
+    pub fields: JsonColumn<Vec<TemplateField>>,
+    /// Who created it, if they still have an account.
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// --- Helpers ---
+
+/// The template `id` of a team, if the team has it and `user_id` is a member.
+async fn find_template(
+    conn: &mut sqlx::PgConnection,
+    team_id: Uuid,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<TemplateResponse>, Status> {
+    sqlx::query_as!(
+        TemplateResponse,
+        "SELECT t.id, t.name, t.title_pattern, t.kind AS \"kind: SecretKind\", t.tags, t.folder_id,
+                t.uris AS \"uris: JsonColumn<Vec<CredentialUri>>\",
+                t.fields AS \"fields: JsonColumn<Vec<TemplateField>>\", t.created_by, t.created_at, t.updated_at
+         FROM credential_templates t
+         JOIN team_members m ON m.team_id = t.team_id AND m.user_id = $3
+         WHERE t.id = $1 AND t.team_id = $2",
+        id,
+        team_id,
+        user_id
+    )
+        .fetch_optional(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+/// The template of a team a member creates a credential from, if `id` is given. Returns
+/// `404 Not Found` if the caller isn't a member of the team, or `422 Unprocessable
+/// Entity` if the team has no such template.
+pub(super) async fn template_to_apply(
+    conn: &mut sqlx::PgConnection,
+    team_id: Uuid,
+    id: Option<Uuid>,
+    user_id: Uuid,
+) -> Result<Option<TemplateResponse>, ApiError> {
+    let Some(id) = id else {
+        return Ok(None);
+    };
+    let template = find_template(conn, team_id, id, user_id).await?;
+    if template.is_none() && !is_team_member(conn, team_id, user_id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let mut v = Validator::new();
+    v.check("template_id", template.is_some(), "must be a template of the same team");
+    v.finish()?;
+    Ok(template)
+}
+
+/// Fills in what the body of a new credential leaves out from a template, so anything
+/// the body gives wins. Each of the body's `fields` takes the label and type of the
+/// template's field at the same position unless it has its own, so a client only sends
+/// the encrypted values. The template's URIs don't apply to a body giving a `hostname`,
+/// as they would replace it. Returns whether the title came from the template, and so
+/// still needs [`expand_title_pattern`].
+pub(super) fn apply_template(template: &TemplateResponse, body: &mut serde_json::Map<String, serde_json::Value>) -> bool {
+    let mut defaults = serde_json::Map::new();
+    defaults.insert("kind".to_string(), serde_json::json!(template.kind));
+    if !template.tags.is_empty() {
+        defaults.insert("tags".to_string(), serde_json::json!(template.tags));
+    }
+    if let Some(folder_id) = template.folder_id {
+        defaults.insert("folder_id".to_string(), serde_json::json!(folder_id));
+    }
+    if !template.uris.is_empty() && !body.contains_key("hostname") {
+        defaults.insert("uris".to_string(), serde_json::json!(template.uris));
+    }
+    let title_from_template = match &template.title_pattern {
+        Some(title_pattern) if !body.contains_key("title") => {
+            defaults.insert("title".to_string(), serde_json::json!(title_pattern));
+            true
+        }
+        _ => false,
+    };
+    for (key, value) in defaults {
+        body.entry(key).or_insert(value);
+    }
+
+    if let Some(serde_json::Value::Array(fields)) = body.get_mut("fields") {
+        for (field, template_field) in fields.iter_mut().zip(template.fields.iter()) {
+            if let serde_json::Value::Object(field) = field {
+                field.entry("label").or_insert_with(|| serde_json::json!(template_field.label));
+                field.entry("field_type").or_insert_with(|| serde_json::json!(template_field.field_type));
+            }
+        }
+    }
+    title_from_template
+}
+
+/// A title pattern with `{hostname}` and `{username}` replaced.
+pub(super) fn expand_title_pattern(title_pattern: &str, hostname: &str, username: &str) -> String {
+    title_pattern.replace("{hostname}", hostname).replace("{username}", username.trim())
+}
+
+/// Checks a template as it would be saved, returning its normalized tags.
+fn check_template(
+    v: &mut Validator,
+    name: &str,
+    title_pattern: Option<&str>,
+    kind: &SecretKind,
+    tags: &[String],
+    uris: &[CredentialUriRequest],
+    fields: &[TemplateField],
+) -> Vec<String> {
+    v.check("name", is_valid_text(name, MAX_NAME_LEN), format!("must be between 1 and {} characters", MAX_NAME_LEN));
+    if let Some(title_pattern) = title_pattern {
+        v.check(
+            "title_pattern",
+            is_valid_text(title_pattern, MAX_TITLE_LEN),
+            format!("must be between 1 and {} characters", MAX_TITLE_LEN),
+        );
+    }
+    check_uris(v, kind, uris);
+    v.check("fields", fields.len() <= MAX_FIELDS, format!("must be at most {} fields", MAX_FIELDS));
+    v.check(
+        "fields",
+        fields.iter().all(|field| is_valid_text(&field.label, MAX_FIELD_LABEL_LEN)),
+        format!("labels must be between 1 and {} characters", MAX_FIELD_LABEL_LEN),
+    );
+    normalize_tags(v, tags)
+}
+
+/// The URIs of a template as stored, trimmed.
+fn template_uris(uris: &[CredentialUriRequest]) -> Vec<CredentialUri> {
+    uris.iter()
+        .map(|uri| CredentialUri { uri: uri.uri.trim().to_string(), match_type: uri.match_type })
+        .collect()
+}
+
+/// The custom fields of a template as stored, with trimmed labels.
+fn template_fields(fields: &[TemplateField]) -> Vec<TemplateField> {
+    fields
+        .iter()
+        .map(|field| TemplateField { label: field.label.trim().to_string(), field_type: field.field_type })
+        .collect()
+}
+
+// --- Routes ---
+
+/// Lists the templates of a team the caller belongs to, ordered by name.
+///
+/// Returns `404 Not Found` if the team doesn't exist or the caller isn't a member, or
+/// `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/templates")]
+pub async fn list_templates(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+) -> Result<Json<Vec<TemplateResponse>>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    if !is_team_member(db.as_mut(), team_id, user.id).await? {
+        return Err(Status::NotFound.into());
+    }
+
+    let templates = sqlx::query_as!(
+        TemplateResponse,
+        "SELECT id, name, title_pattern, kind AS \"kind: SecretKind\", tags, folder_id,
+                uris AS \"uris: JsonColumn<Vec<CredentialUri>>\", fields AS \"fields: JsonColumn<Vec<TemplateField>>\",
+                created_by, created_at, updated_at
+         FROM credential_templates
+         WHERE team_id = $1
+         ORDER BY lower(name), id",
+        team_id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(templates))
+}
+
+/// Returns a template of a team the caller belongs to.
+///
+/// Returns `404 Not Found` if there is no such template or the caller isn't a member of
+/// the team, or `403 Forbidden` for a token not scoped to the team.
+#[get("/<team_id>/templates/<id>")]
+pub async fn get_template(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    id: Uuid,
+) -> Result<Json<TemplateResponse>, ApiError> {
+    if !user.permissions.can_read_team(team_id) {
+        return Err(insufficient_scope());
+    }
+
+    let template = find_template(db.as_mut(), team_id, id, user.id)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(template))
+}
+
+/// Adds a template to a team the caller belongs to, to create credentials from with
+/// `template_id`. Template names must differ ignoring case, and a team has at most 100
+/// templates.
+///
+/// Returns `201 Created` with the template, `404 Not Found` if the team doesn't exist or
+/// the caller isn't a member, `403 Forbidden` for a token not scoped to the team or a
+/// member whose role may not edit, `409 Conflict` if another template has the same name
+/// or with `{"error": "too_many_templates"}`, or `422 Unprocessable Entity` listing
+/// invalid fields, or with `{"error": "invalid_reference"}` if `folder_id` isn't a
+/// folder of the team.
+#[post("/<team_id>/templates", data = "<template_data>")]
+pub async fn create_template(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    template_data: JsonBody<CreateTemplateRequest>,
+) -> Result<(Status, Json<TemplateResponse>), ApiError> {
+    let mut v = Validator::new();
+    let tags = check_template(
+        &mut v,
+        &template_data.name,
+        template_data.title_pattern.as_deref(),
+        &template_data.kind,
+        &template_data.tags,
+        &template_data.uris,
+        &template_data.fields,
+    );
+    v.finish()?;
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, &user, team_id).await?;
+
+    // Locking the team keeps concurrent creates from going over the limit together.
+    let count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM credential_templates
+         WHERE team_id = (SELECT id FROM teams WHERE id = $1 FOR UPDATE)",
+        team_id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .unwrap_or(0);
+    if count >= MAX_TEMPLATES {
+        return Err(ApiError::new(Status::Conflict, "too_many_templates").with("max", MAX_TEMPLATES));
+    }
+
+    let template = sqlx::query_as!(
+        TemplateResponse,
+        "INSERT INTO credential_templates (team_id, name, title_pattern, kind, tags, folder_id, uris, fields, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+         RETURNING id, name, title_pattern, kind AS \"kind: SecretKind\", tags, folder_id,
+                   uris AS \"uris: JsonColumn<Vec<CredentialUri>>\", fields AS \"fields: JsonColumn<Vec<TemplateField>>\",
+                   created_by, created_at, updated_at",
+        team_id,
+        template_data.name.trim(),
+        template_data.title_pattern.as_deref().map(str::trim),
+        &template_data.kind as &SecretKind,
+        &tags,
+        template_data.folder_id,
+        JsonColumn(template_uris(&template_data.uris)) as _,
+        JsonColumn(template_fields(&template_data.fields)) as _,
+        user.id
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok((Status::Created, Json(template)))
+}
+
+/// Changes a template of a team the caller belongs to. Credentials already created from
+/// it stay as they are.
+///
+/// Returns the template, `404 Not Found` if there is no such template or the caller
+/// isn't a member of the team, `403 Forbidden` for a token not scoped to the team or a
+/// member whose role may not edit, `409 Conflict` if another template has the same name,
+/// or `422 Unprocessable Entity` listing invalid fields, or with `{"error":
+/// "invalid_reference"}` if `folder_id` isn't a folder of the team.
+#[patch("/<team_id>/templates/<id>", data = "<update_data>")]
+pub async fn update_template(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    id: Uuid,
+    update_data: JsonBody<UpdateTemplateRequest>,
+) -> Result<Json<TemplateResponse>, ApiError> {
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    check_can_edit(&mut tx, &user, team_id).await?;
+
+    let current = find_template(&mut tx, team_id, id, user.id)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    // The URIs are checked even if unchanged, as a new kind may not allow them.
+    let uris = match &update_data.uris {
+        Some(uris) => template_uris(uris),
+        None => current.uris.0,
+    };
+    let uri_requests: Vec<CredentialUriRequest> = uris
+        .iter()
+        .map(|uri| CredentialUriRequest { uri: uri.uri.clone(), match_type: uri.match_type })
+        .collect();
+    let name = update_data.name.as_deref().unwrap_or(&current.name);
+    let title_pattern = match &update_data.title_pattern {
+        Some(title_pattern) => title_pattern.as_deref(),
+        None => current.title_pattern.as_deref(),
+    };
+    let kind = update_data.kind.as_ref().unwrap_or(&current.kind);
+    let fields = update_data.fields.as_deref().unwrap_or(&current.fields.0);
+    let mut v = Validator::new();
+    let tags = check_template(
+        &mut v,
+        name,
+        title_pattern,
+        kind,
+        update_data.tags.as_deref().unwrap_or(&current.tags),
+        &uri_requests,
+        fields,
+    );
+    v.finish()?;
+    let folder_id = match update_data.folder_id {
+        Some(folder_id) => folder_id,
+        None => current.folder_id,
+    };
+
+    let template = sqlx::query_as!(
+        TemplateResponse,
+        "UPDATE credential_templates SET
+             name = $2, title_pattern = $3, kind = $4, tags = $5, folder_id = $6, uris = $7, fields = $8
+         WHERE id = $1
+         RETURNING id, name, title_pattern, kind AS \"kind: SecretKind\", tags, folder_id,
+                   uris AS \"uris: JsonColumn<Vec<CredentialUri>>\", fields AS \"fields: JsonColumn<Vec<TemplateField>>\",
+                   created_by, created_at, updated_at",
+        id,
+        name.trim(),
+        title_pattern.map(str::trim),
+        kind as &SecretKind,
+        &tags,
+        folder_id,
+        JsonColumn(uris) as _,
+        JsonColumn(template_fields(fields)) as _
+    )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(ApiError::from_db)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(template))
+}
+
+/// Deletes a template of a team the caller belongs to. Credentials created from it
+/// don't refer to it and stay as they are.
+///
+/// Returns `204 No Content`, `404 Not Found` if there is no such template or the caller
+/// isn't a member of the team, or `403 Forbidden` for a token not scoped to the team or
+/// a member whose role may not edit.
+#[delete("/<team_id>/templates/<id>")]
+pub async fn delete_template(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    team_id: Uuid,
+    id: Uuid,
+) -> Result<Status, ApiError> {
+    check_can_edit(db.as_mut(), &user, team_id).await?;
+
+    let result = sqlx::query!("DELETE FROM credential_templates WHERE id = $1 AND team_id = $2", id, team_id)
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if result.rows_affected() == 0 {
+        return Err(Status::NotFound.into());
+    }
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,583 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::{http::Status, State};
+use rocket::serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::crypto::{decrypt_secret, encrypt_secret};
+use crate::error::ApiError;
+use crate::guards::{AuthenticatedUser, ClientInfo};
+use crate::lockout::{locked_out, lockout_remaining};
+use crate::login_events::{record_login_event, LoginEventOutcome, LoginSubject};
+use crate::notifications::LoginNotifier;
+use crate::web_sessions::SessionDelivery;
+use crate::tokens::{generate_opaque_token, generate_recovery_code, hash_token, normalize_recovery_code};
+use crate::totp;
+use super::auth::{complete_login, LoginResponse};
+use super::devices::DeviceRequest;
+
+/// The issuer shown in authenticator apps.
+const TOTP_ISSUER: &str = "HomeDesk";
+/// Wrong second-factor codes allowed before the account is briefly locked.
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+/// How long the second-factor step stays locked after too many wrong codes, in seconds.
+const LOCKOUT_SECS: i64 = 30;
+/// How many recovery codes are issued at a time.
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// --- Request DTOs ---
+
+/// Request DTO carrying a code from the user's authenticator app.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+/// Request DTO for completing a login with a TOTP code.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TwoFactorLoginRequest {
+    /// The token returned by `/auth/login`.
+    pub two_factor_token: String,
+    /// The current code from the user's authenticator app.
+    pub code: String,
+}
+
+/// Request DTO for completing a login with a recovery code instead of a TOTP code.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecoveryCodeLoginRequest {
+    /// The token returned by `/auth/login`.
+    pub two_factor_token: String,
+    /// One of the user's unused recovery codes.
+    pub recovery_code: String,
+}
+
+// --- Response DTOs ---
+
+/// The data needed to add a new TOTP secret to an authenticator app.
+///
+/// This is the only time the secret is ever returned.
+#[derive(Serialize)]
+pub struct TotpEnrollResponse {
+    /// The secret, Base32-encoded for manual entry.
+    pub secret: String,
+    /// The `otpauth://` URI, typically rendered as a QR code.
+    pub otpauth_uri: String,
+}
+
+/// A freshly issued set of recovery codes.
+///
+/// This is the only time the plaintext codes are ever returned.
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+    /// How many unused codes the user has; always the full set right after issuing.
+    pub remaining_recovery_codes: i64,
+}
+
+/// A completed login via recovery code, with the number of codes left so the
+/// client can warn the user when they are running low.
+#[derive(Serialize)]
+pub struct RecoveryCodeLoginResponse {
+    #[serde(flatten)]
+    pub login: LoginResponse,
+    pub remaining_recovery_codes: i64,
+}
+
+// --- Helpers ---
+
+/// Replaces the user's recovery codes with a fresh set and returns the plaintext codes.
+///
+/// Only the hashes are stored; any previous codes, used or not, are deleted.
+async fn replace_recovery_codes(
+    conn: &mut sqlx::PgConnection,
+    user_id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+    let hashes: Vec<Vec<u8>> = codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, * FROM UNNEST($2::bytea[])",
+        user_id,
+        &hashes
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(codes)
+}
+
+/// Counts the user's unused recovery codes.
+async fn remaining_recovery_codes(conn: &mut sqlx::PgConnection, user_id: Uuid) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    )
+        .fetch_one(conn)
+        .await?;
+
+    Ok(count.unwrap_or(0))
+}
+
+/// Verifies a TOTP code for the user and marks its time step as used.
+///
+/// Works for both confirmed and still-pending enrollments; callers decide which they
+/// accept. Returns `false` if the user has no secret or the code is wrong or replayed.
+/// Recording the step is done with a conditional update, so two concurrent requests
+/// can't both redeem the same code.
+pub(crate) async fn verify_totp_code(
+    conn: &mut sqlx::PgConnection,
+    config: &AppConfig,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, Status> {
+    let row = sqlx::query!(
+        "SELECT totp_secret_encrypted, totp_secret_nonce, totp_last_used_step FROM users WHERE id = $1",
+        user_id
+    )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let (Some(ciphertext), Some(nonce)) = (row.totp_secret_encrypted, row.totp_secret_nonce) else {
+        return Ok(false);
+    };
+
+    let secret = decrypt_secret(&config.auth.secret_encryption_key, &ciphertext, &nonce)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let now = chrono::Utc::now().timestamp();
+    let Some(step) = totp::verify_code(&secret, code, now, row.totp_last_used_step) else {
+        return Ok(false);
+    };
+
+    let recorded = sqlx::query!(
+        "UPDATE users SET totp_last_used_step = $1
+         WHERE id = $2 AND (totp_last_used_step IS NULL OR totp_last_used_step < $1)",
+        step,
+        user_id
+    )
+        .execute(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+
+    Ok(recorded)
+}
+
+/// Creates a challenge for a user who passed the password step and still needs
+/// to present a second factor, remembering the device the login came from. Returns the
+/// plaintext token; only its hash is stored.
+pub(crate) async fn create_two_factor_challenge(
+    conn: &mut sqlx::PgConnection,
+    config: &AppConfig,
+    user_id: Uuid,
+    device: Option<&DeviceRequest>,
+) -> Result<String, sqlx::Error> {
+    let token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.two_factor_token_ttl_secs);
+
+    sqlx::query!(
+        "INSERT INTO two_factor_challenges (user_id, token_hash, expires_at, device) VALUES ($1, $2, $3, $4)",
+        user_id,
+        hash_token(&token),
+        expires_at,
+        device.map(sqlx::types::Json) as _
+    )
+        .execute(conn)
+        .await?;
+
+    Ok(token)
+}
+
+/// A pending second factor login, as resolved from its token.
+pub(crate) struct TwoFactorChallenge {
+    pub user_id: Uuid,
+    /// The device the login was started from, if the client sent one.
+    pub device: Option<DeviceRequest>,
+}
+
+/// Resolves a two-factor token to the user it was issued to and the device they are
+/// logging in from.
+///
+/// Fails with `401 Unauthorized` if the token is unknown or expired, and with
+/// `429 Too Many Requests` while the user is locked out after too many wrong codes,
+/// or while the account's login lockout is in effect (so a challenge obtained before
+/// the lockout can't be used to get around it).
+pub(crate) async fn resolve_two_factor_challenge(
+    conn: &mut sqlx::PgConnection,
+    client: &ClientInfo,
+    token: &str,
+) -> Result<TwoFactorChallenge, ApiError> {
+    let challenge = sqlx::query!(
+        "SELECT c.user_id, c.device AS \"device: sqlx::types::Json<DeviceRequest>\", u.email, u.two_factor_locked_until
+         FROM two_factor_challenges c JOIN users u ON u.id = c.user_id
+         WHERE c.token_hash = $1 AND c.expires_at > NOW()",
+        hash_token(token)
+    )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    if challenge.two_factor_locked_until.is_some_and(|until| until > chrono::Utc::now()) {
+        return Err(Status::TooManyRequests.into());
+    }
+
+    let locked = lockout_remaining(conn, &challenge.email, None)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    if let Some(retry_after) = locked {
+        let subject = LoginSubject::User(challenge.user_id);
+        record_login_event(conn, subject, client, LoginEventOutcome::LockedOut).await;
+        return Err(locked_out(retry_after));
+    }
+
+    Ok(TwoFactorChallenge {
+        user_id: challenge.user_id,
+        device: challenge.device.map(|device| device.0),
+    })
+}
+
+/// Records a wrong second factor, locking the step for [`LOCKOUT_SECS`] once
+/// [`MAX_FAILED_ATTEMPTS`] is reached. It also counts as a failed login, so guessing
+/// second factors escalates the account lockout just like guessing passwords.
+pub(crate) async fn record_two_factor_failure(
+    conn: &mut sqlx::PgConnection,
+    client: &ClientInfo,
+    user_id: Uuid,
+) -> Result<(), Status> {
+    record_login_event(conn, LoginSubject::User(user_id), client, LoginEventOutcome::TwoFactorFailed).await;
+
+    sqlx::query!(
+        "INSERT INTO login_attempts (email) SELECT email FROM users WHERE id = $1",
+        user_id
+    )
+        .execute(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let locked_until = chrono::Utc::now() + chrono::Duration::seconds(LOCKOUT_SECS);
+
+    sqlx::query!(
+        "UPDATE users SET
+            two_factor_failed_attempts = CASE WHEN two_factor_failed_attempts + 1 >= $2 THEN 0
+                                              ELSE two_factor_failed_attempts + 1 END,
+            two_factor_locked_until = CASE WHEN two_factor_failed_attempts + 1 >= $2 THEN $3
+                                           ELSE two_factor_locked_until END
+         WHERE id = $1",
+        user_id,
+        MAX_FAILED_ATTEMPTS,
+        locked_until
+    )
+        .execute(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(())
+}
+
+/// Consumes a two-factor challenge after a correct second factor and resets the
+/// failure counter. Fails with `401 Unauthorized` if the challenge was already used.
+pub(crate) async fn consume_two_factor_challenge(
+    conn: &mut sqlx::PgConnection,
+    token: &str,
+    user_id: Uuid,
+) -> Result<(), Status> {
+    let consumed = sqlx::query!(
+        "DELETE FROM two_factor_challenges WHERE token_hash = $1 AND user_id = $2",
+        hash_token(token),
+        user_id
+    )
+        .execute(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+
+    if !consumed {
+        return Err(Status::Unauthorized);
+    }
+
+    sqlx::query!(
+        "UPDATE users SET two_factor_failed_attempts = 0, two_factor_locked_until = NULL WHERE id = $1",
+        user_id
+    )
+        .execute(&mut *conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(())
+}
+
+// --- Routes ---
+
+/// Completes a login for an account with TOTP enabled.
+///
+/// Takes the `two_factor_token` from `/auth/login` and a current TOTP code. After
+/// [`MAX_FAILED_ATTEMPTS`] wrong codes the step is locked for [`LOCKOUT_SECS`] seconds.
+///
+/// Returns the same session tokens and key material as a login without 2FA,
+/// `401 Unauthorized` if the token is invalid/expired or the code is wrong,
+/// or `429 Too Many Requests` while locked out.
+#[post("/login/2fa", data = "<login_data>")]
+pub async fn login_two_factor(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    client: ClientInfo,
+    notifier: LoginNotifier,
+    delivery: SessionDelivery<'_>,
+    login_data: JsonBody<TwoFactorLoginRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    let challenge = resolve_two_factor_challenge(db.as_mut(), &client, &login_data.two_factor_token).await?;
+    let user_id = challenge.user_id;
+
+    if !verify_totp_code(db.as_mut(), config, user_id, &login_data.code).await? {
+        record_two_factor_failure(db.as_mut(), &client, user_id).await?;
+        return Err(Status::Unauthorized.into());
+    }
+
+    consume_two_factor_challenge(db.as_mut(), &login_data.two_factor_token, user_id).await?;
+
+    Ok(Json(complete_login(&mut db, config, &client, &notifier, user_id, challenge.device.as_ref(), delivery).await?))
+}
+
+/// Starts TOTP enrollment by generating a new secret.
+///
+/// The secret is stored encrypted and stays inactive until confirmed through
+/// `/auth/2fa/totp/confirm`. Calling this again before confirming replaces the pending secret.
+///
+/// Returns the secret and its `otpauth://` URI, or `409 Conflict` if TOTP is already enabled.
+#[post("/2fa/totp/enroll")]
+pub async fn enroll_totp(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    user: AuthenticatedUser,
+) -> Result<Json<TotpEnrollResponse>, Status> {
+    let secret = totp::generate_secret();
+    let (ciphertext, nonce) = encrypt_secret(&config.auth.secret_encryption_key, &secret)
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Only overwrite the secret while 2FA is not yet enabled.
+    let email = sqlx::query_scalar!(
+        "UPDATE users SET totp_secret_encrypted = $1, totp_secret_nonce = $2, totp_last_used_step = NULL
+         WHERE id = $3 AND totp_enabled = false
+         RETURNING email",
+        ciphertext,
+        nonce,
+        user.id
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Conflict)?;
+
+    Ok(Json(TotpEnrollResponse {
+        secret: totp::base32_encode(&secret),
+        otpauth_uri: totp::otpauth_uri(TOTP_ISSUER, &email, &secret),
+    }))
+}
+
+/// Confirms a pending TOTP enrollment with a code from the authenticator app,
+/// enabling two-factor authentication for the account.
+///
+/// Also issues the account's recovery codes, which are returned in plaintext only here.
+///
+/// Returns the recovery codes on success, `409 Conflict` if TOTP is already enabled,
+/// `404 Not Found` if there is no pending enrollment, or `403 Forbidden` if the code is wrong.
+#[post("/2fa/totp/confirm", data = "<code_data>")]
+pub async fn confirm_totp(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    user: AuthenticatedUser,
+    code_data: JsonBody<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, Status> {
+    let state = sqlx::query!(
+        "SELECT totp_enabled, totp_secret_encrypted IS NOT NULL AS \"pending!\" FROM users WHERE id = $1",
+        user.id
+    )
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if state.totp_enabled {
+        return Err(Status::Conflict);
+    }
+    if !state.pending {
+        return Err(Status::NotFound);
+    }
+
+    if !verify_totp_code(db.as_mut(), config, user.id, &code_data.code).await? {
+        return Err(Status::Forbidden);
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!("UPDATE users SET totp_enabled = true WHERE id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let recovery_codes = replace_recovery_codes(&mut tx, user.id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(RecoveryCodesResponse {
+        remaining_recovery_codes: recovery_codes.len() as i64,
+        recovery_codes,
+    }))
+}
+
+/// Disables TOTP after verifying a current code, and deletes the secret and recovery codes.
+///
+/// Returns `204 No Content` on success, `404 Not Found` if TOTP isn't enabled,
+/// or `403 Forbidden` if the code is wrong.
+#[delete("/2fa/totp", data = "<code_data>")]
+pub async fn disable_totp(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    user: AuthenticatedUser,
+    code_data: JsonBody<TotpCodeRequest>,
+) -> Result<Status, Status> {
+    let enabled = sqlx::query_scalar!("SELECT totp_enabled FROM users WHERE id = $1", user.id)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !enabled {
+        return Err(Status::NotFound);
+    }
+
+    if !verify_totp_code(db.as_mut(), config, user.id, &code_data.code).await? {
+        return Err(Status::Forbidden);
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "UPDATE users SET totp_enabled = false, totp_secret_encrypted = NULL, totp_secret_nonce = NULL,
+                          totp_last_used_step = NULL
+         WHERE id = $1",
+        user.id
+    )
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Status::NoContent)
+}
+
+/// Replaces the caller's recovery codes with a fresh set, invalidating all old ones.
+///
+/// Requires a current TOTP code, so a stolen access token alone can't be turned into
+/// a way around the second factor.
+///
+/// Returns the new codes, `404 Not Found` if TOTP isn't enabled, or `403 Forbidden`
+/// if the code is wrong.
+#[post("/2fa/recovery-codes/regenerate", data = "<code_data>")]
+pub async fn regenerate_recovery_codes(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    user: AuthenticatedUser,
+    code_data: JsonBody<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, Status> {
+    let enabled = sqlx::query_scalar!("SELECT totp_enabled FROM users WHERE id = $1", user.id)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !enabled {
+        return Err(Status::NotFound);
+    }
+
+    if !verify_totp_code(db.as_mut(), config, user.id, &code_data.code).await? {
+        return Err(Status::Forbidden);
+    }
+
+    let mut tx = sqlx::Acquire::begin(&mut *db)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let recovery_codes = replace_recovery_codes(&mut tx, user.id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    tx.commit().await.map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(RecoveryCodesResponse {
+        remaining_recovery_codes: recovery_codes.len() as i64,
+        recovery_codes,
+    }))
+}
+
+/// Completes a login for an account with 2FA enabled using a recovery code, for when
+/// the authenticator app isn't available. The code is consumed.
+///
+/// Wrong codes count towards the same lockout as wrong TOTP codes.
+///
+/// Returns the session tokens and key material along with the number of recovery codes
+/// left, `401 Unauthorized` if the token is invalid/expired or the code is wrong or used,
+/// or `429 Too Many Requests` while locked out.
+#[post("/login/recovery-code", data = "<login_data>")]
+pub async fn login_recovery_code(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    client: ClientInfo,
+    notifier: LoginNotifier,
+    delivery: SessionDelivery<'_>,
+    login_data: JsonBody<RecoveryCodeLoginRequest>,
+) -> Result<Json<RecoveryCodeLoginResponse>, ApiError> {
+    let challenge = resolve_two_factor_challenge(db.as_mut(), &client, &login_data.two_factor_token).await?;
+    let user_id = challenge.user_id;
+
+    let code_hash = hash_token(&normalize_recovery_code(&login_data.recovery_code));
+    let consumed = sqlx::query!(
+        "UPDATE recovery_codes SET used_at = NOW() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        user_id,
+        code_hash
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+
+    if !consumed {
+        record_two_factor_failure(db.as_mut(), &client, user_id).await?;
+        return Err(Status::Unauthorized.into());
+    }
+
+    consume_two_factor_challenge(db.as_mut(), &login_data.two_factor_token, user_id).await?;
+
+    let login = complete_login(&mut db, config, &client, &notifier, user_id, challenge.device.as_ref(), delivery).await?;
+    let remaining = remaining_recovery_codes(db.as_mut(), user_id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(RecoveryCodeLoginResponse {
+        login,
+        remaining_recovery_codes: remaining,
+    }))
+}
@@ -0,0 +1,90 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::http::Status;
+use rocket::serde::Serialize;
+use uuid::Uuid;
+use crate::DatabasePool;
+use crate::crypto::public_key_fingerprint;
+use crate::error::ApiError;
+use crate::guards::{AuthenticatedUser, ClientInfo};
+use crate::rate_limit::CallerRateLimit;
+use super::auth::serialize_base64;
+
+// --- Response DTOs ---
+
+/// Another user's public key, for wrapping a team key to them.
+#[derive(Serialize)]
+pub struct PublicKeyResponse {
+    pub user_id: Uuid,
+    #[serde(serialize_with = "serialize_base64")]
+    pub public_key: Vec<u8>,
+    /// The SHA256 of the public key, for users to compare out of band.
+    pub fingerprint: String,
+}
+
+// --- Helpers ---
+
+/// Appends a public key lookup to the audit log. Failing to write it is logged but
+/// never fails the lookup itself.
+async fn record_lookup(
+    conn: &mut sqlx::PgConnection,
+    user_id: Uuid,
+    email: &str,
+    found_user_id: Option<Uuid>,
+    client: &ClientInfo,
+) {
+    let result = sqlx::query!(
+        "INSERT INTO public_key_lookups (user_id, email, found_user_id, ip_address) VALUES ($1, $2, $3, $4)",
+        user_id,
+        email,
+        found_user_id,
+        client.ip_address()
+    )
+        .execute(conn)
+        .await;
+
+    if let Err(e) = result {
+        warn!("Failed to record public key lookup: {}", e);
+    }
+}
+
+// --- Routes ---
+
+/// Looks up the public key of the account with exactly the given email, ignoring case.
+///
+/// There is deliberately no partial matching, so accounts can only be found by someone
+/// who already knows the address. Lookups are limited per caller by the
+/// `users/public-key` rate limit and recorded, found or not.
+///
+/// Returns `404 Not Found` if no single account has the email, or `429 Too Many
+/// Requests` with a `Retry-After` header once the caller is over the limit.
+#[get("/public-key?<email>")]
+pub async fn lookup_public_key(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    rate_limit: CallerRateLimit<'_>,
+    email: &str,
+) -> Result<Json<PublicKeyResponse>, ApiError> {
+    rate_limit.check("users/public-key", user.id).await?;
+
+    // Emails are unique as entered, so several accounts may share one ignoring case;
+    // such an address matches none of them rather than an arbitrary one.
+    let mut matches = sqlx::query!(
+        "SELECT id, public_key FROM users WHERE lower(email) = lower($1) LIMIT 2",
+        email
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+    let found = if matches.len() == 1 { matches.pop() } else { None };
+
+    record_lookup(db.as_mut(), user.id, email, found.as_ref().map(|f| f.id), &client).await;
+
+    let found = found.ok_or(Status::NotFound)?;
+    Ok(Json(PublicKeyResponse {
+        user_id: found.id,
+        fingerprint: public_key_fingerprint(&found.public_key),
+        public_key: found.public_key,
+    }))
+}
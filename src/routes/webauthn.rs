@@ -0,0 +1,369 @@
+use rocket_db_pools::{sqlx, Connection};
+use rocket::serde::json::Json;
+use rocket::{http::Status, State};
+use rocket::serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, Passkey, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, RequestChallengeResponse, WebauthnError,
+};
+use webauthn_rs::Webauthn;
+use crate::DatabasePool;
+use crate::body::JsonBody;
+use crate::config::AppConfig;
+use crate::error::ApiError;
+use crate::guards::{AuthenticatedUser, ClientInfo};
+use crate::notifications::LoginNotifier;
+use crate::web_sessions::SessionDelivery;
+use super::auth::{complete_login, LoginResponse};
+use super::two_factor::{consume_two_factor_challenge, record_two_factor_failure, resolve_two_factor_challenge};
+
+/// How long a registration or authentication ceremony may take, in seconds.
+const CHALLENGE_TTL_SECS: i64 = 5 * 60;
+/// The maximum length of a credential label.
+const MAX_LABEL_LEN: usize = 100;
+
+const REGISTRATION: &str = "registration";
+const AUTHENTICATION: &str = "authentication";
+
+// --- Request DTOs ---
+
+/// Request DTO for completing the registration of a new WebAuthn credential.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebauthnRegisterFinishRequest {
+    /// The id returned by `/auth/webauthn/register/begin`.
+    pub challenge_id: Uuid,
+    /// A user-chosen name for the credential, e.g. "YubiKey 5C".
+    pub label: String,
+    /// The authenticator's response, as produced by `navigator.credentials.create()`.
+    pub credential: RegisterPublicKeyCredential,
+}
+
+/// Request DTO for starting a WebAuthn second-factor login.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebauthnLoginBeginRequest {
+    /// The token returned by `/auth/login`.
+    pub two_factor_token: String,
+}
+
+/// Request DTO for completing a WebAuthn second-factor login.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebauthnLoginFinishRequest {
+    /// The token returned by `/auth/login`.
+    pub two_factor_token: String,
+    /// The id returned by `/auth/webauthn/login/begin`.
+    pub challenge_id: Uuid,
+    /// The authenticator's response, as produced by `navigator.credentials.get()`.
+    pub credential: PublicKeyCredential,
+}
+
+// --- Response DTOs ---
+
+/// The options to pass to the browser's WebAuthn API, plus the id of the
+/// server-side state to reference when finishing the ceremony.
+#[derive(Serialize)]
+pub struct WebauthnChallengeResponse<T> {
+    pub challenge_id: Uuid,
+    pub options: T,
+}
+
+/// An enrolled WebAuthn credential, without any key material.
+#[derive(Serialize)]
+pub struct WebauthnCredentialResponse {
+    pub id: Uuid,
+    pub label: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// --- Helpers ---
+
+/// Stores the server-side state of a ceremony and returns the id referencing it.
+async fn store_challenge(
+    conn: &mut sqlx::PgConnection,
+    user_id: Uuid,
+    ceremony: &str,
+    state: serde_json::Value,
+) -> Result<Uuid, sqlx::Error> {
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(CHALLENGE_TTL_SECS);
+
+    sqlx::query_scalar!(
+        "INSERT INTO webauthn_challenges (user_id, ceremony, state, expires_at) VALUES ($1, $2, $3, $4) RETURNING id",
+        user_id,
+        ceremony,
+        state,
+        expires_at
+    )
+        .fetch_one(conn)
+        .await
+}
+
+/// Removes and returns the state of an unexpired ceremony belonging to the user.
+///
+/// Each challenge can be used at most once, whether or not the ceremony succeeds.
+async fn take_challenge(
+    conn: &mut sqlx::PgConnection,
+    challenge_id: Uuid,
+    user_id: Uuid,
+    ceremony: &str,
+) -> Result<Option<serde_json::Value>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "DELETE FROM webauthn_challenges
+         WHERE id = $1 AND user_id = $2 AND ceremony = $3 AND expires_at > NOW()
+         RETURNING state",
+        challenge_id,
+        user_id,
+        ceremony
+    )
+        .fetch_optional(conn)
+        .await
+}
+
+/// Loads every passkey enrolled by the user.
+async fn load_passkeys(conn: &mut sqlx::PgConnection, user_id: Uuid) -> Result<Vec<Passkey>, Status> {
+    let rows = sqlx::query_scalar!(
+        "SELECT public_key FROM webauthn_credentials WHERE user_id = $1",
+        user_id
+    )
+        .fetch_all(conn)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    rows.into_iter()
+        .map(|value| serde_json::from_value(value).map_err(|_| Status::InternalServerError))
+        .collect()
+}
+
+// --- Routes ---
+
+/// Starts registering a new WebAuthn credential (security key or passkey) for the caller.
+///
+/// Returns the creation options for `navigator.credentials.create()` and a
+/// `challenge_id` to send back to `/auth/webauthn/register/finish`.
+#[post("/webauthn/register/begin")]
+pub async fn register_begin(
+    mut db: Connection<DatabasePool>,
+    webauthn: &State<Webauthn>,
+    user: AuthenticatedUser,
+) -> Result<Json<WebauthnChallengeResponse<CreationChallengeResponse>>, Status> {
+    let profile = sqlx::query!("SELECT email, name FROM users WHERE id = $1", user.id)
+        .fetch_one(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Keep the same authenticator from being enrolled twice.
+    let existing = load_passkeys(db.as_mut(), user.id).await?
+        .iter()
+        .map(|passkey| passkey.cred_id().clone())
+        .collect();
+
+    let (options, registration) = webauthn
+        .start_passkey_registration(user.id, &profile.email, &profile.name, Some(existing))
+        .map_err(|_| Status::InternalServerError)?;
+
+    let state = serde_json::to_value(&registration).map_err(|_| Status::InternalServerError)?;
+    let challenge_id = store_challenge(db.as_mut(), user.id, REGISTRATION, state)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(WebauthnChallengeResponse { challenge_id, options }))
+}
+
+/// Completes the registration of a new WebAuthn credential and stores it under the given label.
+///
+/// Returns `201 Created` with the new credential, `422 Unprocessable Entity` if the label
+/// is empty or too long, `400 Bad Request` if the challenge is unknown/expired or the
+/// authenticator response doesn't verify, or `409 Conflict` if the credential is already enrolled.
+#[post("/webauthn/register/finish", data = "<finish_data>")]
+pub async fn register_finish(
+    mut db: Connection<DatabasePool>,
+    webauthn: &State<Webauthn>,
+    user: AuthenticatedUser,
+    finish_data: JsonBody<WebauthnRegisterFinishRequest>,
+) -> Result<(Status, Json<WebauthnCredentialResponse>), Status> {
+    let label = finish_data.label.trim();
+    if label.is_empty() || label.chars().count() > MAX_LABEL_LEN {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let state = take_challenge(db.as_mut(), finish_data.challenge_id, user.id, REGISTRATION)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::BadRequest)?;
+    let registration: PasskeyRegistration = serde_json::from_value(state)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let passkey = webauthn
+        .finish_passkey_registration(&finish_data.credential, &registration)
+        .map_err(|_| Status::BadRequest)?;
+
+    let public_key = serde_json::to_value(&passkey).map_err(|_| Status::InternalServerError)?;
+
+    let credential = sqlx::query!(
+        "INSERT INTO webauthn_credentials (user_id, credential_id, public_key, label)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (credential_id) DO NOTHING
+         RETURNING id, created_at",
+        user.id,
+        passkey.cred_id().as_ref(),
+        public_key,
+        label
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Conflict)?;
+
+    Ok((Status::Created, Json(WebauthnCredentialResponse {
+        id: credential.id,
+        label: label.to_string(),
+        created_at: credential.created_at,
+        last_used_at: None,
+    })))
+}
+
+/// Starts a WebAuthn second-factor login for the user behind a `two_factor_token`.
+///
+/// Returns the request options for `navigator.credentials.get()` and a `challenge_id`,
+/// `401 Unauthorized` if the token is invalid/expired, `429 Too Many Requests` while
+/// locked out, or `404 Not Found` if the user has no WebAuthn credentials.
+#[post("/webauthn/login/begin", data = "<begin_data>")]
+pub async fn login_begin(
+    mut db: Connection<DatabasePool>,
+    webauthn: &State<Webauthn>,
+    client: ClientInfo,
+    begin_data: JsonBody<WebauthnLoginBeginRequest>,
+) -> Result<Json<WebauthnChallengeResponse<RequestChallengeResponse>>, ApiError> {
+    let user_id = resolve_two_factor_challenge(db.as_mut(), &client, &begin_data.two_factor_token).await?.user_id;
+
+    let passkeys = load_passkeys(db.as_mut(), user_id).await?;
+    if passkeys.is_empty() {
+        return Err(Status::NotFound.into());
+    }
+
+    let (options, authentication) = webauthn
+        .start_passkey_authentication(&passkeys)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let state = serde_json::to_value(&authentication).map_err(|_| Status::InternalServerError)?;
+    let challenge_id = store_challenge(db.as_mut(), user_id, AUTHENTICATION, state)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(WebauthnChallengeResponse { challenge_id, options }))
+}
+
+/// Completes a WebAuthn second-factor login.
+///
+/// Failed assertions count towards the same lockout as wrong TOTP codes. A signature
+/// counter that didn't increase suggests a cloned authenticator; it is logged and the
+/// login is rejected.
+///
+/// Returns the same session tokens and key material as a login without 2FA,
+/// `401 Unauthorized` if the token, challenge, or assertion is invalid, or
+/// `429 Too Many Requests` while locked out.
+#[post("/webauthn/login/finish", data = "<finish_data>")]
+pub async fn login_finish(
+    mut db: Connection<DatabasePool>,
+    config: &State<AppConfig>,
+    webauthn: &State<Webauthn>,
+    client: ClientInfo,
+    notifier: LoginNotifier,
+    delivery: SessionDelivery<'_>,
+    finish_data: JsonBody<WebauthnLoginFinishRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    let challenge = resolve_two_factor_challenge(db.as_mut(), &client, &finish_data.two_factor_token).await?;
+    let user_id = challenge.user_id;
+
+    let state = take_challenge(db.as_mut(), finish_data.challenge_id, user_id, AUTHENTICATION)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+    let authentication: PasskeyAuthentication = serde_json::from_value(state)
+        .map_err(|_| Status::InternalServerError)?;
+
+    let result = match webauthn.finish_passkey_authentication(&finish_data.credential, &authentication) {
+        Ok(result) => result,
+        Err(e) => {
+            if matches!(e, WebauthnError::CredentialPossibleCompromise) {
+                warn!("WebAuthn sign-count regression for user {}; possible cloned authenticator", user_id);
+            }
+            record_two_factor_failure(db.as_mut(), &client, user_id).await?;
+            return Err(Status::Unauthorized.into());
+        }
+    };
+
+    // Persist the advanced signature counter.
+    let row = sqlx::query!(
+        "SELECT id, public_key FROM webauthn_credentials WHERE user_id = $1 AND credential_id = $2",
+        user_id,
+        result.cred_id().as_ref()
+    )
+        .fetch_optional(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized)?;
+
+    let mut passkey: Passkey = serde_json::from_value(row.public_key)
+        .map_err(|_| Status::InternalServerError)?;
+    passkey.update_credential(&result);
+    let public_key = serde_json::to_value(&passkey).map_err(|_| Status::InternalServerError)?;
+
+    sqlx::query!(
+        "UPDATE webauthn_credentials SET public_key = $1, sign_count = $2, last_used_at = NOW() WHERE id = $3",
+        public_key,
+        i64::from(result.counter()),
+        row.id
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    consume_two_factor_challenge(db.as_mut(), &finish_data.two_factor_token, user_id).await?;
+
+    Ok(Json(complete_login(&mut db, config, &client, &notifier, user_id, challenge.device.as_ref(), delivery).await?))
+}
+
+/// Lists the caller's enrolled WebAuthn credentials.
+#[get("/webauthn/credentials")]
+pub async fn list_credentials(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Vec<WebauthnCredentialResponse>>, Status> {
+    let credentials = sqlx::query_as!(
+        WebauthnCredentialResponse,
+        "SELECT id, label, created_at, last_used_at FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at",
+        user.id
+    )
+        .fetch_all(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(credentials))
+}
+
+/// Removes one of the caller's WebAuthn credentials.
+///
+/// Returns `204 No Content` on success or `404 Not Found` if the caller has no
+/// credential with that id.
+#[delete("/webauthn/credentials/<id>")]
+pub async fn delete_credential(
+    mut db: Connection<DatabasePool>,
+    user: AuthenticatedUser,
+    id: Uuid,
+) -> Result<Status, Status> {
+    let deleted = sqlx::query!(
+        "DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2",
+        id,
+        user.id
+    )
+        .execute(db.as_mut())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .rows_affected() > 0;
+
+    if deleted { Ok(Status::NoContent) } else { Err(Status::NotFound) }
+}
@@ -0,0 +1,156 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::guards::ClientInfo;
+
+/// Starts a new login session for the user on the given device, if known, and returns
+/// its id.
+///
+/// Every access token carries the id of the session it belongs to, and every
+/// refresh token is tied to one, so revoking the session kills both.
+pub async fn create_session(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    device_id: Option<Uuid>,
+    client: &ClientInfo,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO sessions (user_id, device_id, user_agent, ip_address) VALUES ($1, $2, $3, $4) RETURNING id",
+        user_id,
+        device_id,
+        client.user_agent,
+        client.ip_address()
+    )
+        .fetch_one(conn)
+        .await
+}
+
+/// Records activity on a session and its device, updating where it was last seen from.
+pub async fn touch_session(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    client: &ClientInfo,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE sessions SET last_seen_at = NOW(), user_agent = $2, ip_address = $3 WHERE id = $1",
+        session_id,
+        client.user_agent,
+        client.ip_address()
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        "UPDATE devices SET last_seen_at = NOW() WHERE id = (SELECT device_id FROM sessions WHERE id = $1)",
+        session_id
+    )
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// What the auth guard needs to know about an active session.
+pub struct ActiveSession {
+    /// Whether the user must change their password before doing anything else.
+    pub must_change_password: bool,
+    /// Whether the user must store a new recovery key after using theirs.
+    pub recovery_key_required: bool,
+    /// Whether the session's device has been approved. Sessions without a device count
+    /// as approved.
+    pub device_approved: bool,
+}
+
+/// Looks up a session of the user that has not been revoked and belongs to an account
+/// that isn't suspended, returning `None` if there is no such session.
+pub async fn active_session(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<ActiveSession>, sqlx::Error> {
+    sqlx::query_as!(
+        ActiveSession,
+        "SELECT u.must_change_password, u.recovery_key_required, COALESCE(d.approved, true) AS \"device_approved!\"
+         FROM sessions s JOIN users u ON u.id = s.user_id
+         LEFT JOIN devices d ON d.id = s.device_id
+         WHERE s.id = $1 AND s.user_id = $2 AND s.revoked_at IS NULL AND u.suspended_at IS NULL",
+        session_id,
+        user_id
+    )
+        .fetch_optional(conn)
+        .await
+}
+
+/// Revokes a single session of the user along with all of its refresh tokens.
+///
+/// Returns `false` if the user has no active session with that id.
+pub async fn revoke_session(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let revoked = sqlx::query!(
+        "UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        session_id,
+        user_id
+    )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() > 0;
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked = true WHERE session_id = $1 AND revoked = false",
+        session_id
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(revoked)
+}
+
+/// Revokes every session of the user, optionally sparing one (usually the caller's own).
+pub async fn revoke_user_sessions(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    except: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE sessions SET revoked_at = NOW()
+         WHERE user_id = $1 AND revoked_at IS NULL AND ($2::uuid IS NULL OR id <> $2)",
+        user_id,
+        except
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked = true
+         WHERE user_id = $1 AND revoked = false AND ($2::uuid IS NULL OR session_id <> $2)",
+        user_id,
+        except
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Revokes every session on a device along with all of their refresh tokens.
+pub async fn revoke_device_sessions(conn: &mut PgConnection, device_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE sessions SET revoked_at = NOW() WHERE device_id = $1 AND revoked_at IS NULL",
+        device_id
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked = true
+         WHERE session_id IN (SELECT id FROM sessions WHERE device_id = $1) AND revoked = false",
+        device_id
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::models::SshKeyType;
+
+/// An OpenSSH public key, as in `~/.ssh/id_ed25519.pub`, checked and reduced to the
+/// details kept for a credential.
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub key_type: SshKeyType,
+    /// `SHA256:` and the unpadded Base64 of the key blob's digest, as `ssh-keygen -l`
+    /// prints it.
+    pub fingerprint: String,
+    pub comment: Option<String>,
+}
+
+/// Parses a public key line: the algorithm, the Base64 key blob, and an optional
+/// comment. The blob must be well formed and name the same algorithm as the line.
+/// Errors are reasons to show for the `public_key` field.
+pub fn parse_public_key(line: &str) -> Result<PublicKey, &'static str> {
+    let mut parts = line.trim().splitn(3, char::is_whitespace);
+    let algorithm = parts.next().unwrap_or_default();
+    let Some(encoded) = parts.next().filter(|encoded| !encoded.is_empty()) else {
+        return Err("must be in OpenSSH format, such as `ssh-ed25519 AAAA... comment`");
+    };
+    let comment = parts.next().map(str::trim).filter(|comment| !comment.is_empty());
+
+    let key_type = match algorithm {
+        "ssh-ed25519" => SshKeyType::Ed25519,
+        "ssh-rsa" => SshKeyType::Rsa,
+        "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => SshKeyType::Ecdsa,
+        _ => return Err("must be an ssh-ed25519, ssh-rsa, or ecdsa-sha2-nistp256/384/521 key"),
+    };
+    let blob = STANDARD.decode(encoded).map_err(|_| "has a key that isn't valid Base64")?;
+
+    let mut reader = Reader(&blob);
+    if reader.string()? != algorithm.as_bytes() {
+        return Err("has a key of a different type than it declares");
+    }
+    match key_type {
+        SshKeyType::Ed25519 => {
+            if reader.string()?.len() != 32 {
+                return Err("has an Ed25519 key of the wrong length");
+            }
+        }
+        SshKeyType::Rsa => {
+            let exponent = reader.string()?;
+            let modulus = reader.string()?;
+            if exponent.iter().all(|&b| b == 0) || modulus.iter().all(|&b| b == 0) {
+                return Err("has an RSA key with an empty exponent or modulus");
+            }
+        }
+        SshKeyType::Ecdsa => {
+            let curve = algorithm.trim_start_matches("ecdsa-sha2-");
+            if reader.string()? != curve.as_bytes() {
+                return Err("has an ECDSA key on a different curve than it declares");
+            }
+            let point_len = match curve {
+                "nistp256" => 65,
+                "nistp384" => 97,
+                _ => 133,
+            };
+            let point = reader.string()?;
+            if point.len() != point_len || point[0] != 0x04 {
+                return Err("has an ECDSA key that isn't an uncompressed point on its curve");
+            }
+        }
+    }
+    if !reader.0.is_empty() {
+        return Err("has unexpected data after the key");
+    }
+
+    Ok(PublicKey {
+        key_type,
+        fingerprint: format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(&blob))),
+        comment: comment.map(str::to_string),
+    })
+}
+
+/// Reads the length-prefixed strings a key blob is made of (RFC 4251 section 5).
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn string(&mut self) -> Result<&'a [u8], &'static str> {
+        const TRUNCATED: &str = "has a truncated key";
+        let (len, rest) = self.0.split_first_chunk::<4>().ok_or(TRUNCATED)?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            return Err(TRUNCATED);
+        }
+        let (value, rest) = rest.split_at(len);
+        self.0 = rest;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generated with `ssh-keygen -t <type> -C alice@host`; the fingerprints are what
+    // `ssh-keygen -lf` prints for them.
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICStXVUPc2NpmbMgf4Uhg342YUna1JU/S6ZuUXceAK5E alice@host";
+    const ED25519_FINGERPRINT: &str = "SHA256:kuf8c/6QdfmdLdFnSCcJkpK2LWMCwQQRFeMVwqwK1NM";
+    const RSA: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC3QVb7Be1T8XcoRh2v3e+CFnMa+X8OOjZEHlOUtummKZp8lJKcoDTTgsTF4\
+        Dg2dEycqFMSTQQv09mJJnMUFoR+/Um/eCJoqvhvChPEvunx0s4igaS0tqxacWtpG3tCnq3gWCcigZD5FHIKaiR9IiVtIgMVIjnnd9zsT0AJCth\
+        OkgoEM6cn30kfhvHyOQzIofA1tXMNGDqDnQwS34rwnvmNwZl1nBFFx5FkjpDJ8hmQBMrNYiLn/YywJluNylfmUNKV1r2rBWjAvoq0FajfbKxww0\
+        hiUcLss084UbJKPLLdUnTKipyyRp+39F7ISXp0CVCg/mq8g3i0Ex3W8Umw/jN/ alice@host";
+    const RSA_FINGERPRINT: &str = "SHA256:b15fTKGims+JvxW8oF8RWarXde6H2s3ZpufS5tm2J2w";
+    const ECDSA_256: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBG0J2ZukCHbPEeXr\
+        c/zEklJb+xTE7AUeQCyaRBzm1p7jU6U83L3uShIxVZqY2xgkHA39LnzBj354SpnRKEp5xYo= alice@host";
+    const ECDSA_256_FINGERPRINT: &str = "SHA256:uFr4n8Flmni0cUcYpNeQij1EPDbi1Wp2PqTlMF27+Rw";
+    const ECDSA_384: &str = "ecdsa-sha2-nistp384 AAAAE2VjZHNhLXNoYTItbmlzdHAzODQAAAAIbmlzdHAzODQAAABhBD0Q8WOA6uU9vuzB\
+        aZp5SuRIAHoorqKCZCOQ5Q6NO/bEbbi57uENNXgFhVO5nb6u27oqKwEPJ/C1u2JVPWXLImhiBbjR8mdGtcvTA4Fq6tNm3V7M3YP67RDIk2iFBee\
+        H2A== alice@host";
+    const ECDSA_384_FINGERPRINT: &str = "SHA256:tVNPn31xhaiNnH1auLwKMLbK+Uu0nVD6t3ibimLh72Q";
+    const ECDSA_521: &str = "ecdsa-sha2-nistp521 AAAAE2VjZHNhLXNoYTItbmlzdHA1MjEAAAAIbmlzdHA1MjEAAACFBAFG07+RkfUIYpcj\
+        /vg5GX69D5Sw+SDTUYQsmXB421eLTP8Saiqb1BxGS+gvcZMBtiAKANZ3eThhsDgrL2TTFe358QB89VIZ41f47GFpQd/MKl4eMbA9nHVSCCF4i/FL\
+        h7JwOY1VnQc2J8Ctr6qBa2+0B4XPNRw4o8zZfSHOIZ8gmUW4KQ== alice@host";
+    const ECDSA_521_FINGERPRINT: &str = "SHA256:VhAqI6O+aiB/jIonA4iLPjypw0bWnc4FJJgaUhWyva4";
+
+    /// The line with its key blob replaced by `f` applied to the decoded blob.
+    fn with_blob(line: &str, f: impl FnOnce(&mut Vec<u8>)) -> String {
+        let parts: Vec<&str> = line.split(' ').collect();
+        let mut blob = STANDARD.decode(parts[1]).unwrap();
+        f(&mut blob);
+        format!("{} {} {}", parts[0], STANDARD.encode(blob), parts[2])
+    }
+
+    #[test]
+    fn parses_ed25519() {
+        let key = parse_public_key(ED25519).unwrap();
+        assert_eq!(key.key_type, SshKeyType::Ed25519);
+        assert_eq!(key.fingerprint, ED25519_FINGERPRINT);
+        assert_eq!(key.comment.as_deref(), Some("alice@host"));
+    }
+
+    #[test]
+    fn parses_rsa() {
+        let key = parse_public_key(RSA).unwrap();
+        assert_eq!(key.key_type, SshKeyType::Rsa);
+        assert_eq!(key.fingerprint, RSA_FINGERPRINT);
+    }
+
+    #[test]
+    fn parses_ecdsa_on_each_curve() {
+        for (line, fingerprint) in [
+            (ECDSA_256, ECDSA_256_FINGERPRINT),
+            (ECDSA_384, ECDSA_384_FINGERPRINT),
+            (ECDSA_521, ECDSA_521_FINGERPRINT),
+        ] {
+            let key = parse_public_key(line).unwrap();
+            assert_eq!(key.key_type, SshKeyType::Ecdsa);
+            assert_eq!(key.fingerprint, fingerprint);
+        }
+    }
+
+    #[test]
+    fn ignores_surrounding_whitespace() {
+        let key = parse_public_key(&format!("  {}  \r\n", ED25519)).unwrap();
+        assert_eq!(key.fingerprint, ED25519_FINGERPRINT);
+        assert_eq!(key.comment.as_deref(), Some("alice@host"));
+    }
+
+    #[test]
+    fn keeps_spaces_inside_the_comment() {
+        let line = ED25519.replace("alice@host", "alice's laptop ");
+        assert_eq!(parse_public_key(&line).unwrap().comment.as_deref(), Some("alice's laptop"));
+    }
+
+    #[test]
+    fn allows_no_comment() {
+        let line = ED25519.trim_end_matches(" alice@host");
+        assert_eq!(parse_public_key(line).unwrap().comment, None);
+    }
+
+    #[test]
+    fn rejects_truncated_blobs() {
+        for line in [ED25519, RSA, ECDSA_256] {
+            let truncated = with_blob(line, |blob| blob.truncate(blob.len() - 1));
+            assert_eq!(parse_public_key(&truncated).unwrap_err(), "has a truncated key");
+        }
+        let only_length = with_blob(ED25519, |blob| blob.truncate(2));
+        assert_eq!(parse_public_key(&only_length).unwrap_err(), "has a truncated key");
+    }
+
+    #[test]
+    fn rejects_data_after_the_key() {
+        let padded = with_blob(ED25519, |blob| blob.push(0));
+        assert_eq!(parse_public_key(&padded).unwrap_err(), "has unexpected data after the key");
+    }
+
+    #[test]
+    fn rejects_blobs_of_another_algorithm() {
+        let swapped = ED25519.replacen("ssh-ed25519", "ssh-rsa", 1);
+        assert_eq!(parse_public_key(&swapped).unwrap_err(), "has a key of a different type than it declares");
+        let other_curve = ECDSA_384.replacen("nistp384", "nistp256", 1);
+        assert_eq!(parse_public_key(&other_curve).unwrap_err(), "has a key of a different type than it declares");
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse_public_key("").is_err());
+        assert!(parse_public_key("ssh-ed25519").is_err());
+        assert!(parse_public_key("ssh-dss AAAAB3NzaC1kc3M= alice@host").is_err());
+        assert_eq!(parse_public_key("ssh-ed25519 not*base64").unwrap_err(), "has a key that isn't valid Base64");
+    }
+}
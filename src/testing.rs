@@ -0,0 +1,135 @@
+//! Helpers for tests that drive the whole app through Rocket's local client, against a
+//! database from `#[sqlx::test]`.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::sqlx::{self, PgPool};
+use crate::DatabasePool;
+
+/// The stand-in for the client-side password hash every test account signs up with.
+pub const PASSWORD_HASH: [u8; 32] = [1; 32];
+
+/// Launches the app on `pool`, which already has the migrations applied.
+///
+/// Background work that would reach outside the test (HIBP, expiry notices, the trash
+/// purge) and rate limiting are turned off.
+pub async fn client(pool: PgPool) -> Client {
+    client_with(pool, |figment| figment).await
+}
+
+/// Like [`client`], with `configure` changing the config first, e.g. to set
+/// `bootstrap_admin_email`.
+pub async fn client_with(pool: PgPool, configure: impl FnOnce(Figment) -> Figment) -> Client {
+    let figment = Figment::from(rocket::Config::debug_default())
+        .merge(("log_level", "off"))
+        .merge(("auth.jwt_secret", "test-secret"))
+        .merge(("auth.secret_encryption_key", "YpUOJRUyjX+M22Cz+xv+2JJCgL+8aByTxgjzqRWHh1A="))
+        .merge(("rate_limit.enabled", false))
+        .merge(("trash.auto_purge", false))
+        .merge(("credentials.expiry_notice_days", Vec::<u32>::new()))
+        .merge(("hibp.enabled", false));
+    let rocket = crate::app(rocket::custom(configure(figment)).manage(DatabasePool(pool)));
+    Client::tracked(rocket).await.expect("the app should launch")
+}
+
+/// The pool the app was launched on.
+pub fn pool(client: &Client) -> &PgPool {
+    &client.rocket().state::<DatabasePool>().expect("the pool is managed").0
+}
+
+/// Signs up `email` with a fresh single-use invite and returns the signup response.
+pub async fn signup(client: &Client, email: &str) -> Value {
+    let code = format!("invite-{}", email);
+    sqlx::query("INSERT INTO invite_codes (code) VALUES ($1)")
+        .bind(&code)
+        .execute(pool(client))
+        .await
+        .expect("the invite is inserted");
+
+    let response = client.post("/auth/signup")
+        .header(ContentType::JSON)
+        .body(signup_body(&code, email))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Created, "signing up {}", email);
+    response.into_json().await.expect("a JSON signup response")
+}
+
+/// Logs `email` in on a new session and returns the login response.
+pub async fn login(client: &Client, email: &str) -> Value {
+    let response = client.post("/auth/login")
+        .header(ContentType::JSON)
+        .body(json!({ "email": email, "password_hash": STANDARD.encode(PASSWORD_HASH) }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok, "logging in {}", email);
+    response.into_json().await.expect("a JSON login response")
+}
+
+/// Logs `email` in on a new session and returns its access token.
+pub async fn access_token(client: &Client, email: &str) -> String {
+    login(client, email).await["access_token"].as_str().expect("an access token").to_string()
+}
+
+/// The `Authorization` header for `token`.
+pub fn bearer(token: &str) -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+/// Creates a personal access token from the session of `session`, with `body` as the
+/// request, and returns the token.
+pub async fn api_token(client: &Client, session: &str, body: Value) -> String {
+    let response = client.post("/auth/tokens")
+        .header(bearer(session))
+        .header(ContentType::JSON)
+        .body(body.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Created, "creating a token");
+    let created: Value = response.into_json().await.expect("a JSON token");
+    created["token"].as_str().expect("a token").to_string()
+}
+
+/// A valid signup request for `email` using `invite_code`.
+pub fn signup_body(invite_code: &str, email: &str) -> String {
+    json!({
+        "invite_code": invite_code,
+        "email": email,
+        "name": "Test User",
+        "password_hash": STANDARD.encode(PASSWORD_HASH),
+        "password_salt": STANDARD.encode([2; 16]),
+        "public_key": STANDARD.encode([3; 32]),
+        "encrypted_private_key": STANDARD.encode([4; 48]),
+        "private_key_nonce": STANDARD.encode([5; 24]),
+        "wrapped_personal_key": STANDARD.encode([6; 48]),
+        "personal_key_nonce": STANDARD.encode([7; 24]),
+    }).to_string()
+}
+
+/// Creates a password credential with one custom field in `team_id` and returns its summary.
+pub async fn create_credential(client: &Client, token: &str, team_id: &str) -> Value {
+    let response = client.post(format!("/teams/{}/credentials", team_id))
+        .header(bearer(token))
+        .header(ContentType::JSON)
+        .body(json!({
+            "title": "Router",
+            "hostname": "router.example.com",
+            "username": "admin",
+            "kind": "password",
+            "encrypted_secret": STANDARD.encode([12; 32]),
+            "nonce": STANDARD.encode([13; 24]),
+            "fields": [{
+                "label": "PIN",
+                "field_type": "hidden",
+                "encrypted_value": STANDARD.encode([14; 16]),
+                "nonce": STANDARD.encode([15; 24]),
+            }],
+        }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Created, "creating a credential in {}", team_id);
+    response.into_json().await.expect("a JSON credential")
+}
@@ -0,0 +1,121 @@
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use rocket::serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::AuthConfig;
+
+/// The claims carried by an access token.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Claims {
+    /// The id of the user the token was issued to.
+    pub sub: Uuid,
+    /// The id of the session the token belongs to.
+    pub sid: Uuid,
+    /// Issued-at time as a Unix timestamp.
+    pub iat: i64,
+    /// Expiry time as a Unix timestamp.
+    pub exp: i64,
+}
+
+/// Signs a new HS256 access token for the given user and session.
+pub fn issue_access_token(
+    config: &AuthConfig,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> jsonwebtoken::errors::Result<String> {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user_id,
+        sid: session_id,
+        iat: now,
+        exp: now + config.access_token_ttl_secs,
+    };
+
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    )
+}
+
+/// Verifies an access token's signature and expiry and returns its claims.
+pub fn decode_access_token(config: &AuthConfig, token: &str) -> jsonwebtoken::errors::Result<Claims> {
+    let validation = Validation::new(Algorithm::HS256);
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+        &validation,
+    )
+        .map(|data| data.claims)
+}
+
+/// Generates a new opaque token (e.g. a refresh token) from 32 random bytes,
+/// encoded as unpadded URL-safe Base64.
+pub fn generate_opaque_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hashes an opaque token for storage and lookup.
+///
+/// Opaque tokens carry 256 bits of entropy, so a plain SHA256 is sufficient;
+/// only the hash is ever written to the database.
+pub fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// Generates a human-friendly recovery code such as `k7qm-2xfd-9pwt`.
+///
+/// Codes are 12 characters from an unambiguous lowercase alphabet (about 60 bits of
+/// entropy), split into groups of four for readability.
+pub fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::rngs::OsRng;
+
+    (0..12)
+        .map(|i| {
+            let c = ALPHABET[(rng.next_u32() as usize) % ALPHABET.len()] as char;
+            if i > 0 && i % 4 == 0 { format!("-{c}") } else { c.to_string() }
+        })
+        .collect()
+}
+
+/// Normalizes a recovery code as typed by a user (case, dashes, and spaces are ignored)
+/// before it is hashed.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The prefix marking a personal access token, so it can't be mistaken for a JWT.
+pub const API_TOKEN_PREFIX: &str = "hd_pat_";
+
+/// The length of the random lookup id following [`API_TOKEN_PREFIX`].
+pub const API_TOKEN_LOOKUP_LEN: usize = 8;
+
+/// Generates a personal access token such as `hd_pat_Ab3dE-gh_<secret>`.
+///
+/// Returns the lookup id, which is stored in plain text and indexed, together with the
+/// full token, of which only the hash is stored.
+pub fn generate_api_token() -> (String, String) {
+    let mut bytes = [0u8; 6];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    let lookup = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    let token = format!("{}{}_{}", API_TOKEN_PREFIX, lookup, generate_opaque_token());
+    (lookup, token)
+}
+
+/// Extracts the lookup id from a personal access token, or `None` if `token` isn't one.
+pub fn api_token_lookup(token: &str) -> Option<&str> {
+    let rest = token.strip_prefix(API_TOKEN_PREFIX)?;
+    let lookup = rest.get(..API_TOKEN_LOOKUP_LEN)?;
+    rest[API_TOKEN_LOOKUP_LEN..].starts_with('_').then_some(lookup)
+}
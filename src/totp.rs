@@ -0,0 +1,94 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+use crate::crypto::constant_time_eq;
+
+/// The length of a time step, in seconds.
+pub const PERIOD_SECS: i64 = 30;
+/// The number of digits in a code.
+pub const DIGITS: u32 = 6;
+/// How many steps before and after the current one are still accepted, to allow for clock drift.
+const SKEW_STEPS: i64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Generates a new random 160-bit TOTP secret, as recommended by RFC 4226.
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Encodes bytes as unpadded RFC 4648 Base32, the format authenticator apps expect.
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | u64::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+/// Builds the `otpauth://` URI that authenticator apps import (usually via QR code).
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECS}",
+        issuer = urlencoding::encode(issuer),
+        account = urlencoding::encode(account),
+        secret = base32_encode(secret),
+    )
+}
+
+/// Returns the time step a Unix timestamp falls into.
+pub fn time_step(unix_time: i64) -> i64 {
+    unix_time.div_euclid(PERIOD_SECS)
+}
+
+/// Computes the HOTP value (RFC 4226) for the given counter.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    truncated % 10u32.pow(DIGITS)
+}
+
+/// Checks a code against the secret at the given time.
+///
+/// Codes for the current step and [`SKEW_STEPS`] on either side are accepted, except for
+/// steps at or before `last_used_step`, which prevents a code from being replayed.
+/// Returns the matching step so the caller can record it as used.
+pub fn verify_code(secret: &[u8], code: &str, unix_time: i64, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = time_step(unix_time);
+    (current - SKEW_STEPS..=current + SKEW_STEPS)
+        .filter(|&step| step >= 0 && last_used_step.is_none_or(|last| step > last))
+        .find(|&step| {
+            let expected = format!("{:0width$}", hotp(secret, step as u64), width = DIGITS as usize);
+            constant_time_eq(expected.as_bytes(), code.as_bytes())
+        })
+}
@@ -0,0 +1,14 @@
+use sqlx::PgExecutor;
+
+/// Permanently deletes credentials that have been in the trash for more than
+/// `retention_days`, returning how many were removed.
+pub async fn purge_trash<'c>(executor: impl PgExecutor<'c>, retention_days: u32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM credentials WHERE deleted_at < NOW() - make_interval(days => $1)",
+        retention_days.min(i32::MAX as u32) as i32
+    )
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
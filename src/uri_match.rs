@@ -0,0 +1,126 @@
+use url::{Host, Url};
+
+use crate::models::UriMatchType;
+
+/// Parses a URI as entered for a credential, reading one without a scheme as
+/// `https://`. Returns `None` unless it has a host.
+pub fn parse_uri(uri: &str) -> Option<Url> {
+    let uri = uri.trim();
+    let parsed = if uri.contains("://") {
+        Url::parse(uri)
+    } else {
+        Url::parse(&format!("https://{}", uri))
+    };
+    let parsed = parsed.ok()?;
+    match parsed.host() {
+        Some(Host::Domain("")) | None => None,
+        Some(_) => Some(parsed),
+    }
+}
+
+/// The registrable domain of a URL's host (its eTLD+1, e.g. `example.co.uk` for
+/// `login.example.co.uk`), by the public suffix list. IP addresses, and names that are
+/// only a public suffix or unknown to the list, such as `localhost`, stand for
+/// themselves.
+fn registrable_domain(url: &Url) -> Option<String> {
+    match url.host()? {
+        Host::Domain(host) => {
+            let host = host.trim_end_matches('.');
+            Some(psl::domain_str(host).unwrap_or(host).to_string())
+        }
+        Host::Ipv4(ip) => Some(ip.to_string()),
+        Host::Ipv6(ip) => Some(ip.to_string()),
+    }
+}
+
+/// A URL being filled in, parsed once to be compared with many credential URIs.
+pub struct PageUrl {
+    url: Url,
+    domain: Option<String>,
+}
+
+impl PageUrl {
+    /// Parses the page's URL, or `None` unless it has a host.
+    pub fn parse(url: &str) -> Option<Self> {
+        let mut url = parse_uri(url)?;
+        url.set_fragment(None);
+        let domain = registrable_domain(&url);
+        Some(PageUrl { url, domain })
+    }
+
+    /// Whether a credential URI matches the page under `match_type`. URIs that don't
+    /// parse match nothing.
+    pub fn matches(&self, uri: &str, match_type: UriMatchType) -> bool {
+        let Some(mut uri) = parse_uri(uri) else {
+            return false;
+        };
+        uri.set_fragment(None);
+        match match_type {
+            UriMatchType::Exact => uri == self.url,
+            UriMatchType::StartsWith => self.url.as_str().starts_with(uri.as_str()),
+            UriMatchType::Host => {
+                uri.host() == self.url.host() && uri.port_or_known_default() == self.url.port_or_known_default()
+            }
+            UriMatchType::Domain => self.domain.is_some() && registrable_domain(&uri) == self.domain,
+        }
+    }
+}
+
+/// How specific a match of `match_type` is, higher for narrower ones, for ranking.
+pub fn specificity(match_type: UriMatchType) -> u8 {
+    match match_type {
+        UriMatchType::Exact => 3,
+        UriMatchType::StartsWith => 2,
+        UriMatchType::Host => 1,
+        UriMatchType::Domain => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(uri: &str) -> Option<String> {
+        registrable_domain(&parse_uri(uri).unwrap())
+    }
+
+    fn matches(page: &str, uri: &str, match_type: UriMatchType) -> bool {
+        PageUrl::parse(page).unwrap().matches(uri, match_type)
+    }
+
+    #[test]
+    fn registrable_domain_spans_multi_label_suffixes() {
+        assert_eq!(domain("https://login.example.co.uk").as_deref(), Some("example.co.uk"));
+        assert_eq!(domain("example.co.uk").as_deref(), Some("example.co.uk"));
+        assert_eq!(domain("https://foo.github.io/app").as_deref(), Some("foo.github.io"));
+    }
+
+    #[test]
+    fn registrable_domain_of_ips_and_bare_names_is_the_host() {
+        assert_eq!(domain("http://192.168.1.10:8080").as_deref(), Some("192.168.1.10"));
+        assert_eq!(domain("http://localhost:3000").as_deref(), Some("localhost"));
+        assert_eq!(domain("https://co.uk").as_deref(), Some("co.uk"));
+    }
+
+    #[test]
+    fn domain_match_joins_subdomains_of_one_registrable_domain() {
+        assert!(matches("https://a.example.co.uk/login", "shop.example.co.uk", UriMatchType::Domain));
+    }
+
+    #[test]
+    fn domain_match_keeps_apart_sites_under_a_public_suffix() {
+        assert!(!matches("https://example.co.uk", "https://other.co.uk", UriMatchType::Domain));
+        assert!(!matches("https://foo.github.io", "https://bar.github.io", UriMatchType::Domain));
+    }
+
+    #[test]
+    fn host_match_respects_ports_on_ip_addresses() {
+        assert!(matches("https://10.0.0.1:8443/admin", "10.0.0.1:8443", UriMatchType::Host));
+        assert!(!matches("https://10.0.0.1:8443/admin", "10.0.0.1:9443", UriMatchType::Host));
+        assert!(!matches("https://10.0.0.1:8443/admin", "10.0.0.1", UriMatchType::Host));
+        // An explicit default port is the same as none.
+        assert!(matches("https://10.0.0.1/", "https://10.0.0.1:443", UriMatchType::Host));
+        assert!(matches("http://[::1]:8080/", "http://[::1]:8080/x", UriMatchType::Host));
+        assert!(!matches("http://[::1]:8080/", "http://[::1]:8081", UriMatchType::Host));
+    }
+}
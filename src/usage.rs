@@ -0,0 +1,110 @@
+use chrono::{DateTime, Utc};
+use rocket::tokio::sync::mpsc;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How many uses can wait for the next flush before new ones are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// How long uses are gathered before they are written together.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A user reading a credential's secret.
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    pub user_id: Uuid,
+    pub credential_id: Uuid,
+    pub used_at: DateTime<Utc>,
+}
+
+/// Queues credential uses so reads don't wait on writing them. A background task started
+/// at liftoff writes them in batches to `credentials.last_used_at` and `credential_usage`.
+pub struct UsageRecorder {
+    sender: mpsc::Sender<Usage>,
+    receiver: Mutex<Option<mpsc::Receiver<Usage>>>,
+}
+
+impl UsageRecorder {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        UsageRecorder { sender, receiver: Mutex::new(Some(receiver)) }
+    }
+
+    /// Queues a use of each credential by the user. Uses are dropped rather than waited
+    /// on when the queue is full, since they only order "recent" lists.
+    pub fn record(&self, user_id: Uuid, credential_ids: &[Uuid]) {
+        let used_at = Utc::now();
+        for &credential_id in credential_ids {
+            if self.sender.try_send(Usage { user_id, credential_id, used_at }).is_err() {
+                warn!("Credential usage queue is full; dropping uses");
+                return;
+            }
+        }
+    }
+
+    /// Hands the queue to the task that flushes it. Only the first call gets it.
+    pub fn take_receiver(&self) -> Option<mpsc::Receiver<Usage>> {
+        self.receiver.lock().ok()?.take()
+    }
+}
+
+/// Writes queued uses every `FLUSH_INTERVAL` until the recorder is dropped.
+pub async fn flush_usage(pool: PgPool, mut receiver: mpsc::Receiver<Usage>) {
+    while let Some(first) = receiver.recv().await {
+        rocket::tokio::time::sleep(FLUSH_INTERVAL).await;
+
+        let mut latest = HashMap::new();
+        let mut next = Some(first);
+        while let Some(usage) = next {
+            latest
+                .entry((usage.user_id, usage.credential_id))
+                .and_modify(|used_at: &mut DateTime<Utc>| *used_at = (*used_at).max(usage.used_at))
+                .or_insert(usage.used_at);
+            next = receiver.try_recv().ok();
+        }
+
+        if let Err(e) = write_usage(&pool, &latest).await {
+            warn!("Recording credential usage failed: {}", e);
+        }
+    }
+}
+
+/// Writes the latest use of each credential by each user in one statement. Credentials
+/// or users deleted since the read are skipped.
+async fn write_usage(pool: &PgPool, latest: &HashMap<(Uuid, Uuid), DateTime<Utc>>) -> Result<(), sqlx::Error> {
+    let mut user_ids = Vec::with_capacity(latest.len());
+    let mut credential_ids = Vec::with_capacity(latest.len());
+    let mut used_ats = Vec::with_capacity(latest.len());
+    for (&(user_id, credential_id), &used_at) in latest {
+        user_ids.push(user_id);
+        credential_ids.push(credential_id);
+        used_ats.push(used_at);
+    }
+
+    sqlx::query!(
+        "WITH used AS (
+             SELECT u.user_id, u.credential_id, u.used_at
+             FROM UNNEST($1::uuid[], $2::uuid[], $3::timestamptz[]) AS u(user_id, credential_id, used_at)
+             JOIN credentials c ON c.id = u.credential_id
+             JOIN users s ON s.id = u.user_id
+         ), bumped AS (
+             UPDATE credentials c SET last_used_at = GREATEST(c.last_used_at, latest.used_at)
+             FROM (SELECT credential_id, MAX(used_at) AS used_at FROM used GROUP BY credential_id) latest
+             WHERE c.id = latest.credential_id
+         )
+         INSERT INTO credential_usage (user_id, credential_id, used_at)
+         SELECT user_id, credential_id, used_at FROM used
+         ON CONFLICT (user_id, credential_id)
+         DO UPDATE SET used_at = GREATEST(credential_usage.used_at, EXCLUDED.used_at)",
+        &user_ids,
+        &credential_ids,
+        &used_ats
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use rocket::http::Status;
+use rocket::serde::Serialize;
+
+use crate::error::ApiError;
+
+/// The longest display name accepted, in characters.
+pub const MAX_NAME_LEN: usize = 100;
+/// The longest email address accepted, in bytes (the SMTP path limit).
+pub const MAX_EMAIL_LEN: usize = 254;
+
+/// One invalid field and what's wrong with it.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    /// Which entry of a list field is invalid, for errors about one entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+/// Collects every invalid field of a request so they can be reported together.
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    /// Records `message` against `field` unless `valid` holds.
+    pub fn check(&mut self, field: &'static str, valid: bool, message: impl Into<String>) -> &mut Self {
+        if !valid {
+            self.errors.push(FieldError { field, index: None, message: message.into() });
+        }
+        self
+    }
+
+    /// Records `message` against entry `index` of the list `field` unless `valid` holds.
+    pub fn check_entry(&mut self, field: &'static str, index: usize, valid: bool, message: impl Into<String>) -> &mut Self {
+        if !valid {
+            self.errors.push(FieldError { field, index: Some(index), message: message.into() });
+        }
+        self
+    }
+
+    /// Records an error unless `bytes` has one of the `allowed` lengths.
+    pub fn check_len(&mut self, field: &'static str, bytes: &[u8], allowed: &[usize]) -> &mut Self {
+        let message = match allowed {
+            [len] => format!("must be exactly {} bytes", len),
+            _ => format!(
+                "must be {} bytes",
+                allowed.iter().map(usize::to_string).collect::<Vec<_>>().join(" or ")
+            ),
+        };
+        self.check(field, allowed.contains(&bytes.len()), message)
+    }
+
+    /// Records an error unless `bytes` is non-empty and at most `max` bytes long.
+    pub fn check_max_len(&mut self, field: &'static str, bytes: &[u8], max: usize) -> &mut Self {
+        let valid = !bytes.is_empty() && bytes.len() <= max;
+        self.check(field, valid, format!("must be between 1 and {} bytes", max))
+    }
+
+    /// The errors recorded so far, for requests that report them per item.
+    pub fn into_errors(self) -> Vec<FieldError> {
+        self.errors
+    }
+
+    /// Fails with `422 Unprocessable Entity` listing every recorded error, if any.
+    pub fn finish(self) -> Result<(), ApiError> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(ApiError::new(Status::UnprocessableEntity, "validation_failed").with("errors", self.errors))
+    }
+}
+
+/// Whether `email` plausibly is an email address: one `@` with something on either
+/// side, a dot in the domain, no whitespace, and at most [`MAX_EMAIL_LEN`] bytes.
+pub fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    email.len() <= MAX_EMAIL_LEN
+        && !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.chars().any(char::is_whitespace)
+}
+
+/// Whether `name`, once trimmed, is 1 to [`MAX_NAME_LEN`] characters long.
+pub fn is_valid_name(name: &str) -> bool {
+    let len = name.trim().chars().count();
+    (1..=MAX_NAME_LEN).contains(&len)
+}
@@ -0,0 +1,124 @@
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::time::Duration;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::crypto::constant_time_eq;
+use crate::tokens::{generate_opaque_token, hash_token};
+
+/// The HttpOnly cookie carrying a web session.
+pub const SESSION_COOKIE: &str = "homedesk_session";
+/// The cookie carrying the web session's CSRF token, readable by the web client so it
+/// can echo it in [`CSRF_HEADER`].
+pub const CSRF_COOKIE: &str = "homedesk_csrf";
+/// The header state-changing requests authenticated by cookie must send the CSRF token in.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// How a completed login hands its session to the client, chosen with the `session`
+/// query parameter of the login routes.
+///
+/// By default (`?session=bearer` or no parameter) the response carries an access and
+/// refresh token. With `?session=cookie` it sets a web session cookie instead, for
+/// browsers that shouldn't keep tokens where scripts can read them. Any other value
+/// fails the request with `400 Bad Request`.
+pub enum SessionDelivery<'r> {
+    Bearer,
+    Cookie(&'r CookieJar<'r>),
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionDelivery<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.query_value::<&str>("session") {
+            None | Some(Ok("bearer")) => Outcome::Success(SessionDelivery::Bearer),
+            Some(Ok("cookie")) => Outcome::Success(SessionDelivery::Cookie(request.cookies())),
+            Some(_) => Outcome::Error((Status::BadRequest, ())),
+        }
+    }
+}
+
+/// Stores a web session backing the given login session and returns its cookie value
+/// and CSRF token, for [`set_web_session_cookies`] once the login is committed.
+pub async fn create_web_session(
+    conn: &mut PgConnection,
+    config: &AppConfig,
+    session_id: Uuid,
+) -> Result<(String, String), sqlx::Error> {
+    let token = generate_opaque_token();
+    let csrf_token = generate_opaque_token();
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(config.auth.refresh_token_ttl_secs);
+
+    sqlx::query!(
+        "INSERT INTO web_sessions (session_id, token_hash, csrf_token_hash, expires_at) VALUES ($1, $2, $3, $4)",
+        session_id,
+        hash_token(&token),
+        hash_token(&csrf_token),
+        expires_at
+    )
+        .execute(conn)
+        .await?;
+
+    Ok((token, csrf_token))
+}
+
+/// Sets the cookies of a web session created by [`create_web_session`].
+///
+/// The session cookie is HttpOnly, Secure, and SameSite=Strict; the CSRF cookie is the
+/// same except that scripts can read it. Both last as long as a refresh token would.
+pub fn set_web_session_cookies(cookies: &CookieJar<'_>, config: &AppConfig, token: String, csrf_token: String) {
+    let max_age = Duration::seconds(config.auth.refresh_token_ttl_secs);
+    cookies.add(
+        Cookie::build((SESSION_COOKIE, token))
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Strict)
+            .max_age(max_age),
+    );
+    cookies.add(
+        Cookie::build((CSRF_COOKIE, csrf_token))
+            .path("/")
+            .secure(true)
+            .same_site(SameSite::Strict)
+            .max_age(max_age),
+    );
+}
+
+/// Removes the web session cookies, e.g. on logout.
+pub fn clear_web_session_cookies(cookies: &CookieJar<'_>) {
+    cookies.remove(Cookie::build(SESSION_COOKIE).path("/"));
+    cookies.remove(Cookie::build(CSRF_COOKIE).path("/"));
+}
+
+/// A web session that hasn't expired, as found by [`find_web_session`].
+pub struct WebSession {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+    csrf_token_hash: Vec<u8>,
+}
+
+impl WebSession {
+    /// Whether `csrf_token` is the one issued with this web session.
+    pub fn csrf_token_matches(&self, csrf_token: &str) -> bool {
+        constant_time_eq(&hash_token(csrf_token), &self.csrf_token_hash)
+    }
+}
+
+/// Looks up the web session a session cookie belongs to, returning `None` if it is
+/// unknown or expired. Whether the login session behind it is still active is left to
+/// the caller.
+pub async fn find_web_session(conn: &mut PgConnection, token: &str) -> Result<Option<WebSession>, sqlx::Error> {
+    sqlx::query_as!(
+        WebSession,
+        "SELECT w.session_id, s.user_id, w.csrf_token_hash
+         FROM web_sessions w JOIN sessions s ON s.id = w.session_id
+         WHERE w.token_hash = $1 AND w.expires_at > NOW()",
+        hash_token(token)
+    )
+        .fetch_optional(conn)
+        .await
+}